@@ -0,0 +1,130 @@
+#![no_main]
+
+use std::{any::Any, env, sync::OnceLock};
+
+use libfuzzer_sys::fuzz_target;
+use wedis::{
+    connection::{ClientError, Connection},
+    database::{open_shard, Database},
+    dispatch::dispatch,
+};
+
+// Picking the command name from a fixed list gets the fuzzer into the
+// handlers much faster than having it guess names byte by byte
+const COMMANDS: &[&str] = &[
+    "APPEND",
+    "BF.ADD",
+    "BF.EXISTS",
+    "BF.MADD",
+    "BF.MEXISTS",
+    "BF.RESERVE",
+    "BITCOUNT",
+    "BITPOS",
+    "CLIENT",
+    "CMS.INCRBY",
+    "CMS.INFO",
+    "CMS.INITBYDIM",
+    "CMS.INITBYPROB",
+    "CMS.MERGE",
+    "CMS.QUERY",
+    "DECR",
+    "DECRBY",
+    "DEL",
+    "ECHO",
+    "EXISTS",
+    "EXPIRE",
+    "EXPIREAT",
+    "EXPIRETIME",
+    "GET",
+    "GETBIT",
+    "GETDEL",
+    "GETRANGE",
+    "GETSET",
+    "HELLO",
+    "HGET",
+    "HSET",
+    "HSTRLEN",
+    "INCR",
+    "INCRBY",
+    "INCRBYFLOAT",
+    "INFO",
+    "MGET",
+    "MSETNX",
+    "PERSIST",
+    "PEXPIRE",
+    "PEXPIREAT",
+    "PEXPIRETIME",
+    "PING",
+    "PSETEX",
+    "PTTL",
+    "SET",
+    "SETBIT",
+    "SETEX",
+    "SETNX",
+    "SETRANGE",
+    "STRLEN",
+    "SUBSTR",
+    "TDIGEST.ADD",
+    "TDIGEST.CDF",
+    "TDIGEST.CREATE",
+    "TDIGEST.MERGE",
+    "TDIGEST.QUANTILE",
+    "TOPK.ADD",
+    "TOPK.COUNT",
+    "TOPK.INFO",
+    "TOPK.LIST",
+    "TOPK.QUERY",
+    "TOPK.RESERVE",
+    "TS.ADD",
+    "TS.CREATE",
+    "TS.INFO",
+    "TS.MRANGE",
+    "TS.RANGE",
+    "TTL",
+    "TYPE",
+    "UNLINK",
+];
+
+struct NullConnection {
+    context: Option<Box<dyn Any>>,
+}
+
+impl Connection for NullConnection {
+    fn write_bulk(&mut self, _msg: &[u8]) {}
+
+    fn write_array(&mut self, _count: usize) {}
+
+    fn write_string(&mut self, _msg: &str) {}
+
+    fn write_integer(&mut self, _x: i64) {}
+
+    fn write_error(&mut self, _err: ClientError) {}
+
+    fn write_null(&mut self) {}
+
+    fn context(&mut self) -> &mut Option<Box<dyn Any>> {
+        &mut self.context
+    }
+
+    fn connection_id(&mut self) -> i64 {
+        -1
+    }
+}
+
+fn database() -> &'static Database {
+    static DB: OnceLock<Database> = OnceLock::new();
+    DB.get_or_init(|| {
+        let path = env::temp_dir().join(format!("wedis-fuzz-{}", std::process::id()));
+        let db_raw = open_shard(path).expect("Failed to open database");
+        Database::new(db_raw)
+    })
+}
+
+fuzz_target!(|input: (u8, Vec<Vec<u8>>)| {
+    let (command, mut args) = input;
+    let name = COMMANDS[command as usize % COMMANDS.len()];
+    args.insert(0, name.as_bytes().to_vec());
+
+    let mut conn = NullConnection { context: None };
+    dispatch(&mut conn, database(), &args);
+});
@@ -0,0 +1,358 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use tracing::{error, info};
+
+use crate::{config, keyspec, time::unix_timestamp};
+
+// Like the metadata Redis keeps in every object for its LRU and LFU policies,
+// each tracked key has the time of its last access and a logarithmic access
+// counter, which decays while the key sits idle. Only so many keys are
+// tracked at once; when full, the longest idle of a few sampled keys makes
+// room, the same way Redis picks keys to evict.
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_SECS: u64 = 60;
+const EVICTION_SAMPLES: usize = 5;
+
+// Saved in the data directory, which RocksDB shares when there's one shard,
+// so it's named to stay clear of RocksDB's own files
+const SAVE_FILE: &str = "wedis-access";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+    // Unix time in seconds
+    pub last_access: u64,
+    counter: u8,
+}
+
+impl Access {
+    fn new(now: u64) -> Self {
+        Self {
+            last_access: now,
+            counter: LFU_INIT_VAL,
+        }
+    }
+
+    // The access counter, after decaying by one for every period idle
+    pub fn frequency(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.last_access) / LFU_DECAY_SECS;
+        self.counter
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    pub fn idle_time(&self, now: u64) -> u64 {
+        now.saturating_sub(self.last_access)
+    }
+
+    // Frequently accessed keys become less and less likely to count up, so
+    // the counter can cover millions of accesses in a byte. `roll` is
+    // uniform in [0, 1).
+    fn touch(&mut self, now: u64, roll: f64) {
+        let counter = self.frequency(now);
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        self.counter = if counter < u8::MAX && roll < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            counter + 1
+        } else {
+            counter
+        };
+        self.last_access = now;
+    }
+}
+
+pub struct AccessTracker {
+    max_keys: usize,
+    // Entries are kept in a vector so that keys can be sampled at random
+    entries: Vec<(Vec<u8>, Access)>,
+    index: HashMap<Vec<u8>, usize>,
+    rng: u64,
+}
+
+impl AccessTracker {
+    pub fn new(max_keys: usize) -> Self {
+        Self {
+            max_keys: max_keys.max(1),
+            entries: vec![],
+            index: HashMap::new(),
+            rng: unix_timestamp()
+                .map(|t| t.as_nanos() as u64)
+                .unwrap_or_default()
+                | 1,
+        }
+    }
+
+    // xorshift64, which is plenty for sampling
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Access> {
+        self.index.get(key).map(|i| self.entries[*i].1)
+    }
+
+    pub fn record(&mut self, key: &[u8], now: u64) {
+        let roll = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        if let Some(i) = self.index.get(key) {
+            self.entries[*i].1.touch(now, roll);
+            return;
+        }
+
+        self.insert(key.to_vec(), Access::new(now));
+    }
+
+    fn insert(&mut self, key: Vec<u8>, access: Access) {
+        while self.entries.len() >= self.max_keys {
+            self.evict();
+        }
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, access));
+    }
+
+    fn evict(&mut self) {
+        let n = self.entries.len() as u64;
+        let mut oldest = self.next_random() % n;
+        for _ in 1..EVICTION_SAMPLES {
+            let candidate = self.next_random() % n;
+            if self.entries[candidate as usize].1.last_access
+                < self.entries[oldest as usize].1.last_access
+            {
+                oldest = candidate;
+            }
+        }
+
+        let (key, _) = self.entries.swap_remove(oldest as usize);
+        self.index.remove(&key);
+        if let Some((moved, _)) = self.entries.get(oldest as usize) {
+            self.index.insert(moved.clone(), oldest as usize);
+        }
+    }
+
+    // Each entry is the key's length as a big-endian u32, the key, the last
+    // access time as a big-endian u64 and then the counter
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for (key, access) in self.entries.iter() {
+            out.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            out.extend_from_slice(key);
+            out.extend_from_slice(&access.last_access.to_be_bytes());
+            out.push(access.counter);
+        }
+        out
+    }
+
+    // Loads saved entries, up to max_keys of them. A truncated entry ends
+    // the data, so a file cut short by a crash still loads everything
+    // before it.
+    pub fn decode(mut data: &[u8], max_keys: usize) -> Self {
+        let mut tracker = Self::new(max_keys);
+        while let Some((key_len, rest)) = data.split_first_chunk::<4>() {
+            let key_len = u32::from_be_bytes(*key_len) as usize;
+            if rest.len() < key_len + 9 {
+                break;
+            }
+
+            let (key, rest) = rest.split_at(key_len);
+            let (last_access, rest) = rest.split_first_chunk::<8>().unwrap();
+            let access = Access {
+                last_access: u64::from_be_bytes(*last_access),
+                counter: rest[0],
+            };
+            if !tracker.index.contains_key(key) {
+                tracker.insert(key.to_vec(), access);
+            }
+            data = &rest[1..];
+        }
+        tracker
+    }
+}
+
+struct State {
+    tracker: AccessTracker,
+    // Keys that aren't tracked haven't been accessed since at least then
+    started: u64,
+    save_path: PathBuf,
+}
+
+fn state() -> &'static Mutex<Option<State>> {
+    static STATE: OnceLock<Mutex<Option<State>>> = OnceLock::new();
+    STATE.get_or_init(|| Mutex::new(None))
+}
+
+fn now() -> u64 {
+    unix_timestamp().map(|t| t.as_secs()).unwrap_or_default()
+}
+
+fn settings() -> (bool, usize, u64) {
+    let config = config::config().read().unwrap();
+    let max_keys = config
+        .value("access-tracking-max-keys")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(65536);
+    let save_seconds = config
+        .value("access-tracking-save-seconds")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60);
+    (
+        config.value("access-tracking") == Some("yes"),
+        max_keys,
+        save_seconds,
+    )
+}
+
+// Loads what was tracked before the last restart from the data directory,
+// and saves it back there every access-tracking-save-seconds
+pub fn start_from_config(dir: &Path) -> io::Result<()> {
+    let (enabled, max_keys, save_seconds) = settings();
+    if !enabled {
+        return Ok(());
+    }
+
+    let path = dir.join(SAVE_FILE);
+    let tracker = match fs::read(&path) {
+        Ok(data) => AccessTracker::decode(&data, max_keys),
+        Err(err) if { err.kind() == ErrorKind::NotFound } => AccessTracker::new(max_keys),
+        Err(err) => return Err(err),
+    };
+    info!("Loaded access metadata for {} keys", tracker.len());
+
+    *state().lock().unwrap() = Some(State {
+        tracker,
+        started: now(),
+        save_path: path,
+    });
+
+    if save_seconds > 0 {
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(save_seconds));
+            if let Err(err) = save() {
+                error!("Failed to save access metadata: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Written to a temporary file first, so a crash mid-save leaves the previous
+// save intact
+pub fn save() -> io::Result<()> {
+    let (data, path) = match state().lock().unwrap().as_ref() {
+        Some(state) => (state.tracker.encode(), state.save_path.clone()),
+        None => return Ok(()),
+    };
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(tmp_path, path)
+}
+
+// Counts an access to each key the command reads or writes. Commands that
+// only inspect keys, like OBJECT, don't count.
+pub fn record(args: &[Vec<u8>]) {
+    let mut state = state().lock().unwrap();
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+
+    let now = now();
+    for key in keyspec::accessed_keys(args) {
+        state.tracker.record(key, now);
+    }
+}
+
+// Forgets every key's accesses, as when the keyspace is flushed
+pub fn clear() {
+    let (_, max_keys, _) = settings();
+    if let Some(state) = state().lock().unwrap().as_mut() {
+        state.tracker = AccessTracker::new(max_keys);
+        state.started = now();
+    }
+}
+
+// Seconds since the key was last accessed, or None if tracking is off
+pub fn idle_time(key: &[u8]) -> Option<u64> {
+    let state = state().lock().unwrap();
+    let state = state.as_ref()?;
+    let now = now();
+    Some(match state.tracker.get(key) {
+        Some(access) => access.idle_time(now),
+        None => now.saturating_sub(state.started),
+    })
+}
+
+// The key's logarithmic access counter, or None if tracking is off
+pub fn frequency(key: &[u8]) -> Option<u8> {
+    let state = state().lock().unwrap();
+    let state = state.as_ref()?;
+    Some(
+        state
+            .tracker
+            .get(key)
+            .map_or(0, |access| access.frequency(now())),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_access_frequency() {
+        let mut access = Access::new(1000);
+        assert_eq!(LFU_INIT_VAL, access.frequency(1000));
+
+        // Counting up gets harder the higher the counter is
+        access.touch(1000, 0.05);
+        assert_eq!(LFU_INIT_VAL + 1, access.frequency(1000));
+        access.touch(1000, 0.5);
+        assert_eq!(LFU_INIT_VAL + 1, access.frequency(1000));
+
+        // One period idle takes one off the counter
+        assert_eq!(LFU_INIT_VAL, access.frequency(1000 + LFU_DECAY_SECS));
+        assert_eq!(0, access.frequency(u64::MAX));
+        assert_eq!(30, access.idle_time(1030));
+    }
+
+    #[test]
+    fn test_tracker_bounded() {
+        let mut tracker = AccessTracker::new(2);
+        tracker.record(b"a", 1);
+        tracker.record(b"b", 2);
+        tracker.record(b"c", 3);
+
+        assert_eq!(2, tracker.len());
+        assert!(tracker.get(b"c").is_some());
+        for (i, (key, _)) in tracker.entries.iter().enumerate() {
+            assert_eq!(Some(&i), tracker.index.get(key));
+        }
+    }
+
+    #[test]
+    fn test_tracker_encode_decode() {
+        let mut tracker = AccessTracker::new(10);
+        tracker.record(b"a", 1);
+        tracker.record(b"bc", 2);
+
+        let data = tracker.encode();
+        let decoded = AccessTracker::decode(&data, 10);
+        assert_eq!(tracker.get(b"a"), decoded.get(b"a"));
+        assert_eq!(tracker.get(b"bc"), decoded.get(b"bc"));
+
+        // A truncated entry is dropped, and only so many keys are loaded
+        assert_eq!(1, AccessTracker::decode(&data[..data.len() - 1], 10).len());
+        assert_eq!(1, AccessTracker::decode(&data, 1).len());
+    }
+}
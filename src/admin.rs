@@ -0,0 +1,207 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{
+    config,
+    database::{Database, DatabaseOperations},
+    diagnostics, shutdown,
+};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Set until startup checks and imports are done
+static LOADING: AtomicBool = AtomicBool::new(true);
+static ACCEPTING: AtomicBool = AtomicBool::new(false);
+
+fn started() -> &'static Instant {
+    static STARTED: OnceLock<Instant> = OnceLock::new();
+    STARTED.get_or_init(Instant::now)
+}
+
+pub fn set_loading(loading: bool) {
+    LOADING.store(loading, Ordering::SeqCst);
+}
+
+pub fn set_accepting(accepting: bool) {
+    ACCEPTING.store(accepting, Ordering::SeqCst);
+}
+
+fn healthz(db: &dyn DatabaseOperations) -> (u16, Value) {
+    let loading = LOADING.load(Ordering::SeqCst);
+    let accepting = ACCEPTING.load(Ordering::SeqCst) && !shutdown::is_shutting_down();
+    let writable = db.check_writable().is_ok();
+
+    // The server isn't expected to accept connections while it's still
+    // loading, and that shouldn't get it restarted
+    let healthy = (accepting || loading) && writable;
+    let body = json!({
+        "status": if healthy { "ok" } else { "unhealthy" },
+        "accepting_connections": accepting,
+        "storage_writable": writable,
+    });
+    (if healthy { 200 } else { 503 }, body)
+}
+
+fn readyz() -> (u16, Value) {
+    let loading = LOADING.load(Ordering::SeqCst);
+    let ready = !loading && ACCEPTING.load(Ordering::SeqCst) && !shutdown::is_shutting_down();
+
+    // There's no replication, so every instance is a master with nothing to
+    // catch up on
+    let body = json!({
+        "status": if ready { "ready" } else { "not ready" },
+        "loading": loading,
+        "role": "master",
+    });
+    (if ready { 200 } else { 503 }, body)
+}
+
+fn stats(db: &dyn DatabaseOperations) -> (u16, Value) {
+    let storage = match db.storage_stats() {
+        Ok(stats) => stats
+            .properties
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), json!(value)))
+            .collect(),
+        Err(_) => serde_json::Map::new(),
+    };
+
+    let body = json!({
+        "uptime_in_seconds": started().elapsed().as_secs(),
+        "connected_clients": diagnostics::connection_count(),
+        "total_commands_processed": diagnostics::total_commands(),
+        "shards": db.shard_count(),
+        "storage": storage,
+    });
+    (200, body)
+}
+
+pub fn respond(method: &str, path: &str, db: &dyn DatabaseOperations) -> (u16, Value) {
+    if method != "GET" {
+        return (405, json!({ "error": "method not allowed" }));
+    }
+
+    match path {
+        "/healthz" => healthz(db),
+        "/readyz" => readyz(),
+        "/stats" => stats(db),
+        _ => (404, json!({ "error": "not found" })),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    }
+}
+
+fn handle(stream: TcpStream, db: &Database) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers aren't needed for anything, but they have to be read before
+    // replying
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, body) = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        [method, path, _] => {
+            // Query strings are ignored
+            let path = path.split('?').next().unwrap_or(path);
+            respond(method, path, db)
+        }
+        _ => (400, json!({ "error": "bad request" })),
+    };
+
+    let body = body.to_string();
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )
+}
+
+// Serves the admin endpoints on admin-http if it's set. It can only be
+// enabled at startup.
+pub fn start_from_config(db: Arc<Database>) -> io::Result<()> {
+    started();
+
+    let addr = config::config()
+        .read()
+        .unwrap()
+        .value("admin-http")
+        .unwrap_or("")
+        .to_string();
+    if addr.is_empty() {
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(&addr)?;
+    info!("Serving admin endpoints at http://{}", addr);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("Failed to accept admin connection: {}", err);
+                    continue;
+                }
+            };
+
+            let db = db.clone();
+            thread::spawn(move || {
+                if let Err(err) = handle(stream, &db) {
+                    warn!("Failed to serve admin request: {}", err);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::{DatabaseError, MockDatabaseOperations};
+
+    use super::*;
+
+    #[test]
+    fn test_respond() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_check_writable()
+            .times(1)
+            .returning(|| Err(DatabaseError::CrossShard));
+
+        let (status, body) = respond("GET", "/healthz", &mock_db);
+        assert_eq!(503, status);
+        assert_eq!(json!(false), body["storage_writable"]);
+
+        assert_eq!(404, respond("GET", "/nope", &mock_db).0);
+        assert_eq!(405, respond("POST", "/healthz", &mock_db).0);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::net::UnixDatagram,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{config, keyspec, time::unix_timestamp};
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+// Rotated logs are kept as <path>.1 (newest) through <path>.<ROTATIONS>
+const ROTATIONS: usize = 5;
+const REDACTED: &str = "(redacted)";
+
+// Commands that change server state rather than keys. Their arguments can
+// hold secrets (e.g. CONFIG SET requirepass), so only the first two are
+// kept when redacting.
+const ADMIN_COMMANDS: &[&str] = &[
+    "BGSAVE", "BIGKEYS", "CHECK", "CONFIG", "DEBUG", "FLUSHALL", "FLUSHDB", "MODULE", "SAVE",
+    "SHUTDOWN", "SLOWLOG",
+];
+const ADMIN_ARGS_KEPT: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEntry {
+    // Unix time in milliseconds
+    pub timestamp: u64,
+    pub user: String,
+    pub client: String,
+    pub client_id: i64,
+    pub command: String,
+    pub keys: Vec<String>,
+    pub args: Vec<String>,
+    pub ok: bool,
+}
+
+enum Sink {
+    File {
+        path: PathBuf,
+        file: File,
+        size: u64,
+        max_size: u64,
+    },
+    Syslog(UnixDatagram),
+}
+
+impl Sink {
+    fn open_file(path: PathBuf, max_size: u64) -> io::Result<Sink> {
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Sink::File {
+            path,
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::File {
+                path,
+                file,
+                size,
+                max_size,
+            } => {
+                if *max_size > 0 && *size + line.len() as u64 > *max_size {
+                    rotate(path)?;
+                    *file = OpenOptions::new().append(true).create(true).open(&path)?;
+                    *size = 0;
+                }
+                file.write_all(line.as_bytes())?;
+                file.write_all(b"\n")?;
+                *size += line.len() as u64 + 1;
+                Ok(())
+            }
+            // Facility local0, severity info
+            Sink::Syslog(socket) => socket
+                .send(format!("<134>wedis-audit: {}", line).as_bytes())
+                .map(|_| ()),
+        }
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn rotate(path: &Path) -> io::Result<()> {
+    for n in (1..ROTATIONS).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, n + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+struct AuditLog {
+    sink: Sink,
+    redact: bool,
+}
+
+fn audit_log() -> &'static OnceLock<Mutex<AuditLog>> {
+    static AUDIT_LOG: OnceLock<Mutex<AuditLog>> = OnceLock::new();
+    &AUDIT_LOG
+}
+
+// Opens the audit log configured by audit-log, which is either a file path
+// or "syslog". It can only be enabled at startup.
+pub fn start_from_config() -> io::Result<()> {
+    let (target, max_size, redact) = {
+        let config = config::config().read().unwrap();
+        (
+            config.value("audit-log").unwrap_or("").to_string(),
+            config
+                .value("audit-log-max-size")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0),
+            config.value("audit-log-redact") != Some("no"),
+        )
+    };
+    let sink = match target.as_str() {
+        "" => return Ok(()),
+        "syslog" => {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(SYSLOG_SOCKET)?;
+            Sink::Syslog(socket)
+        }
+        path => Sink::open_file(PathBuf::from(path), max_size)?,
+    };
+
+    let _ = audit_log().set(Mutex::new(AuditLog { sink, redact }));
+    info!("Writing audit log to {}", target);
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    audit_log().get().is_some()
+}
+
+fn is_admin(name: &str) -> bool {
+    ADMIN_COMMANDS.contains(&name)
+}
+
+// Write commands are those with keys they may modify
+pub fn is_audited(name: &str, args: &[Vec<u8>]) -> bool {
+    is_admin(name) || !keyspec::written_keys(args).is_empty()
+}
+
+fn lossy(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+pub fn entry(
+    name: &str,
+    args: &[Vec<u8>],
+    client: String,
+    client_id: i64,
+    ok: bool,
+    redact: bool,
+) -> AuditEntry {
+    let key_indices: Vec<usize> = keyspec::get_keys(args)
+        .map(|keys| keys.into_iter().map(|(index, _)| index).collect())
+        .unwrap_or_default();
+
+    let keys = key_indices.iter().map(|&i| lossy(&args[i])).collect();
+
+    // Values are redacted, but keys and the command's shape are kept so the
+    // entry still says what was done to which keys
+    let args = args
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, arg)| {
+            let kept = key_indices.contains(&i) || (is_admin(name) && i < ADMIN_ARGS_KEPT);
+            if redact && !kept {
+                REDACTED.to_string()
+            } else {
+                lossy(arg)
+            }
+        })
+        .collect();
+
+    AuditEntry {
+        timestamp: unix_timestamp().map(|t| t.as_millis() as u64).unwrap_or(0),
+        // AUTH only knows Redis' default user, so every client is it
+        user: "default".to_string(),
+        client,
+        client_id,
+        command: name.to_string(),
+        keys,
+        args,
+        ok,
+    }
+}
+
+pub fn record(name: &str, args: &[Vec<u8>], client: String, client_id: i64, ok: bool) {
+    let log = match audit_log().get() {
+        Some(log) => log,
+        None => return,
+    };
+    let mut log = log.lock().unwrap();
+
+    let entry = entry(name, args, client, client_id, ok, log.redact);
+    // Serializing plain strings and integers can't fail
+    let line = serde_json::to_string(&entry).unwrap();
+    if let Err(err) = log.sink.write(&line) {
+        error!("Failed to write audit log: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_redaction() {
+        let args: Vec<Vec<u8>> = vec!["SET".into(), "key".into(), "secret".into()];
+        let logged = entry("SET", &args, "127.0.0.1:1234".into(), 1, true, true);
+        assert_eq!(vec!["key".to_string()], logged.keys);
+        assert_eq!(vec!["key".to_string(), REDACTED.to_string()], logged.args);
+
+        let args: Vec<Vec<u8>> = vec![
+            "CONFIG".into(),
+            "SET".into(),
+            "requirepass".into(),
+            "hunter2".into(),
+        ];
+        let logged = entry("CONFIG", &args, "127.0.0.1:1234".into(), 1, true, true);
+        assert_eq!(
+            vec!["SET".to_string(), "requirepass".into(), REDACTED.into()],
+            logged.args
+        );
+
+        let logged = entry("CONFIG", &args, "127.0.0.1:1234".into(), 1, true, false);
+        assert_eq!("hunter2", logged.args[2]);
+    }
+
+    #[test]
+    fn test_is_audited() {
+        let args: Vec<Vec<u8>> = vec!["GET".into(), "key".into()];
+        assert!(!is_audited("GET", &args));
+        let args: Vec<Vec<u8>> = vec!["DEL".into(), "key".into()];
+        assert!(is_audited("DEL", &args));
+        let args: Vec<Vec<u8>> = vec!["CONFIG".into(), "GET".into(), "*".into()];
+        assert!(is_audited("CONFIG", &args));
+    }
+
+    #[test]
+    fn test_file_rotation() {
+        let path = std::env::temp_dir().join("wedis_test_audit_rotation");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(rotated_path(&path, 1));
+
+        let mut sink = Sink::open_file(path.clone(), 10).unwrap();
+        sink.write("first").unwrap();
+        sink.write("second").unwrap();
+
+        assert_eq!("second\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(
+            "first\n",
+            fs::read_to_string(rotated_path(&path, 1)).unwrap()
+        );
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(rotated_path(&path, 1));
+    }
+}
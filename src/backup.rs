@@ -0,0 +1,368 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    database::{Database, DumpedKey},
+    import::ImportSummary,
+    inspect::Inspector,
+    lists::ListInfo,
+    time::unix_timestamp,
+};
+
+// Keyspace dumps are line-delimited JSON, one key per line, like
+//
+//   {"key":"greeting","type":"string","expires_at":1718000000000,"value":"hi"}
+//
+// Strings are written as they are, hashes as objects, lists and sets as
+// arrays, time series as their info and samples, and the sketch types as
+// they're stored. Dumps don't depend on the storage layout, so they can be
+// diffed, edited by hand and restored into any data directory.
+
+// Keys and strings that are valid UTF-8 are written as JSON strings, and
+// anything else as its bytes in hex, so binary data survives the round trip
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Bytes {
+    Text(String),
+    Hex { hex: String },
+}
+
+impl Bytes {
+    fn new(data: &[u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text) => Bytes::Text(text.to_string()),
+            Err(_) => Bytes::Hex {
+                hex: data.iter().map(|b| format!("{:02x}", b)).collect(),
+            },
+        }
+    }
+
+    fn into_bytes(self) -> Result<Vec<u8>> {
+        match self {
+            Bytes::Text(text) => Ok(text.into_bytes()),
+            Bytes::Hex { hex } => (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                })
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| anyhow!("invalid hex '{}'", hex)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Line {
+    key: Bytes,
+    #[serde(rename = "type")]
+    type_name: String,
+    // Unix time in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    value: Value,
+}
+
+// Hashes are objects, unless one of their fields isn't valid UTF-8 and so
+// can't be an object key, in which case they're arrays of field and value
+// pairs
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Hash {
+    Object(BTreeMap<String, Bytes>),
+    Pairs(Vec<(Bytes, Bytes)>),
+}
+
+impl Hash {
+    fn new(fields: &[(Vec<u8>, Vec<u8>)]) -> Self {
+        let object: Option<BTreeMap<String, Bytes>> = fields
+            .iter()
+            .map(|(field, value)| {
+                let field = String::from_utf8(field.clone()).ok()?;
+                Some((field, Bytes::new(value)))
+            })
+            .collect();
+        match object {
+            Some(object) => Hash::Object(object),
+            None => Hash::Pairs(
+                fields
+                    .iter()
+                    .map(|(field, value)| (Bytes::new(field), Bytes::new(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn into_fields(self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            Hash::Object(object) => object
+                .into_iter()
+                .map(|(field, value)| Ok((field.into_bytes(), value.into_bytes()?)))
+                .collect(),
+            Hash::Pairs(pairs) => pairs
+                .into_iter()
+                .map(|(field, value)| Ok((field.into_bytes()?, value.into_bytes()?)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TimeSeries {
+    info: Value,
+    samples: Vec<(u64, f64)>,
+}
+
+fn to_line(dumped: DumpedKey) -> Result<Line> {
+    let value = match dumped.type_name.as_str() {
+        "string" => serde_json::to_value(Bytes::new(&dumped.data))?,
+        "hash" => serde_json::to_value(Hash::new(&dumped.fields))?,
+        "list" | "set" => serde_json::to_value(
+            dumped
+                .elements
+                .iter()
+                .map(|element| Bytes::new(element))
+                .collect::<Vec<Bytes>>(),
+        )?,
+        "TSDB-TYPE" => serde_json::to_value(TimeSeries {
+            info: serde_json::from_slice(&dumped.data)?,
+            samples: dumped.samples,
+        })?,
+        _ => serde_json::from_slice(&dumped.data)?,
+    };
+
+    Ok(Line {
+        key: Bytes::new(&dumped.key),
+        type_name: dumped.type_name,
+        expires_at: dumped.expires_at.map(|t| t.as_millis() as u64),
+        value,
+    })
+}
+
+fn from_line(line: Line) -> Result<DumpedKey> {
+    let (data, samples, elements, fields) = match line.type_name.as_str() {
+        "string" => (
+            serde_json::from_value::<Bytes>(line.value)?.into_bytes()?,
+            vec![],
+            vec![],
+            vec![],
+        ),
+        "TSDB-TYPE" => {
+            let series: TimeSeries = serde_json::from_value(line.value)?;
+            (
+                serde_json::to_vec(&series.info)?,
+                series.samples,
+                vec![],
+                vec![],
+            )
+        }
+        // Restoring counts the fields as it writes them, so only they're
+        // kept
+        "hash" => (
+            serde_json::to_vec(&0u64)?,
+            vec![],
+            vec![],
+            serde_json::from_value::<Hash>(line.value)?.into_fields()?,
+        ),
+        // Restoring lays the elements out afresh and counts the members, so
+        // only they're kept
+        "list" | "set" => (
+            match line.type_name.as_str() {
+                "list" => serde_json::to_vec(&ListInfo::default())?,
+                _ => serde_json::to_vec(&0u64)?,
+            },
+            vec![],
+            serde_json::from_value::<Vec<Bytes>>(line.value)?
+                .into_iter()
+                .map(Bytes::into_bytes)
+                .collect::<Result<Vec<Vec<u8>>>>()?,
+            vec![],
+        ),
+        _ => (serde_json::to_vec(&line.value)?, vec![], vec![], vec![]),
+    };
+
+    Ok(DumpedKey {
+        key: line.key.into_bytes()?,
+        type_name: line.type_name,
+        data,
+        expires_at: line.expires_at.map(Duration::from_millis),
+        samples,
+        elements,
+        fields,
+    })
+}
+
+// Writes every live key, returning how many were written
+pub fn export(inspector: &Inspector, out: &mut impl Write) -> Result<u64> {
+    let mut exported = 0;
+    for key in inspector.keys(b"*")? {
+        // Keys that expire mid-export are left out
+        if let Some(dumped) = inspector.dump(&key)? {
+            serde_json::to_writer(&mut *out, &to_line(dumped)?)?;
+            out.write_all(b"\n")?;
+            exported += 1;
+        }
+    }
+    Ok(exported)
+}
+
+// Restores every key of a dump, replacing any existing keys of the same
+// name. Keys that expired since the dump was taken are skipped.
+pub fn import(db: &Database, input: &mut impl BufRead) -> Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let dumped = serde_json::from_str(&line)
+            .map_err(anyhow::Error::from)
+            .and_then(from_line)
+            .map_err(|err| anyhow!("line {}: {}", i + 1, err))?;
+        if dumped
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= unix_timestamp().unwrap_or_default())
+        {
+            summary.skipped += 1;
+            continue;
+        }
+
+        db.restore_key(&dumped)
+            .map_err(|err| anyhow!("line {}: {}", i + 1, err))?;
+        summary.imported += 1;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, io::Cursor, path::Path};
+
+    use rocksdb::{Options, DB};
+
+    use crate::{
+        database::{open_shard, DatabaseOperations},
+        lists::ListEnd,
+        timeseries::TimeSeriesInfo,
+    };
+
+    use super::*;
+
+    fn open(path: &Path) -> Database {
+        Database::new(open_shard(path).expect("Failed to open database"))
+    }
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(Bytes::Text("abc".into()), Bytes::new(b"abc"));
+        let binary = Bytes::new(b"\xff\x00");
+        assert_eq!(Bytes::Hex { hex: "ff00".into() }, binary);
+        assert_eq!(b"\xff\x00".to_vec(), binary.into_bytes().unwrap());
+        assert!(Bytes::Hex { hex: "f".into() }.into_bytes().is_err());
+    }
+
+    #[test]
+    fn test_hash() {
+        let fields = vec![(b"f".to_vec(), b"\xff".to_vec())];
+        let hash = Hash::new(&fields);
+        assert_eq!(
+            r#"{"f":{"hex":"ff"}}"#,
+            serde_json::to_string(&hash).unwrap()
+        );
+        assert_eq!(fields, hash.into_fields().unwrap());
+
+        // Fields that can't be object keys turn the hash into pairs
+        let fields = vec![(b"\xff".to_vec(), b"v".to_vec())];
+        let hash = Hash::new(&fields);
+        assert_eq!(
+            r#"[[{"hex":"ff"},"v"]]"#,
+            serde_json::to_string(&hash).unwrap()
+        );
+        assert_eq!(fields, hash.into_fields().unwrap());
+    }
+
+    #[test]
+    fn test_export_import() {
+        let src = env::temp_dir().join(format!("wedis-test-export-{}", std::process::id()));
+        let dest = env::temp_dir().join(format!("wedis-test-import-{}", std::process::id()));
+        let _ = DB::destroy(&Options::default(), &src);
+        let _ = DB::destroy(&Options::default(), &dest);
+        {
+            let db = open(&src);
+            db.put_string(b"a", b"1").unwrap();
+            db.put_string_with_expiry(b"b", b"\xff", Duration::from_secs(100))
+                .unwrap();
+            db.put_hash_fields(b"h", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
+            db.ts_add(b"ts", 5, 1.5, TimeSeriesInfo::default()).unwrap();
+            db.push_list(
+                b"l",
+                vec![b"x".to_vec(), b"y".to_vec()],
+                ListEnd::Front,
+                false,
+            )
+            .unwrap();
+            db.add_set_members(b"s", vec![b"m".to_vec()]).unwrap();
+        }
+
+        let mut dump = vec![];
+        let inspector = Inspector::open(&src).unwrap();
+        assert_eq!(6, export(&inspector, &mut dump).unwrap());
+        drop(inspector);
+
+        let dump = String::from_utf8(dump).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(r#"{"key":"a","type":"string","value":"1"}"#, lines[0]);
+        assert_eq!(r#"{"key":"h","type":"hash","value":{"f":"v"}}"#, lines[2]);
+
+        {
+            let db = open(&dest);
+            let summary = import(&db, &mut Cursor::new(dump.as_bytes())).unwrap();
+            assert_eq!(6, summary.imported);
+
+            assert_eq!(Some(b"\xff".to_vec()), db.get_string(b"b").unwrap());
+            let ttl = DatabaseOperations::get_expiry(&db, b"b").unwrap();
+            assert!(ttl.is_some_and(|ttl| ttl > Duration::from_secs(90)));
+            assert_eq!(Some(b"v".to_vec()), db.get_hash_field(b"h", b"f").unwrap());
+            assert_eq!(vec![(5, 1.5)], db.ts_range(b"ts", 0, 10).unwrap());
+            assert!(db.is_set_member(b"s", b"m").unwrap());
+        }
+
+        let inspector = Inspector::open(&dest).unwrap();
+        assert_eq!(
+            vec![b"y".to_vec(), b"x".to_vec()],
+            inspector.dump(b"l").unwrap().unwrap().elements
+        );
+        drop(inspector);
+
+        let _ = DB::destroy(&Options::default(), &src);
+        let _ = DB::destroy(&Options::default(), &dest);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let path = env::temp_dir().join(format!("wedis-test-import-bad-{}", std::process::id()));
+        let _ = DB::destroy(&Options::default(), &path);
+        {
+            let db = open(&path);
+
+            // Expired keys are skipped rather than restored
+            let expired = r#"{"key":"a","type":"string","expires_at":1,"value":"1"}"#;
+            let summary = import(&db, &mut Cursor::new(expired.as_bytes())).unwrap();
+            assert_eq!((0, 1), (summary.imported, summary.skipped));
+
+            let bad_hash = "\n{\"key\":\"h\",\"type\":\"hash\",\"value\":[1]}";
+            let err = import(&db, &mut Cursor::new(bad_hash.as_bytes())).unwrap_err();
+            assert!(err.to_string().starts_with("line 2:"));
+        }
+        let _ = DB::destroy(&Options::default(), &path);
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+};
+
+use thiserror::Error;
+use tracing::{error, info};
+
+use crate::database::{Database, DatabaseError, DatabaseOperations, KeySize};
+
+// Keys scanned per lock acquisition, so that clients only ever wait for one
+// small page rather than the whole scan
+const PAGE_SIZE: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum BigKeysError {
+    #[error("ERR a BIGKEYS scan is already running")]
+    AlreadyRunning,
+    #[error("ERR BIGKEYS scans aren't available")]
+    Unavailable,
+    #[error("ERR no BIGKEYS scan has finished yet, use BIGKEYS START")]
+    NoReport,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TypeSummary {
+    pub keys: u64,
+    pub total_elements: u64,
+    pub biggest: Option<KeySize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Report {
+    pub scanned: u64,
+    pub types: BTreeMap<&'static str, TypeSummary>,
+}
+
+impl Report {
+    // Keys are ranked by element count, then by size, so collections are
+    // compared by their length like redis-cli --bigkeys does, and sketches
+    // (which are all one element) by their size
+    pub fn add(&mut self, size: KeySize) {
+        self.scanned += 1;
+        let summary = self.types.entry(size.type_name).or_default();
+        summary.keys += 1;
+        summary.total_elements += size.elements;
+        if summary
+            .biggest
+            .as_ref()
+            .is_none_or(|biggest| (size.elements, size.bytes) > (biggest.elements, biggest.bytes))
+        {
+            summary.biggest = Some(size);
+        }
+    }
+}
+
+// Scans one page of a shard into the report, returning where the next page
+// starts, or None once the shard is done
+pub fn scan_page(
+    db: &dyn DatabaseOperations,
+    shard: usize,
+    after: Option<Vec<u8>>,
+    report: &mut Report,
+) -> Result<Option<Vec<u8>>, DatabaseError> {
+    let sizes = db.key_sizes(shard, after, PAGE_SIZE)?;
+    let next = match sizes.last() {
+        Some(last) if { sizes.len() == PAGE_SIZE } => Some(last.key.clone()),
+        _ => None,
+    };
+    for size in sizes {
+        report.add(size);
+    }
+    Ok(next)
+}
+
+#[derive(Default)]
+struct State {
+    running: bool,
+    last_report: Option<Report>,
+    requests: Option<Sender<()>>,
+}
+
+fn state() -> &'static Mutex<State> {
+    static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+fn run_scan(db: &Database) -> Result<Report, DatabaseError> {
+    let mut report = Report::default();
+    let shard_count = db.shard_count();
+    for shard in 0..shard_count {
+        let mut after = None;
+        loop {
+            let next = scan_page(db, shard, after, &mut report)?;
+            match next {
+                Some(key) => after = Some(key),
+                None => break,
+            }
+        }
+    }
+    Ok(report)
+}
+
+// Scans run on their own thread, one page at a time, without locking any
+// keys, so that a scan of a large keyspace doesn't stall clients
+pub fn start_worker(db: Arc<Database>) {
+    let (tx, rx) = mpsc::channel();
+    state().lock().unwrap().requests = Some(tx);
+
+    thread::spawn(move || {
+        for _ in rx {
+            info!("Scanning for big keys");
+            let result = run_scan(&db);
+
+            let mut state = state().lock().unwrap();
+            state.running = false;
+            match result {
+                Ok(report) => {
+                    info!("Big key scan finished after {} keys", report.scanned);
+                    state.last_report = Some(report);
+                }
+                Err(err) => error!("Big key scan failed: {}", err),
+            }
+        }
+    });
+}
+
+pub fn start_scan() -> Result<(), BigKeysError> {
+    let mut state = state().lock().unwrap();
+    if state.running {
+        return Err(BigKeysError::AlreadyRunning);
+    }
+
+    match state.requests.as_ref().map(|tx| tx.send(())) {
+        Some(Ok(_)) => {
+            state.running = true;
+            Ok(())
+        }
+        _ => Err(BigKeysError::Unavailable),
+    }
+}
+
+// Whether a scan is running, and the report from the last one to finish
+pub fn status() -> (bool, Option<Report>) {
+    let state = state().lock().unwrap();
+    (state.running, state.last_report.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::MockDatabaseOperations;
+    use mockall::predicate::*;
+
+    use super::*;
+
+    fn size(key: &str, type_name: &'static str, bytes: u64, elements: u64) -> KeySize {
+        KeySize {
+            key: key.into(),
+            type_name,
+            bytes,
+            elements,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = Report::default();
+        report.add(size("a", "string", 5, 5));
+        report.add(size("b", "string", 50, 50));
+        report.add(size("c", "hash", 100, 2));
+
+        assert_eq!(3, report.scanned);
+        let strings = &report.types["string"];
+        assert_eq!((2, 55), (strings.keys, strings.total_elements));
+        assert_eq!(b"b".to_vec(), strings.biggest.as_ref().unwrap().key);
+        assert_eq!(1, report.types["hash"].keys);
+    }
+
+    #[test]
+    fn test_scan_page() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_key_sizes()
+            .with(eq(0), eq(None::<Vec<u8>>), eq(PAGE_SIZE))
+            .times(1)
+            .returning(|_, _, _| Ok(vec![size("a", "string", 1, 1)]));
+
+        let mut report = Report::default();
+        assert_eq!(None, scan_page(&mock_db, 0, None, &mut report).unwrap());
+        assert_eq!(1, report.scanned);
+    }
+}
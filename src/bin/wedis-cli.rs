@@ -0,0 +1,145 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader, Write},
+    path::Path,
+    process,
+};
+
+use anyhow::{bail, Result};
+use wedis::{
+    backup,
+    database::{open_shard, shard_paths, Database},
+    inspect::{detect_shards, Expiry, Inspector},
+};
+
+const USAGE: &str = "\
+Usage: wedis-cli <data dir> <command> [args]
+
+Commands:
+  keys [pattern]  List keys matching a glob pattern (default *)
+  type <key>      Show a key's type
+  ttl <key>       Show a key's remaining time to live
+  get <key>       Dump a key's raw stored value
+  info <key>      Show a key's type, TTL and stored size
+  stats           Show key counts and sizes by type
+  export          Dump every key as JSON lines to stdout
+  import [file]   Restore keys from a JSON lines dump (default stdin). The
+                  server must not be running.";
+
+fn format_expiry(expiry: &Expiry) -> String {
+    match expiry {
+        Expiry::Never => "none".into(),
+        Expiry::In(ttl) => format!("{}ms", ttl.as_millis()),
+        Expiry::Expired => "expired".into(),
+    }
+}
+
+fn run(inspector: &Inspector, command: &str, args: &[String]) -> Result<()> {
+    let key = || match args.first() {
+        Some(key) => Ok(key.as_bytes()),
+        None => bail!("{} requires a key", command),
+    };
+
+    match command {
+        "keys" => {
+            let pattern = args.first().map_or("*", |p| p.as_str());
+            for key in inspector.keys(pattern.as_bytes())? {
+                println!("{}", String::from_utf8_lossy(&key));
+            }
+        }
+        "type" => match inspector.key_info(key()?)? {
+            Some(info) => println!("{}", info.type_name),
+            None => println!("none"),
+        },
+        "ttl" => match inspector.key_info(key()?)? {
+            Some(info) => println!("{}", format_expiry(&info.expiry)),
+            None => println!("no such key"),
+        },
+        "get" => match inspector.value(key()?)? {
+            // Values are written as-is, so binary data survives piping
+            Some(value) => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&value)?;
+                stdout.write_all(b"\n")?;
+            }
+            None => println!("no such key"),
+        },
+        "info" => match inspector.key_info(key()?)? {
+            Some(info) => {
+                println!("type: {}", info.type_name);
+                println!("ttl: {}", format_expiry(&info.expiry));
+                println!("size: {}", info.data_size);
+            }
+            None => println!("no such key"),
+        },
+        "stats" => {
+            let stats = inspector.stats()?;
+            println!("shards: {}", stats.shards);
+            for (type_name, (count, size)) in stats.types.iter() {
+                println!("{}: keys={} size={}", type_name, count, size);
+            }
+            println!("keys with ttl: {}", stats.with_ttl);
+            println!("expired keys: {}", stats.expired);
+            println!("time series samples: {}", stats.samples);
+        }
+        "export" => {
+            let mut stdout = io::stdout().lock();
+            let exported = backup::export(inspector, &mut stdout)?;
+            stdout.flush()?;
+            eprintln!("exported {} keys", exported);
+        }
+        _ => bail!("unknown command '{}'\n\n{}", command, USAGE),
+    }
+
+    Ok(())
+}
+
+// Importing needs to write, so it opens the data directory itself rather
+// than going through the read-only inspector
+fn import(root: &Path, args: &[String]) -> Result<()> {
+    let shards = shard_paths(root, detect_shards(root))
+        .iter()
+        .map(open_shard)
+        .collect::<Result<Vec<_>, _>>()?;
+    let db = Database::with_shards(shards);
+
+    let summary = match args.first() {
+        Some(path) => backup::import(&db, &mut BufReader::new(File::open(path)?))?,
+        None => backup::import(&db, &mut io::stdin().lock())?,
+    };
+    eprintln!(
+        "imported {} keys ({} already expired)",
+        summary.imported, summary.skipped
+    );
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+
+    if args[1] == "import" {
+        if let Err(err) = import(Path::new(&args[0]), &args[2..]) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let inspector = match Inspector::open(Path::new(&args[0])) {
+        Ok(inspector) => inspector,
+        Err(err) => {
+            eprintln!("Failed to open {}: {:?}", args[0], err);
+            process::exit(1);
+        }
+    };
+
+    if let Err(err) = run(&inspector, &args[1], &args[2..]) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,41 @@
+use std::{env, path::Path, process};
+
+use wedis::journal;
+
+const USAGE: &str = "\
+Usage: wedis-replay <journal> [address] [--timing]
+
+Replays a command journal recorded with journal-file against a server
+(default 127.0.0.1:6379). With --timing, the original gaps between
+commands are reproduced instead of replaying as fast as possible.";
+
+fn main() {
+    let mut timing = false;
+    let mut positional = vec![];
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--timing" => timing = true,
+            _ => positional.push(arg),
+        }
+    }
+
+    let (path, addr) = match positional.as_slice() {
+        [path] => (path, "127.0.0.1:6379"),
+        [path, addr] => (path, addr.as_str()),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    match journal::replay(Path::new(path), addr, timing) {
+        Ok(summary) => println!(
+            "Replayed {} commands over {} connections ({} errors)",
+            summary.commands, summary.connections, summary.errors
+        ),
+        Err(err) => {
+            eprintln!("Failed to replay {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
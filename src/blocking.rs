@@ -0,0 +1,204 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Condvar, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{diagnostics, shutdown};
+
+// Blocking commands like BLPOP can't wait inside their handlers, since they
+// would keep holding the locks on their keys and shut out the very pushes
+// they're waiting for. A handler that finds nothing to take parks its
+// connection on its keys instead, and returns without replying. The
+// connection then waits with its locks released, and once a push wakes it,
+// runs the command again, like Redis does for its blocked clients.
+//
+// Waiters queue up per key, and each push wakes the one that has waited
+// longest on that key.
+
+// How often waiting connections check whether they were killed or the
+// server is shutting down
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Waiter {
+    keys: Vec<Vec<u8>>,
+    // None to wait for as long as it takes
+    deadline: Option<Instant>,
+    woken: Mutex<bool>,
+    cond: Condvar,
+}
+
+type Queues = HashMap<Vec<u8>, VecDeque<Arc<Waiter>>>;
+
+fn queues() -> &'static Mutex<Queues> {
+    static QUEUES: OnceLock<Mutex<Queues>> = OnceLock::new();
+    QUEUES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+thread_local! {
+    // Left by a handler that parked, for its connection to wait on
+    static PARKED: RefCell<Option<Arc<Waiter>>> = const { RefCell::new(None) };
+    // The deadline of a command that's running again after being woken, so
+    // that parking again doesn't restart its timeout
+    static RESUMED: Cell<Option<Option<Instant>>> = const { Cell::new(None) };
+}
+
+fn remove(queues: &mut Queues, waiter: &Arc<Waiter>) {
+    for key in waiter.keys.iter() {
+        if let Some(queue) = queues.get_mut(key) {
+            queue.retain(|queued| !Arc::ptr_eq(queued, waiter));
+            if queue.is_empty() {
+                queues.remove(key);
+            }
+        }
+    }
+}
+
+// Queues the running command's connection on its keys. The waiter is queued
+// right away, while the command still holds its key locks, so that a push
+// can't slip in between the handler finding nothing and the connection
+// starting to wait.
+pub fn park(keys: Vec<Vec<u8>>, timeout: Option<Duration>) {
+    let deadline = match RESUMED.get() {
+        Some(deadline) => deadline,
+        None => timeout.map(|timeout| Instant::now() + timeout),
+    };
+    let waiter = Arc::new(Waiter {
+        keys,
+        deadline,
+        woken: Mutex::new(false),
+        cond: Condvar::new(),
+    });
+
+    let mut queues = queues().lock().unwrap();
+    for key in waiter.keys.iter() {
+        queues
+            .entry(key.clone())
+            .or_default()
+            .push_back(waiter.clone());
+    }
+    drop(queues);
+    PARKED.set(Some(waiter));
+}
+
+pub fn is_parked() -> bool {
+    PARKED.with_borrow(|parked| parked.is_some())
+}
+
+pub fn take_parked() -> Option<Arc<Waiter>> {
+    PARKED.take()
+}
+
+// Runs a woken command again, keeping its original deadline
+pub fn resume<T>(waiter: &Waiter, f: impl FnOnce() -> T) -> T {
+    RESUMED.set(Some(waiter.deadline));
+    let result = f();
+    RESUMED.set(None);
+    result
+}
+
+// Wakes the longest waiting connection on a key, after something was pushed
+// onto it. Connections waiting on several keys are only woken once.
+pub fn wake(key: &[u8]) {
+    let mut queues = queues().lock().unwrap();
+    let waiter = match queues.get_mut(key).and_then(|queue| queue.pop_front()) {
+        Some(waiter) => waiter,
+        None => return,
+    };
+    remove(&mut queues, &waiter);
+    drop(queues);
+
+    *waiter.woken.lock().unwrap() = true;
+    waiter.cond.notify_one();
+}
+
+impl Waiter {
+    // Waits until woken, returning false if the deadline passed first, or if
+    // the connection was killed or the server is shutting down
+    pub fn wait(self: &Arc<Self>, connection_id: i64) -> bool {
+        let mut woken = self.woken.lock().unwrap();
+        while !*woken {
+            if shutdown::is_shutting_down() || diagnostics::is_killed(connection_id) {
+                break;
+            }
+            let now = Instant::now();
+            let interval = match self.deadline {
+                Some(deadline) if { deadline <= now } => break,
+                Some(deadline) => (deadline - now).min(CHECK_INTERVAL),
+                None => CHECK_INTERVAL,
+            };
+            woken = self.cond.wait_timeout(woken, interval).unwrap().0;
+        }
+        if *woken {
+            return true;
+        }
+        drop(woken);
+
+        // A push may have picked this waiter after it gave up, in which case
+        // it still counts as woken, so that the push isn't left unserved
+        remove(&mut queues().lock().unwrap(), self);
+        *self.woken.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_wake_in_order() {
+        park(vec![b"blocking-a".to_vec()], None);
+        let first = take_parked().unwrap();
+        park(vec![b"blocking-b".to_vec(), b"blocking-a".to_vec()], None);
+        let second = take_parked().unwrap();
+        assert!(!is_parked());
+
+        wake(b"blocking-a");
+        assert!(*first.woken.lock().unwrap());
+        assert!(!*second.woken.lock().unwrap());
+
+        // Woken waiters leave every queue they were in
+        wake(b"blocking-b");
+        assert!(*second.woken.lock().unwrap());
+        assert!(!queues()
+            .lock()
+            .unwrap()
+            .contains_key(b"blocking-a".as_slice()));
+        assert!(first.wait(-1));
+        assert!(second.wait(-1));
+    }
+
+    #[test]
+    fn test_wait_across_threads() {
+        park(vec![b"blocking-c".to_vec()], None);
+        let waiter = take_parked().unwrap();
+
+        let handle = thread::spawn(move || waiter.wait(-1));
+        thread::sleep(Duration::from_millis(10));
+        wake(b"blocking-c");
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        park(
+            vec![b"blocking-d".to_vec()],
+            Some(Duration::from_millis(10)),
+        );
+        let waiter = take_parked().unwrap();
+        assert!(!waiter.wait(-1));
+        assert!(!queues()
+            .lock()
+            .unwrap()
+            .contains_key(b"blocking-d".as_slice()));
+
+        // Parking again while resuming keeps the original deadline
+        resume(&waiter, || park(vec![b"blocking-d".to_vec()], None));
+        let resumed = take_parked().unwrap();
+        assert_eq!(waiter.deadline, resumed.deadline);
+        assert!(!resumed.wait(-1));
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use crate::sketches::hash64;
+
+// A key's type, data and TTL records, as they're stored
+pub type Record = (Option<Vec<u8>>, Option<Vec<u8>>, Option<Vec<u8>>);
+
+// Keys are spread over several independently locked stripes, so that reads
+// of different keys don't all wait on the same lock
+const STRIPES: usize = 16;
+
+// Only strings and small aggregates are worth keeping around; anything
+// bigger would push out many hot keys at once
+const MAX_ENTRY_SIZE: usize = 64 * 1024;
+
+fn entry_size(key: &[u8], record: &Record) -> usize {
+    let (type_value, data_value, ttl_value) = record;
+    key.len()
+        + [type_value, data_value, ttl_value]
+            .iter()
+            .map(|value| value.as_ref().map_or(0, |v| v.len()))
+            .sum::<usize>()
+}
+
+struct Entry {
+    record: Record,
+    size: usize,
+    last_used: u64,
+}
+
+// Least recently used entries are evicted first, using a logical clock that
+// ticks on every access
+#[derive(Default)]
+struct Stripe {
+    capacity: usize,
+    used: usize,
+    tick: u64,
+    entries: HashMap<Vec<u8>, Entry>,
+    by_use: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Stripe {
+    fn get(&mut self, key: &[u8]) -> Option<Record> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        self.by_use.remove(&entry.last_used);
+        self.by_use.insert(self.tick, key.to_vec());
+        entry.last_used = self.tick;
+        Some(entry.record.clone())
+    }
+
+    fn insert(&mut self, key: &[u8], record: Record) {
+        let size = entry_size(key, &record);
+        if size > MAX_ENTRY_SIZE || size > self.capacity {
+            return;
+        }
+
+        self.remove(key);
+        while self.used + size > self.capacity {
+            match self.by_use.pop_first() {
+                Some((_, oldest)) => self.remove(&oldest),
+                None => break,
+            }
+        }
+
+        self.tick += 1;
+        self.used += size;
+        self.by_use.insert(self.tick, key.to_vec());
+        self.entries.insert(
+            key.to_vec(),
+            Entry {
+                record,
+                size,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.by_use.remove(&entry.last_used);
+            self.used -= entry.size;
+        }
+    }
+}
+
+// A bounded cache of recently read records in front of storage. Writers
+// must invalidate keys only after their writes are visible to readers.
+pub struct ValueCache {
+    stripes: Vec<Mutex<Stripe>>,
+}
+
+impl ValueCache {
+    // The capacity is in bytes, split evenly between the stripes
+    pub fn new(capacity: usize) -> Self {
+        let stripes = (0..STRIPES)
+            .map(|_| {
+                Mutex::new(Stripe {
+                    capacity: capacity / STRIPES,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Self { stripes }
+    }
+
+    fn stripe(&self, key: &[u8]) -> &Mutex<Stripe> {
+        &self.stripes[(hash64(key, 0) % STRIPES as u64) as usize]
+    }
+
+    // Returns the cached record, or loads and caches it on a miss. The stripe
+    // stays locked while loading, so a load that read a record before it was
+    // overwritten always finishes before the writer's invalidation, and can't
+    // leave the old record behind in the cache.
+    pub fn get_or_load<E>(
+        &self,
+        key: &[u8],
+        load: impl FnOnce() -> Result<Record, E>,
+    ) -> Result<Record, E> {
+        let mut stripe = self.stripe(key).lock().unwrap();
+        if let Some(record) = stripe.get(key) {
+            return Ok(record);
+        }
+
+        let record = load()?;
+        stripe.insert(key, record.clone());
+        Ok(record)
+    }
+
+    pub fn invalidate(&self, key: &[u8]) {
+        self.stripe(key).lock().unwrap().remove(key);
+    }
+
+    pub fn clear(&self) {
+        for stripe in self.stripes.iter() {
+            let mut stripe = stripe.lock().unwrap();
+            let capacity = stripe.capacity;
+            *stripe = Stripe {
+                capacity,
+                ..Default::default()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(data: &str) -> Record {
+        (Some(b"S".to_vec()), Some(data.as_bytes().to_vec()), None)
+    }
+
+    #[test]
+    fn test_stripe_evicts_least_recently_used() {
+        let mut stripe = Stripe {
+            capacity: 8,
+            ..Default::default()
+        };
+        stripe.insert(b"a", record("12"));
+        stripe.insert(b"b", record("12"));
+        assert!(stripe.get(b"a").is_some());
+
+        // Each entry takes 4 bytes, so b has to make room for c
+        stripe.insert(b"c", record("12"));
+        assert!(stripe.get(b"b").is_none());
+        assert!(stripe.get(b"a").is_some());
+        assert!(stripe.get(b"c").is_some());
+        assert_eq!(8, stripe.used);
+    }
+
+    #[test]
+    fn test_stripe_skips_large_entries() {
+        let mut stripe = Stripe {
+            capacity: 2 * MAX_ENTRY_SIZE,
+            ..Default::default()
+        };
+        stripe.insert(b"a", record(&"x".repeat(MAX_ENTRY_SIZE)));
+        assert!(stripe.get(b"a").is_none());
+        assert_eq!(0, stripe.used);
+    }
+
+    #[test]
+    fn test_get_or_load() {
+        let cache = ValueCache::new(1 << 20);
+        let loaded: Result<_, ()> = cache.get_or_load(b"key", || Ok(record("1")));
+        assert_eq!(record("1"), loaded.unwrap());
+
+        // Hits don't load again
+        let cached: Result<_, ()> = cache.get_or_load(b"key", || panic!("loaded twice"));
+        assert_eq!(record("1"), cached.unwrap());
+
+        cache.invalidate(b"key");
+        let reloaded: Result<_, ()> = cache.get_or_load(b"key", || Ok(record("2")));
+        assert_eq!(record("2"), reloaded.unwrap());
+    }
+}
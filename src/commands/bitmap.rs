@@ -4,13 +4,14 @@ use anyhow::Result;
 use tracing::debug;
 
 use crate::{
+    commands::{integer_reply, is_keyword, parse_int},
     connection::{ClientError, Connection},
     database::{DatabaseError, DatabaseOperations},
-    indexing::adjust_indices,
+    indexing::normalize_range,
 };
 
-fn bit_to_mask(to_bit: usize) -> usize {
-    (1 << to_bit) - 1
+fn bit_to_mask(to_bit: usize) -> u8 {
+    ((1u16 << to_bit) - 1) as u8
 }
 
 fn bit_range(data: &[u8], start_bit: usize, mut end_bit_exclusive: usize) -> Vec<u8> {
@@ -36,12 +37,12 @@ fn bit_range(data: &[u8], start_bit: usize, mut end_bit_exclusive: usize) -> Vec
     2) 00011111 - subtract 1 (mask LSB)
     3) 11111000 - reverse (mask MSB instead of LSB)
     */
-    let start_bitmask: u8 = bit_to_mask(start_bit % 8).try_into().unwrap();
+    let start_bitmask: u8 = bit_to_mask(start_bit % 8);
     let start_bitmask = start_bitmask.reverse_bits();
     let end_bitmask: u8 = if end_bit_exclusive % 8 == 0 && end_bit_exclusive != 0 {
         255
     } else {
-        bit_to_mask(end_bit_exclusive % 8).try_into().unwrap()
+        bit_to_mask(end_bit_exclusive % 8)
     };
     let end_bitmask = end_bitmask.reverse_bits();
 
@@ -90,16 +91,22 @@ fn find_first_bit_pos(
     start_bit: usize,
     end_bit_exclusive: usize,
 ) -> Option<usize> {
-    if end_bit_exclusive <= start_bit {
-        return None;
-    }
-
-    for (i, bale) in haystack.iter().enumerate() {
-        let pos = find_first_bit_pos_byte(*bale, needle).and_then(|p| Some(i * 8 + p));
-        if let Some(pos) = pos {
-            if pos >= start_bit && pos < end_bit_exclusive {
+    let end_bit_exclusive = cmp::min(end_bit_exclusive, haystack.len() * 8);
+
+    let mut pos = start_bit;
+    while pos < end_bit_exclusive {
+        let byte = haystack[pos / 8];
+        if pos % 8 == 0 && pos + 8 <= end_bit_exclusive {
+            // Whole byte is in range, so we can check it all at once
+            if let Some(p) = find_first_bit_pos_byte(byte, needle) {
+                return Some(pos + p);
+            }
+            pos += 8;
+        } else {
+            if get_bit(byte, pos % 8) == needle {
                 return Some(pos);
             }
+            pos += 1;
         }
     }
 
@@ -115,7 +122,7 @@ fn set_bit_at_padding(data: &[u8], pos: usize, value: u8) -> Vec<u8> {
     let mut data_copy: Vec<u8> = vec![0; required_len];
     data_copy[..data.len()].copy_from_slice(&data);
 
-    let byte = data_copy.get_mut(required_len - 1).unwrap();
+    let byte = &mut data_copy[required_len - 1];
     *byte = set_bit(*byte, pos % 8, value);
 
     data_copy
@@ -134,7 +141,7 @@ pub fn getbit(
 
     let key = &args[1];
     let offset: usize = match String::from_utf8_lossy(&args[2]).parse::<i32>() {
-        Ok(o) if { o >= 0 } => o.try_into().unwrap(),
+        Ok(o) if { o >= 0 } => o as usize,
         _ => {
             conn.write_error(ClientError::BitOffset);
             return Ok(());
@@ -169,7 +176,7 @@ pub fn setbit(
 
     let key = &args[1];
     let offset: usize = match String::from_utf8_lossy(&args[2]).parse::<i32>() {
-        Ok(o) if { o >= 0 } => o.try_into().unwrap(),
+        Ok(o) if { o >= 0 } => o as usize,
         _ => {
             conn.write_error(ClientError::BitOffset);
             return Ok(());
@@ -185,7 +192,7 @@ pub fn setbit(
             let existing_bit = get_bit_at(&val, offset).unwrap_or_default();
             let val = set_bit_at_padding(&val, offset, bit);
 
-            db.put_string(key, &val)?;
+            db.update_string(key, &val)?;
 
             Ok(conn.write_integer(existing_bit.into()))
         }
@@ -208,47 +215,65 @@ pub fn bitpos(
     }
 
     let key = &args[1];
-    let bit: u8 = if &args[2] == "1".as_bytes() { 1 } else { 0 };
-    match db.get_string(key) {
-        Ok(value) => {
-            let val = value.unwrap_or_default();
-            debug!("Retrieved value {:?}", String::from_utf8_lossy(&val));
-
-            if args.len() == 6 && String::from_utf8_lossy(&args[5]).to_uppercase() == "BIT" {
-                let start = String::from_utf8_lossy(&args[3]).parse::<i64>()?;
-                let end = String::from_utf8_lossy(&args[4]).parse::<i64>()?;
-
-                let (new_start, new_end) = adjust_indices(val.len() * 8 - 1, start, end);
+    let bit: u8 = match args[2].as_slice() {
+        b"0" => 0,
+        b"1" => 1,
+        _ => {
+            conn.write_error(ClientError::BitArgument);
+            return Ok(());
+        }
+    };
 
-                let pos = find_first_bit_pos(&val, bit, new_start, new_end + 1)
-                    .and_then(|p| Some(p as i64))
-                    .unwrap_or(-1);
-                Ok(conn.write_integer(pos))
-            } else if args.len() >= 5 {
-                let start = String::from_utf8_lossy(&args[3]).parse::<i64>()?;
-                let end = String::from_utf8_lossy(&args[4]).parse::<i64>()?;
+    let bit_unit = if args.len() == 6 {
+        match String::from_utf8_lossy(&args[5]).to_uppercase().as_str() {
+            "BIT" => true,
+            "BYTE" => false,
+            _ => {
+                conn.write_error(ClientError::Syntax);
+                return Ok(());
+            }
+        }
+    } else {
+        false
+    };
 
-                let (new_start, new_end) = adjust_indices(val.len() - 1, start, end);
+    let start = match args.get(3) {
+        Some(arg) => parse_int::<i64>(arg)?,
+        None => 0,
+    };
+    let end = match args.get(4) {
+        Some(arg) => Some(parse_int::<i64>(arg)?),
+        None => None,
+    };
 
-                let pos = find_first_bit_pos(&val, bit, new_start * 8, new_end * 8)
-                    .and_then(|p| Some(p as i64))
-                    .unwrap_or(-1);
-                Ok(conn.write_integer(pos))
-            } else if args.len() >= 4 {
-                let start = String::from_utf8_lossy(&args[3]).parse::<i64>()?;
-                let end: i64 = cmp::max(val.len() as i64 - 1, 0);
+    match db.get_string(key) {
+        Ok(None) => {
+            // A missing key is treated as an infinite run of zeroes
+            debug!("Value does not exist");
+            Ok(conn.write_integer(if bit == 1 { -1 } else { 0 }))
+        }
+        Ok(Some(val)) => {
+            debug!("Retrieved value {:?}", String::from_utf8_lossy(&val));
 
-                let (new_start, new_end) = adjust_indices(val.len() - 1, start, end);
+            let len = if bit_unit { val.len() * 8 } else { val.len() };
+            let Some((start, end)) = normalize_range(len, start, end.unwrap_or(-1)) else {
+                return Ok(conn.write_integer(-1));
+            };
 
-                let pos = find_first_bit_pos(&val, bit, new_start * 8, new_end * 8)
-                    .and_then(|p| Some(p as i64))
-                    .unwrap_or(val.len() as i64);
-                Ok(conn.write_integer(pos))
+            let (start_bit, end_bit_exclusive) = if bit_unit {
+                (start, end + 1)
             } else {
-                let pos =
-                    find_first_bit_pos(&val, bit, 0, val.len() * 8).unwrap_or(val.len()) as i64;
-                Ok(conn.write_integer(pos))
-            }
+                (start * 8, (end + 1) * 8)
+            };
+
+            let pos = match find_first_bit_pos(&val, bit, start_bit, end_bit_exclusive) {
+                Some(p) => p as i64,
+                // When looking for a clear bit without an explicit end, the
+                // string is considered to be padded with zeroes on the right
+                None if { bit == 0 && end.is_none() } => end_bit_exclusive as i64,
+                None => -1,
+            };
+            Ok(conn.write_integer(pos))
         }
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
@@ -274,24 +299,26 @@ pub fn bitcount(
             let val = value.unwrap_or_default();
             debug!("Retrieved value {:?}", String::from_utf8_lossy(&val));
 
-            if args.len() == 5 && String::from_utf8_lossy(&args[4]).to_uppercase() == "BIT" {
-                let start = String::from_utf8_lossy(&args[2]).parse::<i64>()?;
-                let end = String::from_utf8_lossy(&args[3]).parse::<i64>()?;
+            if args.len() == 5 && is_keyword(&args[4], "BIT") {
+                let start = parse_int::<i64>(&args[2])?;
+                let end = parse_int::<i64>(&args[3])?;
 
-                let (start, end) = adjust_indices((val.len() * 8) - 1, start, end);
-                let slice = bit_range(&val, start, end + 1);
-
-                let bits: i64 = popcnt::count_ones(&slice).try_into().unwrap();
-                Ok(conn.write_integer(bits))
+                let bits = match normalize_range(val.len() * 8, start, end) {
+                    Some((start, end)) => popcnt::count_ones(&bit_range(&val, start, end + 1)),
+                    None => 0,
+                };
+                Ok(conn.write_integer(integer_reply(bits)))
             } else if args.len() >= 4 {
-                let start = String::from_utf8_lossy(&args[2]).parse::<i64>()?;
-                let end = String::from_utf8_lossy(&args[3]).parse::<i64>()?;
-                let (start, end) = adjust_indices(val.len() - 1, start, end);
-                let bits: i64 = popcnt::count_ones(&val[start..=end]).try_into().unwrap();
-                Ok(conn.write_integer(bits))
+                let start = parse_int::<i64>(&args[2])?;
+                let end = parse_int::<i64>(&args[3])?;
+
+                let bits = match normalize_range(val.len(), start, end) {
+                    Some((start, end)) => popcnt::count_ones(&val[start..=end]),
+                    None => 0,
+                };
+                Ok(conn.write_integer(integer_reply(bits)))
             } else {
-                let bits: i64 = popcnt::count_ones(&val).try_into().unwrap();
-                Ok(conn.write_integer(bits))
+                Ok(conn.write_integer(integer_reply(popcnt::count_ones(&val))))
             }
         }
         Err(DatabaseError::WrongType { expected: _ }) => {
@@ -319,7 +346,7 @@ mod test {
             .times(1)
             .returning(|_| Ok(None));
         mock_db
-            .expect_put_string()
+            .expect_update_string()
             .with(eq(key.as_bytes()), eq(vec![0b00000001]))
             .times(1)
             .returning(|_, _| Ok(()));
@@ -515,6 +542,62 @@ mod test {
         let _ = bitcount(&mut mock_conn, &mock_db, &args).unwrap();
     }
 
+    #[test]
+    fn test_bitcount_empty_value() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_string()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(Some(vec![])));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "BITCOUNT".into(),
+            key.into(),
+            0.to_string().into(),
+            (-1).to_string().into(),
+            "BIT".into(),
+        ];
+        let _ = bitcount(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_bitcount_start_after_end() {
+        let key = "key";
+        let value = "foobar";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_string()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(Some(value.into())));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "BITCOUNT".into(),
+            key.into(),
+            4.to_string().into(),
+            1.to_string().into(),
+        ];
+        let _ = bitcount(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
     #[test]
     fn test_bit_to_mask_zero() {
         assert_eq!(0, bit_to_mask(0));
@@ -789,6 +872,149 @@ mod test {
         let _ = bitpos(&mut mock_conn, &mock_db, &args).unwrap();
     }
 
+    fn assert_bitpos(value: Option<Vec<u8>>, options: &[&str], expected: i64) {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_string()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(move |_| Ok(value.clone()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(expected))
+            .times(1)
+            .return_const(());
+
+        let mut args: Vec<Vec<u8>> = vec!["BITPOS".into(), key.into()];
+        args.extend(options.iter().map(|o| o.as_bytes().to_vec()));
+        let _ = bitpos(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_bitpos_all_ones_clear_open_ended() {
+        assert_bitpos(Some(vec![0xFF; 3]), &["0"], 24);
+    }
+
+    #[test]
+    fn test_bitpos_all_ones_clear_start_only() {
+        assert_bitpos(Some(vec![0xFF; 3]), &["0", "1"], 24);
+    }
+
+    #[test]
+    fn test_bitpos_all_ones_clear_explicit_end() {
+        assert_bitpos(Some(vec![0xFF; 3]), &["0", "0", "-1"], -1);
+    }
+
+    #[test]
+    fn test_bitpos_all_ones_clear_bit_unit() {
+        assert_bitpos(Some(vec![0xFF; 3]), &["0", "0", "23", "BIT"], -1);
+    }
+
+    #[test]
+    fn test_bitpos_all_zeroes_set() {
+        assert_bitpos(Some(vec![0x00; 3]), &["1"], -1);
+    }
+
+    #[test]
+    fn test_bitpos_all_zeroes_set_start_only() {
+        assert_bitpos(Some(vec![0x00; 3]), &["1", "1"], -1);
+    }
+
+    #[test]
+    fn test_bitpos_missing_key_set() {
+        assert_bitpos(None, &["1"], -1);
+    }
+
+    #[test]
+    fn test_bitpos_missing_key_clear() {
+        assert_bitpos(None, &["0"], 0);
+    }
+
+    #[test]
+    fn test_bitpos_missing_key_clear_range() {
+        assert_bitpos(None, &["0", "1", "2"], 0);
+    }
+
+    #[test]
+    fn test_bitpos_empty_string() {
+        assert_bitpos(Some(vec![]), &["0"], -1);
+    }
+
+    #[test]
+    fn test_bitpos_negative_start() {
+        assert_bitpos(Some(vec![0x00, 0xFF, 0xF0]), &["1", "-2"], 8);
+    }
+
+    #[test]
+    fn test_bitpos_start_after_end() {
+        assert_bitpos(Some(vec![0xFF, 0xF0, 0x00]), &["0", "2", "1"], -1);
+    }
+
+    #[test]
+    fn test_bitpos_bit_unit_within_byte() {
+        assert_bitpos(Some(vec![0xFF]), &["1", "3", "5", "BIT"], 3);
+    }
+
+    #[test]
+    fn test_bitpos_bit_unit_negative() {
+        assert_bitpos(Some(vec![0xFF, 0xF0]), &["0", "-8", "-1", "BIT"], 12);
+    }
+
+    #[test]
+    fn test_bitpos_byte_unit_explicit() {
+        assert_bitpos(Some(vec![0xFF, 0xF0, 0x00]), &["0", "1", "3", "BYTE"], 12);
+    }
+
+    #[test]
+    fn test_bitpos_invalid_unit() {
+        let key = "key";
+
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::Syntax))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "BITPOS".into(),
+            key.into(),
+            0.to_string().into(),
+            0.to_string().into(),
+            1.to_string().into(),
+            "NIBBLE".into(),
+        ];
+        let _ = bitpos(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_bitpos_invalid_bit() {
+        for bit in ["2", "-1", "one", ""] {
+            let mock_db = MockDatabaseOperations::new();
+
+            let mut mock_conn = MockConnection::new();
+            mock_conn
+                .expect_write_error()
+                .withf(|err| matches!(err, ClientError::BitArgument))
+                .times(1)
+                .return_const(());
+
+            let args: Vec<Vec<u8>> = vec!["BITPOS".into(), "key".into(), bit.into()];
+            let _ = bitpos(&mut mock_conn, &mock_db, &args).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_find_first_bit_pos_within_byte() {
+        assert_eq!(Some(3), find_first_bit_pos(&vec![0xFF], 1, 3, 6));
+    }
+
     #[test]
     fn test_find_first_bit_pos_byte_1() {
         assert_eq!(Some(4), find_first_bit_pos_byte(0xF0, 0));
@@ -0,0 +1,285 @@
+use anyhow::Result;
+
+use crate::{
+    commands::{write_module_error, Options},
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    sketches::bloom::BloomFilter,
+};
+
+fn write_bools(conn: &mut dyn Connection, values: Vec<bool>) {
+    conn.write_array(values.len());
+    for value in values {
+        conn.write_integer(value.into());
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn bf_reserve(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // BF.RESERVE key error_rate capacity [EXPANSION expansion] [NONSCALING]
+    if args.len() < 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let error_rate = match String::from_utf8_lossy(&args[2]).parse::<f64>() {
+        Ok(x) => x,
+        Err(_) => {
+            conn.write_error(ClientError::Module("ERR bad error rate".into()));
+            return Ok(());
+        }
+    };
+    let capacity = match String::from_utf8_lossy(&args[3]).parse::<u64>() {
+        Ok(x) => x,
+        Err(_) => {
+            conn.write_error(ClientError::Module("ERR bad capacity".into()));
+            return Ok(());
+        }
+    };
+
+    let mut expansion = 2;
+    let mut nonscaling = false;
+    let mut options = Options::new(&args[4..]);
+    while let Some(option) = options.next_keyword() {
+        match option.as_str() {
+            "NONSCALING" => nonscaling = true,
+            "EXPANSION" => {
+                expansion = match options.int::<u32>() {
+                    Ok(x) => x,
+                    Err(ClientError::NotInteger) => {
+                        conn.write_error(ClientError::Module("ERR bad expansion".into()));
+                        return Ok(());
+                    }
+                    Err(err) => return Ok(conn.write_error(err)),
+                };
+            }
+            _ => {
+                conn.write_error(ClientError::Syntax);
+                return Ok(());
+            }
+        }
+    }
+
+    let filter = match BloomFilter::new(error_rate, capacity, expansion, nonscaling) {
+        Ok(filter) => filter,
+        Err(err) => {
+            conn.write_error(ClientError::Module(err.to_string()));
+            return Ok(());
+        }
+    };
+
+    match db.bloom_reserve(&args[1], filter) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn bf_add(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.bloom_add(&args[1], vec![args[2].clone()]) {
+        Ok(added) => Ok(conn.write_integer(added[0].into())),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn bf_madd(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.bloom_add(&args[1], args[2..].to_vec()) {
+        Ok(added) => Ok(write_bools(conn, added)),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn bf_exists(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.bloom_exists(&args[1], vec![args[2].clone()]) {
+        Ok(found) => Ok(conn.write_integer(found[0].into())),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn bf_mexists(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.bloom_exists(&args[1], args[2..].to_vec()) {
+        Ok(found) => Ok(write_bools(conn, found)),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        connection::MockConnection, database::MockDatabaseOperations, sketches::SketchError,
+    };
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_bf_reserve() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_bloom_reserve()
+            .with(
+                eq(key.as_bytes()),
+                eq(BloomFilter::new(0.01, 1000, 4, false).unwrap()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "BF.RESERVE".into(),
+            key.into(),
+            "0.01".into(),
+            "1000".into(),
+            "EXPANSION".into(),
+            "4".into(),
+        ];
+        let _ = bf_reserve(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_bf_reserve_exists() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_bloom_reserve()
+            .times(1)
+            .returning(|_, _| Err(SketchError::KeyExists.into()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| err.to_string() == "ERR item exists")
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["BF.RESERVE".into(), key.into(), "0.01".into(), "100".into()];
+        let _ = bf_reserve(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_bf_reserve_bad_error_rate() {
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| err.to_string() == "ERR (0 < error rate range < 1)")
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "BF.RESERVE".into(),
+            "key".into(),
+            "1.5".into(),
+            "100".into(),
+        ];
+        let _ = bf_reserve(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_bf_add() {
+        let key = "key";
+        let item = "item";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_bloom_add()
+            .with(eq(key.as_bytes()), eq(vec![item.as_bytes().to_vec()]))
+            .times(1)
+            .returning(|_, _| Ok(vec![true]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["BF.ADD".into(), key.into(), item.into()];
+        let _ = bf_add(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_bf_mexists() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_bloom_exists()
+            .with(eq(key.as_bytes()), eq(vec![b"a".to_vec(), b"b".to_vec()]))
+            .times(1)
+            .returning(|_, _| Ok(vec![true, false]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["BF.MEXISTS".into(), key.into(), "a".into(), "b".into()];
+        let _ = bf_mexists(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::{
+    commands::{integer_reply, write_module_error},
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    sketches::cms::CountMinSketch,
+};
+
+fn write_counts(conn: &mut dyn Connection, counts: Vec<u64>) {
+    conn.write_array(counts.len());
+    for count in counts {
+        conn.write_integer(integer_reply(count));
+    }
+}
+
+fn init(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    key: &[u8],
+    sketch: CountMinSketch,
+) -> Result<()> {
+    match db.cms_init(key, sketch) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn cms_initbydim(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let width = String::from_utf8_lossy(&args[2]).parse::<u32>();
+    let depth = String::from_utf8_lossy(&args[3]).parse::<u32>();
+    if width.is_err() || depth.is_err() {
+        conn.write_error(ClientError::Module("ERR invalid width/depth".into()));
+        return Ok(());
+    }
+
+    match CountMinSketch::by_dim(width.unwrap(), depth.unwrap()) {
+        Ok(sketch) => init(conn, db, &args[1], sketch),
+        Err(err) => Ok(conn.write_error(ClientError::Module(err.to_string()))),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn cms_initbyprob(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let error = String::from_utf8_lossy(&args[2]).parse::<f64>();
+    let probability = String::from_utf8_lossy(&args[3]).parse::<f64>();
+    if error.is_err() || probability.is_err() {
+        conn.write_error(ClientError::Module("ERR invalid prob value".into()));
+        return Ok(());
+    }
+
+    match CountMinSketch::by_prob(error.unwrap(), probability.unwrap()) {
+        Ok(sketch) => init(conn, db, &args[1], sketch),
+        Err(err) => Ok(conn.write_error(ClientError::Module(err.to_string()))),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn cms_incrby(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // CMS.INCRBY key item increment [item increment ...]
+    if args.len() < 4 || args.len() % 2 != 0 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let mut items = vec![];
+    for (item, increment) in args[2..].iter().tuples() {
+        match String::from_utf8_lossy(increment).parse::<u64>() {
+            Ok(n) => items.push((item.clone(), n)),
+            Err(_) => {
+                conn.write_error(ClientError::Module("ERR cannot parse number".into()));
+                return Ok(());
+            }
+        }
+    }
+
+    match db.cms_incr_by(&args[1], items) {
+        Ok(counts) => Ok(write_counts(conn, counts)),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn cms_query(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.cms_query(&args[1], args[2..].to_vec()) {
+        Ok(counts) => Ok(write_counts(conn, counts)),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn cms_merge(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // CMS.MERGE destination numKeys source [source ...] [WEIGHTS weight [weight ...]]
+    if args.len() < 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let n_keys = match String::from_utf8_lossy(&args[2]).parse::<usize>() {
+        Ok(n) if { n > 0 && 3 + n <= args.len() } => n,
+        _ => {
+            conn.write_error(ClientError::Syntax);
+            return Ok(());
+        }
+    };
+
+    let sources = &args[3..3 + n_keys];
+    let rest = &args[3 + n_keys..];
+    let weights: Vec<u64> = if rest.is_empty() {
+        vec![1; n_keys]
+    } else {
+        if !String::from_utf8_lossy(&rest[0]).eq_ignore_ascii_case("WEIGHTS")
+            || rest.len() != n_keys + 1
+        {
+            conn.write_error(ClientError::Syntax);
+            return Ok(());
+        }
+
+        let parsed: Result<Vec<u64>, _> = rest[1..]
+            .iter()
+            .map(|w| String::from_utf8_lossy(w).parse::<u64>())
+            .collect();
+        match parsed {
+            Ok(weights) => weights,
+            Err(_) => {
+                conn.write_error(ClientError::Module("ERR invalid weight".into()));
+                return Ok(());
+            }
+        }
+    };
+
+    let sources = sources.iter().cloned().zip(weights).collect();
+    match db.cms_merge(&args[1], sources) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn cms_info(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.cms_info(&args[1]) {
+        Ok(sketch) => {
+            conn.write_array(6);
+            conn.write_string("width");
+            conn.write_integer(sketch.width().into());
+            conn.write_string("depth");
+            conn.write_integer(sketch.depth().into());
+            conn.write_string("count");
+            conn.write_integer(integer_reply(sketch.count()));
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        connection::MockConnection, database::MockDatabaseOperations, sketches::SketchError,
+    };
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_cms_initbydim() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_cms_init()
+            .with(
+                eq(key.as_bytes()),
+                eq(CountMinSketch::by_dim(2000, 5).unwrap()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "CMS.INITBYDIM".into(),
+            key.into(),
+            "2000".into(),
+            "5".into(),
+        ];
+        let _ = cms_initbydim(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_cms_incrby() {
+        let key = "key";
+        let items: Vec<(Vec<u8>, u64)> = vec![(b"a".to_vec(), 3), (b"b".to_vec(), 1)];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_cms_incr_by()
+            .with(eq(key.as_bytes()), eq(items))
+            .times(1)
+            .returning(|_, _| Ok(vec![3, 1]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(3))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "CMS.INCRBY".into(),
+            key.into(),
+            "a".into(),
+            "3".into(),
+            "b".into(),
+            "1".into(),
+        ];
+        let _ = cms_incrby(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_cms_query_missing() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_cms_query()
+            .times(1)
+            .returning(|_, _| Err(SketchError::KeyMissing.into()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| err.to_string() == "ERR not found")
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["CMS.QUERY".into(), "key".into(), "a".into()];
+        let _ = cms_query(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_cms_merge_weights() {
+        let dest = "dest";
+        let sources: Vec<(Vec<u8>, u64)> = vec![(b"a".to_vec(), 1), (b"b".to_vec(), 2)];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_cms_merge()
+            .with(eq(dest.as_bytes()), eq(sources))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "CMS.MERGE".into(),
+            dest.into(),
+            "2".into(),
+            "a".into(),
+            "b".into(),
+            "WEIGHTS".into(),
+            "1".into(),
+            "2".into(),
+        ];
+        let _ = cms_merge(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+}
@@ -1,4 +1,50 @@
-use crate::connection::{ClientError, Connection, ConnectionContext};
+use std::borrow::Cow;
+
+use crate::{
+    config,
+    connection::{ClientError, Connection, ConnectionContext},
+    diagnostics::{self, KillFilter},
+    tracking,
+};
+
+// CLIENT KILL addr:port, or CLIENT KILL <filter> <value> ..., along with
+// whether it was the old form, which replies differently. The new form
+// skips the connection asking unless told otherwise.
+fn parse_kill_filter(
+    args: &[Vec<u8>],
+    connection_id: i64,
+) -> Result<(KillFilter, bool), ClientError> {
+    if args.len() == 3 {
+        let filter = KillFilter {
+            addr: Some(String::from_utf8_lossy(&args[2]).into_owned()),
+            ..Default::default()
+        };
+        return Ok((filter, true));
+    }
+    if args.len() < 5 || args.len() % 2 == 0 {
+        return Err(ClientError::Syntax);
+    }
+
+    let mut filter = KillFilter {
+        skip: Some(connection_id),
+        ..Default::default()
+    };
+    for pair in args[2..].chunks(2) {
+        let value = String::from_utf8_lossy(&pair[1]).into_owned();
+        match String::from_utf8_lossy(&pair[0]).to_uppercase().as_str() {
+            "ID" => filter.id = Some(value.parse().map_err(|_| ClientError::NotInteger)?),
+            "ADDR" => filter.addr = Some(value),
+            "LADDR" => filter.laddr = Some(value),
+            "SKIPME" => match value.to_lowercase().as_str() {
+                "yes" => filter.skip = Some(connection_id),
+                "no" => filter.skip = None,
+                _ => return Err(ClientError::Syntax),
+            },
+            _ => return Err(ClientError::Syntax),
+        }
+    }
+    Ok((filter, false))
+}
 
 #[tracing::instrument(skip_all)]
 pub fn client(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
@@ -87,6 +133,119 @@ pub fn client(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
             }
             None => conn.write_error(ClientError::NoContext),
         },
+        "KILL" => {
+            let connection_id = conn.connection_id();
+            let (filter, old_form) = match parse_kill_filter(args, connection_id) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    conn.write_error(err);
+                    return;
+                }
+            };
+
+            let killed = diagnostics::kill_connections(&filter);
+            match (old_form, killed) {
+                (true, 0) => conn.write_error(ClientError::NoSuchClient),
+                (true, _) => conn.write_string("OK"),
+                (false, n) => conn.write_integer(n as i64),
+            }
+        }
+        "INFO" => {
+            if args.len() != 2 {
+                conn.write_error(ClientError::ArgCount);
+                return;
+            }
+
+            let connection_id = conn.connection_id();
+            conn.write_verbatim(
+                "txt",
+                diagnostics::client_list(Some(&[connection_id])).as_bytes(),
+            );
+        }
+        "NO-EVICT" | "NO-TOUCH" => match conn.context() {
+            Some(ctx) => {
+                if args.len() != 3 {
+                    conn.write_error(ClientError::ArgCount);
+                    return;
+                }
+
+                let ctx = ctx
+                    .downcast_mut::<ConnectionContext>()
+                    .expect("context should be a ConnectionContext");
+
+                let enabled = match String::from_utf8_lossy(&args[2]).to_uppercase().as_str() {
+                    "ON" => true,
+                    "OFF" => false,
+                    _ => {
+                        conn.write_error(ClientError::Syntax);
+                        return;
+                    }
+                };
+                if subcommand == "NO-EVICT" {
+                    ctx.set_no_evict(enabled);
+                } else {
+                    ctx.set_no_touch(enabled);
+                }
+                conn.write_string("OK");
+            }
+            None => conn.write_error(ClientError::NoContext),
+        },
+        "TRACKING" => match conn.context() {
+            Some(ctx) => {
+                // CLIENT TRACKING ON|OFF. Broadcasting, prefixes, opt-in and
+                // opt-out, and redirection aren't supported.
+                if args.len() != 3 {
+                    conn.write_error(ClientError::Syntax);
+                    return;
+                }
+
+                let ctx = ctx
+                    .downcast_mut::<ConnectionContext>()
+                    .expect("context should be a ConnectionContext");
+
+                let id = ctx.id();
+                match String::from_utf8_lossy(&args[2]).to_uppercase().as_str() {
+                    "ON" if { ctx.protocol() < 3 } => {
+                        conn.write_error(ClientError::TrackingNeedsResp3)
+                    }
+                    "ON" => {
+                        tracking::enable(id);
+                        conn.write_string("OK");
+                    }
+                    "OFF" => {
+                        tracking::disable(id);
+                        conn.write_string("OK");
+                    }
+                    _ => conn.write_error(ClientError::Syntax),
+                }
+            }
+            None => conn.write_error(ClientError::NoContext),
+        },
+        "LIST" => {
+            // CLIENT LIST [ID client-id [client-id ...]]
+            let ids = match args.get(2) {
+                None => None,
+                Some(option) if { option.eq_ignore_ascii_case(b"ID") && args.len() > 3 } => {
+                    let ids: Result<Vec<i64>, _> = args[3..]
+                        .iter()
+                        .map(|arg| String::from_utf8_lossy(arg).parse::<i64>())
+                        .collect();
+                    match ids {
+                        Ok(ids) => Some(ids),
+                        Err(_) => {
+                            conn.write_error(ClientError::NotInteger);
+                            return;
+                        }
+                    }
+                }
+                Some(_) => {
+                    conn.write_error(ClientError::Syntax);
+                    return;
+                }
+            };
+
+            conn.write_bulk(diagnostics::client_list(ids.as_deref()).as_bytes());
+        }
         _ => conn.write_error(ClientError::UnknownCommand),
     }
 }
@@ -112,20 +271,178 @@ pub fn ping(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
     }
 }
 
+#[derive(Debug, Default, PartialEq)]
+struct Hello {
+    protocol: Option<u8>,
+    auth: Option<(String, String)>,
+    name: Option<String>,
+}
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]]
+fn parse_hello(args: &[Vec<u8>]) -> Result<Hello, ClientError> {
+    let mut hello = Hello::default();
+    let protocol = match args.get(1) {
+        Some(protocol) => protocol,
+        None => return Ok(hello),
+    };
+    hello.protocol = match String::from_utf8_lossy(protocol).parse::<i64>() {
+        Ok(protocol @ (2 | 3)) => Some(protocol as u8),
+        Ok(_) => return Err(ClientError::NoProto),
+        Err(_) => return Err(ClientError::InvalidProtocolVersion),
+    };
+
+    let mut i = 2;
+    while i < args.len() {
+        let option = String::from_utf8_lossy(&args[i]).to_uppercase();
+        match option.as_str() {
+            "AUTH" if { i + 2 < args.len() } => {
+                hello.auth = Some((
+                    String::from_utf8_lossy(&args[i + 1]).into_owned(),
+                    String::from_utf8_lossy(&args[i + 2]).into_owned(),
+                ));
+                i += 3;
+            }
+            "SETNAME" if { i + 1 < args.len() } => {
+                hello.name = Some(String::from_utf8_lossy(&args[i + 1]).into_owned());
+                i += 2;
+            }
+            _ => return Err(ClientError::Syntax),
+        }
+    }
+    Ok(hello)
+}
+
+// Commands a connection can run before it has authenticated
+const UNAUTHENTICATED_COMMANDS: &[&str] = &["AUTH", "HELLO", "QUIT"];
+
+fn requirepass() -> String {
+    let config = config::config().read().unwrap();
+    config.value("requirepass").unwrap_or("").to_string()
+}
+
+// There's only Redis' default user. Without requirepass it takes any
+// password, like a nopass user.
+fn is_valid_password(requirepass: &str, username: &str, password: &str) -> bool {
+    username == "default" && (requirepass.is_empty() || password == requirepass)
+}
+
+fn set_authenticated(conn: &mut dyn Connection) {
+    if let Some(ctx) = conn.context() {
+        ctx.downcast_mut::<ConnectionContext>()
+            .expect("context should be a ConnectionContext")
+            .set_authenticated();
+    }
+}
+
+fn check_auth_with(conn: &mut dyn Connection, requirepass: &str, args: &[Vec<u8>]) -> bool {
+    if requirepass.is_empty() {
+        return true;
+    }
+    let authenticated = conn
+        .context()
+        .as_mut()
+        .and_then(|ctx| ctx.downcast_mut::<ConnectionContext>())
+        .is_some_and(|ctx| ctx.is_authenticated());
+    let exempt = args.first().is_some_and(|name| {
+        UNAUTHENTICATED_COMMANDS
+            .iter()
+            .any(|command| name.eq_ignore_ascii_case(command.as_bytes()))
+    });
+    if !authenticated && !exempt {
+        conn.write_error(ClientError::NoAuth);
+        return false;
+    }
+    true
+}
+
+// With requirepass set, connections have to authenticate with AUTH or
+// HELLO AUTH before running anything else. Returns false if the command
+// was turned away.
+pub fn check_auth(conn: &mut dyn Connection, args: &[Vec<u8>]) -> bool {
+    check_auth_with(conn, &requirepass(), args)
+}
+
+fn auth_with(conn: &mut dyn Connection, requirepass: &str, args: &[Vec<u8>]) {
+    // AUTH [username] password
+    let (username, password): (Cow<str>, Cow<str>) = match args {
+        [_, password] => {
+            if requirepass.is_empty() {
+                conn.write_error(ClientError::NoPasswordConfigured);
+                return;
+            }
+            ("default".into(), String::from_utf8_lossy(password))
+        }
+        [_, username, password] => (
+            String::from_utf8_lossy(username),
+            String::from_utf8_lossy(password),
+        ),
+        _ => {
+            conn.write_error(ClientError::Syntax);
+            return;
+        }
+    };
+
+    if !is_valid_password(requirepass, &username, &password) {
+        conn.write_error(ClientError::WrongPass);
+        return;
+    }
+    set_authenticated(conn);
+    conn.write_string("OK");
+}
+
+#[tracing::instrument(skip_all)]
+pub fn auth(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    auth_with(conn, &requirepass(), args)
+}
+
+fn current_protocol(conn: &mut dyn Connection) -> u8 {
+    match conn.context() {
+        Some(ctx) => ctx
+            .downcast_mut::<ConnectionContext>()
+            .expect("context should be a ConnectionContext")
+            .protocol(),
+        None => 2,
+    }
+}
+
 #[tracing::instrument(skip_all)]
 pub fn hello(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
-    if args.len() != 1 {
-        conn.write_error(ClientError::ArgCount);
-        return;
+    let hello = match parse_hello(args) {
+        Ok(hello) => hello,
+        Err(err) => {
+            conn.write_error(err);
+            return;
+        }
+    };
+    if let Some((username, password)) = &hello.auth {
+        if !is_valid_password(&requirepass(), username, password) {
+            conn.write_error(ClientError::WrongPass);
+            return;
+        }
+        set_authenticated(conn);
+    }
+
+    // Nothing changes unless every option was accepted
+    if let Some(ctx) = conn.context() {
+        let ctx = ctx
+            .downcast_mut::<ConnectionContext>()
+            .expect("context should be a ConnectionContext");
+        if let Some(protocol) = hello.protocol {
+            ctx.set_protocol(protocol);
+        }
+        if let Some(name) = &hello.name {
+            ctx.set_connection_name(name);
+        }
     }
 
-    conn.write_array(14);
+    let protocol = hello.protocol.unwrap_or_else(|| current_protocol(conn));
+    conn.write_map(7);
     conn.write_string("server");
     conn.write_string("redis");
     conn.write_string("version");
     conn.write_string("7.2.5");
     conn.write_string("proto");
-    conn.write_integer(2);
+    conn.write_integer(protocol as i64);
     conn.write_string("id");
 
     let connection_id = conn.connection_id();
@@ -142,3 +459,115 @@ pub fn hello(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
 pub fn quit(conn: &mut dyn Connection) {
     conn.write_string("OK")
 }
+
+#[cfg(test)]
+mod test {
+    use std::any::Any;
+
+    use crate::connection::MockConnection;
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_unauthenticated_client() {
+        let ctx: Box<dyn Any> = Box::new(ConnectionContext::new(1, ([127, 0, 0, 1], 1234).into()));
+        let mut mock_conn = MockConnection::new();
+        mock_conn.expect_context().return_var(Some(ctx));
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::NoAuth))
+            .times(3)
+            .return_const(());
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::WrongPass))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let get: Vec<Vec<u8>> = vec!["GET".into(), "key".into()];
+        let flushall: Vec<Vec<u8>> = vec!["FLUSHALL".into()];
+        assert!(check_auth_with(&mut mock_conn, "", &get));
+        assert!(!check_auth_with(&mut mock_conn, "secret", &get));
+        assert!(!check_auth_with(&mut mock_conn, "secret", &flushall));
+        let args: Vec<Vec<u8>> = vec!["auth".into(), "secret".into()];
+        assert!(check_auth_with(&mut mock_conn, "secret", &args));
+
+        let args: Vec<Vec<u8>> = vec!["AUTH".into(), "wrong".into()];
+        auth_with(&mut mock_conn, "secret", &args);
+        assert!(!check_auth_with(&mut mock_conn, "secret", &get));
+
+        let args: Vec<Vec<u8>> = vec!["AUTH".into(), "default".into(), "secret".into()];
+        auth_with(&mut mock_conn, "secret", &args);
+        assert!(check_auth_with(&mut mock_conn, "secret", &get));
+        assert!(check_auth_with(&mut mock_conn, "secret", &flushall));
+    }
+
+    #[test]
+    fn test_auth_without_requirepass() {
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::NoPasswordConfigured))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["AUTH".into(), "secret".into()];
+        auth_with(&mut mock_conn, "", &args);
+    }
+
+    #[test]
+    fn test_parse_hello() {
+        let args: Vec<Vec<u8>> = vec![
+            "HELLO".into(),
+            "3".into(),
+            "AUTH".into(),
+            "default".into(),
+            "secret".into(),
+            "SETNAME".into(),
+            "worker".into(),
+        ];
+        assert_eq!(
+            Hello {
+                protocol: Some(3),
+                auth: Some(("default".to_string(), "secret".to_string())),
+                name: Some("worker".to_string()),
+            },
+            parse_hello(&args).unwrap()
+        );
+
+        let args: Vec<Vec<u8>> = vec!["HELLO".into()];
+        assert_eq!(Hello::default(), parse_hello(&args).unwrap());
+
+        let args: Vec<Vec<u8>> = vec!["HELLO".into(), "3".into(), "AUTH".into(), "default".into()];
+        assert!(matches!(parse_hello(&args), Err(ClientError::Syntax)));
+    }
+
+    #[test]
+    fn test_hello_unsupported_protocol() {
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::NoProto))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["HELLO".into(), "4".into()];
+        hello(&mut mock_conn, &args);
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::InvalidProtocolVersion))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["HELLO".into(), "three".into()];
+        hello(&mut mock_conn, &args);
+    }
+}
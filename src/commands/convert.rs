@@ -0,0 +1,69 @@
+use std::str::FromStr;
+
+use crate::connection::ClientError;
+
+// Argument parsing fails with the error Redis would reply with, so handlers
+// can use ? on it and still send the client a proper reply
+pub fn parse_int<T: FromStr>(arg: &[u8]) -> Result<T, ClientError> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or(ClientError::NotInteger)
+}
+
+pub fn parse_float(arg: &[u8]) -> Result<f64, ClientError> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse::<f64>().ok())
+        .filter(|x| !x.is_nan())
+        .ok_or(ClientError::NotFloat)
+}
+
+// Expiry options like EX and PXAT only take times past zero
+pub fn positive_time(time: i64) -> Result<u64, ClientError> {
+    match time {
+        1.. => Ok(time as u64),
+        _ => Err(ClientError::InvalidExpireTime),
+    }
+}
+
+// Counts and lengths past i64::MAX can't be sent as integer replies, so they
+// saturate rather than failing partway through a reply
+pub fn integer_reply<T: TryInto<i64>>(n: T) -> i64 {
+    n.try_into().unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_int() {
+        assert_eq!(-5, parse_int::<i64>(b"-5").unwrap());
+        assert!(matches!(
+            parse_int::<i64>(b"99999999999999999999"),
+            Err(ClientError::NotInteger)
+        ));
+        assert!(matches!(
+            parse_int::<u32>(b"-1"),
+            Err(ClientError::NotInteger)
+        ));
+        assert!(matches!(
+            parse_int::<i64>(b"\xff"),
+            Err(ClientError::NotInteger)
+        ));
+    }
+
+    #[test]
+    fn test_parse_float() {
+        assert_eq!(1.5, parse_float(b"1.5").unwrap());
+        assert!(matches!(parse_float(b"nan"), Err(ClientError::NotFloat)));
+        assert!(matches!(parse_float(b"abc"), Err(ClientError::NotFloat)));
+    }
+
+    #[test]
+    fn test_integer_reply() {
+        assert_eq!(5, integer_reply(5usize));
+        assert_eq!(i64::MAX, integer_reply(u64::MAX));
+    }
+}
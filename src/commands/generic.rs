@@ -4,9 +4,12 @@ use anyhow::Result;
 use tracing::debug;
 
 use crate::{
+    access,
+    commands::{integer_reply, parse_int, Options},
     connection::{ClientError, Connection},
-    database::DatabaseOperations,
-    time::unix_timestamp,
+    database::{DatabaseError, DatabaseOperations, ScanFilter},
+    keyformat::DEFAULT_DB,
+    lazyfree, notify,
 };
 
 #[tracing::instrument(skip_all)]
@@ -21,16 +24,26 @@ pub fn persist(
     }
 
     let key = &args[1];
-    match db.delete_expiry(&key) {
-        Ok(_) => {
-            conn.write_integer(1);
-            Ok(())
-        }
-        Err(err) => {
-            conn.write_integer(0);
-            Err(err.into())
-        }
+    let removed = db.delete_expiry(&key)?;
+    conn.write_integer(removed);
+    Ok(())
+}
+
+// Replies 1 if the TTL was set, or 0 if the key doesn't exist. Errors are
+// returned without a reply, so the client only sees the error.
+fn put_expiry(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    key: &[u8],
+    expires_in: Duration,
+) -> Result<()> {
+    let set = db.put_expiry(key, expires_in)?;
+    // Like Redis, a time that has already passed deletes the key
+    if set && expires_in.is_zero() {
+        notify::replace_event("del");
     }
+    conn.write_integer(set.into());
+    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
@@ -45,20 +58,11 @@ pub fn expireat(
     }
 
     let key = &args[1];
-    let ts = String::from_utf8_lossy(&args[2]).parse::<u64>()?;
-    let expires_at = Duration::from_secs(ts);
-    let expires_in = expires_at.saturating_sub(unix_timestamp()?);
+    let ts = parse_int::<i64>(&args[2])?;
+    let expires_at = Duration::from_secs(ts.try_into().unwrap_or(0));
+    let expires_in = expires_at.saturating_sub(db.now()?);
 
-    match db.put_expiry(&key, expires_in) {
-        Ok(_) => {
-            conn.write_integer(1);
-            Ok(())
-        }
-        Err(err) => {
-            conn.write_integer(0);
-            Err(err.into())
-        }
-    }
+    put_expiry(conn, db, key, expires_in)
 }
 
 #[tracing::instrument(skip_all)]
@@ -73,20 +77,11 @@ pub fn pexpireat(
     }
 
     let key = &args[1];
-    let ts = String::from_utf8_lossy(&args[2]).parse::<u64>()?;
-    let expires_at = Duration::from_millis(ts);
-    let expires_in = expires_at.saturating_sub(unix_timestamp()?);
+    let ts = parse_int::<i64>(&args[2])?;
+    let expires_at = Duration::from_millis(ts.try_into().unwrap_or(0));
+    let expires_in = expires_at.saturating_sub(db.now()?);
 
-    match db.put_expiry(&key, expires_in) {
-        Ok(_) => {
-            conn.write_integer(1);
-            Ok(())
-        }
-        Err(err) => {
-            conn.write_integer(0);
-            Err(err.into())
-        }
-    }
+    put_expiry(conn, db, key, expires_in)
 }
 
 #[tracing::instrument(skip_all)]
@@ -101,57 +96,44 @@ pub fn expire(
     }
 
     let key = &args[1];
-    let secs = String::from_utf8_lossy(&args[2]).parse::<u64>()?;
-    let expires_in = Duration::from_secs(secs);
-
-    let mut update_expiry = || match db.put_expiry(&key, expires_in) {
-        Ok(_) => Ok(conn.write_integer(1)),
-        Err(err) => {
-            conn.write_integer(0);
-            Err(err.into())
-        }
-    };
+    // Non-positive TTLs expire the key immediately
+    let secs = parse_int::<i64>(&args[2])?;
+    let expires_in = Duration::from_secs(secs.try_into().unwrap_or(0));
 
-    let mut options: Vec<String> = vec![];
-    for arg in args[3..].iter() {
-        options.push(String::from_utf8_lossy(&arg).into_owned().to_uppercase());
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    let mut options = Options::new(&args[3..]);
+    while let Some(option) = options.next_keyword() {
+        match option.as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            _ => return Ok(conn.write_error(ClientError::UnsupportedOption(option))),
+        }
     }
 
-    let nx = options.contains(&"NX".to_string());
-    let xx = options.contains(&"XX".to_string());
-    let gt = options.contains(&"GT".to_string());
-    let lt = options.contains(&"LT".to_string());
-
     if nx && (xx || gt || lt) {
         return Ok(conn.write_error(ClientError::ExpireNxOptions));
     }
 
-    if nx {
+    // The conditions are checked against the key's TTL before the update
+    let update = if nx || xx || gt || lt {
         match db.get_expiry(&key)? {
-            Some(_) => Ok(conn.write_integer(0)),
-            None => update_expiry(),
-        }
-    } else if gt {
-        match db.get_expiry(&key)? {
-            Some(ttl) if { expires_in < ttl } => Ok(conn.write_integer(0)),
-            None if { xx } => Ok(conn.write_integer(0)),
-            _ => update_expiry(),
-        }
-    } else if lt {
-        match db.get_expiry(&key)? {
-            Some(ttl) if { expires_in > ttl } => Ok(conn.write_integer(0)),
-            None if { xx } => Ok(conn.write_integer(0)),
-            _ => update_expiry(),
-        }
-    } else if xx {
-        match db.get_expiry(&key)? {
-            Some(_) => update_expiry(),
-            _ => Ok(conn.write_integer(0)),
+            Some(_) if { nx } => false,
+            Some(ttl) if { gt } => expires_in >= ttl,
+            Some(ttl) if { lt } => expires_in <= ttl,
+            Some(_) => true,
+            None => !xx,
         }
     } else {
-        // No options
-        update_expiry()
+        true
+    };
+
+    if !update {
+        conn.write_integer(0);
+        return Ok(());
     }
+    put_expiry(conn, db, key, expires_in)
 }
 
 #[tracing::instrument(skip_all)]
@@ -166,19 +148,10 @@ pub fn pexpire(
     }
 
     let key = &args[1];
-    let ms = String::from_utf8_lossy(&args[2]).parse::<u64>()?;
-    let expires_in = Duration::from_millis(ms);
+    let ms = parse_int::<i64>(&args[2])?;
+    let expires_in = Duration::from_millis(ms.try_into().unwrap_or(0));
 
-    match db.put_expiry(&key, expires_in) {
-        Ok(_) => {
-            conn.write_integer(1);
-            Ok(())
-        }
-        Err(err) => {
-            conn.write_integer(0);
-            Err(err.into())
-        }
-    }
+    put_expiry(conn, db, key, expires_in)
 }
 
 #[tracing::instrument(skip_all)]
@@ -194,16 +167,17 @@ pub fn ttl(
 
     let key = &args[1];
 
-    let ttl = db.get_expiry(key)?;
-    if let None = ttl {
+    let Some(ttl) = db.get_expiry(key)? else {
         return match db.exists(key)? {
             0 => Ok(conn.write_integer(-2)),
             _ => Ok(conn.write_integer(-1)),
         };
-    }
+    };
 
-    let ttl: i64 = ttl.unwrap().as_secs().try_into()?;
-    conn.write_integer(ttl);
+    // Rounded to the nearest second like Redis does, so that a key with
+    // 900ms left doesn't report 0 while it's still alive
+    let ttl_ms = ttl.as_millis();
+    conn.write_integer(integer_reply((ttl_ms + 500) / 1000));
 
     Ok(())
 }
@@ -221,16 +195,14 @@ pub fn pttl(
 
     let key = &args[1];
 
-    let ttl = db.get_expiry(key)?;
-    if let None = ttl {
+    let Some(ttl) = db.get_expiry(key)? else {
         return match db.exists(key)? {
             0 => Ok(conn.write_integer(-2)),
             _ => Ok(conn.write_integer(-1)),
         };
-    }
+    };
 
-    let ttl: i64 = ttl.unwrap().as_millis().try_into()?;
-    conn.write_integer(ttl);
+    conn.write_integer(integer_reply(ttl.as_millis()));
 
     Ok(())
 }
@@ -248,20 +220,14 @@ pub fn expiretime(
 
     let key = &args[1];
 
-    let ttl = db.get_expiry(key)?;
-    if let None = ttl {
+    let Some(expires_at) = db.get_expiry_at(key)? else {
         return match db.exists(key)? {
             0 => Ok(conn.write_integer(-2)),
             _ => Ok(conn.write_integer(-1)),
         };
-    }
+    };
 
-    let ttl: i64 = ttl
-        .unwrap()
-        .saturating_add(unix_timestamp()?)
-        .as_secs()
-        .try_into()?;
-    conn.write_integer(ttl);
+    conn.write_integer(integer_reply(expires_at.as_secs()));
 
     Ok(())
 }
@@ -279,20 +245,14 @@ pub fn pexpiretime(
 
     let key = &args[1];
 
-    let ttl = db.get_expiry(key)?;
-    if let None = ttl {
+    let Some(expires_at) = db.get_expiry_at(key)? else {
         return match db.exists(key)? {
             0 => Ok(conn.write_integer(-2)),
             _ => Ok(conn.write_integer(-1)),
         };
-    }
+    };
 
-    let ttl: i64 = ttl
-        .unwrap()
-        .saturating_add(unix_timestamp()?)
-        .as_millis()
-        .try_into()?;
-    conn.write_integer(ttl);
+    conn.write_integer(integer_reply(expires_at.as_millis()));
 
     Ok(())
 }
@@ -303,7 +263,16 @@ pub fn unlink(
     db: &dyn DatabaseOperations,
     args: &Vec<Vec<u8>>,
 ) -> Result<()> {
-    del(conn, db, args)
+    let mut n_unlinked = 0;
+    for arg in args[1..].iter() {
+        if db.unlink(arg)? == 1 {
+            lazyfree::free(db, arg)?;
+            n_unlinked += 1;
+        }
+    }
+
+    conn.write_integer(n_unlinked);
+    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
@@ -317,10 +286,7 @@ pub fn del(
         return Ok(());
     }
 
-    let mut n_deleted = 0;
-    for arg in args[1..].iter() {
-        n_deleted += db.delete(&arg)?;
-    }
+    let n_deleted = db.delete_many(args[1..].to_vec())?;
 
     debug!("Deleted {} values", n_deleted);
 
@@ -328,6 +294,43 @@ pub fn del(
     Ok(())
 }
 
+#[tracing::instrument(skip_all)]
+pub fn copy(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // COPY source destination [DB destination-db] [REPLACE]
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let mut replace = false;
+    let mut options = Options::new(&args[3..]);
+    while let Some(option) = options.next_keyword() {
+        match option.as_str() {
+            "REPLACE" => replace = true,
+            // Until SELECT is supported, there's only the default database
+            "DB" => {
+                if options.int::<u16>()? != DEFAULT_DB {
+                    return Ok(conn.write_error(ClientError::DbIndexOutOfRange));
+                }
+            }
+            _ => return Ok(conn.write_error(ClientError::Syntax)),
+        }
+    }
+
+    let (src, dest) = (&args[1], &args[2]);
+    if src == dest {
+        return Ok(conn.write_error(ClientError::SameObject));
+    }
+
+    let copied = db.copy(src, dest, replace)?;
+    conn.write_integer(copied.into());
+    Ok(())
+}
+
 #[tracing::instrument(skip_all)]
 pub fn exists(
     conn: &mut dyn Connection,
@@ -350,6 +353,110 @@ pub fn exists(
     Ok(())
 }
 
+#[tracing::instrument(skip_all)]
+pub fn r#type(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.get_type(&args[1])? {
+        Some(type_name) => conn.write_string(&type_name),
+        None => conn.write_string("none"),
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn scan(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+    if args.len() < 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let cursor = match parse_int::<u64>(&args[1]) {
+        Ok(cursor) => cursor,
+        Err(_) => return Ok(conn.write_error(ClientError::InvalidCursor)),
+    };
+
+    let mut count = 10;
+    let mut filter = ScanFilter::default();
+    let mut options = Options::new(&args[2..]);
+    while let Some(option) = options.next_keyword() {
+        match option.as_str() {
+            "MATCH" => filter.pattern = Some(options.value()?.to_vec()),
+            "COUNT" => count = options.int::<usize>()?,
+            "TYPE" => {
+                filter.type_name = Some(String::from_utf8_lossy(options.value()?).into_owned())
+            }
+            _ => return Ok(conn.write_error(ClientError::Syntax)),
+        }
+    }
+    if count == 0 {
+        return Ok(conn.write_error(ClientError::Syntax));
+    }
+    // Matching everything is the same as not matching at all
+    if filter.pattern.as_deref() == Some(b"*") {
+        filter.pattern = None;
+    }
+
+    match db.scan(cursor, count, filter) {
+        Ok((cursor, keys)) => {
+            conn.write_array(2);
+            conn.write_bulk(cursor.to_string().as_bytes());
+            conn.write_array(keys.len());
+            for key in keys {
+                conn.write_bulk(&key);
+            }
+            Ok(())
+        }
+        Err(DatabaseError::InvalidCursor) => Ok(conn.write_error(ClientError::InvalidCursor)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn object(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // OBJECT IDLETIME|FREQ key
+    if args.len() != 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let subcommand = String::from_utf8_lossy(&args[1]).to_uppercase();
+    if subcommand != "IDLETIME" && subcommand != "FREQ" {
+        return Ok(conn.write_error(ClientError::UnknownCommand));
+    }
+
+    let key = &args[2];
+    if db.exists(key)? == 0 {
+        return Ok(conn.write_null());
+    }
+
+    let value = match subcommand.as_str() {
+        "IDLETIME" => access::idle_time(key).map(integer_reply),
+        _ => access::frequency(key).map(i64::from),
+    };
+    match value {
+        Some(value) => Ok(conn.write_integer(value)),
+        None => Ok(conn.write_error(ClientError::AccessTrackingOff)),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{connection::MockConnection, database::MockDatabaseOperations};
@@ -363,8 +470,8 @@ mod test {
 
         let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_delete()
-            .with(eq(key.as_bytes()))
+            .expect_delete_many()
+            .with(eq(vec![key.as_bytes().to_vec()]))
             .times(1)
             .returning(|_| Ok(1));
 
@@ -386,24 +493,562 @@ mod test {
 
         let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_delete()
-            .with(eq(key1.as_bytes()))
+            .expect_delete_many()
+            .with(eq(vec![key1.as_bytes().to_vec(), key2.as_bytes().to_vec()]))
             .times(1)
-            .returning(|_| Ok(1));
+            .returning(|_| Ok(2));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["DEL".into(), key1.into(), key2.into()];
+        let _ = del(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_unlink() {
+        let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_delete()
-            .with(eq(key2.as_bytes()))
+            .expect_unlink()
+            .with(eq("key1".as_bytes()))
             .times(1)
             .returning(|_| Ok(1));
+        mock_db
+            .expect_unlink()
+            .with(eq("key2".as_bytes()))
+            .times(1)
+            .returning(|_| Ok(0));
+        mock_db
+            .expect_reclaim()
+            .with(eq("key1".as_bytes()))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["UNLINK".into(), "key1".into(), "key2".into()];
+        let _ = unlink(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_expire_negative() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_expiry()
+            .with(eq(key.as_bytes()), eq(Duration::ZERO))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["EXPIRE".into(), key.into(), "-1".into()];
+        let _ = expire(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_pexpire_missing_key() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_expiry()
+            .with(eq(key.as_bytes()), eq(Duration::from_millis(1500)))
+            .times(1)
+            .returning(|_, _| Ok(false));
 
         let mut mock_conn = MockConnection::new();
         mock_conn
             .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["PEXPIRE".into(), key.into(), "1500".into()];
+        pexpire(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_expire_unsupported_option() {
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::UnsupportedOption(option) if option == "KEEP"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["EXPIRE".into(), "key".into(), "10".into(), "keep".into()];
+        let _ = expire(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_expire_error_replies_once() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_expiry()
+            .times(1)
+            .returning(|_, _| Err(DatabaseError::CrossShard));
+
+        // The error is the only reply
+        let mut mock_conn = MockConnection::new();
+        mock_conn.expect_write_integer().times(0);
+
+        let args: Vec<Vec<u8>> = vec!["EXPIRE".into(), "key".into(), "10".into()];
+        assert!(expire(&mut mock_conn, &mock_db, &args).is_err());
+    }
+
+    #[test]
+    fn test_expire_options() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_expiry()
+            .with(eq(key.as_bytes()))
+            .returning(|_| Ok(Some(Duration::from_secs(100))));
+        mock_db
+            .expect_put_expiry()
+            .with(eq(key.as_bytes()), eq(Duration::from_secs(200)))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(2)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["EXPIRE".into(), key.into(), "200".into(), "NX".into()];
+        expire(&mut mock_conn, &mock_db, &args).unwrap();
+        let args: Vec<Vec<u8>> = vec!["EXPIRE".into(), key.into(), "200".into(), "LT".into()];
+        expire(&mut mock_conn, &mock_db, &args).unwrap();
+        let args: Vec<Vec<u8>> = vec!["EXPIRE".into(), key.into(), "200".into(), "GT".into()];
+        expire(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_persist() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_delete_expiry()
+            .with(eq(b"key".as_slice()))
+            .times(1)
+            .returning(|_| Ok(0));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["PERSIST".into(), "key".into()];
+        persist(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_pexpireat_past() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_now()
+            .times(1)
+            .returning(|| Ok(Duration::from_secs(2000)));
+        mock_db
+            .expect_put_expiry()
+            .with(eq(key.as_bytes()), eq(Duration::ZERO))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["PEXPIREAT".into(), key.into(), "1000".into()];
+        let _ = pexpireat(&mut mock_conn, &mock_db, &args).unwrap();
+        assert_eq!(Some("del"), notify::take_event());
+    }
+
+    #[test]
+    fn test_pexpireat_future() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_now()
+            .times(1)
+            .returning(|| Ok(Duration::from_millis(400)));
+        mock_db
+            .expect_put_expiry()
+            .with(eq(key.as_bytes()), eq(Duration::from_millis(600)))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["PEXPIREAT".into(), key.into(), "1000".into()];
+        let _ = pexpireat(&mut mock_conn, &mock_db, &args).unwrap();
+        assert_eq!(None, notify::take_event());
+    }
+
+    #[test]
+    fn test_expireat_past_missing_key() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_now()
+            .times(1)
+            .returning(|| Ok(Duration::from_secs(2000)));
+        mock_db
+            .expect_put_expiry()
+            .with(eq(b"key".as_slice()), eq(Duration::ZERO))
+            .times(1)
+            .returning(|_, _| Ok(false));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["EXPIREAT".into(), "key".into(), "1000".into()];
+        expireat(&mut mock_conn, &mock_db, &args).unwrap();
+        assert_eq!(None, notify::take_event());
+    }
+
+    #[test]
+    fn test_expiretime() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_expiry_at()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(Some(Duration::from_millis(1_005_900))));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1005))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["EXPIRETIME".into(), key.into()];
+        let _ = expiretime(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_ttl_rounding() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_expiry()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(Some(Duration::from_millis(900))));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TTL".into(), key.into()];
+        let _ = ttl(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_ttl_missing() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_expiry()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(None));
+        mock_db
+            .expect_exists()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(0));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(-2))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TTL".into(), key.into()];
+        let _ = ttl(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_type() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_type()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(Some("string".into())));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("string"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TYPE".into(), key.into()];
+        let _ = r#type(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_type_missing() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_type()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(None));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("none"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TYPE".into(), key.into()];
+        let _ = r#type(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_copy()
+            .with(eq("a".as_bytes()), eq("b".as_bytes()), eq(true))
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "COPY".into(),
+            "a".into(),
+            "b".into(),
+            "db".into(),
+            "0".into(),
+            "REPLACE".into(),
+        ];
+        copy(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_copy_other_db() {
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::DbIndexOutOfRange))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "COPY".into(),
+            "a".into(),
+            "b".into(),
+            "DB".into(),
+            "1".into(),
+        ];
+        copy(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_scan()
+            .with(eq(0), eq(2), eq(ScanFilter::default()))
+            .times(1)
+            .returning(|_, _, _| Ok((7, vec!["a".into(), "b".into()])));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
             .with(eq(2))
+            .times(2)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("7".as_bytes()))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("a".as_bytes()))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("b".as_bytes()))
             .times(1)
             .return_const(());
 
-        let args: Vec<Vec<u8>> = vec!["DEL".into(), key1.into(), key2.into()];
-        let _ = del(&mut mock_conn, &mock_db, &args).unwrap();
+        let args: Vec<Vec<u8>> = vec!["SCAN".into(), "0".into(), "COUNT".into(), "2".into()];
+        let _ = scan(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_scan_options() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_scan()
+            .with(
+                eq(0),
+                eq(100),
+                eq(ScanFilter {
+                    pattern: Some(b"user:*".to_vec()),
+                    type_name: Some("hash".into()),
+                }),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok((0, vec![])));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("0".as_bytes()))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_array()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "SCAN".into(),
+            "0".into(),
+            "type".into(),
+            "hash".into(),
+            "MATCH".into(),
+            "user:*".into(),
+            "COUNT".into(),
+            "100".into(),
+        ];
+        scan(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_scan_invalid_cursor() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_scan()
+            .with(eq(12345), eq(10), always())
+            .times(1)
+            .returning(|_, _, _| Err(DatabaseError::InvalidCursor));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::InvalidCursor))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SCAN".into(), "12345".into()];
+        let _ = scan(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_object_missing_key() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_exists()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(0));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn.expect_write_null().times(1).return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["OBJECT".into(), "IDLETIME".into(), key.into()];
+        let _ = object(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_object_tracking_off() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_exists()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(1));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::AccessTrackingOff))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["OBJECT".into(), "FREQ".into(), key.into()];
+        let _ = object(&mut mock_conn, &mock_db, &args).unwrap();
     }
 }
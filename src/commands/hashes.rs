@@ -1,10 +1,13 @@
+use std::time::Duration;
+
 use anyhow::Result;
 use itertools::Itertools;
 use tracing::debug;
 
 use crate::{
-    connection::{ClientError, Connection},
-    database::{DatabaseError, DatabaseOperations},
+    commands::{integer_reply, is_keyword, parse_int, positive_time, Options},
+    connection::{write_bulk_segmented, ClientError, Connection},
+    database::{DatabaseError, DatabaseOperations, ExpiryCondition, SetExpiry},
 };
 
 #[tracing::instrument(skip_all)]
@@ -76,7 +79,153 @@ pub fn hstrlen(
         Ok(value) => {
             let val = value.unwrap_or_default();
             debug!("Hash field has length {}", val.len());
-            Ok(conn.write_integer(val.len().try_into().unwrap()))
+            Ok(conn.write_integer(integer_reply(val.len())))
+        }
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn hgetall(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    // Big hashes are written out a chunk at a time as they're read, rather
+    // than being copied into a reply of their own first
+    let mut started = false;
+    let found = db.read_hash(&args[1], &mut |len, chunk| {
+        if !started {
+            conn.write_map(len);
+            started = true;
+        }
+        for (field, value) in chunk {
+            write_bulk_segmented(conn, field);
+            write_bulk_segmented(conn, value);
+        }
+    });
+    match found {
+        Ok(true) => Ok(()),
+        Ok(false) => Ok(conn.write_map(0)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Parses the "FIELDS numfields field [field ...]" block that ends the field
+// TTL commands
+fn parse_fields(args: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, ClientError> {
+    if args.len() < 2 || !is_keyword(&args[0], "FIELDS") {
+        return Err(ClientError::FieldsMissing);
+    }
+
+    let n_fields = parse_int::<i64>(&args[1])?;
+    if n_fields <= 0 {
+        return Err(ClientError::NumFieldsZero);
+    }
+    if n_fields as usize != args.len() - 2 {
+        return Err(ClientError::NumFieldsMismatch);
+    }
+    Ok(args[2..].to_vec())
+}
+
+fn write_integers(conn: &mut dyn Connection, values: Vec<i64>) {
+    conn.write_array(values.len());
+    for value in values {
+        conn.write_integer(value);
+    }
+}
+
+fn put_field_expiry(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+    unit: fn(u64) -> Duration,
+) -> Result<()> {
+    if args.len() < 6 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let key = &args[1];
+    let ttl = parse_int::<i64>(&args[2])?;
+    if ttl < 0 {
+        return Ok(conn.write_error(ClientError::InvalidExpireTime));
+    }
+    let expires_in = unit(ttl as u64);
+
+    let (condition, rest) = match String::from_utf8_lossy(&args[3]).to_uppercase().as_str() {
+        "NX" => (ExpiryCondition::IfNone, &args[4..]),
+        "XX" => (ExpiryCondition::IfSome, &args[4..]),
+        "GT" => (ExpiryCondition::IfGreater, &args[4..]),
+        "LT" => (ExpiryCondition::IfLess, &args[4..]),
+        _ => (ExpiryCondition::Always, &args[3..]),
+    };
+    let fields = match parse_fields(rest) {
+        Ok(fields) => fields,
+        Err(err) => return Ok(conn.write_error(err)),
+    };
+
+    match db.put_field_expiry(key, fields, expires_in, condition) {
+        Ok(results) => Ok(write_integers(conn, results)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn hexpire(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    put_field_expiry(conn, db, args, Duration::from_secs)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn hpexpire(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    put_field_expiry(conn, db, args, Duration::from_millis)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn httl(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 5 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let fields = match parse_fields(&args[2..]) {
+        Ok(fields) => fields,
+        Err(err) => return Ok(conn.write_error(err)),
+    };
+    match db.get_field_expiry(&args[1], fields) {
+        Ok(results) => {
+            // Remaining times are rounded to the nearest second, like TTL
+            let secs = results
+                .into_iter()
+                .map(|ms| if ms < 0 { ms } else { (ms + 500) / 1000 })
+                .collect();
+            Ok(write_integers(conn, secs))
         }
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
@@ -85,6 +234,105 @@ pub fn hstrlen(
     }
 }
 
+#[tracing::instrument(skip_all)]
+pub fn hpersist(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 5 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let fields = match parse_fields(&args[2..]) {
+        Ok(fields) => fields,
+        Err(err) => return Ok(conn.write_error(err)),
+    };
+    match db.delete_field_expiry(&args[1], fields) {
+        Ok(results) => Ok(write_integers(conn, results)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_values(conn: &mut dyn Connection, values: Vec<Option<Vec<u8>>>) {
+    conn.write_array(values.len());
+    for value in values {
+        match value {
+            Some(value) => conn.write_bulk(&value),
+            None => conn.write_null(),
+        }
+    }
+}
+
+// HGETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+//   PXAT unix-time-milliseconds | PERSIST] FIELDS numfields field [field ...]
+fn parse_getex_options(args: &[Vec<u8>]) -> Result<(SetExpiry, &[Vec<u8>]), ClientError> {
+    let mut options = Options::new(args);
+    let expiry = match options.next_keyword().as_deref() {
+        Some("EX") => SetExpiry::In(Duration::from_secs(positive_time(options.int()?)?)),
+        Some("PX") => SetExpiry::In(Duration::from_millis(positive_time(options.int()?)?)),
+        Some("EXAT") => SetExpiry::At(Duration::from_secs(positive_time(options.int()?)?)),
+        Some("PXAT") => SetExpiry::At(Duration::from_millis(positive_time(options.int()?)?)),
+        Some("PERSIST") => SetExpiry::Clear,
+        _ => return Ok((SetExpiry::Keep, args)),
+    };
+    Ok((expiry, options.rest()))
+}
+
+#[tracing::instrument(skip_all)]
+pub fn hgetex(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 5 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let (expiry, fields) = match parse_getex_options(&args[2..])
+        .and_then(|(expiry, rest)| Ok((expiry, parse_fields(rest)?)))
+    {
+        Ok(parsed) => parsed,
+        Err(err) => return Ok(conn.write_error(err)),
+    };
+    match db.get_hash_fields_with_expiry(&args[1], fields, expiry) {
+        Ok(values) => Ok(write_values(conn, values)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn hgetdel(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 5 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let fields = match parse_fields(&args[2..]) {
+        Ok(fields) => fields,
+        Err(err) => return Ok(conn.write_error(err)),
+    };
+    match db.take_hash_fields(&args[1], fields) {
+        Ok(values) => Ok(write_values(conn, values)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{connection::MockConnection, database::MockDatabaseOperations};
@@ -154,4 +402,306 @@ mod test {
         ];
         let _ = hset(&mut mock_conn, &mock_db, &args).unwrap();
     }
+
+    #[test]
+    fn test_hgetall() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_read_hash()
+            .withf(move |k, _| k == key.as_bytes())
+            .times(1)
+            .returning(|_, f| {
+                f(2, &[(b"a".as_slice(), b"1".as_slice())]);
+                f(2, &[(b"b".as_slice(), b"2".as_slice())]);
+                Ok(true)
+            });
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_map()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        for bulk in ["a", "1", "b", "2"] {
+            mock_conn
+                .expect_write_bulk()
+                .with(eq(bulk.as_bytes()))
+                .times(1)
+                .return_const(());
+        }
+
+        let args: Vec<Vec<u8>> = vec!["HGETALL".into(), key.into()];
+        let _ = hgetall(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_hgetall_missing() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_read_hash()
+            .times(1)
+            .returning(|_, _| Ok(false));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_map()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["HGETALL".into(), "key".into()];
+        let _ = hgetall(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_hexpire() {
+        let key = "key";
+        let fields: Vec<Vec<u8>> = vec!["a".into(), "b".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_field_expiry()
+            .with(
+                eq(key.as_bytes()),
+                eq(fields),
+                eq(Duration::from_secs(10)),
+                eq(ExpiryCondition::IfGreater),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![1, -2]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        for reply in [1, -2] {
+            mock_conn
+                .expect_write_integer()
+                .with(eq(reply))
+                .times(1)
+                .return_const(());
+        }
+
+        let args: Vec<Vec<u8>> = vec![
+            "HEXPIRE".into(),
+            key.into(),
+            "10".into(),
+            "gt".into(),
+            "FIELDS".into(),
+            "2".into(),
+            "a".into(),
+            "b".into(),
+        ];
+        let _ = hexpire(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_hexpire_errors() {
+        let mock_db = MockDatabaseOperations::new();
+        let cases: Vec<(Vec<&str>, &str)> = vec![
+            (
+                vec!["HEXPIRE", "key", "-1", "FIELDS", "1", "a"],
+                "ERR invalid expire time",
+            ),
+            (
+                vec!["HEXPIRE", "key", "10", "NX", "1", "a"],
+                "ERR Mandatory argument FIELDS is missing or not at the right position",
+            ),
+            (
+                vec!["HEXPIRE", "key", "10", "FIELDS", "0", "a"],
+                "ERR Parameter `numFields` should be greater than 0",
+            ),
+            (
+                vec!["HEXPIRE", "key", "10", "FIELDS", "2", "a"],
+                "ERR The `numfields` parameter must match the number of arguments",
+            ),
+        ];
+        for (args, message) in cases {
+            let mut mock_conn = MockConnection::new();
+            mock_conn
+                .expect_write_error()
+                .withf(move |err| err.to_string() == message)
+                .times(1)
+                .return_const(());
+
+            let args: Vec<Vec<u8>> = args.into_iter().map(|arg| arg.into()).collect();
+            let _ = hexpire(&mut mock_conn, &mock_db, &args).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_httl() {
+        let key = "key";
+        let fields: Vec<Vec<u8>> = vec!["a".into(), "b".into(), "c".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_field_expiry()
+            .with(eq(key.as_bytes()), eq(fields))
+            .times(1)
+            .returning(|_, _| Ok(vec![9_600, -1, -2]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(3))
+            .times(1)
+            .return_const(());
+        for reply in [10, -1, -2] {
+            mock_conn
+                .expect_write_integer()
+                .with(eq(reply))
+                .times(1)
+                .return_const(());
+        }
+
+        let args: Vec<Vec<u8>> = vec![
+            "HTTL".into(),
+            key.into(),
+            "FIELDS".into(),
+            "3".into(),
+            "a".into(),
+            "b".into(),
+            "c".into(),
+        ];
+        let _ = httl(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_hpersist() {
+        let key = "key";
+        let fields: Vec<Vec<u8>> = vec!["a".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_delete_field_expiry()
+            .with(eq(key.as_bytes()), eq(fields))
+            .times(1)
+            .returning(|_, _| Ok(vec![1]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "HPERSIST".into(),
+            key.into(),
+            "FIELDS".into(),
+            "1".into(),
+            "a".into(),
+        ];
+        let _ = hpersist(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_hgetex() {
+        let key = "key";
+        let fields: Vec<Vec<u8>> = vec!["a".into(), "b".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_hash_fields_with_expiry()
+            .with(
+                eq(key.as_bytes()),
+                eq(fields),
+                eq(SetExpiry::In(Duration::from_millis(1500))),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(vec![Some(b"1".to_vec()), None]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq(b"1".as_slice()))
+            .times(1)
+            .return_const(());
+        mock_conn.expect_write_null().times(1).return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "HGETEX".into(),
+            key.into(),
+            "px".into(),
+            "1500".into(),
+            "FIELDS".into(),
+            "2".into(),
+            "a".into(),
+            "b".into(),
+        ];
+        let _ = hgetex(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_hgetex_options() {
+        let fields: Vec<Vec<u8>> = vec!["FIELDS".into(), "1".into(), "a".into()];
+        let (expiry, rest) = parse_getex_options(&fields).unwrap();
+        assert_eq!(SetExpiry::Keep, expiry);
+        assert_eq!(&fields[..], rest);
+
+        let args: Vec<Vec<u8>> = vec!["PERSIST".into(), "FIELDS".into()];
+        let (expiry, rest) = parse_getex_options(&args).unwrap();
+        assert_eq!(SetExpiry::Clear, expiry);
+        assert_eq!(&args[1..], rest);
+
+        let args: Vec<Vec<u8>> = vec!["EX".into(), "0".into(), "FIELDS".into()];
+        assert!(matches!(
+            parse_getex_options(&args),
+            Err(ClientError::InvalidExpireTime)
+        ));
+        let args: Vec<Vec<u8>> = vec!["EXAT".into()];
+        assert!(matches!(
+            parse_getex_options(&args),
+            Err(ClientError::Syntax)
+        ));
+    }
+
+    #[test]
+    fn test_hgetdel() {
+        let key = "key";
+        let fields: Vec<Vec<u8>> = vec!["a".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_take_hash_fields()
+            .with(eq(key.as_bytes()), eq(fields))
+            .times(1)
+            .returning(|_, _| Ok(vec![Some(b"1".to_vec())]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq(b"1".as_slice()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "HGETDEL".into(),
+            key.into(),
+            "FIELDS".into(),
+            "1".into(),
+            "a".into(),
+        ];
+        let _ = hgetdel(&mut mock_conn, &mock_db, &args).unwrap();
+    }
 }
@@ -0,0 +1,824 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{
+    blocking,
+    commands::{is_keyword, parse_int},
+    connection::{ClientError, Connection},
+    database::{DatabaseError, DatabaseOperations},
+    lists::ListEnd,
+    notify,
+};
+
+fn push(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+    end: ListEnd,
+    if_exists: bool,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.push_list(&args[1], args[2..].to_vec(), end, if_exists) {
+        Ok(len) => {
+            if len > 0 {
+                blocking::wake(&args[1]);
+            }
+            Ok(conn.write_integer(len))
+        }
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn lpush(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    push(conn, db, args, ListEnd::Front, false)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn rpush(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    push(conn, db, args, ListEnd::Back, false)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn lpushx(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    push(conn, db, args, ListEnd::Front, true)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn rpushx(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    push(conn, db, args, ListEnd::Back, true)
+}
+
+fn pop(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+    end: ListEnd,
+) -> Result<()> {
+    if args.len() < 2 || args.len() > 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    // With a count, the reply is an array even for a single element
+    let count = match args.get(2) {
+        Some(arg) => match parse_int::<i64>(arg)? {
+            count if { count < 0 } => return Ok(conn.write_error(ClientError::NotPositive)),
+            count => Some(count as usize),
+        },
+        None => None,
+    };
+
+    let popped = match db.pop_list(&args[1], end, count.unwrap_or(1)) {
+        Ok(popped) => popped,
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            return Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    match (popped, count) {
+        (Some(elements), Some(_)) => {
+            conn.write_array(elements.len());
+            for element in elements {
+                conn.write_bulk(&element);
+            }
+        }
+        (Some(elements), None) if { !elements.is_empty() } => conn.write_bulk(&elements[0]),
+        _ => conn.write_null(),
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn lpop(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    pop(conn, db, args, ListEnd::Front)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn rpop(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    pop(conn, db, args, ListEnd::Back)
+}
+
+// In seconds, where 0 waits for as long as it takes
+fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, ClientError> {
+    let timeout = String::from_utf8_lossy(arg)
+        .parse::<f64>()
+        .map_err(|_| ClientError::InvalidTimeout)?;
+    if timeout < 0.0 {
+        return Err(ClientError::NegativeTimeout);
+    }
+    match Duration::try_from_secs_f64(timeout) {
+        Ok(timeout) if { timeout.is_zero() } => Ok(None),
+        Ok(timeout) => Ok(Some(timeout)),
+        Err(_) => Err(ClientError::InvalidTimeout),
+    }
+}
+
+fn blocking_pop(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+    end: ListEnd,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let timeout = match parse_timeout(&args[args.len() - 1]) {
+        Ok(timeout) => timeout,
+        Err(err) => return Ok(conn.write_error(err)),
+    };
+    let keys = &args[1..args.len() - 1];
+    for key in keys {
+        let element = match db.pop_list(key, end, 1) {
+            Ok(popped) => popped.and_then(|mut elements| elements.pop()),
+            Err(DatabaseError::WrongType { expected: _ }) => {
+                return Ok(conn.write_error(ClientError::WrongType))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if let Some(element) = element {
+            // Pushes only wake one waiter each, so anything left over is
+            // passed on to the next one
+            if db.get_list_length(key)? > 0 {
+                blocking::wake(key);
+            }
+            notify::replace_event(match end {
+                ListEnd::Front => "lpop",
+                ListEnd::Back => "rpop",
+            });
+
+            conn.write_array(2);
+            conn.write_bulk(key);
+            conn.write_bulk(&element);
+            return Ok(());
+        }
+    }
+
+    blocking::park(keys.to_vec(), timeout);
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn blpop(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    blocking_pop(conn, db, args, ListEnd::Front)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn brpop(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    blocking_pop(conn, db, args, ListEnd::Back)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn llen(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.get_list_length(&args[1]) {
+        Ok(len) => Ok(conn.write_integer(len)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn lindex(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let index = parse_int::<i64>(&args[2])?;
+    match db.get_list_element(&args[1], index) {
+        Ok(Some(element)) => Ok(conn.write_bulk(&element)),
+        Ok(None) => Ok(conn.write_null()),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn lrange(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let start = parse_int::<i64>(&args[2])?;
+    let stop = parse_int::<i64>(&args[3])?;
+    match db.get_list_range(&args[1], start, stop) {
+        Ok(elements) => {
+            conn.write_array(elements.len());
+            for element in elements {
+                conn.write_bulk(&element);
+            }
+            Ok(())
+        }
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn lset(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let index = parse_int::<i64>(&args[2])?;
+    match db.set_list_element(&args[1], index, args[3].clone()) {
+        Ok(()) => Ok(conn.write_string("OK")),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(DatabaseError::NoSuchKey) => Ok(conn.write_error(ClientError::NoSuchKey)),
+        Err(DatabaseError::IndexOutOfRange) => Ok(conn.write_error(ClientError::IndexOutOfRange)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn linsert(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 5 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let before = match &args[2] {
+        arg if { is_keyword(arg, "BEFORE") } => true,
+        arg if { is_keyword(arg, "AFTER") } => false,
+        _ => return Ok(conn.write_error(ClientError::Syntax)),
+    };
+    match db.insert_list(&args[1], &args[3], args[4].clone(), before) {
+        Ok(len) => Ok(conn.write_integer(len)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn lrem(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let count = parse_int::<i64>(&args[2])?;
+    match db.remove_list(&args[1], count, &args[3]) {
+        Ok(removed) => Ok(conn.write_integer(removed)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ltrim(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let start = parse_int::<i64>(&args[2])?;
+    let stop = parse_int::<i64>(&args[3])?;
+    match db.trim_list(&args[1], start, stop) {
+        Ok(()) => Ok(conn.write_string("OK")),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{connection::MockConnection, database::MockDatabaseOperations};
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_lpush() {
+        let key = "key";
+        let elements: Vec<Vec<u8>> = vec!["a".into(), "b".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_push_list()
+            .with(
+                eq(key.as_bytes()),
+                eq(elements),
+                eq(ListEnd::Front),
+                eq(false),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(2));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LPUSH".into(), key.into(), "a".into(), "b".into()];
+        let _ = lpush(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_rpushx_missing() {
+        let key = "key";
+        let elements: Vec<Vec<u8>> = vec!["a".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_push_list()
+            .with(
+                eq(key.as_bytes()),
+                eq(elements),
+                eq(ListEnd::Back),
+                eq(true),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(0));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["RPUSHX".into(), key.into(), "a".into()];
+        let _ = rpushx(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_push_wrong_type() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db.expect_push_list().times(1).returning(|_, _, _, _| {
+            Err(DatabaseError::WrongType {
+                expected: "list".into(),
+            })
+        });
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::WrongType))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["RPUSH".into(), "key".into(), "a".into()];
+        let _ = rpush(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_lpop() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_pop_list()
+            .with(eq(key.as_bytes()), eq(ListEnd::Front), eq(1))
+            .times(1)
+            .returning(|_, _, _| Ok(Some(vec![b"a".to_vec()])));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_bulk()
+            .with(eq(b"a".as_slice()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LPOP".into(), key.into()];
+        let _ = lpop(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_rpop_count() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_pop_list()
+            .with(eq(key.as_bytes()), eq(ListEnd::Back), eq(3))
+            .times(1)
+            .returning(|_, _, _| Ok(Some(vec![b"c".to_vec(), b"b".to_vec()])));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        for element in ["c", "b"] {
+            mock_conn
+                .expect_write_bulk()
+                .with(eq(element.as_bytes()))
+                .times(1)
+                .return_const(());
+        }
+
+        let args: Vec<Vec<u8>> = vec!["RPOP".into(), key.into(), "3".into()];
+        let _ = rpop(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_pop_missing() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_pop_list()
+            .times(2)
+            .returning(|_, _, _| Ok(None));
+
+        for args in [vec!["LPOP", "key"], vec!["LPOP", "key", "2"]] {
+            let mut mock_conn = MockConnection::new();
+            mock_conn.expect_write_null().times(1).return_const(());
+
+            let args: Vec<Vec<u8>> = args.into_iter().map(|arg| arg.into()).collect();
+            let _ = lpop(&mut mock_conn, &mock_db, &args).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_pop_negative_count() {
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::NotPositive))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LPOP".into(), "key".into(), "-1".into()];
+        let _ = lpop(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_blpop() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_pop_list()
+            .with(eq(b"a".as_slice()), eq(ListEnd::Front), eq(1))
+            .times(1)
+            .returning(|_, _, _| Ok(None));
+        mock_db
+            .expect_pop_list()
+            .with(eq(b"b".as_slice()), eq(ListEnd::Front), eq(1))
+            .times(1)
+            .returning(|_, _, _| Ok(Some(vec![b"x".to_vec()])));
+        mock_db
+            .expect_get_list_length()
+            .with(eq(b"b".as_slice()))
+            .times(1)
+            .returning(|_| Ok(0));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        for reply in ["b", "x"] {
+            mock_conn
+                .expect_write_bulk()
+                .with(eq(reply.as_bytes()))
+                .times(1)
+                .return_const(());
+        }
+
+        let args: Vec<Vec<u8>> = vec!["BLPOP".into(), "a".into(), "b".into(), "0".into()];
+        let _ = blpop(&mut mock_conn, &mock_db, &args).unwrap();
+        assert!(blocking::take_parked().is_none());
+    }
+
+    #[test]
+    fn test_brpop_parks() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_pop_list()
+            .with(eq(b"brpop-parks".as_slice()), eq(ListEnd::Back), eq(1))
+            .times(1)
+            .returning(|_, _, _| Ok(None));
+
+        // Nothing is written until the connection is woken or times out
+        let mut mock_conn = MockConnection::new();
+
+        let args: Vec<Vec<u8>> = vec!["BRPOP".into(), "brpop-parks".into(), "0.01".into()];
+        let _ = brpop(&mut mock_conn, &mock_db, &args).unwrap();
+        let waiter = blocking::take_parked().unwrap();
+        assert!(!waiter.wait(-1));
+    }
+
+    #[test]
+    fn test_blocking_pop_timeout() {
+        let mock_db = MockDatabaseOperations::new();
+
+        for (timeout, expected) in [
+            ("-1", "ERR timeout is negative"),
+            ("x", "ERR timeout is not a float or out of range"),
+        ] {
+            let mut mock_conn = MockConnection::new();
+            mock_conn
+                .expect_write_error()
+                .withf(move |err| err.to_string() == expected)
+                .times(1)
+                .return_const(());
+
+            let args: Vec<Vec<u8>> = vec!["BLPOP".into(), "key".into(), timeout.into()];
+            let _ = blpop(&mut mock_conn, &mock_db, &args).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_llen() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_list_length()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(3));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(3))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LLEN".into(), key.into()];
+        let _ = llen(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_lindex() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_list_element()
+            .with(eq(key.as_bytes()), eq(-1))
+            .times(1)
+            .returning(|_, _| Ok(Some(b"c".to_vec())));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_bulk()
+            .with(eq(b"c".as_slice()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LINDEX".into(), key.into(), "-1".into()];
+        let _ = lindex(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_lrange() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_list_range()
+            .with(eq(key.as_bytes()), eq(0), eq(-1))
+            .times(1)
+            .returning(|_, _, _| Ok(vec![b"a".to_vec(), b"b".to_vec()]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        for element in ["a", "b"] {
+            mock_conn
+                .expect_write_bulk()
+                .with(eq(element.as_bytes()))
+                .times(1)
+                .return_const(());
+        }
+
+        let args: Vec<Vec<u8>> = vec!["LRANGE".into(), key.into(), "0".into(), "-1".into()];
+        let _ = lrange(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_lset() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_set_list_element()
+            .with(eq(key.as_bytes()), eq(1), eq(b"x".to_vec()))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LSET".into(), key.into(), "1".into(), "x".into()];
+        let _ = lset(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_linsert() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_insert_list()
+            .withf(move |k, pivot, element, before| {
+                k == key.as_bytes() && pivot == b"b" && element == b"x" && *before
+            })
+            .times(1)
+            .returning(|_, _, _, _| Ok(4));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(4))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "LINSERT".into(),
+            key.into(),
+            "before".into(),
+            "b".into(),
+            "x".into(),
+        ];
+        let _ = linsert(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_linsert_syntax() {
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::Syntax))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "LINSERT".into(),
+            "key".into(),
+            "middle".into(),
+            "b".into(),
+            "x".into(),
+        ];
+        let _ = linsert(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_lrem() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_remove_list()
+            .withf(move |k, count, element| k == key.as_bytes() && *count == -2 && element == b"a")
+            .times(1)
+            .returning(|_, _, _| Ok(2));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LREM".into(), key.into(), "-2".into(), "a".into()];
+        let _ = lrem(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_ltrim() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_trim_list()
+            .with(eq(key.as_bytes()), eq(1), eq(-2))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LTRIM".into(), key.into(), "1".into(), "-2".into()];
+        let _ = ltrim(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_lset_out_of_range() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_set_list_element()
+            .times(1)
+            .returning(|_, _, _| Err(DatabaseError::IndexOutOfRange));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::IndexOutOfRange))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["LSET".into(), "key".into(), "5".into(), "x".into()];
+        let _ = lset(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+}
@@ -1,13 +1,56 @@
 mod bitmap;
+mod bloom;
+mod cms;
 mod connection;
+mod convert;
 mod generic;
 mod hashes;
+mod lists;
+#[cfg(feature = "native-modules")]
+mod module;
+mod options;
 mod server;
+mod sets;
 mod strings;
+mod tdigest;
+mod timeseries;
+mod topk;
 
 pub use crate::commands::bitmap::*;
+pub use crate::commands::bloom::*;
+pub use crate::commands::cms::*;
 pub use crate::commands::connection::*;
+pub use crate::commands::convert::*;
 pub use crate::commands::generic::*;
 pub use crate::commands::hashes::*;
+pub use crate::commands::lists::*;
+#[cfg(feature = "native-modules")]
+pub use crate::commands::module::*;
+pub use crate::commands::options::*;
 pub use crate::commands::server::*;
+pub use crate::commands::sets::*;
 pub use crate::commands::strings::*;
+pub use crate::commands::tdigest::*;
+pub use crate::commands::timeseries::*;
+pub use crate::commands::topk::*;
+
+use anyhow::Result;
+
+use crate::{
+    connection::{ClientError, Connection},
+    database::DatabaseError,
+};
+
+// Shared error handling for the module-style types (sketches and time
+// series), whose errors are reported to the client verbatim
+fn write_module_error(conn: &mut dyn Connection, err: DatabaseError) -> Result<()> {
+    match err {
+        DatabaseError::WrongType { expected: _ } => Ok(conn.write_error(ClientError::WrongType)),
+        DatabaseError::Sketch(err) => Ok(conn.write_error(ClientError::Module(err.to_string()))),
+        DatabaseError::TimeSeries(err) => {
+            Ok(conn.write_error(ClientError::Module(err.to_string())))
+        }
+        DatabaseError::CrossShard => Ok(conn.write_error(ClientError::CrossSlot)),
+        err => Err(err.into()),
+    }
+}
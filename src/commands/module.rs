@@ -0,0 +1,75 @@
+use crate::{
+    connection::{ClientError, Connection},
+    modules,
+};
+
+#[tracing::instrument(skip_all)]
+pub fn module(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    if args.len() < 2 {
+        conn.write_error(ClientError::ArgCount);
+        return;
+    }
+
+    let subcommand = String::from_utf8_lossy(&args[1]).to_uppercase();
+    match subcommand.as_str() {
+        "LIST" => {
+            let loaded = modules::list();
+            conn.write_array(loaded.len());
+            for (name, version, path) in loaded {
+                conn.write_array(8);
+                conn.write_bulk(b"name");
+                conn.write_bulk(name.as_bytes());
+                conn.write_bulk(b"ver");
+                conn.write_integer(version.into());
+                conn.write_bulk(b"path");
+                conn.write_bulk(path.as_bytes());
+                conn.write_bulk(b"args");
+                conn.write_array(0);
+            }
+        }
+        "LOAD" => {
+            if args.len() != 3 {
+                conn.write_error(ClientError::ArgCount);
+                return;
+            }
+
+            match modules::load(&String::from_utf8_lossy(&args[2])) {
+                Ok(_) => conn.write_string("OK"),
+                Err(err) => conn.write_error(ClientError::Module(err.to_string())),
+            }
+        }
+        "UNLOAD" => {
+            if args.len() != 3 {
+                conn.write_error(ClientError::ArgCount);
+                return;
+            }
+
+            match modules::unload(&String::from_utf8_lossy(&args[2])) {
+                Ok(_) => conn.write_string("OK"),
+                Err(err) => conn.write_error(ClientError::Module(err.to_string())),
+            }
+        }
+        _ => conn.write_error(ClientError::UnknownCommand),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::connection::MockConnection;
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_module_unload_missing() {
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| err.to_string() == "ERR no such module with that name")
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["MODULE".into(), "UNLOAD".into(), "missing".into()];
+        module(&mut mock_conn, &args);
+    }
+}
@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use crate::{
+    commands::{parse_float, parse_int},
+    connection::ClientError,
+};
+
+// Whether an argument is the given keyword, ignoring case like Redis does
+pub fn is_keyword(arg: &[u8], keyword: &str) -> bool {
+    arg.eq_ignore_ascii_case(keyword.as_bytes())
+}
+
+// Walks the trailing options of a command, like
+//
+//   let mut options = Options::new(&args[3..]);
+//   while let Some(option) = options.next_keyword() {
+//       match option.as_str() {
+//           "NX" => nx = true,
+//           "COUNT" => count = options.int()?,
+//           _ => return Err(ClientError::Syntax.into()),
+//       }
+//   }
+//
+// Keywords are uppercased, and a keyword missing its value is a syntax
+// error, so handlers only have to deal with the options they know.
+pub struct Options<'a> {
+    args: &'a [Vec<u8>],
+    pos: usize,
+}
+
+impl<'a> Options<'a> {
+    pub fn new(args: &'a [Vec<u8>]) -> Self {
+        Self { args, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.args.len()
+    }
+
+    pub fn next_keyword(&mut self) -> Option<String> {
+        let arg = self.args.get(self.pos)?;
+        self.pos += 1;
+        Some(String::from_utf8_lossy(arg).to_uppercase())
+    }
+
+    // The argument following the last keyword
+    pub fn value(&mut self) -> Result<&'a [u8], ClientError> {
+        let arg = self.args.get(self.pos).ok_or(ClientError::Syntax)?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    pub fn int<T: FromStr>(&mut self) -> Result<T, ClientError> {
+        parse_int(self.value()?)
+    }
+
+    pub fn float(&mut self) -> Result<f64, ClientError> {
+        parse_float(self.value()?)
+    }
+
+    // Everything not consumed yet, for options like LABELS that take the
+    // rest of the arguments
+    pub fn rest(&mut self) -> &'a [Vec<u8>] {
+        let rest = &self.args[self.pos.min(self.args.len())..];
+        self.pos = self.args.len();
+        rest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let args: Vec<Vec<u8>> = vec![
+            "nx".into(),
+            "Count".into(),
+            "10".into(),
+            "LABELS".into(),
+            "a".into(),
+            "b".into(),
+        ];
+        let mut options = Options::new(&args);
+        assert_eq!(Some("NX".to_string()), options.next_keyword());
+        assert_eq!(Some("COUNT".to_string()), options.next_keyword());
+        assert_eq!(10, options.int::<u32>().unwrap());
+        assert_eq!(Some("LABELS".to_string()), options.next_keyword());
+        assert_eq!(&args[4..], options.rest());
+        assert!(options.is_empty());
+        assert_eq!(None, options.next_keyword());
+    }
+
+    #[test]
+    fn test_options_errors() {
+        let args: Vec<Vec<u8>> = vec!["COUNT".into(), "x".into(), "COUNT".into()];
+        let mut options = Options::new(&args);
+        options.next_keyword();
+        assert!(matches!(options.int::<u32>(), Err(ClientError::NotInteger)));
+        options.next_keyword();
+        assert!(matches!(options.value(), Err(ClientError::Syntax)));
+    }
+
+    #[test]
+    fn test_is_keyword() {
+        assert!(is_keyword(b"withCount", "WITHCOUNT"));
+        assert!(!is_keyword(b"WITH", "WITHCOUNT"));
+    }
+}
@@ -1,22 +1,347 @@
-use crate::{connection::Connection, time::unix_timestamp};
+use crate::{
+    access, bigkeys,
+    commands::integer_reply,
+    config,
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    expire, hotkeys, ipfilter, keyspec, lazyfree,
+    registry::{self, Command},
+    shutdown, slowlog,
+    time::unix_timestamp,
+    tracking,
+};
 use anyhow::Result;
 
+// Laid out like Redis' COMMAND INFO entries, leaving out the ACL categories,
+// tips, key specs and subcommands
+fn write_command_info(conn: &mut dyn Connection, command: &Command) {
+    let (first, last, step) = keyspec::key_range(command.name).unwrap_or_default();
+    conn.write_array(10);
+    conn.write_bulk(command.name.to_lowercase().as_bytes());
+    conn.write_integer(command.arity);
+    conn.write_array(command.flags.len());
+    for flag in command.flags {
+        conn.write_string(flag);
+    }
+    conn.write_integer(first);
+    conn.write_integer(last);
+    conn.write_integer(step);
+    for _ in 0..4 {
+        conn.write_array(0);
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn command(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    if args.len() == 1 {
+        conn.write_array(registry::COMMANDS.len());
+        for command in registry::COMMANDS {
+            write_command_info(conn, command);
+        }
+        return;
+    }
+
+    let subcommand = String::from_utf8_lossy(&args[1]).to_uppercase();
+    let with_flags = match subcommand.as_str() {
+        "COUNT" if { args.len() == 2 } => {
+            conn.write_integer(registry::COMMANDS.len() as i64);
+            return;
+        }
+        "LIST" if { args.len() == 2 } => {
+            conn.write_array(registry::COMMANDS.len());
+            for command in registry::COMMANDS {
+                conn.write_bulk(command.name.to_lowercase().as_bytes());
+            }
+            return;
+        }
+        "COUNT" | "LIST" => {
+            conn.write_error(ClientError::ArgCount);
+            return;
+        }
+        "INFO" => {
+            conn.write_array(args.len() - 2);
+            for name in &args[2..] {
+                let name = String::from_utf8_lossy(name).to_uppercase();
+                match registry::lookup(&name) {
+                    Some(command) => write_command_info(conn, command),
+                    None => conn.write_null(),
+                }
+            }
+            return;
+        }
+        "GETKEYS" => false,
+        "GETKEYSANDFLAGS" => true,
+        _ => {
+            conn.write_error(ClientError::UnknownCommand);
+            return;
+        }
+    };
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return;
+    }
+
+    let command = &args[2..];
+    match keyspec::get_keys(command) {
+        Ok(keys) => {
+            conn.write_array(keys.len());
+            for (index, flags) in keys {
+                if with_flags {
+                    conn.write_array(2);
+                    conn.write_bulk(&command[index]);
+                    conn.write_array(flags.len());
+                    for flag in flags {
+                        conn.write_string(flag);
+                    }
+                } else {
+                    conn.write_bulk(&command[index]);
+                }
+            }
+        }
+        Err(err) => conn.write_error(err.into()),
+    }
+}
+
 #[tracing::instrument(skip_all)]
 pub fn time(conn: &mut dyn Connection) -> Result<()> {
     let ts = unix_timestamp()?.as_micros();
 
     conn.write_array(2);
-    conn.write_integer((ts / 1000000).try_into()?);
-    conn.write_integer((ts % 1000000).try_into()?);
+    conn.write_integer(integer_reply(ts / 1000000));
+    conn.write_integer(integer_reply(ts % 1000000));
 
     Ok(())
 }
 
 #[tracing::instrument(skip_all)]
-pub fn info(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+pub fn config(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    if args.len() < 2 {
+        conn.write_error(ClientError::ArgCount);
+        return;
+    }
+
+    let subcommand = String::from_utf8_lossy(&args[1]).to_uppercase();
+    match subcommand.as_str() {
+        "GET" => {
+            if args.len() < 3 {
+                conn.write_error(ClientError::ArgCount);
+                return;
+            }
+
+            let config = config::config().read().unwrap();
+            let mut results: Vec<(String, String)> = vec![];
+            for pattern in args[2..].iter() {
+                for result in config.get(&String::from_utf8_lossy(pattern)) {
+                    if !results.contains(&result) {
+                        results.push(result);
+                    }
+                }
+            }
+
+            conn.write_array(results.len() * 2);
+            for (name, value) in results {
+                conn.write_bulk(name.as_bytes());
+                conn.write_bulk(value.as_bytes());
+            }
+        }
+        "SET" => {
+            if args.len() < 4 || args.len() % 2 != 0 {
+                conn.write_error(ClientError::ArgCount);
+                return;
+            }
+
+            let mut config = config::config().write().unwrap();
+            let params: Vec<(String, String)> = args[2..]
+                .chunks(2)
+                .map(|pair| {
+                    (
+                        String::from_utf8_lossy(&pair[0]).into_owned(),
+                        String::from_utf8_lossy(&pair[1]).into_owned(),
+                    )
+                })
+                .collect();
+
+            // Validate everything up front so that either all parameters
+            // are applied or none are
+            for (name, value) in params.iter() {
+                if let Err(err) = config.check(name, value) {
+                    conn.write_error(ClientError::ConfigSet(err.to_string()));
+                    return;
+                }
+            }
+
+            for (name, value) in params.iter() {
+                if let Err(err) = config.set(name, value) {
+                    conn.write_error(ClientError::ConfigSet(err.to_string()));
+                    return;
+                }
+            }
+
+            conn.write_string("OK");
+        }
+        _ => conn.write_error(ClientError::UnknownCommand),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn slowlog(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    if args.len() < 2 {
+        conn.write_error(ClientError::ArgCount);
+        return;
+    }
+
+    let subcommand = String::from_utf8_lossy(&args[1]).to_uppercase();
+    match subcommand.as_str() {
+        "GET" => {
+            // A negative count returns the whole log
+            let count = match args.get(2) {
+                Some(arg) => match String::from_utf8_lossy(arg).parse::<i64>() {
+                    Ok(n) => n.try_into().unwrap_or(usize::MAX),
+                    Err(_) => {
+                        conn.write_error(ClientError::Syntax);
+                        return;
+                    }
+                },
+                None => 10,
+            };
+
+            let entries = slowlog::get(count);
+            conn.write_array(entries.len());
+            for entry in entries {
+                conn.write_array(6);
+                conn.write_integer(integer_reply(entry.id));
+                conn.write_integer(integer_reply(entry.timestamp));
+                conn.write_integer(integer_reply(entry.duration.as_micros()));
+                conn.write_array(entry.args.len());
+                for arg in entry.args {
+                    conn.write_bulk(&arg);
+                }
+                // Client address and name aren't tracked
+                conn.write_bulk(b"");
+                conn.write_bulk(b"");
+            }
+        }
+        "LEN" => conn.write_integer(integer_reply(slowlog::len())),
+        "RESET" => {
+            slowlog::reset();
+            conn.write_string("OK");
+        }
+        _ => conn.write_error(ClientError::UnknownCommand),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn hotkeys(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    // HOTKEYS [COUNT count]
+    let count = match args.len() {
+        1 => 10,
+        3 if { String::from_utf8_lossy(&args[1]).eq_ignore_ascii_case("COUNT") } => {
+            match String::from_utf8_lossy(&args[2]).parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => {
+                    conn.write_error(ClientError::Syntax);
+                    return;
+                }
+            }
+        }
+        _ => {
+            conn.write_error(ClientError::Syntax);
+            return;
+        }
+    };
+
+    let top = hotkeys::top(count);
+    conn.write_array(top.len());
+    for (key, accesses) in top {
+        conn.write_array(2);
+        conn.write_bulk(&key);
+        conn.write_integer(integer_reply(accesses));
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn bigkeys(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    // BIGKEYS [START]
+    match args.len() {
+        1 => {}
+        2 if { String::from_utf8_lossy(&args[1]).eq_ignore_ascii_case("START") } => {
+            match bigkeys::start_scan() {
+                Ok(_) => conn.write_string("OK"),
+                Err(err) => conn.write_error(err.into()),
+            }
+            return;
+        }
+        _ => {
+            conn.write_error(ClientError::Syntax);
+            return;
+        }
+    }
+
+    let (_, Some(report)) = bigkeys::status() else {
+        conn.write_error(bigkeys::BigKeysError::NoReport.into());
+        return;
+    };
+
+    // One entry per type: type, keys, total elements, then the biggest key
+    // with its size and element count
+    conn.write_array(report.types.len());
+    for (type_name, summary) in report.types {
+        conn.write_array(6);
+        conn.write_bulk(type_name.as_bytes());
+        conn.write_integer(integer_reply(summary.keys));
+        conn.write_integer(integer_reply(summary.total_elements));
+        match summary.biggest {
+            Some(biggest) => {
+                conn.write_bulk(&biggest.key);
+                conn.write_integer(integer_reply(biggest.bytes));
+                conn.write_integer(integer_reply(biggest.elements));
+            }
+            None => {
+                conn.write_null();
+                conn.write_integer(0);
+                conn.write_integer(0);
+            }
+        }
+    }
+}
+
+fn stats_info(conn: &mut dyn Connection) {
+    conn.write_bulk(
+        format!(
+            "# Stats\r\nexpired_keys:{}\r\nrejected_connections:{}\r\n",
+            expire::expired_keys(),
+            ipfilter::rejected_connections()
+        )
+        .as_bytes(),
+    );
+}
+
+fn hotkeys_info(conn: &mut dyn Connection) {
+    let mut info = String::from("# Hotkeys\r\n");
+    for (i, (key, accesses)) in hotkeys::top(10).into_iter().enumerate() {
+        info.push_str(&format!(
+            "hotkey_{}:key={},accesses={}",
+            i,
+            String::from_utf8_lossy(&key),
+            accesses
+        ));
+        if let Some(idle) = access::idle_time(&key) {
+            info.push_str(&format!(",idle={}", idle));
+        }
+        info.push_str("\r\n");
+    }
+    conn.write_bulk(info.as_bytes());
+}
+
+#[tracing::instrument(skip_all)]
+pub fn info(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
     if args.len() >= 2 {
         let section = String::from_utf8_lossy(&args[1]).to_lowercase();
-        return match section.as_str() {
+        return Ok(match section.as_str() {
             "replication" => conn.write_bulk(
                 concat_string!(
                     "# Replication\r\n",
@@ -65,10 +390,23 @@ pub fn info(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
                 )
                 .as_bytes(),
             ),
+            "rocksdb" | "storage" => storage_info(conn, db)?,
+            "hotkeys" => hotkeys_info(conn),
+            "stats" => stats_info(conn),
             _ => (),
-        };
+        });
     }
 
+    let rejected_connections = format!(
+        "rejected_connections:{}\r\n",
+        ipfilter::rejected_connections()
+    );
+    let expired_keys = format!("expired_keys:{}\r\n", expire::expired_keys());
+    let lazyfree = format!(
+        "lazyfree_pending_objects:{}\r\nlazyfreed_objects:{}\r\n",
+        lazyfree::pending_objects(),
+        lazyfree::freed_objects()
+    );
     conn.write_bulk(
         concat_string!(
             "# Server\r\n",
@@ -160,8 +498,7 @@ pub fn info(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
             "mem_aof_buffer:0\r\n",
             "mem_allocator:jemalloc-5.3.0\r\n",
             "active_defrag_running:0\r\n",
-            "lazyfree_pending_objects:0\r\n",
-            "lazyfreed_objects:0\r\n",
+            lazyfree,
             "\r\n",
             "# Persistence\r\n",
             "loading:0\r\n",
@@ -207,11 +544,11 @@ pub fn info(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
             "instantaneous_output_kbps:0.00\r\n",
             "instantaneous_input_repl_kbps:0.00\r\n",
             "instantaneous_output_repl_kbps:0.00\r\n",
-            "rejected_connections:0\r\n",
+            rejected_connections,
             "sync_full:0\r\n",
             "sync_partial_ok:0\r\n",
             "sync_partial_err:0\r\n",
-            "expired_keys:0\r\n",
+            expired_keys,
             "expired_stale_perc:0.00\r\n",
             "expired_time_cap_reached_count:0\r\n",
             "expire_cycle_cpu_milliseconds:1062047\r\n",
@@ -288,4 +625,120 @@ pub fn info(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
         )
         .as_bytes(),
     );
+
+    Ok(())
+}
+
+fn storage_info(conn: &mut dyn Connection, db: &dyn DatabaseOperations) -> Result<()> {
+    let stats = db.storage_stats()?;
+
+    let mut info = String::from("# RocksDB\r\n");
+    for (name, value) in stats.properties {
+        info.push_str(&format!("{}:{}\r\n", name, value));
+    }
+    for (level, (files, size_mb)) in stats.levels.iter().enumerate() {
+        info.push_str(&format!(
+            "level{}:files={},size_mb={}\r\n",
+            level, files, size_mb
+        ));
+    }
+    info.push_str(&format!("write_stall_delays:{}\r\n", stats.total_delays));
+    info.push_str(&format!("write_stall_stops:{}\r\n", stats.total_stops));
+
+    conn.write_bulk(info.as_bytes());
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn check(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // CHECK [REPAIR]
+    let repair = match args.len() {
+        1 => false,
+        2 if { String::from_utf8_lossy(&args[1]).to_uppercase() == "REPAIR" } => true,
+        2 => {
+            conn.write_error(ClientError::Syntax);
+            return Ok(());
+        }
+        _ => {
+            conn.write_error(ClientError::ArgCount);
+            return Ok(());
+        }
+    };
+
+    let found = db.check_integrity(repair)?;
+    conn.write_array(found.len());
+    for inconsistency in found {
+        let key = String::from_utf8_lossy(&inconsistency.key);
+        conn.write_bulk(format!("{}: {}", key, inconsistency.problem).as_bytes());
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn flushall(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // FLUSHALL [ASYNC|SYNC]
+    let lazy = match args.len() {
+        1 => false,
+        2 => match String::from_utf8_lossy(&args[1]).to_uppercase().as_str() {
+            "ASYNC" => true,
+            "SYNC" => false,
+            _ => {
+                conn.write_error(ClientError::Syntax);
+                return Ok(());
+            }
+        },
+        _ => {
+            conn.write_error(ClientError::Syntax);
+            return Ok(());
+        }
+    };
+
+    db.flush_keys(lazy)?;
+    if lazy {
+        lazyfree::free_all(db)?;
+    }
+
+    // What was collected about the old keys goes with them
+    access::clear();
+    hotkeys::clear();
+    tracking::invalidate_all();
+
+    conn.write_string("OK");
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn shutdown(conn: &mut dyn Connection, args: &Vec<Vec<u8>>) {
+    // SHUTDOWN [NOSAVE|SAVE]
+    let save = match args.len() {
+        1 => true,
+        2 => match String::from_utf8_lossy(&args[1]).to_uppercase().as_str() {
+            "SAVE" => true,
+            "NOSAVE" => false,
+            _ => {
+                conn.write_error(ClientError::Syntax);
+                return;
+            }
+        },
+        _ => {
+            conn.write_error(ClientError::Syntax);
+            return;
+        }
+    };
+
+    // Like Redis, there's no reply on success; the connection is closed
+    // when the server exits. This command counts as in flight too, but the
+    // draining runs on its own thread, so it's done as soon as it returns.
+    if !shutdown::begin(save) {
+        conn.write_error(ClientError::ShuttingDown);
+    }
 }
@@ -0,0 +1,217 @@
+use anyhow::Result;
+
+use crate::{
+    connection::{ClientError, Connection},
+    database::{DatabaseError, DatabaseOperations},
+};
+
+#[tracing::instrument(skip_all)]
+pub fn sadd(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.add_set_members(&args[1], args[2..].to_vec()) {
+        Ok(added) => Ok(conn.write_integer(added)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn srem(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.remove_set_members(&args[1], args[2..].to_vec()) {
+        Ok(removed) => Ok(conn.write_integer(removed)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn smembers(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.get_set_members(&args[1]) {
+        Ok(members) => {
+            conn.write_set(members.len());
+            for member in members {
+                conn.write_bulk(&member);
+            }
+            Ok(())
+        }
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn sismember(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.is_set_member(&args[1], &args[2]) {
+        Ok(is_member) => Ok(conn.write_integer(is_member as i64)),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{connection::MockConnection, database::MockDatabaseOperations};
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_sadd() {
+        let key = "key";
+        let members: Vec<Vec<u8>> = vec!["a".into(), "b".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_add_set_members()
+            .with(eq(key.as_bytes()), eq(members))
+            .times(1)
+            .returning(|_, _| Ok(1));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SADD".into(), key.into(), "a".into(), "b".into()];
+        let _ = sadd(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_sadd_wrong_type() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db.expect_add_set_members().times(1).returning(|_, _| {
+            Err(DatabaseError::WrongType {
+                expected: "set".into(),
+            })
+        });
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::WrongType))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SADD".into(), "key".into(), "a".into()];
+        let _ = sadd(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_srem() {
+        let key = "key";
+        let members: Vec<Vec<u8>> = vec!["a".into(), "z".into()];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_remove_set_members()
+            .with(eq(key.as_bytes()), eq(members))
+            .times(1)
+            .returning(|_, _| Ok(1));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SREM".into(), key.into(), "a".into(), "z".into()];
+        let _ = srem(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_smembers() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_set_members()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(vec![b"a".to_vec(), b"b".to_vec()]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_set()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        for member in ["a", "b"] {
+            mock_conn
+                .expect_write_bulk()
+                .with(eq(member.as_bytes()))
+                .times(1)
+                .return_const(());
+        }
+
+        let args: Vec<Vec<u8>> = vec!["SMEMBERS".into(), key.into()];
+        let _ = smembers(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_sismember() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_is_set_member()
+            .with(eq(key.as_bytes()), eq(b"a".as_slice()))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SISMEMBER".into(), key.into(), "a".into()];
+        let _ = sismember(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+}
@@ -1,14 +1,22 @@
 use std::{cmp, time::Duration};
 
 use anyhow::Result;
+use itertools::Itertools;
 use tracing::debug;
 
 use crate::{
-    connection::{ClientError, Connection},
-    database::{DatabaseError, DatabaseOperations},
+    commands::{integer_reply, parse_float, parse_int, positive_time, Options},
+    connection::{write_bulk_segmented, ClientError, Connection},
+    database::{
+        format_float, DatabaseError, DatabaseOperations, SetCondition, SetExpiry, SetOptions,
+    },
+    deadline,
     indexing::adjust_indices,
 };
 
+// Same as Redis' proto-max-bulk-len default
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
 #[tracing::instrument(skip_all)]
 pub fn append(
     conn: &mut dyn Connection,
@@ -22,20 +30,8 @@ pub fn append(
 
     let key = &args[1];
     let value = &args[2];
-    match db.get_string(key) {
-        Ok(existing_value) => match existing_value {
-            Some(ev) => {
-                debug!("Retrieved value {:?}", String::from_utf8_lossy(&ev));
-                let new_value = [ev, value.to_vec()].concat();
-                db.put_string(key, &new_value)?;
-                Ok(conn.write_integer(new_value.len().try_into().unwrap()))
-            }
-            None => {
-                debug!("Value does not exist, creating");
-                db.put_string(key, value)?;
-                Ok(conn.write_integer(value.len().try_into().unwrap()))
-            }
-        },
+    match db.append(key, value) {
+        Ok(length) => Ok(conn.write_integer(integer_reply(length))),
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
         }
@@ -54,37 +50,91 @@ pub fn set(
         return Ok(());
     }
 
-    let mut options: Vec<String> = vec![];
-    for arg in args[3..].iter() {
-        options.push(String::from_utf8_lossy(&arg).into_owned().to_uppercase());
-    }
-
-    let get = options.contains(&"GET".to_string());
+    let set_options = parse_set_options(&args[3..])?;
 
     let key = &args[1];
     let value = &args[2];
-    if get {
-        match db.get_string(key) {
-            Ok(existing_value) => match existing_value {
-                Some(ev) => {
-                    debug!("Retrieved value {:?}", String::from_utf8_lossy(&ev));
-                    db.put_string(key, value)?;
-                    Ok(conn.write_bulk(&ev))
-                }
-                None => {
-                    debug!("Value does not exist");
-                    Ok(conn.write_null())
-                }
-            },
+    // The common forms are blind writes, which skip the transaction
+    let stored = match set_options {
+        SetOptions {
+            condition: SetCondition::Always,
+            expiry: SetExpiry::Clear,
+            get: false,
+        } => {
+            db.put_string(key, value)?;
+            true
+        }
+        SetOptions {
+            condition: SetCondition::Always,
+            expiry: SetExpiry::In(expires_in),
+            get: false,
+        } => {
+            db.put_string_with_expiry(key, value, expires_in)?;
+            true
+        }
+        SetOptions {
+            condition: SetCondition::IfAbsent,
+            expiry: SetExpiry::Clear,
+            get: false,
+        } => db.put_strings_if_absent(vec![(key.to_vec(), value.to_vec())])?,
+        _ => match db.set_string(key, value, set_options) {
+            Ok((_, existing)) if { set_options.get } => {
+                return Ok(match existing {
+                    Some(existing) => conn.write_bulk(&existing),
+                    None => conn.write_null(),
+                });
+            }
+            Ok((stored, _)) => stored,
             Err(DatabaseError::WrongType { expected: _ }) => {
-                Ok(conn.write_error(ClientError::WrongType))
+                return Ok(conn.write_error(ClientError::WrongType));
+            }
+            Err(err) => return Err(err.into()),
+        },
+    };
+
+    match stored {
+        true => Ok(conn.write_string("OK")),
+        false => Ok(conn.write_null()),
+    }
+}
+
+// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
+//   EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]
+fn parse_set_options(args: &[Vec<u8>]) -> Result<SetOptions, ClientError> {
+    let mut set_options = SetOptions::default();
+    let mut options = Options::new(args);
+    while let Some(option) = options.next_keyword() {
+        let expiry = match option.as_str() {
+            "NX" | "XX" if { set_options.condition != SetCondition::Always } => {
+                return Err(ClientError::Syntax);
+            }
+            "NX" => {
+                set_options.condition = SetCondition::IfAbsent;
+                continue;
+            }
+            "XX" => {
+                set_options.condition = SetCondition::IfPresent;
+                continue;
+            }
+            "GET" => {
+                set_options.get = true;
+                continue;
             }
-            Err(err) => Err(err.into()),
+            "KEEPTTL" => SetExpiry::Keep,
+            "EX" => SetExpiry::In(Duration::from_secs(positive_time(options.int()?)?)),
+            "PX" => SetExpiry::In(Duration::from_millis(positive_time(options.int()?)?)),
+            "EXAT" => SetExpiry::At(Duration::from_secs(positive_time(options.int()?)?)),
+            "PXAT" => SetExpiry::At(Duration::from_millis(positive_time(options.int()?)?)),
+            _ => return Err(ClientError::Syntax),
+        };
+
+        // Only one of the expiry options can be given
+        if set_options.expiry != SetExpiry::Clear {
+            return Err(ClientError::Syntax);
         }
-    } else {
-        db.put_string(key, value)?;
-        Ok(conn.write_string("OK"))
+        set_options.expiry = expiry;
     }
+    Ok(set_options)
 }
 
 #[tracing::instrument(skip_all)]
@@ -99,11 +149,31 @@ pub fn setex(
     }
 
     let key = &args[1];
-    let secs = String::from_utf8_lossy(&args[2]).parse::<u64>()?;
+    let secs = parse_int::<u64>(&args[2])?;
     let expires_in = Duration::from_secs(secs);
 
-    db.put_string(key, &args[3])?;
-    db.put_expiry(key, expires_in)?;
+    db.put_string_with_expiry(key, &args[3], expires_in)?;
+
+    conn.write_string("OK");
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn psetex(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let key = &args[1];
+    let ms = parse_int::<u64>(&args[2])?;
+    let expires_in = Duration::from_millis(ms);
+
+    db.put_string_with_expiry(key, &args[3], expires_in)?;
 
     conn.write_string("OK");
     Ok(())
@@ -122,20 +192,31 @@ pub fn setnx(
 
     let key = &args[1];
     let value = &args[2];
-    match db.get_string(key) {
-        Ok(existing_value) => match existing_value {
-            Some(_) => {
-                db.put_string(key, value)?;
-                Ok(conn.write_integer(1))
-            }
-            None => {
-                debug!("Value does not exist");
-                Ok(conn.write_integer(0))
-            }
-        },
-        Err(DatabaseError::WrongType { expected: _ }) => {
-            Ok(conn.write_error(ClientError::WrongType))
-        }
+    let set = db.put_strings_if_absent(vec![(key.to_vec(), value.to_vec())])?;
+    Ok(conn.write_integer(set.into()))
+}
+
+#[tracing::instrument(skip_all)]
+pub fn msetnx(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // Must have at least 3 args to declare "MSETNX key value", and
+    // increments of 2 more for additional key/value pairs
+    if args.len() < 3 || args.len() % 2 != 1 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = args[1..]
+        .into_iter()
+        .map(|x| x.clone())
+        .tuples::<(_, _)>()
+        .collect();
+    match db.put_strings_if_absent(entries) {
+        Ok(set) => Ok(conn.write_integer(set.into())),
+        Err(DatabaseError::CrossShard) => Ok(conn.write_error(ClientError::CrossSlot)),
         Err(err) => Err(err.into()),
     }
 }
@@ -151,10 +232,8 @@ pub fn strlen(
         return Ok(());
     }
 
-    match db.get_string(&args[1]) {
-        Ok(value) => value
-            .map_or(Ok(0), |v| Ok(v.len()))
-            .and_then(|n| Ok(conn.write_integer(n.try_into().unwrap()))),
+    match db.get_string_length(&args[1]) {
+        Ok(n) => Ok(conn.write_integer(integer_reply(n))),
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
         }
@@ -175,8 +254,11 @@ pub fn setrange(
 
     let key = &args[1];
     let value = &args[3];
-    let offset = String::from_utf8_lossy(&args[2]).parse::<usize>()?;
-    let end = offset + value.len();
+    let offset = parse_int::<usize>(&args[2])?;
+    let end = match offset.checked_add(value.len()) {
+        Some(end) if { end <= MAX_STRING_LEN } => end,
+        _ => return Ok(conn.write_error(ClientError::StringTooLong)),
+    };
 
     match db.get_string(key) {
         Ok(existing_value) => {
@@ -185,11 +267,11 @@ pub fn setrange(
             let result_len = cmp::max(existing_value.len(), end);
             let mut result_value: Vec<u8> = vec![0; result_len];
             result_value[..existing_value.len()].copy_from_slice(&existing_value.as_slice());
-            result_value[offset..(offset + value.len())].copy_from_slice(&value);
+            result_value[offset..end].copy_from_slice(&value);
 
-            db.put_string(key, &result_value)?;
+            db.update_string(key, &result_value)?;
 
-            Ok(conn.write_integer(result_len.try_into().unwrap()))
+            Ok(conn.write_integer(integer_reply(result_len)))
         }
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
@@ -219,27 +301,27 @@ pub fn substr(
     }
 
     let key = &args[1];
-    let start = String::from_utf8_lossy(&args[2]).parse::<i64>()?;
-    let end = String::from_utf8_lossy(&args[3]).parse::<i64>()?;
+    let start = parse_int::<i64>(&args[2])?;
+    let end = parse_int::<i64>(&args[3])?;
 
-    match db.get_string(key) {
-        Ok(value) => match value {
-            Some(val) => {
-                if val.len() == 0 {
-                    return Ok(conn.write_bulk("".as_bytes()));
-                }
-
-                let (start, end) = adjust_indices(val.len() - 1, start, end);
-                if start < end {
-                    let result = &val[start..=end];
-                    debug!("Returning value {}", String::from_utf8_lossy(&result));
-                    Ok(conn.write_bulk(&result))
-                } else {
-                    Ok(conn.write_bulk("".as_bytes()))
-                }
-            }
-            None => Ok(conn.write_bulk("".as_bytes())),
-        },
+    // Only the range is written, straight from where the value is stored
+    let found = db.read_string(key, &mut |val| {
+        if val.len() == 0 {
+            return conn.write_bulk("".as_bytes());
+        }
+
+        let (start, end) = adjust_indices(val.len() - 1, start, end);
+        if start < end {
+            let result = &val[start..=end];
+            debug!("Returning value {}", String::from_utf8_lossy(&result));
+            write_bulk_segmented(conn, result)
+        } else {
+            conn.write_bulk("".as_bytes())
+        }
+    });
+    match found {
+        Ok(true) => Ok(()),
+        Ok(false) => Ok(conn.write_bulk("".as_bytes())),
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
         }
@@ -258,17 +340,15 @@ pub fn get(
         return Ok(());
     }
 
-    match db.get_string(&args[1]) {
-        Ok(value) => match value {
-            Some(val) => {
-                debug!("Retrieved value {:?}", String::from_utf8_lossy(&val));
-                Ok(conn.write_bulk(&val))
-            }
-            None => {
-                debug!("Value does not exist");
-                Ok(conn.write_null())
-            }
-        },
+    // Large values are written out in segments as they're read, rather than
+    // being copied out of storage first
+    let found = db.read_string(&args[1], &mut |value| write_bulk_segmented(conn, value));
+    match found {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            debug!("Value does not exist");
+            Ok(conn.write_null())
+        }
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
         }
@@ -287,11 +367,16 @@ pub fn mget(
         return Ok(());
     }
 
-    let n_results = args.len() - 1;
-    conn.write_array(n_results);
-
+    // Look up every key before replying, so the command can still be
+    // aborted if it runs past its deadline
+    let mut values = vec![];
     for key in args[1..].iter() {
-        let value = db.get_string(key).ok().flatten();
+        deadline::check()?;
+        values.push(db.get_string(key).ok().flatten());
+    }
+
+    conn.write_array(values.len());
+    for value in values {
         match value {
             Some(v) => conn.write_bulk(&v),
             None => conn.write_null(),
@@ -314,11 +399,10 @@ pub fn getset(
 
     let key = &args[1];
     let new_value = &args[2];
-    match db.get_string(key) {
+    match db.get_and_put_string(key, new_value) {
         Ok(value) => match value {
             Some(val) => {
                 debug!("Retrieved value {:?}", String::from_utf8_lossy(&val));
-                db.put_string(key, &new_value)?;
                 Ok(conn.write_bulk(&val))
             }
             None => {
@@ -326,7 +410,9 @@ pub fn getset(
                 Ok(conn.write_null())
             }
         },
-        Err(DatabaseError::WrongType { expected: _ }) => Ok(conn.write_null()),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
         Err(err) => Err(err.into()),
     }
 }
@@ -343,11 +429,10 @@ pub fn getdel(
     }
 
     let key = &args[1];
-    match db.get_string(key) {
+    match db.get_and_delete_string(key) {
         Ok(value) => match value {
             Some(val) => {
                 debug!("Retrieved value {:?}", String::from_utf8_lossy(&val));
-                db.delete(key)?;
                 Ok(conn.write_bulk(&val))
             }
             None => {
@@ -355,7 +440,9 @@ pub fn getdel(
                 Ok(conn.write_null())
             }
         },
-        Err(DatabaseError::WrongType { expected: _ }) => Ok(conn.write_null()),
+        Err(DatabaseError::WrongType { expected: _ }) => {
+            Ok(conn.write_error(ClientError::WrongType))
+        }
         Err(err) => Err(err.into()),
     }
 }
@@ -391,9 +478,7 @@ pub fn incrby(
         return Ok(());
     }
 
-    let amount = String::from_utf8_lossy(&args[2])
-        .into_owned()
-        .parse::<i64>()?;
+    let amount = parse_int::<i64>(&args[2])?;
     match db.increment_by(&args[1], amount) {
         Ok(value) => Ok(conn.write_integer(value)),
         Err(DatabaseError::WrongType { expected: _ }) => {
@@ -414,11 +499,9 @@ pub fn incrbyfloat(
         return Ok(());
     }
 
-    let amount = String::from_utf8_lossy(&args[2])
-        .into_owned()
-        .parse::<f64>()?;
+    let amount = parse_float(&args[2])?;
     match db.increment_by_float(&args[1], amount) {
-        Ok(value) => Ok(conn.write_bulk(value.to_string().as_bytes())),
+        Ok(value) => Ok(conn.write_bulk(format_float(value).as_bytes())),
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
         }
@@ -457,10 +540,11 @@ pub fn decrby(
         return Ok(());
     }
 
-    let amount = String::from_utf8_lossy(&args[2])
-        .into_owned()
-        .parse::<i64>()?;
-    match db.increment_by(&args[1], -amount) {
+    let amount = match parse_int::<i64>(&args[2])?.checked_neg() {
+        Some(amount) => amount,
+        None => return Ok(conn.write_error(ClientError::DecrementOverflow)),
+    };
+    match db.increment_by(&args[1], amount) {
         Ok(value) => Ok(conn.write_integer(value)),
         Err(DatabaseError::WrongType { expected: _ }) => {
             Ok(conn.write_error(ClientError::WrongType))
@@ -471,11 +555,34 @@ pub fn decrby(
 
 #[cfg(test)]
 mod test {
-    use crate::{connection::MockConnection, database::MockDatabaseOperations};
+    use crate::{
+        connection::{MockConnection, BULK_SEGMENT_SIZE},
+        database::MockDatabaseOperations,
+    };
     use mockall::predicate::*;
 
     use super::*;
 
+    #[test]
+    fn test_append() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_append()
+            .with(eq("key".as_bytes()), eq(" world".as_bytes()))
+            .times(1)
+            .returning(|_, _| Ok(11));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(11))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["APPEND".into(), "key".into(), " world".into()];
+        append(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
     #[test]
     fn test_get() {
         let key = "key";
@@ -483,10 +590,13 @@ mod test {
 
         let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_get_string()
-            .with(eq(key.as_bytes()))
+            .expect_read_string()
+            .withf(move |k, _| k == key.as_bytes())
             .times(1)
-            .returning(|_| Ok(Some(value.into())));
+            .returning(move |_, f| {
+                f(value.as_bytes());
+                Ok(true)
+            });
 
         let mut mock_conn = MockConnection::new();
         mock_conn
@@ -500,17 +610,339 @@ mod test {
     }
 
     #[test]
-    fn test_strlen() {
+    fn test_get_large() {
+        let len = BULK_SEGMENT_SIZE * 2 + 1;
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_read_string()
+            .times(1)
+            .returning(move |_, f| {
+                f(&vec![b'x'; len]);
+                Ok(true)
+            });
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_begin_bulk()
+            .with(eq(len))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk_segment()
+            .times(3)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["GET".into(), "key".into()];
+        get(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_getset() {
+        let key = "key";
+        let old_value = "old";
+        let new_value = "new";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_and_put_string()
+            .with(eq(key.as_bytes()), eq(new_value.as_bytes()))
+            .times(1)
+            .returning(|_, _| Ok(Some(old_value.into())));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_bulk()
+            .with(eq(old_value.as_bytes()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["GETSET".into(), key.into(), new_value.into()];
+        let _ = getset(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_getdel() {
         let key = "key";
         let value = "value";
 
         let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_get_string()
+            .expect_get_and_delete_string()
             .with(eq(key.as_bytes()))
             .times(1)
             .returning(|_| Ok(Some(value.into())));
 
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_bulk()
+            .with(eq(value.as_bytes()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["GETDEL".into(), key.into()];
+        let _ = getdel(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_setex() {
+        let key = "key";
+        let value = "value";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_string_with_expiry()
+            .with(
+                eq(key.as_bytes()),
+                eq(value.as_bytes()),
+                eq(Duration::from_secs(10)),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SETEX".into(), key.into(), "10".into(), value.into()];
+        let _ = setex(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_psetex() {
+        let key = "key";
+        let value = "value";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_string_with_expiry()
+            .with(
+                eq(key.as_bytes()),
+                eq(value.as_bytes()),
+                eq(Duration::from_millis(1500)),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["PSETEX".into(), key.into(), "1500".into(), value.into()];
+        let _ = psetex(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_setnx() {
+        let key = "key";
+        let value = "value";
+        let entries = vec![(key.as_bytes().to_vec(), value.as_bytes().to_vec())];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_strings_if_absent()
+            .with(eq(entries))
+            .times(1)
+            .returning(|_| Ok(true));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SETNX".into(), key.into(), value.into()];
+        let _ = setnx(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_set_nx_existing() {
+        let key = "key";
+        let value = "value";
+        let entries = vec![(key.as_bytes().to_vec(), value.as_bytes().to_vec())];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_strings_if_absent()
+            .with(eq(entries))
+            .times(1)
+            .returning(|_| Ok(false));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn.expect_write_null().times(1).return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["SET".into(), key.into(), value.into(), "nx".into()];
+        let _ = set(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_set_nx_px() {
+        let key = "key";
+        let value = "value";
+        let options = SetOptions {
+            condition: SetCondition::IfAbsent,
+            expiry: SetExpiry::In(Duration::from_millis(30000)),
+            get: false,
+        };
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_set_string()
+            .with(eq(key.as_bytes()), eq(value.as_bytes()), eq(options))
+            .times(1)
+            .returning(|_, _, _| Ok((true, None)));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "SET".into(),
+            key.into(),
+            value.into(),
+            "NX".into(),
+            "px".into(),
+            "30000".into(),
+        ];
+        let _ = set(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_set_get_missing() {
+        let key = "key";
+        let value = "value";
+        let options = SetOptions {
+            get: true,
+            expiry: SetExpiry::Keep,
+            ..Default::default()
+        };
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_set_string()
+            .with(eq(key.as_bytes()), eq(value.as_bytes()), eq(options))
+            .times(1)
+            .returning(|_, _, _| Ok((true, None)));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn.expect_write_null().times(1).return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "SET".into(),
+            key.into(),
+            value.into(),
+            "KEEPTTL".into(),
+            "GET".into(),
+        ];
+        let _ = set(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_parse_set_options() {
+        let args = |line: &str| -> Vec<Vec<u8>> { line.split(' ').map(|arg| arg.into()).collect() };
+        assert_eq!(
+            SetOptions {
+                condition: SetCondition::IfPresent,
+                expiry: SetExpiry::At(Duration::from_secs(1700000000)),
+                get: false,
+            },
+            parse_set_options(&args("XX EXAT 1700000000")).unwrap()
+        );
+        assert!(matches!(
+            parse_set_options(&args("NX XX")),
+            Err(ClientError::Syntax)
+        ));
+        assert!(matches!(
+            parse_set_options(&args("EX 10 KEEPTTL")),
+            Err(ClientError::Syntax)
+        ));
+        assert!(matches!(
+            parse_set_options(&args("EX 0")),
+            Err(ClientError::InvalidExpireTime)
+        ));
+        assert!(matches!(
+            parse_set_options(&args("PX")),
+            Err(ClientError::Syntax)
+        ));
+    }
+
+    #[test]
+    fn test_msetnx() {
+        let entries = vec![
+            ("key1".as_bytes().to_vec(), "value1".as_bytes().to_vec()),
+            ("key2".as_bytes().to_vec(), "value2".as_bytes().to_vec()),
+        ];
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_strings_if_absent()
+            .with(eq(entries))
+            .times(1)
+            .returning(|_| Ok(false));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "MSETNX".into(),
+            "key1".into(),
+            "value1".into(),
+            "key2".into(),
+            "value2".into(),
+        ];
+        let _ = msetnx(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_msetnx_cross_shard() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_put_strings_if_absent()
+            .times(1)
+            .returning(|_| Err(DatabaseError::CrossShard));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::CrossSlot))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "MSETNX".into(),
+            "key1".into(),
+            "value1".into(),
+            "key2".into(),
+            "value2".into(),
+        ];
+        let _ = msetnx(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_strlen() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_string_length()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(5));
+
         let mut mock_conn = MockConnection::new();
         mock_conn
             .expect_write_integer()
@@ -538,7 +970,7 @@ mod test {
             .returning(|_| Ok(Some(initial_value.into())));
 
         mock_db
-            .expect_put_string()
+            .expect_update_string()
             .with(eq(key.as_bytes()), eq("verye".as_bytes()))
             .times(1)
             .returning(|_, _| Ok(()));
@@ -575,7 +1007,7 @@ mod test {
             .returning(|_| Ok(Some(initial_value.into())));
 
         mock_db
-            .expect_put_string()
+            .expect_update_string()
             .with(eq(key.as_bytes()), eq("value\0kept".as_bytes()))
             .times(1)
             .returning(|_, _| Ok(()));
@@ -611,7 +1043,7 @@ mod test {
             .returning(|_| Ok(None));
 
         mock_db
-            .expect_put_string()
+            .expect_update_string()
             .with(eq(key.as_bytes()), eq("\0\0\0\0\0\0kept".as_bytes()))
             .times(1)
             .returning(|_, _| Ok(()));
@@ -642,10 +1074,13 @@ mod test {
 
         let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_get_string()
-            .with(eq(key.as_bytes()))
+            .expect_read_string()
+            .withf(move |k, _| k == key.as_bytes())
             .times(1)
-            .returning(|_| Ok(Some(value.into())));
+            .returning(move |_, f| {
+                f(value.as_bytes());
+                Ok(true)
+            });
 
         let mut mock_conn = MockConnection::new();
         mock_conn
@@ -673,10 +1108,13 @@ mod test {
 
         let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_get_string()
-            .with(eq(key.as_bytes()))
+            .expect_read_string()
+            .withf(move |k, _| k == key.as_bytes())
             .times(1)
-            .returning(|_| Ok(Some(value.into())));
+            .returning(move |_, f| {
+                f(value.as_bytes());
+                Ok(true)
+            });
 
         let mut mock_conn = MockConnection::new();
         mock_conn
@@ -704,10 +1142,13 @@ mod test {
 
         let mut mock_db = MockDatabaseOperations::new();
         mock_db
-            .expect_get_string()
-            .with(eq(key.as_bytes()))
+            .expect_read_string()
+            .withf(move |k, _| k == key.as_bytes())
             .times(1)
-            .returning(|_| Ok(Some(value.into())));
+            .returning(move |_, f| {
+                f(value.as_bytes());
+                Ok(true)
+            });
 
         let mut mock_conn = MockConnection::new();
         mock_conn
@@ -837,4 +1278,58 @@ mod test {
         let args: Vec<Vec<u8>> = vec!["DECRBY".into(), key.into(), amount.to_string().into()];
         let _ = decrby(&mut mock_conn, &mock_db, &args).unwrap();
     }
+
+    #[test]
+    fn test_setrange_too_long() {
+        let key = "key";
+
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::StringTooLong))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "SETRANGE".into(),
+            key.into(),
+            usize::MAX.to_string().into(),
+            "value".into(),
+        ];
+        let _ = setrange(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_decrby_overflow() {
+        let key = "key";
+
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::DecrementOverflow))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["DECRBY".into(), key.into(), i64::MIN.to_string().into()];
+        let _ = decrby(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_incrby_not_integer() {
+        let key = "key";
+
+        let mock_db = MockDatabaseOperations::new();
+        let mut mock_conn = MockConnection::new();
+
+        let args: Vec<Vec<u8>> = vec!["INCRBY".into(), key.into(), "1.5".into()];
+        let err = incrby(&mut mock_conn, &mock_db, &args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::NotInteger)
+        ));
+    }
 }
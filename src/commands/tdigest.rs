@@ -0,0 +1,317 @@
+use anyhow::Result;
+
+use crate::{
+    commands::{is_keyword, write_module_error, Options},
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    sketches::tdigest::{TDigest, DEFAULT_COMPRESSION},
+};
+
+fn format_double(x: f64) -> String {
+    if x.is_nan() {
+        "nan".into()
+    } else {
+        x.to_string()
+    }
+}
+
+fn parse_doubles(args: &[Vec<u8>]) -> Option<Vec<f64>> {
+    args.iter()
+        .map(|arg| String::from_utf8_lossy(arg).parse::<f64>().ok())
+        .collect()
+}
+
+#[tracing::instrument(skip_all)]
+pub fn tdigest_create(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TDIGEST.CREATE key [COMPRESSION compression]
+    if args.len() != 2 && args.len() != 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let mut compression = DEFAULT_COMPRESSION;
+    if args.len() == 4 {
+        if !is_keyword(&args[2], "COMPRESSION") {
+            conn.write_error(ClientError::Syntax);
+            return Ok(());
+        }
+        compression = match String::from_utf8_lossy(&args[3]).parse::<f64>() {
+            Ok(x) => x,
+            Err(_) => {
+                conn.write_error(ClientError::Module(
+                    "ERR T-Digest: error parsing compression parameter".into(),
+                ));
+                return Ok(());
+            }
+        };
+    }
+
+    let digest = match TDigest::new(compression) {
+        Ok(digest) => digest,
+        Err(err) => {
+            conn.write_error(ClientError::Module(err.to_string()));
+            return Ok(());
+        }
+    };
+
+    match db.tdigest_create(&args[1], digest) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn tdigest_add(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let Some(values) = parse_doubles(&args[2..]) else {
+        conn.write_error(ClientError::Module(
+            "ERR T-Digest: error parsing val parameter".into(),
+        ));
+        return Ok(());
+    };
+
+    match db.tdigest_add(&args[1], values) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn tdigest_quantile(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let quantiles =
+        parse_doubles(&args[2..]).filter(|qs| qs.iter().all(|q| (0.0..=1.0).contains(q)));
+    let Some(quantiles) = quantiles else {
+        conn.write_error(ClientError::Module(
+            "ERR T-Digest: quantile should be in [0,1]".into(),
+        ));
+        return Ok(());
+    };
+
+    match db.tdigest_info(&args[1]) {
+        Ok(digest) => {
+            conn.write_array(quantiles.len());
+            for q in quantiles {
+                conn.write_bulk(format_double(digest.quantile(q)).as_bytes());
+            }
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn tdigest_cdf(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let Some(values) = parse_doubles(&args[2..]) else {
+        conn.write_error(ClientError::Module(
+            "ERR T-Digest: error parsing cdf".into(),
+        ));
+        return Ok(());
+    };
+
+    match db.tdigest_info(&args[1]) {
+        Ok(digest) => {
+            conn.write_array(values.len());
+            for value in values {
+                conn.write_bulk(format_double(digest.cdf(value)).as_bytes());
+            }
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn tdigest_merge(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TDIGEST.MERGE destination numkeys source [source ...]
+    //   [COMPRESSION compression] [OVERRIDE]
+    if args.len() < 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let n_keys = match String::from_utf8_lossy(&args[2]).parse::<usize>() {
+        Ok(n) if { n > 0 && 3 + n <= args.len() } => n,
+        _ => {
+            conn.write_error(ClientError::Module(
+                "ERR T-Digest: error parsing numkeys".into(),
+            ));
+            return Ok(());
+        }
+    };
+
+    let sources = args[3..3 + n_keys].to_vec();
+    let mut compression = None;
+    let mut override_dest = false;
+    let mut options = Options::new(&args[3 + n_keys..]);
+    while let Some(option) = options.next_keyword() {
+        match option.as_str() {
+            "OVERRIDE" => override_dest = true,
+            "COMPRESSION" => {
+                let value = match options.value() {
+                    Ok(value) => value,
+                    Err(err) => return Ok(conn.write_error(err)),
+                };
+                compression = match String::from_utf8_lossy(value).parse::<f64>() {
+                    Ok(x) => Some(x),
+                    Err(_) => {
+                        conn.write_error(ClientError::Module(
+                            "ERR T-Digest: error parsing compression parameter".into(),
+                        ));
+                        return Ok(());
+                    }
+                };
+            }
+            _ => {
+                conn.write_error(ClientError::Syntax);
+                return Ok(());
+            }
+        }
+    }
+
+    match db.tdigest_merge(&args[1], sources, compression, override_dest) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{connection::MockConnection, database::MockDatabaseOperations};
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_tdigest_add() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_tdigest_add()
+            .with(eq(key.as_bytes()), eq(vec![1.0, 2.5]))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TDIGEST.ADD".into(), key.into(), "1".into(), "2.5".into()];
+        let _ = tdigest_add(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_tdigest_quantile_empty() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_tdigest_info()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| Ok(TDigest::new(DEFAULT_COMPRESSION).unwrap()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("nan".as_bytes()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TDIGEST.QUANTILE".into(), key.into(), "0.5".into()];
+        let _ = tdigest_quantile(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_tdigest_quantile_out_of_range() {
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| err.to_string() == "ERR T-Digest: quantile should be in [0,1]")
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TDIGEST.QUANTILE".into(), "key".into(), "1.5".into()];
+        let _ = tdigest_quantile(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_tdigest_merge() {
+        let dest = "dest";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_tdigest_merge()
+            .with(
+                eq(dest.as_bytes()),
+                eq(vec![b"a".to_vec(), b"b".to_vec()]),
+                eq(Some(200.0)),
+                eq(true),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "TDIGEST.MERGE".into(),
+            dest.into(),
+            "2".into(),
+            "a".into(),
+            "b".into(),
+            "COMPRESSION".into(),
+            "200".into(),
+            "OVERRIDE".into(),
+        ];
+        let _ = tdigest_merge(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+}
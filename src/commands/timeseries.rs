@@ -0,0 +1,435 @@
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::{
+    commands::{integer_reply, is_keyword, write_module_error, Options},
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    deadline,
+    time::unix_timestamp,
+    timeseries::{aggregate, Aggregation, LabelFilter, TimeSeriesInfo},
+};
+
+// Options shared by TS.CREATE and TS.ADD: [RETENTION ms] [LABELS label value ...]
+fn parse_series_options(args: &[Vec<u8>]) -> Option<TimeSeriesInfo> {
+    let mut info = TimeSeriesInfo::default();
+    let mut options = Options::new(args);
+    while let Some(option) = options.next_keyword() {
+        match option.as_str() {
+            "RETENTION" => info.retention = options.int().ok()?,
+            // LABELS consumes the rest of the arguments
+            "LABELS" => {
+                let labels = options.rest();
+                if labels.len() % 2 != 0 {
+                    return None;
+                }
+                info.labels = labels
+                    .iter()
+                    .map(|x| String::from_utf8_lossy(x).to_string())
+                    .tuples()
+                    .collect();
+            }
+            _ => return None,
+        }
+    }
+
+    Some(info)
+}
+
+fn parse_range_bound(arg: &[u8]) -> Option<u64> {
+    match arg {
+        b"-" => Some(0),
+        b"+" => Some(u64::MAX),
+        _ => String::from_utf8_lossy(arg).parse().ok(),
+    }
+}
+
+// Parses [AGGREGATION aggregator bucketDuration] starting at args[0]
+fn parse_aggregation(args: &[Vec<u8>]) -> Option<(Aggregation, u64)> {
+    if args.len() != 3 || !is_keyword(&args[0], "AGGREGATION") {
+        return None;
+    }
+
+    let aggregation = Aggregation::parse(&String::from_utf8_lossy(&args[1]))?;
+    let bucket_duration = String::from_utf8_lossy(&args[2]).parse::<u64>().ok()?;
+    if bucket_duration == 0 {
+        return None;
+    }
+
+    Some((aggregation, bucket_duration))
+}
+
+fn write_samples(conn: &mut dyn Connection, samples: Vec<(u64, f64)>) {
+    conn.write_array(samples.len());
+    for (timestamp, value) in samples {
+        conn.write_array(2);
+        conn.write_integer(integer_reply(timestamp));
+        conn.write_bulk(value.to_string().as_bytes());
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ts_create(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TS.CREATE key [RETENTION retentionPeriod] [LABELS label value ...]
+    if args.len() < 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let Some(info) = parse_series_options(&args[2..]) else {
+        conn.write_error(ClientError::Syntax);
+        return Ok(());
+    };
+
+    match db.ts_create(&args[1], info) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ts_add(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TS.ADD key timestamp value [RETENTION retentionPeriod] [LABELS label value ...]
+    if args.len() < 4 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let timestamp: u64 = match args[2].as_slice() {
+        b"*" => unix_timestamp()?.as_millis().try_into()?,
+        arg => match String::from_utf8_lossy(arg).parse::<u64>() {
+            Ok(ts) => ts,
+            Err(_) => {
+                conn.write_error(ClientError::Module("ERR TSDB: invalid timestamp".into()));
+                return Ok(());
+            }
+        },
+    };
+    let value = match String::from_utf8_lossy(&args[3]).parse::<f64>() {
+        Ok(x) if { x.is_finite() } => x,
+        _ => {
+            conn.write_error(ClientError::Module("ERR TSDB: invalid value".into()));
+            return Ok(());
+        }
+    };
+
+    let Some(defaults) = parse_series_options(&args[4..]) else {
+        conn.write_error(ClientError::Syntax);
+        return Ok(());
+    };
+
+    match db.ts_add(&args[1], timestamp, value, defaults) {
+        Ok(_) => Ok(conn.write_integer(integer_reply(timestamp))),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ts_range(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TS.RANGE key fromTimestamp toTimestamp [AGGREGATION aggregator bucketDuration]
+    if args.len() != 4 && args.len() != 7 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let (Some(from), Some(to)) = (parse_range_bound(&args[2]), parse_range_bound(&args[3])) else {
+        conn.write_error(ClientError::Module("ERR TSDB: invalid timestamp".into()));
+        return Ok(());
+    };
+
+    let aggregation = parse_aggregation(&args[4..]);
+    if args.len() == 7 && aggregation.is_none() {
+        conn.write_error(ClientError::Syntax);
+        return Ok(());
+    }
+
+    match db.ts_range(&args[1], from, to) {
+        Ok(samples) => {
+            let samples = match aggregation {
+                Some((aggregation, bucket)) => aggregate(&samples, aggregation, bucket),
+                None => samples,
+            };
+            Ok(write_samples(conn, samples))
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ts_mrange(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TS.MRANGE fromTimestamp toTimestamp [WITHLABELS]
+    //   [AGGREGATION aggregator bucketDuration] FILTER filterExpr ...
+    if args.len() < 5 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let (Some(from), Some(to)) = (parse_range_bound(&args[1]), parse_range_bound(&args[2])) else {
+        conn.write_error(ClientError::Module("ERR TSDB: invalid timestamp".into()));
+        return Ok(());
+    };
+
+    // FILTER has to be followed by at least one filter
+    let filter_pos = args
+        .iter()
+        .position(|arg| is_keyword(arg, "FILTER"))
+        .filter(|pos| pos + 1 < args.len());
+    let Some(filter_pos) = filter_pos else {
+        conn.write_error(ClientError::Syntax);
+        return Ok(());
+    };
+
+    let filters: Option<Vec<LabelFilter>> = args[filter_pos + 1..]
+        .iter()
+        .map(|arg| LabelFilter::parse(&String::from_utf8_lossy(arg)))
+        .collect();
+    let Some(filters) = filters else {
+        conn.write_error(ClientError::Module(
+            "ERR TSDB: failed parsing labels".into(),
+        ));
+        return Ok(());
+    };
+
+    let mut options = &args[3..filter_pos];
+    let with_labels = options
+        .first()
+        .is_some_and(|arg| is_keyword(arg, "WITHLABELS"));
+    if with_labels {
+        options = &options[1..];
+    }
+    let aggregation = parse_aggregation(options);
+    if !options.is_empty() && aggregation.is_none() {
+        conn.write_error(ClientError::Syntax);
+        return Ok(());
+    }
+
+    let series = match db.ts_query_index(filters) {
+        Ok(series) => series,
+        Err(err) => return write_module_error(conn, err),
+    };
+
+    // Read every series before replying, so the command can still be
+    // aborted if it runs past its deadline
+    let mut results = vec![];
+    for (key, info) in series {
+        deadline::check()?;
+        let samples = db.ts_range(&key, from, to)?;
+        let samples = match aggregation {
+            Some((aggregation, bucket)) => aggregate(&samples, aggregation, bucket),
+            None => samples,
+        };
+        results.push((key, info, samples));
+    }
+
+    conn.write_array(results.len());
+    for (key, info, samples) in results {
+        conn.write_array(3);
+        conn.write_bulk(&key);
+        if with_labels {
+            conn.write_array(info.labels.len());
+            for (label, value) in info.labels {
+                conn.write_array(2);
+                conn.write_bulk(label.as_bytes());
+                conn.write_bulk(value.as_bytes());
+            }
+        } else {
+            conn.write_array(0);
+        }
+        write_samples(conn, samples);
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn ts_info(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.ts_info(&args[1]) {
+        Ok(info) => {
+            conn.write_array(10);
+            conn.write_string("totalSamples");
+            conn.write_integer(integer_reply(info.total_samples));
+            conn.write_string("firstTimestamp");
+            conn.write_integer(integer_reply(info.first_timestamp));
+            conn.write_string("lastTimestamp");
+            conn.write_integer(integer_reply(info.last_timestamp));
+            conn.write_string("retentionTime");
+            conn.write_integer(integer_reply(info.retention));
+            conn.write_string("labels");
+            conn.write_array(info.labels.len());
+            for (label, value) in info.labels {
+                conn.write_array(2);
+                conn.write_bulk(label.as_bytes());
+                conn.write_bulk(value.as_bytes());
+            }
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{connection::MockConnection, database::MockDatabaseOperations};
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_ts_create() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_ts_create()
+            .with(
+                eq(key.as_bytes()),
+                eq(TimeSeriesInfo::new(
+                    60000,
+                    vec![("sensor".into(), "a".into())],
+                )),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "TS.CREATE".into(),
+            key.into(),
+            "RETENTION".into(),
+            "60000".into(),
+            "LABELS".into(),
+            "sensor".into(),
+            "a".into(),
+        ];
+        let _ = ts_create(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_ts_add() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_ts_add()
+            .with(
+                eq(key.as_bytes()),
+                eq(1000),
+                eq(1.5),
+                eq(TimeSeriesInfo::default()),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1000))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TS.ADD".into(), key.into(), "1000".into(), "1.5".into()];
+        let _ = ts_add(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_ts_range_aggregation() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_ts_range()
+            .with(eq(key.as_bytes()), eq(0), eq(u64::MAX))
+            .times(1)
+            .returning(|_, _, _| Ok(vec![(0, 1.0), (5, 3.0), (10, 5.0)]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(3)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(0))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(10))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("2".as_bytes()))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("5".as_bytes()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "TS.RANGE".into(),
+            key.into(),
+            "-".into(),
+            "+".into(),
+            "AGGREGATION".into(),
+            "avg".into(),
+            "10".into(),
+        ];
+        let _ = ts_range(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_ts_mrange_requires_filter() {
+        let mock_db = MockDatabaseOperations::new();
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_error()
+            .withf(|err| matches!(err, ClientError::Syntax))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec![
+            "TS.MRANGE".into(),
+            "-".into(),
+            "+".into(),
+            "WITHLABELS".into(),
+            "FILTER".into(),
+        ];
+        let _ = ts_mrange(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+}
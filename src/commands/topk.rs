@@ -0,0 +1,284 @@
+use anyhow::Result;
+
+use crate::{
+    commands::{integer_reply, is_keyword, write_module_error},
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    sketches::topk::{TopK, DEFAULT_DECAY, DEFAULT_DEPTH, DEFAULT_WIDTH},
+};
+
+#[tracing::instrument(skip_all)]
+pub fn topk_reserve(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TOPK.RESERVE key topk [width depth decay]
+    if args.len() != 3 && args.len() != 6 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let k = String::from_utf8_lossy(&args[2]).parse::<u32>();
+    let (width, depth, decay) = if args.len() == 6 {
+        (
+            String::from_utf8_lossy(&args[3]).parse::<u32>(),
+            String::from_utf8_lossy(&args[4]).parse::<u32>(),
+            String::from_utf8_lossy(&args[5]).parse::<f64>(),
+        )
+    } else {
+        (Ok(DEFAULT_WIDTH), Ok(DEFAULT_DEPTH), Ok(DEFAULT_DECAY))
+    };
+    if k.is_err() || width.is_err() || depth.is_err() || decay.is_err() {
+        conn.write_error(ClientError::Module("ERR invalid parameters".into()));
+        return Ok(());
+    }
+
+    let topk = match TopK::new(k.unwrap(), width.unwrap(), depth.unwrap(), decay.unwrap()) {
+        Ok(topk) => topk,
+        Err(err) => {
+            conn.write_error(ClientError::Module(err.to_string()));
+            return Ok(());
+        }
+    };
+
+    match db.topk_reserve(&args[1], topk) {
+        Ok(_) => Ok(conn.write_string("OK")),
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn topk_add(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.topk_add(&args[1], args[2..].to_vec()) {
+        Ok(expelled) => {
+            conn.write_array(expelled.len());
+            for item in expelled {
+                match item {
+                    Some(item) => conn.write_bulk(&item),
+                    None => conn.write_null(),
+                };
+            }
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn topk_query(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.topk_info(&args[1]) {
+        Ok(topk) => {
+            conn.write_array(args.len() - 2);
+            for item in args[2..].iter() {
+                conn.write_integer(topk.query(item).into());
+            }
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn topk_count(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() < 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.topk_info(&args[1]) {
+        Ok(topk) => {
+            conn.write_array(args.len() - 2);
+            for item in args[2..].iter() {
+                conn.write_integer(integer_reply(topk.count(item)));
+            }
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn topk_list(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    // TOPK.LIST key [WITHCOUNT]
+    if args.len() != 2 && args.len() != 3 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    let with_count = args.len() == 3;
+    if with_count && !is_keyword(&args[2], "WITHCOUNT") {
+        conn.write_error(ClientError::Syntax);
+        return Ok(());
+    }
+
+    match db.topk_info(&args[1]) {
+        Ok(topk) => {
+            let entries = topk.list();
+            conn.write_array(if with_count {
+                entries.len() * 2
+            } else {
+                entries.len()
+            });
+            for (item, count) in entries {
+                conn.write_bulk(&item);
+                if with_count {
+                    conn.write_integer(integer_reply(count));
+                }
+            }
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub fn topk_info(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if args.len() != 2 {
+        conn.write_error(ClientError::ArgCount);
+        return Ok(());
+    }
+
+    match db.topk_info(&args[1]) {
+        Ok(topk) => {
+            conn.write_array(8);
+            conn.write_string("k");
+            conn.write_integer(topk.k().into());
+            conn.write_string("width");
+            conn.write_integer(topk.width().into());
+            conn.write_string("depth");
+            conn.write_integer(topk.depth().into());
+            conn.write_string("decay");
+            conn.write_bulk(topk.decay().to_string().as_bytes());
+            Ok(())
+        }
+        Err(err) => write_module_error(conn, err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{connection::MockConnection, database::MockDatabaseOperations};
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_topk_reserve_defaults() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_topk_reserve()
+            .with(
+                eq(key.as_bytes()),
+                eq(TopK::new(10, DEFAULT_WIDTH, DEFAULT_DEPTH, DEFAULT_DECAY).unwrap()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_string()
+            .with(eq("OK"))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TOPK.RESERVE".into(), key.into(), "10".into()];
+        let _ = topk_reserve(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_topk_add() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_topk_add()
+            .with(eq(key.as_bytes()), eq(vec![b"a".to_vec(), b"b".to_vec()]))
+            .times(1)
+            .returning(|_, _| Ok(vec![None, Some(b"c".to_vec())]));
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        mock_conn.expect_write_null().times(1).return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("c".as_bytes()))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TOPK.ADD".into(), key.into(), "a".into(), "b".into()];
+        let _ = topk_add(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+
+    #[test]
+    fn test_topk_list_withcount() {
+        let key = "key";
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_topk_info()
+            .with(eq(key.as_bytes()))
+            .times(1)
+            .returning(|_| {
+                let mut topk = TopK::new(1, 8, 7, 0.9).unwrap();
+                topk.add(b"a");
+                Ok(topk)
+            });
+
+        let mut mock_conn = MockConnection::new();
+        mock_conn
+            .expect_write_array()
+            .with(eq(2))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_bulk()
+            .with(eq("a".as_bytes()))
+            .times(1)
+            .return_const(());
+        mock_conn
+            .expect_write_integer()
+            .with(eq(1))
+            .times(1)
+            .return_const(());
+
+        let args: Vec<Vec<u8>> = vec!["TOPK.LIST".into(), key.into(), "WITHCOUNT".into()];
+        let _ = topk_list(&mut mock_conn, &mock_db, &args).unwrap();
+    }
+}
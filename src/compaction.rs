@@ -0,0 +1,134 @@
+use std::{collections::HashSet, ffi::CStr, time::Duration};
+
+use rocksdb::{
+    compaction_filter::{CompactionFilter, Decision},
+    compaction_filter_factory::{CompactionFilterContext, CompactionFilterFactory},
+};
+
+use crate::{
+    keyformat::{self, Namespace, DEFAULT_DB},
+    time::{parse_timestamp, unix_timestamp},
+};
+
+// Compactions drop the records of keys that have expired, so their space is
+// reclaimed even if the sweeper hasn't reached them yet. A filter only sees
+// one record at a time, but compactions visit records in order, and TTL
+// records sort before every other namespace, so each compaction notes the
+// expired TTLs it comes across and drops the other records of those keys
+// later on.
+//
+// TTL records themselves are always kept. A compaction might not cover all
+// of a key's records, and dropping its TTL while some of its data survived
+// would bring the key back to life. The leftover TTL still hides the key,
+// and the sweeper deletes it along with anything the filter missed.
+
+// Keys tracked per compaction, past which a compaction just keeps
+// everything, so that a huge compaction can't use unbounded memory
+const MAX_TRACKED_KEYS: usize = 1 << 20;
+
+const COMPANIONS: [Namespace; 6] = [
+    Namespace::Data,
+    Namespace::Element,
+    Namespace::FieldTtl,
+    Namespace::Length,
+    Namespace::Sample,
+    Namespace::Type,
+];
+
+pub struct ExpiryFilter {
+    now: Duration,
+    expired: HashSet<Vec<u8>>,
+}
+
+impl ExpiryFilter {
+    pub fn new(now: Duration) -> Self {
+        Self {
+            now,
+            expired: HashSet::new(),
+        }
+    }
+}
+
+impl CompactionFilter for ExpiryFilter {
+    fn filter(&mut self, _level: u32, record_key: &[u8], value: &[u8]) -> Decision {
+        if let Some((key, _)) = keyformat::decode(Namespace::Ttl, DEFAULT_DB, record_key) {
+            if self.expired.len() < MAX_TRACKED_KEYS
+                && parse_timestamp(value).is_ok_and(|expires_at| expires_at <= self.now)
+            {
+                self.expired.insert(key.to_vec());
+            }
+            return Decision::Keep;
+        }
+
+        let key = COMPANIONS
+            .iter()
+            .find_map(|ns| keyformat::decode(*ns, DEFAULT_DB, record_key));
+        match key {
+            Some((key, _)) if { self.expired.contains(key) } => Decision::Remove,
+            _ => Decision::Keep,
+        }
+    }
+
+    fn name(&self) -> &CStr {
+        c"expiry"
+    }
+}
+
+pub struct ExpiryFilterFactory;
+
+impl CompactionFilterFactory for ExpiryFilterFactory {
+    type Filter = ExpiryFilter;
+
+    fn create(&mut self, _context: CompactionFilterContext) -> Self::Filter {
+        ExpiryFilter::new(unix_timestamp().unwrap_or_default())
+    }
+
+    fn name(&self) -> &CStr {
+        c"expiry"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::keyformat::encode;
+
+    use super::*;
+
+    #[test]
+    fn test_expiry_filter() {
+        let mut filter = ExpiryFilter::new(Duration::from_secs(1000));
+        let record = |ns, key| encode(ns, DEFAULT_DB, key);
+
+        assert!(matches!(
+            filter.filter(
+                0,
+                &record(Namespace::Ttl, b"old"),
+                &999_000u64.to_be_bytes()
+            ),
+            Decision::Keep
+        ));
+        assert!(matches!(
+            filter.filter(
+                0,
+                &record(Namespace::Ttl, b"new"),
+                &1_001_000u64.to_be_bytes()
+            ),
+            Decision::Keep
+        ));
+
+        for ns in COMPANIONS {
+            assert!(matches!(
+                filter.filter(0, &record(ns, b"old"), b""),
+                Decision::Remove
+            ));
+            assert!(matches!(
+                filter.filter(0, &record(ns, b"new"), b""),
+                Decision::Keep
+            ));
+        }
+        assert!(matches!(
+            filter.filter(0, &record(Namespace::Data, b"other"), b"x"),
+            Decision::Keep
+        ));
+    }
+}
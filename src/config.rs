@@ -0,0 +1,287 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{OnceLock, RwLock},
+    thread,
+};
+
+use anyhow::Result;
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+use thiserror::Error;
+use tracing::{error, info, level_filters::LevelFilter};
+
+use crate::{glob::glob_match, ipfilter};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("unknown configuration parameter '{0}'")]
+    UnknownParameter(String),
+    #[error("invalid value '{value}' for configuration parameter '{name}'")]
+    InvalidValue { name: String, value: String },
+    #[error("failed to read configuration file")]
+    Io(#[from] std::io::Error),
+}
+
+// Called with the new level whenever loglevel changes
+type LogLevelHook = Box<dyn Fn(LevelFilter) + Send + Sync>;
+
+pub struct Config {
+    values: BTreeMap<&'static str, String>,
+    log_level_hook: Option<LogLevelHook>,
+}
+
+const DEFAULTS: &[(&str, &str)] = &[
+    ("access-tracking", "yes"),
+    ("access-tracking-max-keys", "65536"),
+    ("access-tracking-save-seconds", "60"),
+    ("active-expire-hz", "10"),
+    ("admin-http", ""),
+    ("audit-log", ""),
+    ("audit-log-max-size", "104857600"),
+    ("audit-log-redact", "yes"),
+    ("check-on-startup", "no"),
+    ("command-timeout", "0"),
+    ("hotkeys-tracking", "no"),
+    ("hotkeys-window-seconds", "60"),
+    ("ip-allow", ""),
+    ("ip-deny", ""),
+    ("journal-file", ""),
+    ("loadmodule", ""),
+    ("loglevel", "debug"),
+    ("maxclients", "10000"),
+    ("notify-sink", ""),
+    ("notify-sink-batch-size", "128"),
+    ("notify-sink-queue-size", "4096"),
+    ("periodic-compaction-seconds", "0"),
+    ("proto-max-bulk-len", "536870912"),
+    ("proto-max-multibulk-len", "1048576"),
+    ("proxy-protocol", "no"),
+    ("ratelimit-bytes", "0"),
+    ("ratelimit-commands", "0"),
+    ("ratelimit-scope", "connection"),
+    ("requirepass", ""),
+    ("shards", "1"),
+    ("slowlog-log-slower-than", "10000"),
+    ("slowlog-max-len", "128"),
+    ("tls-auth-clients", "yes"),
+    ("tls-ca-cert-file", ""),
+    ("tls-cert-file", ""),
+    ("tls-key-file", ""),
+    ("tls-port", "0"),
+    ("value-cache-size", "0"),
+    ("wasm-plugin-fuel", "10000000"),
+    ("wasm-plugins", ""),
+];
+
+fn parse_log_level(value: &str) -> Option<LevelFilter> {
+    match value.to_lowercase().as_str() {
+        "debug" => Some(LevelFilter::TRACE),
+        "verbose" => Some(LevelFilter::DEBUG),
+        "notice" => Some(LevelFilter::INFO),
+        "warning" => Some(LevelFilter::WARN),
+        "nothing" => Some(LevelFilter::OFF),
+        _ => None,
+    }
+}
+
+fn validate(name: &str, value: &str) -> bool {
+    match name {
+        "loglevel" => parse_log_level(value).is_some(),
+        "access-tracking-save-seconds"
+        | "active-expire-hz"
+        | "audit-log-max-size"
+        | "hotkeys-window-seconds"
+        | "command-timeout"
+        | "periodic-compaction-seconds"
+        | "ratelimit-bytes"
+        | "ratelimit-commands"
+        | "slowlog-max-len"
+        | "value-cache-size"
+        | "wasm-plugin-fuel" => value.parse::<u64>().is_ok(),
+        "access-tracking" | "audit-log-redact" | "hotkeys-tracking" | "proxy-protocol" => {
+            value == "yes" || value == "no"
+        }
+        "check-on-startup" => ["no", "yes", "repair"].contains(&value),
+        "ratelimit-scope" => value == "connection" || value == "ip",
+        "tls-auth-clients" => ["no", "yes", "optional"].contains(&value),
+        "tls-port" => value.parse::<u16>().is_ok(),
+        "slowlog-log-slower-than" => value.parse::<i64>().is_ok(),
+        "access-tracking-max-keys"
+        | "maxclients"
+        | "notify-sink-batch-size"
+        | "notify-sink-queue-size"
+        | "proto-max-bulk-len"
+        | "proto-max-multibulk-len"
+        | "shards" => value.parse::<usize>().is_ok_and(|n| n > 0),
+        "ip-allow" | "ip-deny" => ipfilter::parse_rules(value).is_ok(),
+        _ => true,
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            values: DEFAULTS
+                .iter()
+                .map(|(name, value)| (*name, value.to_string()))
+                .collect(),
+            log_level_hook: None,
+        }
+    }
+
+    pub fn on_log_level_change(&mut self, hook: LogLevelHook) {
+        self.log_level_hook = Some(hook);
+    }
+
+    pub fn get(&self, pattern: &str) -> Vec<(String, String)> {
+        let pattern = pattern.to_lowercase();
+        self.values
+            .iter()
+            .filter(|(name, _)| glob_match(pattern.as_bytes(), name.as_bytes()))
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|value| value.as_str())
+    }
+
+    fn find_parameter(&self, name: &str) -> Result<&'static str, ConfigError> {
+        let name = name.to_lowercase();
+        match self.values.keys().find(|k| **k == name) {
+            Some(key) => Ok(*key),
+            None => Err(ConfigError::UnknownParameter(name)),
+        }
+    }
+
+    pub fn check(&self, name: &str, value: &str) -> Result<(), ConfigError> {
+        let key = self.find_parameter(name)?;
+        if !validate(key, value) {
+            return Err(ConfigError::InvalidValue {
+                name: key.to_string(),
+                value: value.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ConfigError> {
+        self.check(name, value)?;
+
+        let key = self.find_parameter(name)?;
+        if key == "loglevel" {
+            if let (Some(hook), Some(level)) = (&self.log_level_hook, parse_log_level(value)) {
+                hook(level);
+            }
+        }
+
+        self.values.insert(key, value.to_string());
+        Ok(())
+    }
+
+    // Reads a redis.conf-style file ("name value" per line, # for comments)
+    // and applies every setting in it through the same path as CONFIG SET
+    pub fn load(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim().trim_matches('"');
+            if let Err(err) = self.set(name, value) {
+                error!("{}", err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn config() -> &'static RwLock<Config> {
+    static CONFIG: OnceLock<RwLock<Config>> = OnceLock::new();
+    CONFIG.get_or_init(|| RwLock::new(Config::new()))
+}
+
+pub fn handle_reload_signal(path: PathBuf) -> Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Received SIGHUP, reloading {}", path.display());
+            if let Err(err) = config().write().unwrap().load(&path) {
+                error!("Failed to reload configuration: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_config_get_pattern() {
+        let config = Config::new();
+        let result = config.get("max*");
+        assert_eq!(
+            vec![("maxclients".to_string(), "10000".to_string())],
+            result
+        );
+    }
+
+    #[test]
+    fn test_config_set_invalid() {
+        let mut config = Config::new();
+        assert!(config.set("slowlog-max-len", "soon").is_err());
+        assert!(config.set("unknown", "1").is_err());
+        assert_eq!("128", config.get("slowlog-max-len")[0].1);
+
+        // Parameters nothing reads aren't accepted either
+        for name in ["maxmemory", "save", "timeout"] {
+            assert!(config.set(name, "0").is_err());
+        }
+    }
+
+    #[test]
+    fn test_config_set_log_level_hook() {
+        let level = Arc::new(Mutex::new(None));
+        let hook_level = level.clone();
+
+        let mut config = Config::new();
+        config.on_log_level_change(Box::new(move |l| *hook_level.lock().unwrap() = Some(l)));
+        config.set("LogLevel", "warning").unwrap();
+
+        assert_eq!(Some(LevelFilter::WARN), *level.lock().unwrap());
+    }
+
+    #[test]
+    fn test_config_load() {
+        let path = std::env::temp_dir().join(format!("wedis-test-{}.conf", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\nslowlog-max-len 30\n\nip-allow \"\"\nbogus 1\n",
+        )
+        .unwrap();
+
+        let mut config = Config::new();
+        config.load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!("30", config.get("slowlog-max-len")[0].1);
+        assert_eq!("", config.get("ip-allow")[0].1);
+    }
+}
@@ -1,11 +1,24 @@
-use std::any::Any;
+use std::{any::Any, net::SocketAddr};
 
 use redcon::Conn;
 use thiserror::Error;
 
+use crate::{
+    bigkeys::BigKeysError,
+    diagnostics,
+    keyspec::KeySpecError,
+    protocol::ProtocolError,
+    proxy::ProxyError,
+    ratelimit::Limiter,
+    resp::{self, Value},
+};
+
 #[cfg(test)]
 use mockall::automock;
 
+// Bulk strings larger than this are written in segments of this size
+pub const BULK_SEGMENT_SIZE: usize = 64 * 1024;
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("ERR no context")]
@@ -16,40 +29,140 @@ pub enum ClientError {
     UnknownAttribute,
     #[error("ERR wrong number of arguments for command")]
     ArgCount,
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR {0}")]
+    Internal(String),
+    #[error("ERR Unsupported option {0}")]
+    UnsupportedOption(String),
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+    #[error("ERR value is out of range, must be positive")]
+    NotPositive,
+    #[error("ERR index out of range")]
+    IndexOutOfRange,
+    #[error("ERR no such key")]
+    NoSuchKey,
+    #[error("ERR string exceeds maximum allowed size")]
+    StringTooLong,
+    #[error("ERR decrement would overflow")]
+    DecrementOverflow,
+    #[error("ERR increment or decrement would overflow")]
+    IncrementOverflow,
+    #[error("ERR increment would produce NaN or Infinity")]
+    NotFinite,
     #[error("bit offset is not an integer or out of range")]
     BitOffset,
+    #[error("ERR The bit argument must be 1 or 0.")]
+    BitArgument,
+    #[error("ERR invalid cursor")]
+    InvalidCursor,
+    #[error("ERR DB index is out of range")]
+    DbIndexOutOfRange,
+    #[error("ERR source and destination objects are the same")]
+    SameObject,
+    #[error("ERR invalid expire time")]
+    InvalidExpireTime,
+    #[error("ERR timeout is not a float or out of range")]
+    InvalidTimeout,
+    #[error("ERR timeout is negative")]
+    NegativeTimeout,
+    #[error("ERR access tracking is disabled, set access-tracking to yes")]
+    AccessTrackingOff,
     #[error("NX and XX, GT or LT options at the same time are not compatible")]
     ExpireNxOptions,
+    #[error("ERR Mandatory argument FIELDS is missing or not at the right position")]
+    FieldsMissing,
+    #[error("ERR Parameter `numFields` should be greater than 0")]
+    NumFieldsZero,
+    #[error("ERR The `numfields` parameter must match the number of arguments")]
+    NumFieldsMismatch,
     #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
     WrongType,
+    #[error("ERR server is shutting down")]
+    ShuttingDown,
+    #[error("ERR CONFIG SET failed - {0}")]
+    ConfigSet(String),
+    #[error("{0}")]
+    Module(String),
+    #[error("ERR command timed out")]
+    Timeout,
+    #[error("ERR rate limit exceeded")]
+    RateLimited,
+    #[error("ERR max number of clients reached")]
+    MaxClients,
+    #[error("ERR No such client")]
+    NoSuchClient,
+    #[error("ERR CLIENT TRACKING needs RESP3, switch with HELLO 3")]
+    TrackingNeedsResp3,
+    #[error("ERR Protocol version is not an integer or out of range")]
+    InvalidProtocolVersion,
+    #[error("NOPROTO sorry, this protocol version is not supported")]
+    NoProto,
+    #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+    WrongPass,
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+    #[error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?")]
+    NoPasswordConfigured,
+    #[error("CROSSSLOT Keys in request don't hash to the same slot")]
+    CrossSlot,
+    #[error(transparent)]
+    KeySpec(#[from] KeySpecError),
+    #[error(transparent)]
+    BigKeys(#[from] BigKeysError),
+    #[error(transparent)]
+    Proxy(#[from] ProxyError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
 }
 
 pub struct ConnectionContext {
     id: i64,
+    addr: SocketAddr,
     lib_name: String,
     lib_version: String,
     connection_name: Option<String>,
+    limiter: Limiter,
+    awaiting_proxy_header: bool,
+    protocol: u8,
+    no_evict: bool,
+    no_touch: bool,
+    authenticated: bool,
 }
 
 impl ConnectionContext {
-    pub fn new(id: i64) -> Self {
+    pub fn new(id: i64, addr: SocketAddr) -> Self {
         ConnectionContext {
             id,
+            addr,
             lib_name: "".to_string(),
             lib_version: "".to_string(),
             connection_name: None,
+            limiter: Limiter::default(),
+            awaiting_proxy_header: false,
+            protocol: 2,
+            no_evict: false,
+            no_touch: false,
+            authenticated: false,
         }
     }
 
+    // These are mirrored into the connection registry, for CLIENT LIST
     pub fn set_lib_name(&mut self, lib_name: &str) {
+        diagnostics::set_connection_lib_name(self.id, lib_name);
         self.lib_name = lib_name.to_owned()
     }
 
     pub fn set_lib_version(&mut self, lib_version: &str) {
+        diagnostics::set_connection_lib_version(self.id, lib_version);
         self.lib_version = lib_version.to_owned()
     }
 
     pub fn set_connection_name(&mut self, connection_name: &str) {
+        diagnostics::set_connection_name(self.id, connection_name);
         self.connection_name = Some(connection_name.to_owned())
     }
 
@@ -60,13 +173,103 @@ impl ConnectionContext {
     pub fn id(&self) -> i64 {
         self.id
     }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn expect_proxy_header(&mut self) {
+        self.awaiting_proxy_header = true;
+    }
+
+    pub fn awaiting_proxy_header(&self) -> bool {
+        self.awaiting_proxy_header
+    }
+
+    // Replaces the load balancer's address with the client's, as given by
+    // the PROXY header
+    pub fn set_proxied_addr(&mut self, addr: SocketAddr) {
+        self.addr = addr;
+        self.awaiting_proxy_header = false;
+    }
+
+    // The RESP version negotiated with HELLO
+    pub fn protocol(&self) -> u8 {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: u8) {
+        self.protocol = protocol
+    }
+
+    // Set with CLIENT NO-EVICT. Nothing is evicted for memory yet, so it's
+    // only reported.
+    pub fn no_evict(&self) -> bool {
+        self.no_evict
+    }
+
+    pub fn set_no_evict(&mut self, no_evict: bool) {
+        diagnostics::set_connection_no_evict(self.id, no_evict);
+        self.no_evict = no_evict
+    }
+
+    // Set with CLIENT NO-TOUCH, which keeps the connection's commands from
+    // counting as accesses to their keys
+    pub fn no_touch(&self) -> bool {
+        self.no_touch
+    }
+
+    pub fn set_no_touch(&mut self, no_touch: bool) {
+        diagnostics::set_connection_no_touch(self.id, no_touch);
+        self.no_touch = no_touch
+    }
+
+    // Set once AUTH or HELLO AUTH succeeds. Only checked while requirepass
+    // is set.
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    pub fn set_authenticated(&mut self) {
+        self.authenticated = true
+    }
+
+    pub fn limiter(&mut self) -> &mut Limiter {
+        &mut self.limiter
+    }
 }
 
-pub struct Client<'a>(&'a mut Conn);
+// Tracks whether an error was written, so callers can tell failed commands
+// apart from successful ones after the fact, and how many values were
+// written, so they can tell whether a command replied at all
+pub struct Client<'a> {
+    conn: &'a mut Conn,
+    errored: bool,
+    written: usize,
+    // Bytes still to come of a bulk string being written in segments
+    bulk_remaining: usize,
+}
 
 impl Client<'_> {
     pub fn new(conn: &mut Conn) -> Client {
-        Client(conn)
+        Client {
+            conn,
+            errored: false,
+            written: 0,
+            bulk_remaining: 0,
+        }
+    }
+
+    fn protocol(&mut self) -> u8 {
+        match self.context() {
+            Some(ctx) => {
+                let ctx = ctx
+                    .downcast_mut::<ConnectionContext>()
+                    .expect("context should be a ConnectionContext");
+                ctx.protocol()
+            }
+            None => 2,
+        }
     }
 }
 
@@ -74,16 +277,53 @@ impl Client<'_> {
 pub trait Connection {
     fn write_bulk(&mut self, msg: &[u8]);
 
+    // Starts a bulk string of len bytes, which is then written in segments
+    // that add up to len, so large values never have to be assembled into a
+    // single reply first
+    fn begin_bulk(&mut self, len: usize);
+
+    fn write_bulk_segment(&mut self, segment: &[u8]);
+
     fn write_array(&mut self, count: usize);
 
     fn write_string(&mut self, msg: &str);
 
     fn write_integer(&mut self, x: i64);
 
+    // The header of a map of count pairs, each written as a key and then a
+    // value. RESP2 clients get a flat array instead.
+    fn write_map(&mut self, count: usize);
+
+    // The other RESP3 types fall back the way Redis' do for RESP2 clients:
+    // sets become arrays, doubles, big numbers and verbatim strings become
+    // bulk strings, and booleans become 1 or 0
+    fn write_set(&mut self, count: usize);
+
+    fn write_double(&mut self, x: f64);
+
+    fn write_boolean(&mut self, b: bool);
+
+    fn write_big_number(&mut self, n: &str);
+
+    fn write_verbatim(&mut self, format: &str, text: &[u8]);
+
     fn write_error(&mut self, err: ClientError);
 
     fn write_null(&mut self);
 
+    // Attaches attributes to the reply written next. RESP2 has no way to
+    // send them, so they're dropped for RESP2 clients.
+    fn write_attribute(&mut self, attributes: Vec<(Vec<u8>, Value)>);
+
+    // Sends a push message ahead of the next reply. Like attributes, pushes
+    // need RESP3 and are dropped for RESP2 clients.
+    fn write_push(&mut self, items: Vec<Value>);
+
+    fn replied_with_error(&self) -> bool;
+
+    // How many values have been written, headers of arrays and maps included
+    fn written(&self) -> usize;
+
     fn context(&mut self) -> &mut Option<Box<dyn Any>>;
 
     fn connection_id(&mut self) -> i64;
@@ -91,31 +331,127 @@ pub trait Connection {
 
 impl Connection for Client<'_> {
     fn write_bulk(&mut self, msg: &[u8]) {
-        self.0.write_bulk(msg)
+        self.written += 1;
+        self.conn.write_bulk(msg)
+    }
+
+    fn begin_bulk(&mut self, len: usize) {
+        self.written += 1;
+        self.bulk_remaining = len;
+        self.conn.write_raw(format!("${}\r\n", len).as_bytes());
+        if len == 0 {
+            self.conn.write_raw(b"\r\n");
+        }
+    }
+
+    fn write_bulk_segment(&mut self, segment: &[u8]) {
+        debug_assert!(segment.len() <= self.bulk_remaining);
+        self.bulk_remaining = self.bulk_remaining.saturating_sub(segment.len());
+        self.conn.write_raw(segment);
+        if self.bulk_remaining == 0 {
+            self.conn.write_raw(b"\r\n");
+        }
     }
 
     fn write_array(&mut self, count: usize) {
-        self.0.write_array(count)
+        self.written += 1;
+        self.conn.write_array(count)
     }
 
     fn write_string(&mut self, msg: &str) {
-        self.0.write_string(msg)
+        self.written += 1;
+        self.conn.write_string(msg)
     }
 
     fn write_integer(&mut self, x: i64) {
-        self.0.write_integer(x)
+        self.written += 1;
+        self.conn.write_integer(x)
+    }
+
+    fn write_map(&mut self, count: usize) {
+        self.written += 1;
+        if self.protocol() < 3 {
+            return self.conn.write_array(count * 2);
+        }
+        self.conn.write_raw(format!("%{}\r\n", count).as_bytes())
+    }
+
+    fn write_set(&mut self, count: usize) {
+        self.written += 1;
+        if self.protocol() < 3 {
+            return self.conn.write_array(count);
+        }
+        self.conn.write_raw(format!("~{}\r\n", count).as_bytes())
+    }
+
+    fn write_double(&mut self, x: f64) {
+        self.written += 1;
+        let x = resp::format_double(x);
+        if self.protocol() < 3 {
+            return self.conn.write_bulk(x.as_bytes());
+        }
+        self.conn.write_raw(format!(",{}\r\n", x).as_bytes())
+    }
+
+    fn write_boolean(&mut self, b: bool) {
+        self.written += 1;
+        if self.protocol() < 3 {
+            return self.conn.write_integer(b.into());
+        }
+        self.conn.write_raw(if b { b"#t\r\n" } else { b"#f\r\n" })
+    }
+
+    fn write_big_number(&mut self, n: &str) {
+        self.written += 1;
+        if self.protocol() < 3 {
+            return self.conn.write_bulk(n.as_bytes());
+        }
+        self.conn.write_raw(format!("({}\r\n", n).as_bytes())
+    }
+
+    fn write_verbatim(&mut self, format: &str, text: &[u8]) {
+        self.written += 1;
+        if self.protocol() < 3 {
+            return self.conn.write_bulk(text);
+        }
+        self.conn.write_raw(&resp::encode_verbatim(format, text))
     }
 
     fn write_error(&mut self, err: ClientError) {
-        self.0.write_error(format!("{}", err).as_str())
+        self.written += 1;
+        self.errored = true;
+        self.conn.write_error(format!("{}", err).as_str())
     }
 
     fn write_null(&mut self) {
-        self.0.write_null()
+        self.written += 1;
+        self.conn.write_null()
+    }
+
+    fn write_attribute(&mut self, attributes: Vec<(Vec<u8>, Value)>) {
+        if self.protocol() < 3 {
+            return;
+        }
+        self.conn.write_raw(&resp::encode_attribute(&attributes))
+    }
+
+    fn write_push(&mut self, items: Vec<Value>) {
+        if self.protocol() < 3 {
+            return;
+        }
+        self.conn.write_raw(&resp::encode_push(&items))
+    }
+
+    fn replied_with_error(&self) -> bool {
+        self.errored
+    }
+
+    fn written(&self) -> usize {
+        self.written
     }
 
     fn context(&mut self) -> &mut Option<Box<dyn Any>> {
-        &mut self.0.context
+        &mut self.conn.context
     }
 
     fn connection_id(&mut self) -> i64 {
@@ -130,3 +466,15 @@ impl Connection for Client<'_> {
         }
     }
 }
+
+// Writes a bulk string in one piece if it's small, or in segments otherwise
+pub fn write_bulk_segmented(conn: &mut dyn Connection, value: &[u8]) {
+    if value.len() <= BULK_SEGMENT_SIZE {
+        return conn.write_bulk(value);
+    }
+
+    conn.begin_bulk(value.len());
+    for segment in value.chunks(BULK_SEGMENT_SIZE) {
+        conn.write_bulk_segment(segment);
+    }
+}
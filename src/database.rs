@@ -1,413 +1,5787 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use itertools::Itertools;
-use rocksdb::{Transaction, TransactionDB};
+use rocksdb::{
+    merge_operator::MergeOperands, BlockBasedOptions, Direction, IteratorMode, Options,
+    ReadOptions, SliceTransform, SnapshotWithThreadMode, Transaction, TransactionDB,
+    TransactionDBOptions, WriteBatchWithTransaction,
+};
+use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
+use tracing::debug;
 
 #[cfg(test)]
 use mockall::automock;
 
-use crate::time::{parse_timestamp, serialize_duration_as_timestamp, unix_timestamp, TimeError};
+use crate::{
+    cache::{Record, ValueCache},
+    compaction::ExpiryFilterFactory,
+    glob::glob_match,
+    keyformat::{self, Namespace, DEFAULT_DB},
+    keylocks::{KeyGuard, KeyLocks},
+    lists::{ListEnd, ListInfo},
+    sketches::{
+        bloom::BloomFilter, cms::CountMinSketch, hash64, tdigest::TDigest, topk::TopK, SketchError,
+    },
+    time::{
+        encode_timestamp, is_legacy_timestamp, parse_timestamp, serialize_duration_as_timestamp,
+        Clock, SystemClock, TimeError,
+    },
+    timeseries::{LabelFilter, TimeSeriesError, TimeSeriesInfo},
+};
 
-const TTL_KEY_PREFIX: &str = "T:";
-const TYPE_KEY_PREFIX: &str = "t:";
-const DATA_KEY_PREFIX: &str = "d:";
+// Written and removed again to check that storage accepts writes. It's
+// outside every namespace, so it never shows up as a key.
+const WRITE_PROBE_KEY: &str = "h:write-probe";
+
+// Number of records moved per write while migrating legacy keys
+const MIGRATION_BATCH_SIZE: usize = 1024;
+
+// The records a key has at most one of, unlike sub-records such as a time
+// series' samples
+const KEY_NAMESPACES: [Namespace; 5] = [
+    Namespace::Type,
+    Namespace::Data,
+    Namespace::Ttl,
+    Namespace::Length,
+    Namespace::FieldTtl,
+];
+
+// Fields handed over per call when reading a whole hash
+const HASH_CHUNK_SIZE: usize = 1024;
+
+const BLOOM_BITS_PER_KEY: f64 = 10.0;
+const MEMTABLE_PREFIX_BLOOM_RATIO: f64 = 0.1;
 
 const TYPE_STRING: &str = "S";
 const TYPE_HASH: &str = "H";
+const TYPE_BLOOM: &str = "B";
+const TYPE_CMS: &str = "C";
+const TYPE_TOPK: &str = "K";
+const TYPE_TDIGEST: &str = "D";
+const TYPE_TIMESERIES: &str = "X";
+const TYPE_LIST: &str = "L";
+const TYPE_SET: &str = "E";
+
+// Type IDs are compared case-insensitively, so they must be unique
+// regardless of case
+const TYPE_NAMES: &[(&str, &str)] = &[
+    (TYPE_STRING, "string"),
+    (TYPE_HASH, "hash"),
+    (TYPE_BLOOM, "MBbloom--"),
+    (TYPE_CMS, "CMSk-TYPE"),
+    (TYPE_TOPK, "TopK-TYPE"),
+    (TYPE_TDIGEST, "TDIS-TYPE"),
+    (TYPE_TIMESERIES, "TSDB-TYPE"),
+    (TYPE_LIST, "list"),
+    (TYPE_SET, "set"),
+];
+
+// Until SELECT is supported, every key lives in the default database
+pub(crate) fn encode_key(ns: Namespace, key: &[u8]) -> Vec<u8> {
+    keyformat::encode(ns, DEFAULT_DB, key)
+}
+
+// Time series samples are keyed by the series' record key followed by the
+// big-endian timestamp, so that one series' samples are contiguous and
+// ordered by time
+pub(crate) fn sample_key_prefix(key: &[u8]) -> Vec<u8> {
+    encode_key(Namespace::Sample, key)
+}
+
+fn sample_key(key: &[u8], timestamp: u64) -> Vec<u8> {
+    [sample_key_prefix(key).as_slice(), &timestamp.to_be_bytes()].concat()
+}
+
+// List elements are keyed the same way, by their big-endian sequence number
+pub(crate) fn element_key_prefix(key: &[u8]) -> Vec<u8> {
+    encode_key(Namespace::Element, key)
+}
+
+fn element_key(key: &[u8], seq: u64) -> Vec<u8> {
+    [element_key_prefix(key).as_slice(), &seq.to_be_bytes()].concat()
+}
+
+// Set members and hash fields share the namespace, keyed by the member or
+// field itself. Members have an empty value, and fields their value. No key
+// is more than one of these types at once, so they never mix.
+fn member_key(key: &[u8], member: &[u8]) -> Vec<u8> {
+    [element_key_prefix(key).as_slice(), member].concat()
+}
+
+fn parse_seq(prefix_len: usize, element_key: &[u8]) -> u64 {
+    u64::from_be_bytes(element_key[prefix_len..].try_into().unwrap())
+}
+
+// Where to start walking a list's elements from one end. Seeking back from
+// tail lands on the last element, since tail itself is never taken.
+fn list_seek(key: &[u8], info: &ListInfo, end: ListEnd) -> (Vec<u8>, Direction) {
+    match end {
+        ListEnd::Front => (element_key(key, info.head), Direction::Forward),
+        ListEnd::Back => (element_key(key, info.tail), Direction::Reverse),
+    }
+}
+
+// Hash fields' expiry times by field, as Unix milliseconds. Hashes keep
+// them in a record of their own, which only exists while some field has a
+// TTL. Fields can be any bytes, so it's stored as a list of pairs rather
+// than as a JSON object.
+type FieldTtls = HashMap<Vec<u8>, u64>;
+
+fn parse_field_ttls(value: &[u8]) -> Result<FieldTtls, serde_json::Error> {
+    let pairs: Vec<(Vec<u8>, u64)> = serde_json::from_slice(value)?;
+    Ok(pairs.into_iter().collect())
+}
+
+fn encode_field_ttls(ttls: &FieldTtls) -> Result<Vec<u8>, serde_json::Error> {
+    serde_json::to_vec(&ttls.iter().collect::<Vec<_>>())
+}
+
+// A hash as read, apart from its fields. Fields whose TTL has lapsed keep
+// their records until they're deleted, so they're still counted in len,
+// and skipped as they're read.
+struct HashMeta {
+    len: u64,
+    ttls: FieldTtls,
+    now: u64,
+}
+
+impl HashMeta {
+    fn is_lapsed(&self, field: &[u8]) -> bool {
+        self.ttls
+            .get(field)
+            .is_some_and(|expires_at| *expires_at <= self.now)
+    }
+
+    fn live_len(&self) -> u64 {
+        let lapsed = self
+            .ttls
+            .values()
+            .filter(|expires_at| **expires_at <= self.now)
+            .count();
+        self.len.saturating_sub(lapsed as u64)
+    }
+}
+
+fn expiry_index_key(key: &[u8], ttl_value: &[u8]) -> Result<Vec<u8>, TimeError> {
+    let expires_at = parse_timestamp(ttl_value)?.as_millis() as u64;
+    Ok(keyformat::encode_expiry(DEFAULT_DB, expires_at, key))
+}
+
+pub(crate) fn parse_sample(prefix_len: usize, sample_key: &[u8], value: &[u8]) -> (u64, f64) {
+    let timestamp = u64::from_be_bytes(sample_key[prefix_len..].try_into().unwrap());
+    let value = f64::from_be_bytes(value.try_into().unwrap());
+    (timestamp, value)
+}
+
+pub(crate) fn type_name(type_id: &[u8]) -> &'static str {
+    TYPE_NAMES
+        .iter()
+        .find(|(id, _)| type_id.eq_ignore_ascii_case(id.as_bytes()))
+        .map(|(_, name)| *name)
+        .unwrap_or("none")
+}
+
+fn type_id(type_name: &str) -> Option<&'static str> {
+    TYPE_NAMES
+        .iter()
+        .find(|(_, name)| *name == type_name)
+        .map(|(id, _)| *id)
+}
+
+// Like Redis Cluster, if a key contains a non-empty {...} section, only that
+// part is hashed, so that related keys can be kept on the same shard
+fn hash_tag(key: &[u8]) -> &[u8] {
+    if let Some(start) = key.iter().position(|b| *b == b'{') {
+        let rest = &key[start + 1..];
+        if let Some(end) = rest.iter().position(|b| *b == b'}') {
+            if end > 0 {
+                return &rest[..end];
+            }
+        }
+    }
+    key
+}
+
+pub(crate) fn shard_index(key: &[u8], n_shards: usize) -> usize {
+    if n_shards == 1 {
+        return 0;
+    }
+    (hash64(hash_tag(key), 0) % n_shards as u64) as usize
+}
+
+// A single shard lives directly in the data directory, while multiple
+// shards each get their own subdirectory
+pub fn shard_paths(root: &Path, n_shards: usize) -> Vec<PathBuf> {
+    if n_shards == 1 {
+        return vec![root.to_path_buf()];
+    }
+
+    (0..n_shards)
+        .map(|i| root.join(format!("shard-{}", i)))
+        .collect()
+}
+
+// APPEND merges its suffix into the stored value rather than rewriting it.
+// Merge operands are only ever appended suffixes, so they're simply joined.
+fn append_merge(_: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    let mut value = existing.map(|v| v.to_vec()).unwrap_or_default();
+    for operand in operands.iter() {
+        value.extend_from_slice(operand);
+    }
+    Some(value)
+}
+
+// String lengths are kept on record as big-endian u64s
+fn parse_length(record: &[u8]) -> usize {
+    u64::from_be_bytes(record.try_into().unwrap()) as usize
+}
+
+fn key_prefix(record_key: &[u8]) -> &[u8] {
+    keyformat::key_prefix(record_key).unwrap_or(record_key)
+}
+
+fn has_key_prefix(record_key: &[u8]) -> bool {
+    keyformat::key_prefix(record_key).is_some()
+}
+
+// Bloom filters cover whole record keys for point lookups, and each key's
+// prefix for seeks to its sub-records, like a time series' samples. Files
+// written before the prefix extractor was set just don't have the prefix
+// filters, so existing data directories keep working.
+//
+// Every shard has to be opened with these, including read-only, or values
+// built up by APPEND can't be read back.
+pub fn shard_options() -> Options {
+    let mut table_opts = BlockBasedOptions::default();
+    table_opts.set_bloom_filter(BLOOM_BITS_PER_KEY, false);
+    table_opts.set_whole_key_filtering(true);
+
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.set_merge_operator_associative("append", append_merge);
+    opts.set_compaction_filter_factory(ExpiryFilterFactory);
+    opts.set_block_based_table_factory(&table_opts);
+    opts.set_prefix_extractor(SliceTransform::create(
+        "wedis.key",
+        key_prefix,
+        Some(has_key_prefix),
+    ));
+    opts.set_memtable_prefix_bloom_ratio(MEMTABLE_PREFIX_BLOOM_RATIO);
+    opts
+}
+
+// With a prefix extractor set, iterators only see the records sharing the
+// prefix of where they started. Scans over a whole namespace go through
+// every key, so they need these options.
+pub(crate) fn total_order() -> ReadOptions {
+    let mut read_opts = ReadOptions::default();
+    read_opts.set_total_order_seek(true);
+    read_opts
+}
+
+pub fn open_shard(path: impl AsRef<Path>) -> Result<TransactionDB, rocksdb::Error> {
+    TransactionDB::open(&shard_options(), &TransactionDBOptions::default(), path)
+}
+
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("rocksdb error")]
+    RocksDB(#[from] rocksdb::Error),
+    #[error("serialization error")]
+    Serde(#[from] serde_json::Error),
+    #[error("integer parse error")]
+    ParseInt(#[from] std::num::ParseIntError),
+    #[error("float parse error")]
+    ParseFloat(#[from] std::num::ParseFloatError),
+    #[error("time error")]
+    InvalidTime(#[from] TimeError),
+    #[error("unexpected value type (expected {expected:?})")]
+    WrongType { expected: String },
+    #[error(transparent)]
+    Sketch(#[from] SketchError),
+    #[error(transparent)]
+    TimeSeries(#[from] TimeSeriesError),
+    #[error("keys in request don't belong to the same shard")]
+    CrossShard,
+    #[error("invalid or expired scan cursor")]
+    InvalidCursor,
+    #[error("value is not an integer or out of range")]
+    NotInteger,
+    #[error("increment or decrement would overflow")]
+    Overflow,
+    #[error("increment would produce NaN or Infinity")]
+    NotFinite,
+    #[error("index out of range")]
+    IndexOutOfRange,
+    #[error("no such key")]
+    NoSuchKey,
+}
+
+// RocksDB integer properties reported by INFO rocksdb
+const STORAGE_PROPERTIES: &[(&str, &str)] = &[
+    ("estimated_keys", "rocksdb.estimate-num-keys"),
+    ("total_sst_files_size", "rocksdb.total-sst-files-size"),
+    ("live_sst_files_size", "rocksdb.live-sst-files-size"),
+    ("block_cache_usage", "rocksdb.block-cache-usage"),
+    (
+        "block_cache_pinned_usage",
+        "rocksdb.block-cache-pinned-usage",
+    ),
+    ("memtable_size", "rocksdb.cur-size-all-mem-tables"),
+    ("table_readers_memory", "rocksdb.estimate-table-readers-mem"),
+    (
+        "pending_compaction_bytes",
+        "rocksdb.estimate-pending-compaction-bytes",
+    ),
+    ("running_compactions", "rocksdb.num-running-compactions"),
+    ("running_flushes", "rocksdb.num-running-flushes"),
+    ("delayed_write_rate", "rocksdb.actual-delayed-write-rate"),
+    ("write_stopped", "rocksdb.is-write-stopped"),
+];
+
+// Engine statistics, summed over all shards
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StorageStats {
+    pub properties: Vec<(&'static str, u64)>,
+    // (files, size in MB) for each level
+    pub levels: Vec<(u64, u64)>,
+    pub total_delays: u64,
+    pub total_stops: u64,
+}
+
+// Parses the table returned by rocksdb.levelstats:
+//
+//   Level Files Size(MB)
+//   --------------------
+//     0        3        12
+fn parse_level_stats(stats: &str) -> Vec<(u64, u64)> {
+    stats
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (_, files, size_mb) = line.split_whitespace().collect_tuple()?;
+            Some((files.parse().ok()?, size_mb.parse().ok()?))
+        })
+        .collect()
+}
+
+// Reads one counter out of rocksdb.cf-write-stall-stats, which looks like
+// "Write Stall (count): name: 0, other-name: 1, ..."
+fn parse_stall_count(stats: &str, name: &str) -> u64 {
+    stats
+        .split(|c| c == ',' || c == '\n')
+        .filter_map(|entry| entry.trim().rsplit_once(": "))
+        .find(|(key, _)| key.rsplit(' ').next() == Some(name))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inconsistency {
+    pub key: Vec<u8>,
+    pub problem: &'static str,
+}
+
+// A key's footprint, as reported by BIGKEYS
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySize {
+    pub key: Vec<u8>,
+    pub type_name: &'static str,
+    // Size of the stored value, not counting time series samples
+    pub bytes: u64,
+    // Bytes for strings, fields for hashes, samples for time series, and 1
+    // for the sketch types
+    pub elements: u64,
+}
+
+// Everything stored for one key, for backing up and restoring keys whole
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpedKey {
+    pub key: Vec<u8>,
+    pub type_name: String,
+    pub data: Vec<u8>,
+    // As a Unix time
+    pub expires_at: Option<Duration>,
+    // Only time series have samples
+    pub samples: Vec<(u64, f64)>,
+    // Only lists and sets have elements, a set's being its members
+    pub elements: Vec<Vec<u8>>,
+    // Only hashes have fields
+    pub fields: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+// When SET stores its value
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    IfAbsent,
+    IfPresent,
+}
+
+// What SET does with the key's TTL
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SetExpiry {
+    #[default]
+    Clear,
+    Keep,
+    In(Duration),
+    // As a Unix time
+    At(Duration),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SetOptions {
+    pub condition: SetCondition,
+    pub expiry: SetExpiry,
+    // Whether to return the value being replaced
+    pub get: bool,
+}
+
+// HEXPIRE's NX, XX, GT and LT options, where a field without a TTL counts
+// as never expiring
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ExpiryCondition {
+    #[default]
+    Always,
+    IfNone,
+    IfSome,
+    IfGreater,
+    IfLess,
+}
+
+// SCAN's MATCH and TYPE options
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScanFilter {
+    pub pattern: Option<Vec<u8>>,
+    // As TYPE names it, ignoring case
+    pub type_name: Option<String>,
+}
+
+impl ScanFilter {
+    fn matches(&self, key: &[u8], type_id: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, key))
+            && self
+                .type_name
+                .as_ref()
+                .is_none_or(|name| name.eq_ignore_ascii_case(type_name(type_id)))
+    }
+}
+
+// Strings only count as integers when they're exactly how Redis would have
+// written them, so "+1", "01" and " 1" don't, nor does anything that
+// doesn't fit in an i64
+fn parse_stored_integer(value: &[u8]) -> Option<i64> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    match digits {
+        [] => None,
+        [b'0'] => (value.len() == 1).then_some(0),
+        [b'0', ..] => None,
+        _ if { digits.iter().all(u8::is_ascii_digit) } => {
+            std::str::from_utf8(value).ok()?.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+// Floats are written the way INCRBYFLOAT leaves them in Redis, without an
+// exponent or trailing zeros. Display already writes the fewest digits that
+// read back as the same double, which is never more than 17 significant
+// digits, and never switches to exponent notation.
+pub fn format_float(x: f64) -> String {
+    if x == 0.0 {
+        // Rather than "-0"
+        return "0".to_string();
+    }
+    x.to_string()
+}
+
+fn decodes_as(type_id: &str, data: &[u8]) -> bool {
+    match type_id {
+        TYPE_STRING => true,
+        TYPE_HASH => serde_json::from_slice::<u64>(data).is_ok(),
+        TYPE_BLOOM => serde_json::from_slice::<BloomFilter>(data).is_ok(),
+        TYPE_CMS => serde_json::from_slice::<CountMinSketch>(data).is_ok(),
+        TYPE_TOPK => serde_json::from_slice::<TopK>(data).is_ok(),
+        TYPE_TDIGEST => serde_json::from_slice::<TDigest>(data).is_ok(),
+        TYPE_TIMESERIES => serde_json::from_slice::<TimeSeriesInfo>(data).is_ok(),
+        TYPE_LIST => serde_json::from_slice::<ListInfo>(data).is_ok(),
+        TYPE_SET => serde_json::from_slice::<u64>(data).is_ok(),
+        _ => false,
+    }
+}
+
+// Snapshots keep old versions of every key from being compacted away, so
+// cursors that haven't been used for a while are dropped, and only so many
+// can be open at once
+const SCAN_CURSOR_IDLE: Duration = Duration::from_secs(300);
+const MAX_SCAN_CURSORS: usize = 1024;
+
+// A snapshot that keeps its shard open for as long as it's held, so that it
+// can outlive the call that took it
+struct OwnedSnapshot {
+    // Declared ahead of the shard, so it's released first
+    snapshot: SnapshotWithThreadMode<'static, TransactionDB>,
+    _shard: Arc<TransactionDB>,
+}
+
+impl OwnedSnapshot {
+    fn new(shard: Arc<TransactionDB>) -> Self {
+        // SAFETY: the shard sits behind an Arc that's held right alongside
+        // the snapshot, so it doesn't move and stays open until after the
+        // snapshot is dropped. The snapshot is only ever lent out for as
+        // long as self is borrowed.
+        let db: &'static TransactionDB = unsafe { &*Arc::as_ptr(&shard) };
+        Self {
+            snapshot: db.snapshot(),
+            _shard: shard,
+        }
+    }
+
+    fn snapshot(&self) -> &SnapshotWithThreadMode<'_, TransactionDB> {
+        &self.snapshot
+    }
+}
+
+// A SCAN in progress. It reads from snapshots of every shard taken when it
+// started, so keys that exist for the whole scan are returned exactly once,
+// whatever gets written in between.
+struct ScanCursor {
+    snapshots: Vec<OwnedSnapshot>,
+    shard: usize,
+    after: Option<Vec<u8>>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct ScanCursors {
+    next_id: u64,
+    open: HashMap<u64, ScanCursor>,
+}
+
+impl ScanCursors {
+    fn expire(&mut self, max_idle: Duration) {
+        self.open
+            .retain(|_, cursor| cursor.last_used.elapsed() < max_idle);
+    }
+
+    fn take(&mut self, id: u64) -> Option<ScanCursor> {
+        self.expire(SCAN_CURSOR_IDLE);
+        self.open.remove(&id)
+    }
+
+    fn insert(&mut self, mut cursor: ScanCursor) -> u64 {
+        self.expire(SCAN_CURSOR_IDLE);
+        if self.open.len() >= MAX_SCAN_CURSORS {
+            let oldest = self
+                .open
+                .iter()
+                .min_by_key(|(_, cursor)| cursor.last_used)
+                .map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                self.open.remove(&oldest);
+            }
+        }
+
+        // Cursor 0 starts a new scan, so it's never handed out
+        self.next_id += 1;
+        cursor.last_used = Instant::now();
+        self.open.insert(self.next_id, cursor);
+        self.next_id
+    }
+}
+
+pub struct Database {
+    cursors: Mutex<ScanCursors>,
+    connect_count: AtomicI64,
+    // Commands run concurrently, serialized only by the keys they share
+    locks: KeyLocks,
+    // Keys are partitioned across shards by hash, so that writers to
+    // different shards don't contend with each other. Shared with the
+    // snapshots held by open cursors.
+    shards: Vec<Arc<TransactionDB>>,
+    clock: Arc<dyn Clock>,
+    cache: Option<ValueCache>,
+}
+
+#[cfg_attr(test, automock)]
+pub trait DatabaseOperations {
+    fn get_string(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError>;
+
+    // Hands a string to f straight from storage rather than copying it out
+    // first, returning whether it exists. Range reads slice it in f.
+    fn read_string(&self, key: &[u8], f: &mut dyn FnMut(&[u8])) -> Result<bool, DatabaseError>;
+
+    // 0 if the key doesn't exist
+    fn get_string_length(&self, key: &[u8]) -> Result<usize, DatabaseError>;
+
+    fn get_hash_field(&self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError>;
+
+    // Hands every field and value of a hash to f, a chunk at a time, along
+    // with how many fields there are in all, returning whether it exists.
+    // Fields are read off their records as f goes, so only one chunk is
+    // held at a time.
+    fn read_hash(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(usize, &[(&[u8], &[u8])]),
+    ) -> Result<bool, DatabaseError>;
+
+    fn get_expiry(&self, key: &[u8]) -> Result<Option<Duration>, DatabaseError>;
+
+    // When the key expires, as a Unix time
+    fn get_expiry_at(&self, key: &[u8]) -> Result<Option<Duration>, DatabaseError>;
+
+    fn get_type(&self, key: &[u8]) -> Result<Option<String>, DatabaseError>;
+
+    // Replaces the value, dropping any TTL like SET does
+    fn put_string(&self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError>;
+
+    // Stores a string that was modified in place, keeping its TTL
+    fn update_string(&self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError>;
+
+    fn put_string_with_expiry(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        expires_in: Duration,
+    ) -> Result<(), DatabaseError>;
+
+    fn get_and_put_string(
+        &self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError>;
+
+    fn get_and_delete_string(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError>;
+
+    fn put_strings_if_absent(
+        &self,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<bool, DatabaseError>;
+
+    // Appends to a string, creating it if it doesn't exist, and returns its
+    // new length
+    fn append(&self, key: &[u8], suffix: &[u8]) -> Result<usize, DatabaseError>;
+
+    // SET with all of its options, returning whether the value was stored
+    // and, with get, the string it replaced
+    fn set_string(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        options: SetOptions,
+    ) -> Result<(bool, Option<Vec<u8>>), DatabaseError>;
+
+    fn put_hash_fields(
+        &self,
+        key: &[u8],
+        fields: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<i64, DatabaseError>;
+
+    // Sets the TTL of each field, returning for each -2 if it doesn't
+    // exist, 0 if the condition didn't hold, 1 if the TTL was set, or 2 if
+    // the field was deleted since its time had already come
+    fn put_field_expiry(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+        expires_in: Duration,
+        condition: ExpiryCondition,
+    ) -> Result<Vec<i64>, DatabaseError>;
+
+    // Each field's remaining TTL in milliseconds, or -1 if it has none and
+    // -2 if it doesn't exist
+    fn get_field_expiry(&self, key: &[u8], fields: Vec<Vec<u8>>)
+        -> Result<Vec<i64>, DatabaseError>;
+
+    // Removes each field's TTL, returning for each 1 if it had one, -1 if it
+    // didn't, and -2 if it doesn't exist
+    fn delete_field_expiry(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+    ) -> Result<Vec<i64>, DatabaseError>;
+
+    // Reads fields while updating their TTLs, HGETEX-style. Keep leaves the
+    // TTLs as they are, Clear removes them, and an expiry that has already
+    // passed deletes the fields.
+    fn get_hash_fields_with_expiry(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+        expiry: SetExpiry,
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError>;
+
+    // Reads and deletes fields, deleting the hash if none are left
+    fn take_hash_fields(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError>;
+
+    // Adds members to a set, creating it if needed, and returns how many
+    // weren't already in it
+    fn add_set_members(&self, key: &[u8], members: Vec<Vec<u8>>) -> Result<i64, DatabaseError>;
+
+    // Removes members from a set, deleting it if none are left, and returns
+    // how many were in it
+    fn remove_set_members(&self, key: &[u8], members: Vec<Vec<u8>>) -> Result<i64, DatabaseError>;
+
+    // Every member of a set, in no particular order. Empty if the set
+    // doesn't exist.
+    fn get_set_members(&self, key: &[u8]) -> Result<Vec<Vec<u8>>, DatabaseError>;
+
+    fn is_set_member(&self, key: &[u8], member: &[u8]) -> Result<bool, DatabaseError>;
+
+    // Pushes elements onto one end of a list one at a time, creating the
+    // list unless if_exists is set, and returns its new length, or 0 if it
+    // didn't exist and wasn't created
+    fn push_list(
+        &self,
+        key: &[u8],
+        elements: Vec<Vec<u8>>,
+        end: ListEnd,
+        if_exists: bool,
+    ) -> Result<i64, DatabaseError>;
+
+    // Read from the list's metadata rather than by counting elements. 0 if
+    // the list doesn't exist.
+    fn get_list_length(&self, key: &[u8]) -> Result<i64, DatabaseError>;
+
+    // The element at an index, counting back from the end when negative, or
+    // None if the list doesn't exist or the index is out of range
+    fn get_list_element(&self, key: &[u8], index: i64) -> Result<Option<Vec<u8>>, DatabaseError>;
+
+    // The elements from start to stop inclusive, counting back from the end
+    // when negative
+    fn get_list_range(
+        &self,
+        key: &[u8],
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Vec<u8>>, DatabaseError>;
+
+    // Replaces the element at an index, failing with NoSuchKey or
+    // IndexOutOfRange like LSET does
+    fn set_list_element(
+        &self,
+        key: &[u8],
+        index: i64,
+        element: Vec<u8>,
+    ) -> Result<(), DatabaseError>;
+
+    // Inserts an element before or after the first occurrence of pivot,
+    // returning the list's new length, -1 if pivot wasn't found, or 0 if the
+    // list doesn't exist
+    fn insert_list(
+        &self,
+        key: &[u8],
+        pivot: &[u8],
+        element: Vec<u8>,
+        before: bool,
+    ) -> Result<i64, DatabaseError>;
+
+    // Removes occurrences of an element, the first count of them from the
+    // front when count is positive, the last from the back when negative,
+    // or all of them when 0, and returns how many were removed
+    fn remove_list(&self, key: &[u8], count: i64, element: &[u8]) -> Result<i64, DatabaseError>;
+
+    // Keeps only the elements from start to stop inclusive, counting back
+    // from the end when negative, deleting the list if that leaves nothing
+    fn trim_list(&self, key: &[u8], start: i64, stop: i64) -> Result<(), DatabaseError>;
+
+    // Removes up to count elements from one end of a list, in the order
+    // they were removed, or returns None if the list doesn't exist
+    fn pop_list(
+        &self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Vec<u8>>>, DatabaseError>;
+
+    // Returns false, without setting anything, if the key doesn't exist
+    fn put_expiry(&self, key: &[u8], expires_in: Duration) -> Result<bool, DatabaseError>;
+
+    fn exists(&self, key: &[u8]) -> Result<i64, DatabaseError>;
+
+    fn increment_by(&self, key: &[u8], amount: i64) -> Result<i64, DatabaseError>;
+
+    fn increment_by_float(&self, key: &[u8], amount: f64) -> Result<f64, DatabaseError>;
+
+    fn delete(&self, key: &[u8]) -> Result<i64, DatabaseError>;
+
+    // Deletes every given key with one write per shard, returning how many
+    // of them existed
+    fn delete_many(&self, keys: Vec<Vec<u8>>) -> Result<i64, DatabaseError>;
+
+    // Deletes a key's own records right away, but leaves its sub-records,
+    // like a time series' samples, for reclaim to delete later
+    fn unlink(&self, key: &[u8]) -> Result<i64, DatabaseError>;
+
+    // Deletes what unlink left behind of a key, unless the key has been
+    // created again since
+    fn reclaim(&self, key: &[u8]) -> Result<(), DatabaseError>;
+
+    // Deletes every key. A lazy flush deletes the keys' own records, like
+    // unlink, and leaves their sub-records for reclaim_all.
+    fn flush_keys(&self, lazy: bool) -> Result<(), DatabaseError>;
+
+    // Reclaims every key a lazy flush left sub-records of, returning how
+    // many were reclaimed
+    fn reclaim_all(&self) -> Result<u64, DatabaseError>;
+
+    fn delete_expiry(&self, key: &[u8]) -> Result<i64, DatabaseError>;
+
+    // Copies a key's value and TTL to dest, returning whether it was copied.
+    // Nothing is copied if src doesn't exist, or dest does and replace isn't
+    // set.
+    fn copy(&self, src: &[u8], dest: &[u8], replace: bool) -> Result<bool, DatabaseError>;
+
+    fn bloom_reserve(&self, key: &[u8], filter: BloomFilter) -> Result<(), DatabaseError>;
+
+    fn bloom_add(&self, key: &[u8], items: Vec<Vec<u8>>) -> Result<Vec<bool>, DatabaseError>;
+
+    fn bloom_exists(&self, key: &[u8], items: Vec<Vec<u8>>) -> Result<Vec<bool>, DatabaseError>;
+
+    fn cms_init(&self, key: &[u8], sketch: CountMinSketch) -> Result<(), DatabaseError>;
+
+    fn cms_incr_by(
+        &self,
+        key: &[u8],
+        items: Vec<(Vec<u8>, u64)>,
+    ) -> Result<Vec<u64>, DatabaseError>;
+
+    fn cms_query(&self, key: &[u8], items: Vec<Vec<u8>>) -> Result<Vec<u64>, DatabaseError>;
+
+    fn cms_info(&self, key: &[u8]) -> Result<CountMinSketch, DatabaseError>;
+
+    fn cms_merge(&self, dest: &[u8], sources: Vec<(Vec<u8>, u64)>) -> Result<(), DatabaseError>;
+
+    fn topk_reserve(&self, key: &[u8], topk: TopK) -> Result<(), DatabaseError>;
+
+    fn topk_add(
+        &self,
+        key: &[u8],
+        items: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError>;
+
+    fn topk_info(&self, key: &[u8]) -> Result<TopK, DatabaseError>;
+
+    fn tdigest_create(&self, key: &[u8], digest: TDigest) -> Result<(), DatabaseError>;
+
+    fn tdigest_add(&self, key: &[u8], values: Vec<f64>) -> Result<(), DatabaseError>;
+
+    fn tdigest_info(&self, key: &[u8]) -> Result<TDigest, DatabaseError>;
+
+    fn tdigest_merge(
+        &self,
+        dest: &[u8],
+        sources: Vec<Vec<u8>>,
+        compression: Option<f64>,
+        override_dest: bool,
+    ) -> Result<(), DatabaseError>;
+
+    fn ts_create(&self, key: &[u8], info: TimeSeriesInfo) -> Result<(), DatabaseError>;
+
+    // Creates the series from `defaults` if it doesn't exist yet
+    fn ts_add(
+        &self,
+        key: &[u8],
+        timestamp: u64,
+        value: f64,
+        defaults: TimeSeriesInfo,
+    ) -> Result<(), DatabaseError>;
+
+    fn ts_info(&self, key: &[u8]) -> Result<TimeSeriesInfo, DatabaseError>;
+
+    fn ts_range(&self, key: &[u8], from: u64, to: u64) -> Result<Vec<(u64, f64)>, DatabaseError>;
+
+    fn ts_query_index(
+        &self,
+        filters: Vec<LabelFilter>,
+    ) -> Result<Vec<(Vec<u8>, TimeSeriesInfo)>, DatabaseError>;
+
+    fn storage_stats(&self) -> Result<StorageStats, DatabaseError>;
+
+    fn check_integrity(&self, repair: bool) -> Result<Vec<Inconsistency>, DatabaseError>;
+
+    fn check_writable(&self) -> Result<(), DatabaseError>;
+
+    fn shard_count(&self) -> usize;
+
+    // Up to limit live keys of one shard in storage order, starting after the
+    // given key
+    fn key_sizes(
+        &self,
+        shard: usize,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<KeySize>, DatabaseError>;
+
+    // Deletes up to limit of one shard's keys whose TTL has lapsed, earliest
+    // first. Returns the deleted keys, and whether more may be due.
+    fn delete_expired(
+        &self,
+        shard: usize,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, bool), DatabaseError>;
+
+    // The current Unix time, as seen by the database's expiry logic
+    fn now(&self) -> Result<Duration, DatabaseError>;
+
+    // The live keys among the next count keys that pass the filter,
+    // continuing the scan with the given cursor, or starting a new one for
+    // cursor 0. Like Redis, a page can be short or even empty before the
+    // scan is over. Returns 0 as the next cursor once the scan is complete.
+    fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        filter: ScanFilter,
+    ) -> Result<(u64, Vec<Vec<u8>>), DatabaseError>;
+}
+
+trait RString = AsRef<[u8]>;
+
+impl Database {
+    pub fn new(db: TransactionDB) -> Self {
+        Self::with_shards(vec![db])
+    }
+
+    pub fn with_shards(shards: Vec<TransactionDB>) -> Self {
+        assert!(!shards.is_empty(), "at least one shard is required");
+        Self {
+            cursors: Mutex::new(ScanCursors::default()),
+            shards: shards.into_iter().map(Arc::new).collect(),
+            connect_count: AtomicI64::new(0),
+            locks: KeyLocks::new(),
+            clock: Arc::new(SystemClock),
+            cache: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Keeps up to capacity bytes of recently read values in memory. A
+    // capacity of 0 leaves the cache off.
+    pub fn with_value_cache(mut self, capacity: usize) -> Self {
+        self.cache = (capacity > 0).then(|| ValueCache::new(capacity));
+        self
+    }
+
+    // Keys are considered to be gone as soon as their TTL lapses, even if
+    // their records are still present in storage
+    fn is_expired(&self, ttl_value: &Option<Vec<u8>>) -> Result<bool, DatabaseError> {
+        match ttl_value {
+            Some(ttl) => Ok(
+                parse_timestamp(ttl)?.saturating_sub(self.clock.unix_timestamp()?)
+                    == Duration::ZERO,
+            ),
+            None => Ok(false),
+        }
+    }
+
+    // Replaces the key with a dumped one. Its data has to decode as its type,
+    // so a bad dump can't leave behind values that commands can't read.
+    pub fn restore_key(&self, dumped: &DumpedKey) -> Result<(), DatabaseError> {
+        let type_id = type_id(&dumped.type_name)
+            .filter(|id| decodes_as(id, &dumped.data))
+            .ok_or_else(|| DatabaseError::WrongType {
+                expected: dumped.type_name.clone(),
+            })?;
+
+        let key = dumped.key.as_slice();
+        let txn = self.shard(key).transaction();
+        self.delete_typed_value_txn(&txn, key)?;
+        if type_id == TYPE_LIST {
+            // Elements are numbered afresh, whatever their old sequence
+            // numbers were
+            let mut info = ListInfo::default();
+            for element in dumped.elements.iter() {
+                txn.put(element_key(key, info.push(ListEnd::Back)), element)?;
+            }
+            self.put_typed_value_txn(&txn, key, serde_json::to_vec(&info)?, type_id)?;
+        } else if type_id == TYPE_SET {
+            // Members are counted as they're written, in case the dump
+            // repeats any
+            let mut len = 0u64;
+            for member in dumped.elements.iter() {
+                if txn.get(member_key(key, member))?.is_none() {
+                    txn.put(member_key(key, member), [])?;
+                    len += 1;
+                }
+            }
+            self.put_typed_value_txn(&txn, key, serde_json::to_vec(&len)?, type_id)?;
+        } else if type_id == TYPE_HASH {
+            // Likewise for fields
+            let mut len = 0u64;
+            for (field, value) in dumped.fields.iter() {
+                let field_key = member_key(key, field);
+                if txn.get(&field_key)?.is_none() {
+                    len += 1;
+                }
+                txn.put(field_key, value)?;
+            }
+            self.put_typed_value_txn(&txn, key, serde_json::to_vec(&len)?, type_id)?;
+        } else {
+            self.put_typed_value_txn(&txn, key, &dumped.data, type_id)?;
+        }
+        if let Some(expires_at) = dumped.expires_at {
+            self.put_ttl_txn(&txn, key, &encode_timestamp(expires_at)?)?;
+        }
+        for (timestamp, value) in dumped.samples.iter() {
+            txn.put(sample_key(key, *timestamp), value.to_be_bytes())?;
+        }
+
+        self.commit(txn, [key])
+    }
+
+    // Moves records still keyed in the legacy layout over to the current one,
+    // returning how many were moved. Each record is moved in a single write,
+    // so an interrupted migration just picks up where it left off next time.
+    pub fn migrate_legacy_keys(&self) -> Result<u64, DatabaseError> {
+        let mut migrated = 0;
+        for shard in self.shards.iter() {
+            for ns in Namespace::ALL {
+                let prefix = keyformat::legacy_prefix(ns);
+                let mut batch = WriteBatchWithTransaction::<true>::default();
+                for entry in shard.iterator_opt(
+                    IteratorMode::From(&prefix, Direction::Forward),
+                    total_order(),
+                ) {
+                    let (record_key, value) = entry?;
+                    if !record_key.starts_with(&prefix) {
+                        break;
+                    }
+
+                    // Malformed records are left where they are, untouched
+                    if let Some(upgraded) = keyformat::upgrade_legacy(ns, &record_key) {
+                        batch.put(upgraded, value);
+                        batch.delete(record_key);
+                        migrated += 1;
+                    }
+                    if batch.len() >= MIGRATION_BATCH_SIZE {
+                        shard.write(mem::take(&mut batch))?;
+                    }
+                }
+                shard.write(batch)?;
+            }
+
+            migrated += Self::migrate_legacy_ttls(shard)?;
+            migrated += Self::migrate_legacy_hashes(shard)?;
+        }
+
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+
+        Ok(migrated)
+    }
+
+    // TTLs used to be stored as decimal strings, and weren't in the expiry
+    // index
+    fn migrate_legacy_ttls(shard: &TransactionDB) -> Result<u64, DatabaseError> {
+        let prefix = keyformat::namespace_prefix(Namespace::Ttl, DEFAULT_DB);
+        let mut migrated = 0;
+        let mut batch = WriteBatchWithTransaction::<true>::default();
+        for entry in shard.iterator_opt(
+            IteratorMode::From(&prefix, Direction::Forward),
+            total_order(),
+        ) {
+            let (ttl_key, ttl_value) = entry?;
+            let key = match keyformat::decode(Namespace::Ttl, DEFAULT_DB, &ttl_key) {
+                Some((key, _)) => key,
+                None => break,
+            };
+            if !is_legacy_timestamp(&ttl_value) {
+                continue;
+            }
+
+            // Malformed records are left where they are, untouched
+            let ttl_value = match parse_timestamp(&ttl_value).and_then(encode_timestamp) {
+                Ok(ttl_value) => ttl_value,
+                Err(_) => continue,
+            };
+            batch.put(expiry_index_key(key, &ttl_value)?, []);
+            batch.put(&ttl_key, ttl_value);
+            migrated += 1;
+            if batch.len() >= MIGRATION_BATCH_SIZE {
+                shard.write(mem::take(&mut batch))?;
+            }
+        }
+        shard.write(batch)?;
+
+        Ok(migrated)
+    }
+
+    // Hashes used to be stored whole, as a JSON object in their data record,
+    // and their field TTLs as another. Each hash is moved in a single write.
+    fn migrate_legacy_hashes(shard: &TransactionDB) -> Result<u64, DatabaseError> {
+        let prefix = keyformat::namespace_prefix(Namespace::Type, DEFAULT_DB);
+        let mut migrated = 0;
+        let mut batch = WriteBatchWithTransaction::<true>::default();
+        for entry in shard.iterator_opt(
+            IteratorMode::From(&prefix, Direction::Forward),
+            total_order(),
+        ) {
+            let (type_key, type_value) = entry?;
+            let key = match keyformat::decode(Namespace::Type, DEFAULT_DB, &type_key) {
+                Some((key, _)) => key,
+                None => break,
+            };
+            if !type_value.eq_ignore_ascii_case(TYPE_HASH.as_bytes()) {
+                continue;
+            }
+
+            // Hashes already migrated only have their field count, and
+            // malformed records are left where they are, untouched
+            let data_key = encode_key(Namespace::Data, key);
+            let legacy = shard
+                .get(&data_key)?
+                .and_then(|data| serde_json::from_slice::<HashMap<String, String>>(&data).ok());
+            let Some(dict) = legacy else {
+                continue;
+            };
+            for (field, value) in dict.iter() {
+                batch.put(member_key(key, field.as_bytes()), value);
+            }
+            batch.put(data_key, serde_json::to_vec(&(dict.len() as u64))?);
+
+            let ttls_key = encode_key(Namespace::FieldTtl, key);
+            let legacy_ttls = shard
+                .get(&ttls_key)?
+                .and_then(|ttls| serde_json::from_slice::<HashMap<String, u64>>(&ttls).ok());
+            if let Some(ttls) = legacy_ttls {
+                let ttls: FieldTtls = ttls
+                    .into_iter()
+                    .map(|(field, expires_at)| (field.into_bytes(), expires_at))
+                    .collect();
+                batch.put(ttls_key, encode_field_ttls(&ttls)?);
+            }
+
+            migrated += 1;
+            if batch.len() >= MIGRATION_BATCH_SIZE {
+                shard.write(mem::take(&mut batch))?;
+            }
+        }
+        shard.write(batch)?;
+
+        Ok(migrated)
+    }
+
+    fn start_scan(&self) -> ScanCursor {
+        ScanCursor {
+            snapshots: self
+                .shards
+                .iter()
+                .map(|shard| OwnedSnapshot::new(shard.clone()))
+                .collect(),
+            shard: 0,
+            after: None,
+            last_used: Instant::now(),
+        }
+    }
+
+    // Writes every shard's memtables out to disk
+    pub fn flush(&self) -> Result<(), DatabaseError> {
+        for shard in self.shards.iter() {
+            shard.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn acquire_connection(&self) -> i64 {
+        self.connect_count.fetch_add(1, Ordering::SeqCst)
+    }
+
+    // Held while a command runs, see KeyLocks
+    pub fn lock_keys(&self, args: &[Vec<u8>]) -> KeyGuard<'_> {
+        self.locks.lock(args)
+    }
+
+    pub fn lock_keyspace(&self) -> KeyGuard<'_> {
+        self.locks.lock_keyspace()
+    }
+
+    fn shard(&self, key: &[u8]) -> &TransactionDB {
+        &self.shards[shard_index(key, self.shards.len())]
+    }
+
+    // Writes that don't depend on existing values skip the transaction and
+    // go straight to the shard as one batch
+    fn write_blind(
+        &self,
+        key: &[u8],
+        write: impl FnOnce(&mut WriteBatchWithTransaction<true>),
+    ) -> Result<(), DatabaseError> {
+        let index = shard_index(key, self.shards.len());
+        let mut batch = WriteBatchWithTransaction::default();
+        write(&mut batch);
+        self.shards[index].write(batch)?;
+        self.invalidate([key]);
+        Ok(())
+    }
+
+    // Cached values must only be dropped once the writes replacing them can
+    // be read, or a concurrent read could cache the old value again
+    fn invalidate<'a>(&self, written: impl IntoIterator<Item = &'a [u8]>) {
+        if let Some(cache) = &self.cache {
+            for key in written {
+                cache.invalidate(key);
+            }
+        }
+    }
+
+    fn commit<'a>(
+        &self,
+        txn: Transaction<TransactionDB>,
+        written: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<(), DatabaseError> {
+        txn.commit()?;
+        self.invalidate(written);
+        Ok(())
+    }
+
+    // Multi-key transactions can only span a single shard
+    fn shard_for_keys<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<&TransactionDB, DatabaseError> {
+        let indices: Vec<usize> = keys
+            .into_iter()
+            .map(|key| shard_index(key, self.shards.len()))
+            .unique()
+            .collect();
+        match indices.as_slice() {
+            [index] => Ok(&self.shards[*index]),
+            [] => Ok(&self.shards[0]),
+            _ => Err(DatabaseError::CrossShard),
+        }
+    }
+
+    fn put_expiry<K: RString>(&self, key: K, expires_in: Duration) -> Result<bool, DatabaseError> {
+        let ttl_ms = serialize_duration_as_timestamp(&*self.clock, expires_in)?;
+
+        // Lock the key's records so that we don't set a TTL on a key that's
+        // being replaced or deleted, or one that's already gone
+        let txn = self.shard(key.as_ref()).transaction();
+        if !self.exists_for_update(&txn, key.as_ref())? {
+            return Ok(false);
+        }
+
+        if expires_in.is_zero() {
+            // An expiry that has already passed removes the key outright,
+            // rather than leaving it in storage with a lapsed TTL
+            debug!("Expiry is in the past, deleting key");
+            self.delete_typed_value_txn(&txn, key.as_ref())?;
+            self.commit(txn, [key.as_ref()])?;
+            return Ok(true);
+        }
+
+        // Replace the TTL, along with its entry in the expiry index
+        self.delete_ttl_txn(&txn, key.as_ref())?;
+        self.put_ttl_txn(&txn, key.as_ref(), &ttl_ms)?;
+
+        self.commit(txn, [key.as_ref()])?;
+        Ok(true)
+    }
+
+    fn get_expiry<K: RString>(&self, key: K) -> Result<Option<Duration>, DatabaseError> {
+        let now = self.clock.unix_timestamp()?;
+        match self.get_expiry_at(key)? {
+            Some(expires_at) => Ok(Some(expires_at.saturating_sub(now))),
+            None => Ok(None),
+        }
+    }
+
+    fn get_expiry_at<K: RString>(&self, key: K) -> Result<Option<Duration>, DatabaseError> {
+        match self.get_live_key(key)? {
+            Some((_, Some(ttl))) => Ok(Some(parse_timestamp(&ttl)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn delete_expiry<K: RString>(&self, key: K) -> Result<i64, DatabaseError> {
+        let ttl_key = encode_key(Namespace::Ttl, key.as_ref());
+
+        // Lock the key's records so that we don't remove a TTL while the
+        // value is being replaced. A key whose TTL has lapsed is already
+        // gone, and mustn't be brought back by dropping its TTL.
+        let txn = self.shard(key.as_ref()).transaction();
+        if !self.exists_for_update(&txn, key.as_ref())? {
+            return Ok(0);
+        }
+
+        let existing_ttl = txn.get_for_update(ttl_key, true)?;
+        if let None = existing_ttl {
+            return Ok(0);
+        }
+
+        // Delete the TTL
+        self.delete_ttl_txn(&txn, key.as_ref())?;
+        self.commit(txn, [key.as_ref()])?;
+
+        Ok(1)
+    }
+
+    fn get_triple<K: RString>(
+        &self,
+        db: &TransactionDB,
+        key1: K,
+        key2: K,
+        key3: K,
+    ) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>, Option<Vec<u8>>), rocksdb::Error> {
+        let result =
+            db.multi_get([key1, key2, key3])
+                .into_iter()
+                .fold(Ok(vec![]), |agg, next| {
+                    agg.and_then(|mut results| {
+                        next.and_then(|value| {
+                            results.push(value);
+                            Ok(results)
+                        })
+                    })
+                })?;
+
+        // This will always succeed if we reach this point
+        Ok(result.into_iter().next_tuple::<(_, _, _)>().unwrap())
+    }
+
+    fn get_triple_for_update<K: RString>(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key1: K,
+        key2: K,
+        key3: K,
+        exclusive: bool,
+    ) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>, Option<Vec<u8>>), rocksdb::Error> {
+        let val1 = txn.get_for_update(key1, exclusive)?;
+        let val2 = txn.get_for_update(key2, exclusive)?;
+        let val3 = txn.get_for_update(key3, exclusive)?;
+        Ok((val1, val2, val3))
+    }
+
+    fn get_typed_value<K: RString>(
+        &self,
+        key: K,
+        type_id: &str,
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key.as_ref());
+        let data_key = encode_key(Namespace::Data, key.as_ref());
+        let ttl_key = encode_key(Namespace::Ttl, key.as_ref());
+
+        let load = || -> Result<Record, DatabaseError> {
+            Ok(self.get_triple(self.shard(key.as_ref()), type_key, data_key, ttl_key)?)
+        };
+        let (type_value, data_value, ttl_value) = match &self.cache {
+            Some(cache) => cache.get_or_load(key.as_ref(), load)?,
+            None => load()?,
+        };
+        if self.is_expired(&ttl_value)? {
+            self.invalidate([key.as_ref()]);
+            return Ok(None);
+        }
+
+        Self::validate_typed_value(&type_value, type_id).and_then(|_| Ok(data_value))
+    }
+
+    fn get_typed_value_for_update<K: RString>(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: K,
+        type_id: &str,
+        exclusive: bool,
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key.as_ref());
+        let data_key = encode_key(Namespace::Data, key.as_ref());
+        let ttl_key = encode_key(Namespace::Ttl, key.as_ref());
+
+        let (type_value, data_value, ttl_value) =
+            self.get_triple_for_update(txn, type_key, data_key, ttl_key, exclusive)?;
+        if self.is_expired(&ttl_value)? {
+            return Ok(None);
+        }
+
+        Self::validate_typed_value(&type_value, type_id).and_then(|_| Ok(data_value))
+    }
+
+    fn validate_typed_value(
+        type_value: &Option<Vec<u8>>,
+        expected_type_id: &str,
+    ) -> Result<(), DatabaseError> {
+        type_value.as_ref().map_or_else(
+            || Ok(()),
+            |tv| {
+                if !tv.eq_ignore_ascii_case(expected_type_id.as_bytes()) {
+                    Err(DatabaseError::WrongType {
+                        expected: expected_type_id.to_string(),
+                    })
+                } else {
+                    Ok(())
+                }
+            },
+        )
+    }
+
+    // Replaces whatever is at the key with a string, blindly unless some
+    // other type is there. Lists, sets, hashes and time series have
+    // sub-records and an expiry index entry that have to go with them,
+    // which takes a transaction. Commands hold their keys' locks, so the
+    // type can't change in between.
+    fn put_string_blind(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        ttl_value: Option<Vec<u8>>,
+    ) -> Result<(), DatabaseError> {
+        let type_value = self.shard(key).get(encode_key(Namespace::Type, key))?;
+        if type_value.is_some_and(|tv| !tv.eq_ignore_ascii_case(TYPE_STRING.as_bytes())) {
+            let txn = self.shard(key).transaction();
+            self.delete_typed_value_txn(&txn, key)?;
+            self.put_typed_value_txn(&txn, key, value, TYPE_STRING)?;
+            if let Some(ttl_value) = ttl_value {
+                self.put_ttl_txn(&txn, key, &ttl_value)?;
+            }
+            return self.commit(txn, [key]);
+        }
+
+        let index_key = match &ttl_value {
+            Some(ttl_value) => Some(expiry_index_key(key, ttl_value)?),
+            None => None,
+        };
+        self.write_blind(key, |batch| {
+            Self::put_typed_value_batch(batch, key, value, TYPE_STRING);
+            if let (Some(ttl_value), Some(index_key)) = (ttl_value, index_key) {
+                batch.put(encode_key(Namespace::Ttl, key), ttl_value);
+                batch.put(index_key, []);
+            }
+        })
+    }
+
+    fn put_typed_value_batch<V: RString>(
+        batch: &mut WriteBatchWithTransaction<true>,
+        key: &[u8],
+        value: V,
+        type_id: &str,
+    ) {
+        batch.put(encode_key(Namespace::Type, key), type_id.as_bytes());
+        batch.put(encode_key(Namespace::Data, key), value);
+        batch.delete(encode_key(Namespace::Ttl, key));
+        batch.delete(encode_key(Namespace::Length, key));
+        batch.delete(encode_key(Namespace::FieldTtl, key));
+    }
+
+    fn put_typed_value_txn<K: RString, V: RString>(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: K,
+        value: V,
+        type_id: &str,
+    ) -> Result<(), DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key.as_ref());
+        let data_key = encode_key(Namespace::Data, key.as_ref());
+        let ttl_key = encode_key(Namespace::Ttl, key.as_ref());
+
+        txn.put(type_key, type_id.as_bytes())?;
+        txn.put(data_key, value)?;
+        txn.delete(ttl_key)?;
+        txn.delete(encode_key(Namespace::Length, key.as_ref()))?;
+        txn.delete(encode_key(Namespace::FieldTtl, key.as_ref()))?;
+
+        Ok(())
+    }
+
+    // Like put_typed_value_txn, but for values changed in place, which keep
+    // their TTL. A TTL that already lapsed went with the old value, though.
+    fn update_typed_value_txn<K: RString, V: RString>(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: K,
+        value: V,
+        type_id: &str,
+    ) -> Result<(), DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key.as_ref());
+        let data_key = encode_key(Namespace::Data, key.as_ref());
+        let ttl_key = encode_key(Namespace::Ttl, key.as_ref());
+
+        txn.put(type_key, type_id.as_bytes())?;
+        txn.put(data_key, value)?;
+        txn.delete(encode_key(Namespace::Length, key.as_ref()))?;
+        if self.is_expired(&txn.get_for_update(&ttl_key, true)?)? {
+            txn.delete(ttl_key)?;
+        }
+
+        Ok(())
+    }
+
+    // Every TTL gets an entry in the expiry index. Entries aren't removed
+    // everywhere a TTL is, since that would take an extra read, so the
+    // index can hold stale entries, which the sweeper drops once they come
+    // due.
+    fn put_ttl_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        ttl_value: &[u8],
+    ) -> Result<(), DatabaseError> {
+        txn.put(expiry_index_key(key, ttl_value)?, [])?;
+        txn.put(encode_key(Namespace::Ttl, key), ttl_value)?;
+        Ok(())
+    }
+
+    fn delete_ttl_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+    ) -> Result<(), DatabaseError> {
+        let ttl_key = encode_key(Namespace::Ttl, key);
+        if let Some(ttl_value) = txn.get_for_update(&ttl_key, true)? {
+            // Malformed TTLs never made it into the index
+            if let Ok(index_key) = expiry_index_key(key, &ttl_value) {
+                txn.delete(index_key)?;
+            }
+            txn.delete(ttl_key)?;
+        }
+        Ok(())
+    }
+
+    fn delete_typed_value<K: RString>(&self, key: K) -> Result<(), DatabaseError> {
+        let txn = self.shard(key.as_ref()).transaction();
+        self.delete_typed_value_txn(&txn, key)?;
+        self.commit(txn, [key.as_ref()])
+    }
+
+    fn delete_typed_value_txn<K: RString>(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: K,
+    ) -> Result<(), DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key.as_ref());
+        let data_key = encode_key(Namespace::Data, key.as_ref());
+
+        txn.delete(type_key)?;
+        txn.delete(data_key)?;
+        self.delete_ttl_txn(txn, key.as_ref())?;
+        txn.delete(encode_key(Namespace::Length, key.as_ref()))?;
+        txn.delete(encode_key(Namespace::FieldTtl, key.as_ref()))?;
+        self.delete_samples_txn(txn, key.as_ref(), u64::MAX)?;
+        self.delete_elements_txn(txn, key.as_ref())?;
+
+        Ok(())
+    }
+
+    fn delete_elements_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+    ) -> Result<(), DatabaseError> {
+        let prefix = element_key_prefix(key);
+        for entry in txn.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (element_key, _) = entry?;
+            if !element_key.starts_with(&prefix) {
+                break;
+            }
+            txn.delete(element_key)?;
+        }
+        Ok(())
+    }
+
+    fn get_list_for_update(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+    ) -> Result<Option<ListInfo>, DatabaseError> {
+        match self.get_typed_value_for_update(txn, key, TYPE_LIST, true)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Finds the element at an index, counting back from the end when
+    // negative, as its sequence number and value
+    fn find_element_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        info: &ListInfo,
+        index: i64,
+    ) -> Result<Option<(u64, Vec<u8>)>, DatabaseError> {
+        let len = info.len as i64;
+        let index = if index < 0 { index + len } else { index };
+        if index < 0 || index >= len {
+            return Ok(None);
+        }
+
+        if info.is_dense() {
+            let seq = info.head + index as u64;
+            return Ok(txn.get(element_key(key, seq))?.map(|value| (seq, value)));
+        }
+
+        // Otherwise the elements are walked from whichever end is nearer
+        let (end, skip) = if index < len / 2 {
+            (ListEnd::Front, index)
+        } else {
+            (ListEnd::Back, len - 1 - index)
+        };
+        let prefix = element_key_prefix(key);
+        let (seek_key, direction) = list_seek(key, info, end);
+        match txn
+            .iterator(IteratorMode::From(&seek_key, direction))
+            .nth(skip as usize)
+        {
+            Some(entry) => {
+                let (element_key, value) = entry?;
+                if !element_key.starts_with(&prefix) {
+                    return Ok(None);
+                }
+                Ok(Some((
+                    parse_seq(prefix.len(), &element_key),
+                    value.to_vec(),
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Deletes count elements from one end of a list without reading them.
+    // TransactionDB doesn't support range deletes, so the records are
+    // deleted one by one, but when the list has no gaps their sequence
+    // numbers are known up front and nothing needs to be walked.
+    fn drop_elements_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        info: &mut ListInfo,
+        end: ListEnd,
+        count: u64,
+    ) -> Result<(), DatabaseError> {
+        if count == 0 {
+            return Ok(());
+        }
+
+        if info.is_dense() {
+            let seqs = match end {
+                ListEnd::Front => info.head..info.head + count,
+                ListEnd::Back => info.tail - count..info.tail,
+            };
+            for seq in seqs.clone() {
+                txn.delete(element_key(key, seq))?;
+            }
+            match end {
+                ListEnd::Front => info.head = seqs.end,
+                ListEnd::Back => info.tail = seqs.start,
+            }
+            info.len -= count;
+            return Ok(());
+        }
+
+        let prefix = element_key_prefix(key);
+        let (seek_key, direction) = list_seek(key, info, end);
+        for entry in txn
+            .iterator(IteratorMode::From(&seek_key, direction))
+            .take(count as usize)
+        {
+            let (element_key, _) = entry?;
+            if !element_key.starts_with(&prefix) {
+                break;
+            }
+            let seq = parse_seq(prefix.len(), &element_key);
+            match end {
+                ListEnd::Front => info.head = seq + 1,
+                ListEnd::Back => info.tail = seq,
+            }
+            txn.delete(element_key)?;
+            info.len -= 1;
+        }
+        Ok(())
+    }
+
+    // Frees up a sequence number for a new element by moving the elements
+    // from it toward one end along by one. Only the elements up to the first
+    // gap need to move, and none at all if the sequence number is already
+    // free.
+    fn make_room_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        info: &mut ListInfo,
+        slot: u64,
+        end: ListEnd,
+    ) -> Result<(), DatabaseError> {
+        let prefix = element_key_prefix(key);
+        let direction = match end {
+            ListEnd::Front => Direction::Reverse,
+            ListEnd::Back => Direction::Forward,
+        };
+
+        // The elements are read before any are written, since each one
+        // moves onto the next one's sequence number
+        let mut run = vec![];
+        let mut next = slot;
+        for entry in txn.iterator(IteratorMode::From(&element_key(key, slot), direction)) {
+            let (element_key, value) = entry?;
+            if !element_key.starts_with(&prefix) || parse_seq(prefix.len(), &element_key) != next {
+                break;
+            }
+            run.push(value.to_vec());
+            next = match end {
+                ListEnd::Front => next - 1,
+                ListEnd::Back => next + 1,
+            };
+        }
+
+        // next is now the free sequence number the run extends into
+        let mut to = next;
+        for value in run.into_iter().rev() {
+            txn.put(element_key(key, to), value)?;
+            to = match end {
+                ListEnd::Front => to + 1,
+                ListEnd::Back => to - 1,
+            };
+        }
+        match end {
+            ListEnd::Front => info.head = info.head.min(next),
+            ListEnd::Back => info.tail = info.tail.max(next + 1),
+        }
+        Ok(())
+    }
+
+    // Writes a list's metadata back. Like Redis, a list left without any
+    // elements is deleted.
+    fn put_list_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        info: &ListInfo,
+    ) -> Result<(), DatabaseError> {
+        if info.len == 0 {
+            return self.delete_typed_value_txn(txn, key);
+        }
+        self.update_typed_value_txn(txn, key, serde_json::to_vec(info)?, TYPE_LIST)
+    }
+
+    // Deletes a time series' samples older than the cutoff, returning the
+    // number of samples removed
+    fn delete_samples_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        cutoff: u64,
+    ) -> Result<u64, DatabaseError> {
+        let prefix = sample_key_prefix(key);
+        let mut removed = 0;
+        for entry in txn.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (sample_key, value) = entry?;
+            if !sample_key.starts_with(&prefix) {
+                break;
+            }
+
+            let (timestamp, _) = parse_sample(prefix.len(), &sample_key, &value);
+            if timestamp >= cutoff {
+                break;
+            }
+
+            txn.delete(sample_key)?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    // A hash's field count and field TTLs. Its fields are records of their
+    // own, read as they're needed.
+    fn get_hash(&self, key: &[u8]) -> Result<Option<HashMeta>, DatabaseError> {
+        let len = match self.get_typed_value(key, TYPE_HASH)? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => return Ok(None),
+        };
+        let ttls = match self.shard(key).get(encode_key(Namespace::FieldTtl, key))? {
+            Some(value) => parse_field_ttls(&value)?,
+            None => FieldTtls::new(),
+        };
+        let now = self.clock.unix_timestamp()?.as_millis() as u64;
+
+        Ok(Some(HashMeta { len, ttls, now }))
+    }
+
+    // Like get_hash, but read from a snapshot, so that the fields read from
+    // the same snapshot add up to the count
+    fn get_hash_at(
+        &self,
+        snapshot: &SnapshotWithThreadMode<'_, TransactionDB>,
+        key: &[u8],
+    ) -> Result<Option<HashMeta>, DatabaseError> {
+        if self.is_expired(&snapshot.get(encode_key(Namespace::Ttl, key))?)? {
+            return Ok(None);
+        }
+        let type_value = snapshot.get(encode_key(Namespace::Type, key))?;
+        if type_value.is_none() {
+            return Ok(None);
+        }
+        Self::validate_typed_value(&type_value, TYPE_HASH)?;
+        let len = match snapshot.get(encode_key(Namespace::Data, key))? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => return Ok(None),
+        };
+        let ttls = match snapshot.get(encode_key(Namespace::FieldTtl, key))? {
+            Some(value) => parse_field_ttls(&value)?,
+            None => FieldTtls::new(),
+        };
+        let now = self.clock.unix_timestamp()?.as_millis() as u64;
+
+        Ok(Some(HashMeta { len, ttls, now }))
+    }
+
+    // Like get_hash, but deletes fields whose TTL has lapsed, so that the
+    // count and TTLs it returns only cover live fields
+    fn get_hash_for_update(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+    ) -> Result<Option<(u64, FieldTtls)>, DatabaseError> {
+        let mut len: u64 = match self.get_typed_value_for_update(txn, key, TYPE_HASH, true)? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => return Ok(None),
+        };
+        let mut ttls = match txn.get_for_update(encode_key(Namespace::FieldTtl, key), true)? {
+            Some(value) => parse_field_ttls(&value)?,
+            None => FieldTtls::new(),
+        };
+
+        let now = self.clock.unix_timestamp()?.as_millis() as u64;
+        let lapsed: Vec<Vec<u8>> = ttls
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in lapsed.iter() {
+            txn.delete(member_key(key, field))?;
+            ttls.remove(field);
+            len = len.saturating_sub(1);
+        }
+
+        Ok(Some((len, ttls)))
+    }
+
+    // Writes back a hash's field count along with its field TTLs. Like
+    // Redis, a hash left without any fields is deleted.
+    fn put_hash_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        len: u64,
+        ttls: &FieldTtls,
+    ) -> Result<(), DatabaseError> {
+        if len == 0 {
+            return self.delete_typed_value_txn(txn, key);
+        }
+
+        self.update_typed_value_txn(txn, key, serde_json::to_vec(&len)?, TYPE_HASH)?;
+        self.put_field_ttls_txn(txn, key, ttls)
+    }
+
+    // The hash is indexed by its earliest field TTL, so the sweeper comes
+    // back to it once that lapses. Like key TTLs, entries for field TTLs
+    // that have since changed are left for the sweeper to drop.
+    fn put_field_ttls_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+        ttls: &FieldTtls,
+    ) -> Result<(), DatabaseError> {
+        let ttls_key = encode_key(Namespace::FieldTtl, key);
+        match ttls.values().min() {
+            Some(earliest) => {
+                txn.put(keyformat::encode_expiry(DEFAULT_DB, *earliest, key), [])?;
+                txn.put(ttls_key, encode_field_ttls(ttls)?)?;
+            }
+            None => txn.delete(ttls_key)?,
+        }
+        Ok(())
+    }
+
+    // Deletes a hash's lapsed fields, returning whether that left it empty
+    // and so deleted it too
+    fn expire_fields_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+    ) -> Result<bool, DatabaseError> {
+        let ttls = txn.get_for_update(encode_key(Namespace::FieldTtl, key), true)?;
+        let now = self.clock.unix_timestamp()?.as_millis() as u64;
+        let any_lapsed = match ttls {
+            Some(ttls) => parse_field_ttls(&ttls)?
+                .values()
+                .any(|expires_at| *expires_at <= now),
+            None => false,
+        };
+        if !any_lapsed {
+            return Ok(false);
+        }
+
+        // The lapsed fields are deleted as the hash is read
+        match self.get_hash_for_update(txn, key)? {
+            Some((len, ttls)) => {
+                self.put_hash_txn(txn, key, len, &ttls)?;
+                Ok(len == 0)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn first_sample_txn(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: &[u8],
+    ) -> Result<Option<(u64, f64)>, DatabaseError> {
+        let prefix = sample_key_prefix(key);
+        match txn
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .next()
+        {
+            Some(entry) => {
+                let (sample_key, value) = entry?;
+                if !sample_key.starts_with(&prefix) {
+                    return Ok(None);
+                }
+                Ok(Some(parse_sample(prefix.len(), &sample_key, &value)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Creates a serialized value, failing if anything already lives at the key
+    fn create_object<T: Serialize>(
+        &self,
+        key: &[u8],
+        type_id: &str,
+        object: &T,
+    ) -> Result<(), DatabaseError> {
+        let txn = self.shard(key).transaction();
+        if self.exists_for_update(&txn, key)? {
+            return Err(SketchError::KeyExists.into());
+        }
+
+        let data = serde_json::to_vec(object)?;
+        self.put_typed_value_txn(&txn, key, data, type_id)?;
+
+        self.commit(txn, [key])
+    }
+
+    fn get_object<T: DeserializeOwned>(
+        &self,
+        key: &[u8],
+        type_id: &str,
+    ) -> Result<Option<T>, DatabaseError> {
+        match self.get_typed_value(key, type_id)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Read-modify-write of a serialized value under an exclusive lock. If
+    // the key doesn't exist, `create` is used to make the initial value.
+    fn update_object<T: Serialize + DeserializeOwned, R>(
+        &self,
+        key: &[u8],
+        type_id: &str,
+        create: impl FnOnce() -> Result<T, DatabaseError>,
+        update: impl FnOnce(&mut T) -> Result<R, DatabaseError>,
+    ) -> Result<R, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut object = match self.get_typed_value_for_update(&txn, key, type_id, true)? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => create()?,
+        };
+
+        let result = update(&mut object)?;
+
+        let data = serde_json::to_vec(&object)?;
+        self.update_typed_value_txn(&txn, key, data, type_id)?;
+
+        self.commit(txn, [key])?;
+
+        Ok(result)
+    }
+
+    // Checks a key's records under lock, optionally deleting all of them if
+    // they're inconsistent. Keys whose records are all present are left
+    // alone even if expired, since expiry is lazy.
+    fn check_key(
+        &self,
+        shard: &TransactionDB,
+        key: &[u8],
+        repair: bool,
+    ) -> Result<Option<&'static str>, DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key);
+        let data_key = encode_key(Namespace::Data, key);
+        let ttl_key = encode_key(Namespace::Ttl, key);
+
+        let txn = shard.transaction();
+        let (type_value, data_value, ttl_value) =
+            self.get_triple_for_update(&txn, type_key, data_key, ttl_key, true)?;
+
+        // Compactions can leave expired keys half deleted, which is fine
+        // since they're gone either way, and the sweeper cleans them up
+        if self.is_expired(&ttl_value)? {
+            return Ok(None);
+        }
+
+        let problem = match (type_value, data_value) {
+            (None, Some(_)) => Some("data without a type marker"),
+            (None, None) if { ttl_value.is_some() } => Some("TTL without a key"),
+            (Some(_), None) => Some("type marker without data"),
+            (Some(type_value), Some(data)) => {
+                match TYPE_NAMES
+                    .iter()
+                    .find(|(id, _)| type_value.eq_ignore_ascii_case(id.as_bytes()))
+                {
+                    Some((id, _)) if { !decodes_as(id, &data) } => {
+                        Some("value can't be decoded as its type")
+                    }
+                    Some(_) => None,
+                    None => Some("unknown type marker"),
+                }
+            }
+            (None, None) => None,
+        };
+
+        if problem.is_some() && repair {
+            self.delete_typed_value_txn(&txn, key)?;
+            self.commit(txn, [key])?;
+        }
+
+        Ok(problem)
+    }
+
+    // Checks that a key's samples or elements belong to a key of the type
+    // that has them
+    fn check_sub_records(
+        &self,
+        shard: &TransactionDB,
+        ns: Namespace,
+        key: &[u8],
+        repair: bool,
+    ) -> Result<Option<&'static str>, DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key);
+        let (type_ids, problem): (&[&str], _) = match ns {
+            Namespace::Element => (
+                &[TYPE_LIST, TYPE_SET, TYPE_HASH],
+                "elements without a list, set or hash",
+            ),
+            _ => (&[TYPE_TIMESERIES], "samples without a time series"),
+        };
+
+        let txn = shard.transaction();
+        let type_value = txn.get_for_update(type_key, true)?;
+        if type_value.is_some_and(|tv| {
+            type_ids
+                .iter()
+                .any(|type_id| tv.eq_ignore_ascii_case(type_id.as_bytes()))
+        }) {
+            return Ok(None);
+        }
+
+        if repair {
+            match ns {
+                Namespace::Element => self.delete_elements_txn(&txn, key)?,
+                _ => {
+                    self.delete_samples_txn(&txn, key, u64::MAX)?;
+                }
+            }
+            txn.commit()?;
+        }
+
+        Ok(Some(problem))
+    }
+
+    fn check_shard(
+        &self,
+        shard: &TransactionDB,
+        repair: bool,
+        found: &mut Vec<Inconsistency>,
+    ) -> Result<(), DatabaseError> {
+        let mut report = |key: &[u8], problem: Option<&'static str>| {
+            if let Some(problem) = problem {
+                found.push(Inconsistency {
+                    key: key.to_vec(),
+                    problem,
+                });
+            }
+        };
+
+        // Every key with a type marker gets a full check. Data and TTL
+        // records only need checking when their type marker is missing,
+        // which also keeps each problem from being reported twice.
+        for ns in [Namespace::Type, Namespace::Data, Namespace::Ttl] {
+            let prefix = keyformat::namespace_prefix(ns, DEFAULT_DB);
+            for entry in shard.iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                total_order(),
+            ) {
+                let (record_key, _) = entry?;
+                let key = match keyformat::decode(ns, DEFAULT_DB, &record_key) {
+                    Some((key, _)) => key,
+                    None => break,
+                };
+
+                if ns != Namespace::Type {
+                    let type_key = encode_key(Namespace::Type, key);
+                    if shard.get(type_key)?.is_some() {
+                        continue;
+                    }
+                }
+                report(key, self.check_key(shard, key, repair)?);
+            }
+        }
+
+        for ns in [Namespace::Sample, Namespace::Element] {
+            let prefix = keyformat::namespace_prefix(ns, DEFAULT_DB);
+            let mut last_key: Option<Vec<u8>> = None;
+            for entry in shard.iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                total_order(),
+            ) {
+                let (sub_key, _) = entry?;
+                let key = match keyformat::decode(ns, DEFAULT_DB, &sub_key) {
+                    Some((key, _)) => key,
+                    None => break,
+                };
+                if last_key.as_deref() == Some(key) {
+                    continue;
+                }
+                last_key = Some(key.to_vec());
+
+                report(key, self.check_sub_records(shard, ns, key, repair)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn key_size(
+        &self,
+        shard: &TransactionDB,
+        key: &[u8],
+        type_id: &[u8],
+    ) -> Result<Option<KeySize>, DatabaseError> {
+        let ttl_value = shard.get(encode_key(Namespace::Ttl, key))?;
+        if self.is_expired(&ttl_value)? {
+            return Ok(None);
+        }
+
+        let Some(data) = shard.get(encode_key(Namespace::Data, key))? else {
+            return Ok(None);
+        };
+
+        let type_name = type_name(type_id);
+        let elements = match type_name {
+            "string" => data.len() as u64,
+            "TSDB-TYPE" => {
+                let prefix = sample_key_prefix(key);
+                let mut samples = 0;
+                for entry in shard.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+                    let (sample_key, _) = entry?;
+                    if !sample_key.starts_with(&prefix) {
+                        break;
+                    }
+                    samples += 1;
+                }
+                samples
+            }
+            "list" => serde_json::from_slice::<ListInfo>(&data)
+                .map(|info| info.len)
+                .unwrap_or(0),
+            "set" | "hash" => serde_json::from_slice::<u64>(&data).unwrap_or(0),
+            _ => 1,
+        };
+
+        Ok(Some(KeySize {
+            key: key.to_vec(),
+            type_name,
+            bytes: data.len() as u64,
+            elements,
+        }))
+    }
+
+    // A key's type and TTL records, or None if the key doesn't exist or its
+    // TTL has lapsed. Both are read in one go, so a concurrent write can't
+    // pair one key's type with another's TTL.
+    fn get_live_key<K: RString>(
+        &self,
+        key: K,
+    ) -> Result<Option<(Vec<u8>, Option<Vec<u8>>)>, DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key.as_ref());
+        let ttl_key = encode_key(Namespace::Ttl, key.as_ref());
+
+        let mut values = self
+            .shard(key.as_ref())
+            .multi_get([type_key, ttl_key])
+            .into_iter();
+        let type_value = values.next().unwrap()?;
+        let ttl_value = values.next().unwrap()?;
+        if type_value.is_none() || self.is_expired(&ttl_value)? {
+            return Ok(None);
+        }
+
+        Ok(Some((type_value.unwrap(), ttl_value)))
+    }
+
+    fn get_live_type<K: RString>(&self, key: K) -> Result<Option<Vec<u8>>, DatabaseError> {
+        Ok(self.get_live_key(key)?.map(|(type_value, _)| type_value))
+    }
+
+    fn exists_for_update<K: RString>(
+        &self,
+        txn: &Transaction<TransactionDB>,
+        key: K,
+    ) -> Result<bool, DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key.as_ref());
+        let ttl_key = encode_key(Namespace::Ttl, key.as_ref());
+
+        // Lock the type key even if it doesn't exist yet, so that racing
+        // writers wait for us to either create the key or give up
+        let type_value = txn.get_for_update(type_key, true)?;
+        let ttl_value = txn.get_for_update(ttl_key, true)?;
+
+        Ok(type_value.is_some() && !self.is_expired(&ttl_value)?)
+    }
+
+    fn exists<K: RString>(&self, key: K) -> Result<bool, DatabaseError> {
+        match self.get_live_type(key)? {
+            Some(_) => Ok(true),
+            None => Ok(false),
+        }
+    }
+}
+
+impl DatabaseOperations for Database {
+    fn get_string(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.get_typed_value(key, TYPE_STRING)
+    }
+
+    fn read_string(&self, key: &[u8], f: &mut dyn FnMut(&[u8])) -> Result<bool, DatabaseError> {
+        // Reads through the cache hold their own copy of the value anyway
+        if self.cache.is_some() {
+            let value = self.get_typed_value(key, TYPE_STRING)?;
+            if let Some(value) = &value {
+                f(value);
+            }
+            return Ok(value.is_some());
+        }
+
+        let shard = self.shard(key);
+        let type_value = shard.get(encode_key(Namespace::Type, key))?;
+        let ttl_value = shard.get(encode_key(Namespace::Ttl, key))?;
+        if type_value.is_none() || self.is_expired(&ttl_value)? {
+            return Ok(false);
+        }
+        Self::validate_typed_value(&type_value, TYPE_STRING)?;
+
+        match shard.get_pinned(encode_key(Namespace::Data, key))? {
+            Some(data) => {
+                f(&data);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn get_string_length(&self, key: &[u8]) -> Result<usize, DatabaseError> {
+        let type_value = self.get_live_type(key)?;
+        if type_value.is_none() {
+            return Ok(0);
+        }
+        Self::validate_typed_value(&type_value, TYPE_STRING)?;
+
+        // Strings written by APPEND have their length on record, and others
+        // are only measured where they're stored
+        let shard = self.shard(key);
+        if let Some(length) = shard.get(encode_key(Namespace::Length, key))? {
+            return Ok(parse_length(&length));
+        }
+        Ok(shard
+            .get_pinned(encode_key(Namespace::Data, key))?
+            .map_or(0, |data| data.len()))
+    }
+
+    fn get_hash_field(&self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let hash = match self.get_hash(key)? {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+        if hash.is_lapsed(field) {
+            return Ok(None);
+        }
+
+        Ok(self.shard(key).get(member_key(key, field))?)
+    }
+
+    fn read_hash(
+        &self,
+        key: &[u8],
+        f: &mut dyn FnMut(usize, &[(&[u8], &[u8])]),
+    ) -> Result<bool, DatabaseError> {
+        // Fields can lapse and be swept while the hash is read, so the count
+        // and the fields come from the same snapshot, or the count could
+        // promise fields that are gone by the time they're read
+        let snapshot = self.shard(key).snapshot();
+        let hash = match self.get_hash_at(&snapshot, key)? {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
+        let len = hash.live_len() as usize;
+
+        let prefix = element_key_prefix(key);
+        let mut chunk = Vec::with_capacity(HASH_CHUNK_SIZE.min(len));
+        let mut hand_over = |chunk: &mut Vec<(Box<[u8]>, Box<[u8]>)>| {
+            let pairs: Vec<(&[u8], &[u8])> = chunk
+                .iter()
+                .map(|(field_key, value)| (&field_key[prefix.len()..], &value[..]))
+                .collect();
+            f(len, &pairs);
+            chunk.clear();
+        };
+        for entry in snapshot.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (field_key, value) = entry?;
+            if !field_key.starts_with(&prefix) {
+                break;
+            }
+            if hash.is_lapsed(&field_key[prefix.len()..]) {
+                continue;
+            }
+
+            chunk.push((field_key, value));
+            if chunk.len() == HASH_CHUNK_SIZE {
+                hand_over(&mut chunk);
+            }
+        }
+        if !chunk.is_empty() {
+            hand_over(&mut chunk);
+        }
+        Ok(true)
+    }
+
+    fn get_expiry(&self, key: &[u8]) -> Result<Option<Duration>, DatabaseError> {
+        self.get_expiry(key)
+    }
+
+    fn get_expiry_at(&self, key: &[u8]) -> Result<Option<Duration>, DatabaseError> {
+        self.get_expiry_at(key)
+    }
+
+    fn get_type(&self, key: &[u8]) -> Result<Option<String>, DatabaseError> {
+        let type_value = self.get_live_type(key)?;
+        Ok(type_value.map(|tv| type_name(&tv).to_string()))
+    }
+
+    fn put_string(&self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.put_string_blind(key, value, None)
+    }
+
+    fn update_string(&self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let txn = self.shard(key).transaction();
+        self.get_typed_value_for_update(&txn, key, TYPE_STRING, true)?;
+        self.update_typed_value_txn(&txn, key, value, TYPE_STRING)?;
+        self.commit(txn, [key])
+    }
+
+    fn put_string_with_expiry(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        expires_in: Duration,
+    ) -> Result<(), DatabaseError> {
+        // The value and its TTL must land together, or a failure in between
+        // would leave a value that never expires
+        let ttl_ms = serialize_duration_as_timestamp(&*self.clock, expires_in)?;
+        self.put_string_blind(key, value, Some(ttl_ms))
+    }
+
+    fn get_and_put_string(
+        &self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        // Lock the key for the whole read-replace so that no other writer
+        // can slip in between the two
+        let txn = self.shard(key).transaction();
+        let existing = self.get_typed_value_for_update(&txn, key, TYPE_STRING, true)?;
+        self.put_typed_value_txn(&txn, key, value, TYPE_STRING)?;
+
+        self.commit(txn, [key])?;
+
+        Ok(existing)
+    }
+
+    fn put_strings_if_absent(
+        &self,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<bool, DatabaseError> {
+        // Always lock keys in the same order to avoid deadlocking with
+        // other multi-key writers
+        let mut keys: Vec<&Vec<u8>> = entries.iter().map(|(key, _)| key).collect();
+        keys.sort();
+
+        let txn = self
+            .shard_for_keys(keys.iter().map(|key| key.as_slice()))?
+            .transaction();
+        for key in keys {
+            if self.exists_for_update(&txn, key)? {
+                // Dropping the transaction rolls it back
+                return Ok(false);
+            }
+        }
+
+        // Keys whose TTL lapsed may still have their records, sub-records
+        // included
+        for (key, value) in entries.iter() {
+            self.delete_typed_value_txn(&txn, key)?;
+            self.put_typed_value_txn(&txn, key, value, TYPE_STRING)?;
+        }
+
+        self.commit(txn, entries.iter().map(|(key, _)| key.as_slice()))?;
+
+        Ok(true)
+    }
+
+    fn append(&self, key: &[u8], suffix: &[u8]) -> Result<usize, DatabaseError> {
+        let data_key = encode_key(Namespace::Data, key);
+        let length_key = encode_key(Namespace::Length, key);
+
+        let txn = self.shard(key).transaction();
+        let type_value = txn.get_for_update(encode_key(Namespace::Type, key), true)?;
+        let ttl_value = txn.get_for_update(encode_key(Namespace::Ttl, key), true)?;
+        if type_value.is_none() || self.is_expired(&ttl_value)? {
+            // A key whose TTL lapsed may not have been deleted yet
+            if type_value.is_some() {
+                self.delete_typed_value_txn(&txn, key)?;
+            }
+            self.put_typed_value_txn(&txn, key, suffix, TYPE_STRING)?;
+            txn.put(length_key, (suffix.len() as u64).to_be_bytes())?;
+            self.commit(txn, [key])?;
+            return Ok(suffix.len());
+        }
+        Self::validate_typed_value(&type_value, TYPE_STRING)?;
+
+        // Only strings written by APPEND have their length on record, so the
+        // first append to any other string reads it once to find it
+        let length = match txn.get_for_update(&length_key, true)? {
+            Some(length) => parse_length(&length),
+            None => txn
+                .get_for_update(&data_key, true)?
+                .map_or(0, |data| data.len()),
+        };
+        let length = length + suffix.len();
+
+        txn.merge(data_key, suffix)?;
+        txn.put(length_key, (length as u64).to_be_bytes())?;
+        self.commit(txn, [key])?;
+
+        Ok(length)
+    }
+
+    fn set_string(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        options: SetOptions,
+    ) -> Result<(bool, Option<Vec<u8>>), DatabaseError> {
+        let type_key = encode_key(Namespace::Type, key);
+        let data_key = encode_key(Namespace::Data, key);
+        let ttl_key = encode_key(Namespace::Ttl, key);
+
+        // The condition, the old value and the write are all checked and
+        // made under the same lock
+        let txn = self.shard(key).transaction();
+        let (type_value, data_value, ttl_value) =
+            self.get_triple_for_update(&txn, type_key, data_key, ttl_key, true)?;
+        let exists = type_value.is_some() && !self.is_expired(&ttl_value)?;
+
+        // GET fails on other types even when nothing would be set
+        let existing = if exists && options.get {
+            Self::validate_typed_value(&type_value, TYPE_STRING)?;
+            data_value
+        } else {
+            None
+        };
+
+        let allowed = match options.condition {
+            SetCondition::Always => true,
+            SetCondition::IfAbsent => !exists,
+            SetCondition::IfPresent => exists,
+        };
+        if !allowed {
+            // Dropping the transaction rolls it back
+            return Ok((false, existing));
+        }
+
+        let expires_in = match options.expiry {
+            SetExpiry::In(expires_in) => Some(expires_in),
+            SetExpiry::At(expires_at) => {
+                Some(expires_at.saturating_sub(self.clock.unix_timestamp()?))
+            }
+            SetExpiry::Clear | SetExpiry::Keep => None,
+        };
+
+        // Anything else stored under the key, like time series samples, goes
+        // with the value it replaces, even if its TTL has lapsed
+        if Self::validate_typed_value(&type_value, TYPE_STRING).is_err() {
+            self.delete_typed_value_txn(&txn, key)?;
+        }
+
+        match expires_in {
+            // An expiry that already passed leaves nothing behind
+            Some(expires_in) if { expires_in.is_zero() } => {
+                self.delete_typed_value_txn(&txn, key)?;
+            }
+            Some(expires_in) => {
+                let ttl_ms = serialize_duration_as_timestamp(&*self.clock, expires_in)?;
+                self.put_typed_value_txn(&txn, key, value, TYPE_STRING)?;
+                self.put_ttl_txn(&txn, key, &ttl_ms)?;
+            }
+            None if { options.expiry == SetExpiry::Keep && exists } => {
+                self.put_typed_value_txn(&txn, key, value, TYPE_STRING)?;
+                if let Some(ttl_value) = ttl_value {
+                    self.put_ttl_txn(&txn, key, &ttl_value)?;
+                }
+            }
+            None => self.put_typed_value_txn(&txn, key, value, TYPE_STRING)?,
+        }
+
+        self.commit(txn, [key])?;
+
+        Ok((true, existing))
+    }
+
+    fn get_and_delete_string(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let existing = self.get_typed_value_for_update(&txn, key, TYPE_STRING, true)?;
+        if existing.is_some() {
+            self.delete_typed_value_txn(&txn, key)?;
+        }
+
+        self.commit(txn, [key])?;
+
+        Ok(existing)
+    }
+
+    fn put_hash_fields(
+        &self,
+        key: &[u8],
+        fields: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let (mut len, mut ttls) = match self.get_hash_for_update(&txn, key)? {
+            Some(hash) => hash,
+            None => {
+                // A hash whose TTL lapsed may not have had its fields
+                // deleted yet
+                self.delete_typed_value_txn(&txn, key)?;
+                (0, FieldTtls::new())
+            }
+        };
+
+        // Only the fields being set are written. The transaction buffers
+        // them, and they land in a single write batch when it commits.
+        let mut n_fields = 0;
+        for (field, value) in fields {
+            let field_key = member_key(key, &field);
+            if txn.get(&field_key)?.is_none() {
+                len += 1;
+            }
+            txn.put(field_key, value)?;
+            // Like Redis, a field that's set again loses its TTL
+            ttls.remove(&field);
+            n_fields += 1;
+        }
+
+        self.put_hash_txn(&txn, key, len, &ttls)?;
+
+        self.commit(txn, [key])?;
+
+        Ok(n_fields)
+    }
+
+    fn put_field_expiry(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+        expires_in: Duration,
+        condition: ExpiryCondition,
+    ) -> Result<Vec<i64>, DatabaseError> {
+        let ttl_value = serialize_duration_as_timestamp(&*self.clock, expires_in)?;
+        let expires_at = parse_timestamp(&ttl_value)?.as_millis() as u64;
+
+        let txn = self.shard(key).transaction();
+        let (mut len, mut ttls) = match self.get_hash_for_update(&txn, key)? {
+            Some(hash) => hash,
+            None => return Ok(vec![-2; fields.len()]),
+        };
+
+        let mut results = vec![];
+        for field in fields {
+            let field_key = member_key(key, &field);
+            if txn.get(&field_key)?.is_none() {
+                results.push(-2);
+                continue;
+            }
+
+            let current = ttls.get(&field).copied();
+            let update = match condition {
+                ExpiryCondition::Always => true,
+                ExpiryCondition::IfNone => current.is_none(),
+                ExpiryCondition::IfSome => current.is_some(),
+                ExpiryCondition::IfGreater => current.is_some_and(|at| expires_at > at),
+                ExpiryCondition::IfLess => current.map_or(true, |at| expires_at < at),
+            };
+            if !update {
+                results.push(0);
+            } else if expires_in.is_zero() {
+                txn.delete(field_key)?;
+                ttls.remove(&field);
+                len -= 1;
+                results.push(2);
+            } else {
+                ttls.insert(field, expires_at);
+                results.push(1);
+            }
+        }
+
+        self.put_hash_txn(&txn, key, len, &ttls)?;
+        self.commit(txn, [key])?;
+        Ok(results)
+    }
+
+    fn get_field_expiry(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+    ) -> Result<Vec<i64>, DatabaseError> {
+        let hash = match self.get_hash(key)? {
+            Some(hash) => hash,
+            None => return Ok(vec![-2; fields.len()]),
+        };
+
+        let shard = self.shard(key);
+        let mut results = vec![];
+        for field in fields.iter() {
+            let exists = !hash.is_lapsed(field) && shard.get(member_key(key, field))?.is_some();
+            results.push(match hash.ttls.get(field) {
+                _ if { !exists } => -2,
+                Some(expires_at) => expires_at.saturating_sub(hash.now) as i64,
+                None => -1,
+            });
+        }
+        Ok(results)
+    }
+
+    fn delete_field_expiry(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+    ) -> Result<Vec<i64>, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let (len, mut ttls) = match self.get_hash_for_update(&txn, key)? {
+            Some(hash) => hash,
+            None => return Ok(vec![-2; fields.len()]),
+        };
+
+        let mut results = vec![];
+        for field in fields.iter() {
+            results.push(if txn.get(member_key(key, field))?.is_none() {
+                -2
+            } else if ttls.remove(field).is_some() {
+                1
+            } else {
+                -1
+            });
+        }
+
+        self.put_hash_txn(&txn, key, len, &ttls)?;
+        self.commit(txn, [key])?;
+        Ok(results)
+    }
+
+    fn get_hash_fields_with_expiry(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+        expiry: SetExpiry,
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        let now = self.clock.unix_timestamp()?.as_millis() as u64;
+        let expires_at = match expiry {
+            SetExpiry::In(expires_in) => {
+                let ttl_value = serialize_duration_as_timestamp(&*self.clock, expires_in)?;
+                Some(parse_timestamp(&ttl_value)?.as_millis() as u64)
+            }
+            SetExpiry::At(expires_at) => Some(expires_at.as_millis() as u64),
+            SetExpiry::Clear | SetExpiry::Keep => None,
+        };
+
+        let txn = self.shard(key).transaction();
+        let (mut len, mut ttls) = match self.get_hash_for_update(&txn, key)? {
+            Some(hash) => hash,
+            None => return Ok(vec![None; fields.len()]),
+        };
+
+        let mut values = vec![];
+        for field in fields {
+            let field_key = member_key(key, &field);
+            let value = txn.get(&field_key)?;
+            if value.is_some() {
+                match expires_at {
+                    Some(expires_at) if { expires_at <= now } => {
+                        txn.delete(field_key)?;
+                        ttls.remove(&field);
+                        len -= 1;
+                    }
+                    Some(expires_at) => {
+                        ttls.insert(field, expires_at);
+                    }
+                    None if { expiry == SetExpiry::Clear } => {
+                        ttls.remove(&field);
+                    }
+                    None => {}
+                }
+            }
+            values.push(value);
+        }
+
+        // Dropping the transaction rolls it back
+        if expiry == SetExpiry::Keep {
+            return Ok(values);
+        }
+        self.put_hash_txn(&txn, key, len, &ttls)?;
+        self.commit(txn, [key])?;
+        Ok(values)
+    }
+
+    fn take_hash_fields(
+        &self,
+        key: &[u8],
+        fields: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let (mut len, mut ttls) = match self.get_hash_for_update(&txn, key)? {
+            Some(hash) => hash,
+            None => return Ok(vec![None; fields.len()]),
+        };
+
+        let mut values = vec![];
+        for field in fields.iter() {
+            let field_key = member_key(key, field);
+            let value = txn.get(&field_key)?;
+            ttls.remove(field);
+            if value.is_some() {
+                txn.delete(field_key)?;
+                len -= 1;
+            }
+            values.push(value);
+        }
+
+        if values.iter().any(Option::is_some) {
+            self.put_hash_txn(&txn, key, len, &ttls)?;
+            self.commit(txn, [key])?;
+        }
+        Ok(values)
+    }
+
+    fn add_set_members(&self, key: &[u8], members: Vec<Vec<u8>>) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut len = match self.get_typed_value_for_update(&txn, key, TYPE_SET, true)? {
+            Some(data) => serde_json::from_slice::<u64>(&data)?,
+            None => {
+                // A set whose TTL lapsed may not have had its members
+                // deleted yet
+                self.delete_typed_value_txn(&txn, key)?;
+                0
+            }
+        };
+
+        // Members repeated within the command see their own earlier write,
+        // so they're only counted once
+        let mut added = 0;
+        for member in members {
+            let member_key = member_key(key, &member);
+            if txn.get(&member_key)?.is_none() {
+                txn.put(member_key, [])?;
+                added += 1;
+            }
+        }
+        len += added;
+        if added > 0 {
+            self.update_typed_value_txn(&txn, key, serde_json::to_vec(&len)?, TYPE_SET)?;
+            self.commit(txn, [key])?;
+        }
+        Ok(added as i64)
+    }
+
+    fn remove_set_members(&self, key: &[u8], members: Vec<Vec<u8>>) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut len = match self.get_typed_value_for_update(&txn, key, TYPE_SET, true)? {
+            Some(data) => serde_json::from_slice::<u64>(&data)?,
+            None => return Ok(0),
+        };
+
+        let mut removed = 0;
+        for member in members {
+            let member_key = member_key(key, &member);
+            if txn.get(&member_key)?.is_some() {
+                txn.delete(member_key)?;
+                removed += 1;
+            }
+        }
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        // Like Redis, a set left without any members is deleted
+        len -= removed;
+        if len == 0 {
+            self.delete_typed_value_txn(&txn, key)?;
+        } else {
+            self.update_typed_value_txn(&txn, key, serde_json::to_vec(&len)?, TYPE_SET)?;
+        }
+        self.commit(txn, [key])?;
+        Ok(removed as i64)
+    }
+
+    fn get_set_members(&self, key: &[u8]) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        if self.get_typed_value(key, TYPE_SET)?.is_none() {
+            return Ok(vec![]);
+        }
+
+        // The transaction is only read through, and never committed
+        let txn = self.shard(key).transaction();
+        let prefix = element_key_prefix(key);
+        let mut members = vec![];
+        for entry in txn.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (member_key, _) = entry?;
+            if !member_key.starts_with(&prefix) {
+                break;
+            }
+            members.push(member_key[prefix.len()..].to_vec());
+        }
+        Ok(members)
+    }
+
+    fn is_set_member(&self, key: &[u8], member: &[u8]) -> Result<bool, DatabaseError> {
+        if self.get_typed_value(key, TYPE_SET)?.is_none() {
+            return Ok(false);
+        }
+        Ok(self.shard(key).get(member_key(key, member))?.is_some())
+    }
+
+    fn push_list(
+        &self,
+        key: &[u8],
+        elements: Vec<Vec<u8>>,
+        end: ListEnd,
+        if_exists: bool,
+    ) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut info = match self.get_list_for_update(&txn, key)? {
+            Some(info) => info,
+            None if { if_exists } => return Ok(0),
+            None => {
+                // A list whose TTL lapsed may not have had its elements
+                // deleted yet
+                self.delete_typed_value_txn(&txn, key)?;
+                ListInfo::default()
+            }
+        };
+
+        for element in elements {
+            txn.put(element_key(key, info.push(end)), element)?;
+        }
+        self.put_list_txn(&txn, key, &info)?;
+
+        self.commit(txn, [key])?;
+        Ok(info.len as i64)
+    }
+
+    fn get_list_length(&self, key: &[u8]) -> Result<i64, DatabaseError> {
+        match self.get_typed_value(key, TYPE_LIST)? {
+            Some(data) => Ok(serde_json::from_slice::<ListInfo>(&data)?.len as i64),
+            None => Ok(0),
+        }
+    }
+
+    fn get_list_element(&self, key: &[u8], index: i64) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let info: ListInfo = match self.get_typed_value(key, TYPE_LIST)? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => return Ok(None),
+        };
+
+        // The transaction is only read through, and never committed
+        let txn = self.shard(key).transaction();
+        let element = self.find_element_txn(&txn, key, &info, index)?;
+        Ok(element.map(|(_, value)| value))
+    }
+
+    fn get_list_range(
+        &self,
+        key: &[u8],
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        let info: ListInfo = match self.get_typed_value(key, TYPE_LIST)? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => return Ok(vec![]),
+        };
+
+        let len = info.len as i64;
+        let start = if start < 0 {
+            (start + len).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            stop + len
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop {
+            return Ok(vec![]);
+        }
+        let count = (stop - start + 1) as usize;
+
+        // Only the requested elements are read. Without gaps, the first one
+        // can be sought straight to, and otherwise the elements before it are
+        // skipped over from whichever end is nearer.
+        let prefix = element_key_prefix(key);
+        let from_back = !info.is_dense() && start > len - 1 - stop;
+        let (seek_key, direction, skip) = match (info.is_dense(), from_back) {
+            (true, _) => (
+                element_key(key, info.head + start as u64),
+                Direction::Forward,
+                0,
+            ),
+            (false, false) => {
+                let (seek_key, direction) = list_seek(key, &info, ListEnd::Front);
+                (seek_key, direction, start as usize)
+            }
+            (false, true) => {
+                let (seek_key, direction) = list_seek(key, &info, ListEnd::Back);
+                (seek_key, direction, (len - 1 - stop) as usize)
+            }
+        };
+
+        // The transaction is only read through, and never committed
+        let txn = self.shard(key).transaction();
+        let mut elements = vec![];
+        for entry in txn
+            .iterator(IteratorMode::From(&seek_key, direction))
+            .skip(skip)
+            .take(count)
+        {
+            let (element_key, value) = entry?;
+            if !element_key.starts_with(&prefix) {
+                break;
+            }
+            elements.push(value.to_vec());
+        }
+        if from_back {
+            elements.reverse();
+        }
+        Ok(elements)
+    }
+
+    fn set_list_element(
+        &self,
+        key: &[u8],
+        index: i64,
+        element: Vec<u8>,
+    ) -> Result<(), DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let info = self
+            .get_list_for_update(&txn, key)?
+            .ok_or(DatabaseError::NoSuchKey)?;
+        let (seq, _) = self
+            .find_element_txn(&txn, key, &info, index)?
+            .ok_or(DatabaseError::IndexOutOfRange)?;
+
+        txn.put(element_key(key, seq), element)?;
+        self.commit(txn, [key])
+    }
+
+    fn insert_list(
+        &self,
+        key: &[u8],
+        pivot: &[u8],
+        element: Vec<u8>,
+        before: bool,
+    ) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut info = match self.get_list_for_update(&txn, key)? {
+            Some(info) => info,
+            None => return Ok(0),
+        };
+
+        let prefix = element_key_prefix(key);
+        let (seek_key, direction) = list_seek(key, &info, ListEnd::Front);
+        let mut found = None;
+        for (index, entry) in txn
+            .iterator(IteratorMode::From(&seek_key, direction))
+            .enumerate()
+        {
+            let (element_key, value) = entry?;
+            if !element_key.starts_with(&prefix) {
+                break;
+            }
+            if value.as_ref() == pivot {
+                found = Some((index as u64, parse_seq(prefix.len(), &element_key)));
+                break;
+            }
+        }
+        let (index, seq) = match found {
+            Some(found) => found,
+            None => return Ok(-1),
+        };
+
+        // A gap right next to the pivot is used as it is. Otherwise room is
+        // made on the side of the pivot nearer an end, so that the fewest
+        // elements need renumbering.
+        let (end, slot) = match (index < info.len / 2, before) {
+            (_, true) if { txn.get(element_key(key, seq - 1))?.is_none() } => {
+                (ListEnd::Front, seq - 1)
+            }
+            (_, false) if { txn.get(element_key(key, seq + 1))?.is_none() } => {
+                (ListEnd::Back, seq + 1)
+            }
+            (true, true) => (ListEnd::Front, seq - 1),
+            (true, false) => (ListEnd::Front, seq),
+            (false, true) => (ListEnd::Back, seq),
+            (false, false) => (ListEnd::Back, seq + 1),
+        };
+        self.make_room_txn(&txn, key, &mut info, slot, end)?;
+        txn.put(element_key(key, slot), element)?;
+        info.len += 1;
+
+        self.put_list_txn(&txn, key, &info)?;
+        self.commit(txn, [key])?;
+        Ok(info.len as i64)
+    }
+
+    fn remove_list(&self, key: &[u8], count: i64, element: &[u8]) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut info = match self.get_list_for_update(&txn, key)? {
+            Some(info) => info,
+            None => return Ok(0),
+        };
+
+        let end = if count < 0 {
+            ListEnd::Back
+        } else {
+            ListEnd::Front
+        };
+        let limit = match count {
+            0 => u64::MAX,
+            count => count.unsigned_abs(),
+        };
+
+        let prefix = element_key_prefix(key);
+        let (seek_key, direction) = list_seek(key, &info, end);
+        let mut removed = 0;
+        // Whether everything walked so far was removed, in which case the end
+        // moves past it like it does for pops
+        let mut at_end = true;
+        for entry in txn.iterator(IteratorMode::From(&seek_key, direction)) {
+            if removed == limit {
+                break;
+            }
+            let (element_key, value) = entry?;
+            if !element_key.starts_with(&prefix) {
+                break;
+            }
+            if value.as_ref() != element {
+                at_end = false;
+                continue;
+            }
+
+            let seq = parse_seq(prefix.len(), &element_key);
+            match end {
+                ListEnd::Front if { at_end } => info.head = seq + 1,
+                ListEnd::Back if { at_end } => info.tail = seq,
+                _ => {}
+            }
+            txn.delete(element_key)?;
+            removed += 1;
+        }
+
+        if removed > 0 {
+            info.len -= removed;
+            self.put_list_txn(&txn, key, &info)?;
+            self.commit(txn, [key])?;
+        }
+        Ok(removed as i64)
+    }
+
+    fn trim_list(&self, key: &[u8], start: i64, stop: i64) -> Result<(), DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut info = match self.get_list_for_update(&txn, key)? {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+
+        let len = info.len as i64;
+        let start = if start < 0 {
+            (start + len).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            stop + len
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop {
+            self.delete_typed_value_txn(&txn, key)?;
+            return self.commit(txn, [key]);
+        }
+
+        self.drop_elements_txn(&txn, key, &mut info, ListEnd::Front, start as u64)?;
+        self.drop_elements_txn(&txn, key, &mut info, ListEnd::Back, (len - 1 - stop) as u64)?;
+        self.put_list_txn(&txn, key, &info)?;
+        self.commit(txn, [key])
+    }
+
+    fn pop_list(
+        &self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Vec<u8>>>, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut info = match self.get_list_for_update(&txn, key)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        let prefix = element_key_prefix(key);
+        let (seek_key, direction) = list_seek(key, &info, end);
+        let mut popped = vec![];
+        for entry in txn.iterator(IteratorMode::From(&seek_key, direction)) {
+            if popped.len() == count {
+                break;
+            }
+            let (element_key, value) = entry?;
+            if !element_key.starts_with(&prefix) {
+                break;
+            }
+
+            // The ends move past the popped elements, skipping any gaps
+            let seq = parse_seq(prefix.len(), &element_key);
+            match end {
+                ListEnd::Front => info.head = seq + 1,
+                ListEnd::Back => info.tail = seq,
+            }
+            txn.delete(element_key)?;
+            popped.push(value.to_vec());
+        }
+
+        if !popped.is_empty() {
+            info.len -= popped.len() as u64;
+            self.put_list_txn(&txn, key, &info)?;
+            self.commit(txn, [key])?;
+        }
+        Ok(Some(popped))
+    }
+
+    fn put_expiry(&self, key: &[u8], expires_in: Duration) -> Result<bool, DatabaseError> {
+        self.put_expiry(key, expires_in)
+    }
+
+    fn exists(&self, key: &[u8]) -> Result<i64, DatabaseError> {
+        match self.exists(key)? {
+            true => Ok(1),
+            false => Ok(0),
+        }
+    }
+
+    fn increment_by(&self, key: &[u8], amount: i64) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let current_value = self
+            .get_typed_value_for_update(&txn, key, TYPE_STRING, true)?
+            .unwrap_or_else(|| "0".as_bytes().to_vec());
+
+        let current_value =
+            parse_stored_integer(&current_value).ok_or(DatabaseError::NotInteger)?;
+        let next_value = current_value
+            .checked_add(amount)
+            .ok_or(DatabaseError::Overflow)?;
+
+        self.update_typed_value_txn(&txn, key, next_value.to_string().as_bytes(), TYPE_STRING)?;
+
+        self.commit(txn, [key])?;
+
+        Ok(next_value)
+    }
+
+    fn increment_by_float(&self, key: &[u8], amount: f64) -> Result<f64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let current_value = self
+            .get_typed_value_for_update(&txn, key, TYPE_STRING, true)?
+            .unwrap_or_else(|| "0".as_bytes().to_vec());
+
+        // This needs to be a valid UTF-8 string in order to parse it
+        let current_value = String::from_utf8_lossy(&current_value).into_owned();
+        let current_value = current_value.parse::<f64>()?;
+        let next_value = current_value + amount;
+        if !next_value.is_finite() {
+            return Err(DatabaseError::NotFinite);
+        }
+
+        self.update_typed_value_txn(&txn, key, format_float(next_value).as_bytes(), TYPE_STRING)?;
+
+        self.commit(txn, [key])?;
+
+        Ok(next_value)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<i64, DatabaseError> {
+        if !self.exists(key)? {
+            return Ok(0);
+        }
+
+        self.delete_typed_value(key).and_then(|_| Ok(1))
+    }
+
+    fn delete_many(&self, keys: Vec<Vec<u8>>) -> Result<i64, DatabaseError> {
+        let mut batches: Vec<WriteBatchWithTransaction<true>> = (0..self.shards.len())
+            .map(|_| WriteBatchWithTransaction::default())
+            .collect();
+        let mut n_deleted = 0;
+        for key in keys.iter().unique() {
+            if !self.exists(key)? {
+                continue;
+            }
+
+            let index = shard_index(key, self.shards.len());
+            let batch = &mut batches[index];
+            for ns in KEY_NAMESPACES {
+                batch.delete(encode_key(ns, key));
+            }
+
+            // Sub-records are contiguous, so they're found with one seek
+            let shard = &self.shards[index];
+            for prefix in [sample_key_prefix(key), element_key_prefix(key)] {
+                for entry in shard.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+                    let (sub_key, _) = entry?;
+                    if !sub_key.starts_with(&prefix) {
+                        break;
+                    }
+                    batch.delete(sub_key);
+                }
+            }
+            n_deleted += 1;
+        }
+
+        for (shard, batch) in self.shards.iter().zip(batches) {
+            if !batch.is_empty() {
+                shard.write(batch)?;
+            }
+        }
+        self.invalidate(keys.iter().map(|key| key.as_slice()));
+        Ok(n_deleted)
+    }
+
+    fn unlink(&self, key: &[u8]) -> Result<i64, DatabaseError> {
+        let txn = self.shard(key).transaction();
+        if !self.exists_for_update(&txn, key)? {
+            return Ok(0);
+        }
+
+        // These are single records however big the value is, so deleting
+        // them only writes tombstones, and their space is reclaimed by
+        // compactions
+        for ns in KEY_NAMESPACES {
+            txn.delete(encode_key(ns, key))?;
+        }
+        self.commit(txn, [key])?;
+        Ok(1)
+    }
+
+    fn reclaim(&self, key: &[u8]) -> Result<(), DatabaseError> {
+        let txn = self.shard(key).transaction();
+
+        // A series or list created since then has already cleared out the
+        // old sub-records, and the ones left now are its own
+        if txn
+            .get_for_update(encode_key(Namespace::Type, key), true)?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        self.delete_samples_txn(&txn, key, u64::MAX)?;
+        self.delete_elements_txn(&txn, key)?;
+        self.commit(txn, [key])
+    }
+
+    fn flush_keys(&self, lazy: bool) -> Result<(), DatabaseError> {
+        // Type records go first, so that an interrupted flush doesn't leave
+        // keys behind with only part of their records. A lazy flush also
+        // leaves the expiry index alone, and the sweeper drops its entries
+        // as they come due.
+        let namespaces: &[Namespace] = if lazy {
+            &KEY_NAMESPACES
+        } else {
+            &[
+                Namespace::Type,
+                Namespace::Data,
+                Namespace::Ttl,
+                Namespace::Length,
+                Namespace::FieldTtl,
+                Namespace::Sample,
+                Namespace::Element,
+                Namespace::Expiry,
+            ]
+        };
+
+        // Range deletes aren't available through a TransactionDB, so every
+        // record is deleted on its own, a batch at a time
+        for shard in self.shards.iter() {
+            for ns in namespaces {
+                let prefix = keyformat::namespace_prefix(*ns, DEFAULT_DB);
+                let mut batch = WriteBatchWithTransaction::<true>::default();
+                for entry in shard.iterator_opt(
+                    IteratorMode::From(&prefix, Direction::Forward),
+                    total_order(),
+                ) {
+                    let (record_key, _) = entry?;
+                    if !record_key.starts_with(&prefix) {
+                        break;
+                    }
+
+                    batch.delete(record_key);
+                    if batch.len() >= MIGRATION_BATCH_SIZE {
+                        shard.write(mem::take(&mut batch))?;
+                    }
+                }
+                shard.write(batch)?;
+            }
+        }
+
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+
+        Ok(())
+    }
+
+    fn reclaim_all(&self) -> Result<u64, DatabaseError> {
+        let mut reclaimed = 0;
+        for shard in self.shards.iter() {
+            for ns in [Namespace::Sample, Namespace::Element] {
+                let prefix = keyformat::namespace_prefix(ns, DEFAULT_DB);
+                let mut last_key: Option<Vec<u8>> = None;
+                for entry in shard.iterator_opt(
+                    IteratorMode::From(&prefix, Direction::Forward),
+                    total_order(),
+                ) {
+                    let (sub_key, _) = entry?;
+                    let key = match keyformat::decode(ns, DEFAULT_DB, &sub_key) {
+                        Some((key, _)) => key,
+                        None => break,
+                    };
+                    if last_key.as_deref() == Some(key) {
+                        continue;
+                    }
+                    last_key = Some(key.to_vec());
+
+                    // Keys created since the flush keep their sub-records
+                    if shard.get(encode_key(Namespace::Type, key))?.is_some() {
+                        continue;
+                    }
+                    self.reclaim(key)?;
+                    reclaimed += 1;
+                }
+            }
+        }
+
+        Ok(reclaimed)
+    }
+
+    fn delete_expiry(&self, key: &[u8]) -> Result<i64, DatabaseError> {
+        self.delete_expiry(key)
+    }
+
+    fn copy(&self, src: &[u8], dest: &[u8], replace: bool) -> Result<bool, DatabaseError> {
+        let txn = self.shard_for_keys([src, dest])?.transaction();
+        let (type_value, data_value, ttl_value) = self.get_triple_for_update(
+            &txn,
+            encode_key(Namespace::Type, src),
+            encode_key(Namespace::Data, src),
+            encode_key(Namespace::Ttl, src),
+            false,
+        )?;
+        if type_value.is_none() || self.is_expired(&ttl_value)? {
+            return Ok(false);
+        }
+        if self.exists_for_update(&txn, dest)? && !replace {
+            return Ok(false);
+        }
+
+        self.delete_typed_value_txn(&txn, dest)?;
+        let type_id = String::from_utf8_lossy(&type_value.unwrap()).into_owned();
+        self.put_typed_value_txn(&txn, dest, data_value.unwrap_or_default(), &type_id)?;
+        if let Some(ttl_value) = ttl_value {
+            self.put_ttl_txn(&txn, dest, &ttl_value)?;
+        }
+        if let Some(ttls) = txn.get(encode_key(Namespace::FieldTtl, src))? {
+            self.put_field_ttls_txn(&txn, dest, &parse_field_ttls(&ttls)?)?;
+        }
+
+        // Time series keep their samples in records of their own, and lists,
+        // sets and hashes their elements
+        for (prefix, dest_prefix) in [
+            (sample_key_prefix(src), sample_key_prefix(dest)),
+            (element_key_prefix(src), element_key_prefix(dest)),
+        ] {
+            for entry in txn.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+                let (sub_key, value) = entry?;
+                if !sub_key.starts_with(&prefix) {
+                    break;
+                }
+                txn.put([&dest_prefix, &sub_key[prefix.len()..]].concat(), value)?;
+            }
+        }
+
+        self.commit(txn, [dest])?;
+
+        Ok(true)
+    }
+
+    fn bloom_reserve(&self, key: &[u8], filter: BloomFilter) -> Result<(), DatabaseError> {
+        self.create_object(key, TYPE_BLOOM, &filter)
+    }
+
+    fn bloom_add(&self, key: &[u8], items: Vec<Vec<u8>>) -> Result<Vec<bool>, DatabaseError> {
+        self.update_object(
+            key,
+            TYPE_BLOOM,
+            || Ok(BloomFilter::default()),
+            |filter: &mut BloomFilter| items.iter().map(|item| Ok(filter.add(item)?)).collect(),
+        )
+    }
+
+    fn bloom_exists(&self, key: &[u8], items: Vec<Vec<u8>>) -> Result<Vec<bool>, DatabaseError> {
+        let filter: Option<BloomFilter> = self.get_object(key, TYPE_BLOOM)?;
+        Ok(items
+            .iter()
+            .map(|item| filter.as_ref().is_some_and(|f| f.contains(item)))
+            .collect())
+    }
+
+    fn cms_init(&self, key: &[u8], sketch: CountMinSketch) -> Result<(), DatabaseError> {
+        self.create_object(key, TYPE_CMS, &sketch)
+    }
+
+    fn cms_incr_by(
+        &self,
+        key: &[u8],
+        items: Vec<(Vec<u8>, u64)>,
+    ) -> Result<Vec<u64>, DatabaseError> {
+        self.update_object(
+            key,
+            TYPE_CMS,
+            || Err(SketchError::KeyMissing.into()),
+            |sketch: &mut CountMinSketch| {
+                Ok(items
+                    .iter()
+                    .map(|(item, increment)| sketch.incr_by(item, *increment))
+                    .collect())
+            },
+        )
+    }
+
+    fn cms_query(&self, key: &[u8], items: Vec<Vec<u8>>) -> Result<Vec<u64>, DatabaseError> {
+        let sketch: CountMinSketch = self.cms_info(key)?;
+        Ok(items.iter().map(|item| sketch.query(item)).collect())
+    }
+
+    fn cms_info(&self, key: &[u8]) -> Result<CountMinSketch, DatabaseError> {
+        match self.get_object(key, TYPE_CMS)? {
+            Some(sketch) => Ok(sketch),
+            None => Err(SketchError::KeyMissing.into()),
+        }
+    }
+
+    fn cms_merge(&self, dest: &[u8], sources: Vec<(Vec<u8>, u64)>) -> Result<(), DatabaseError> {
+        let keys = sources.iter().map(|(src, _)| src.as_slice());
+        let txn = self.shard_for_keys(keys.chain([dest]))?.transaction();
+
+        let mut src_sketches = vec![];
+        for (src, weight) in sources.iter() {
+            match self.get_typed_value_for_update(&txn, src, TYPE_CMS, false)? {
+                Some(data) => {
+                    let sketch: CountMinSketch = serde_json::from_slice(&data)?;
+                    src_sketches.push((sketch, *weight));
+                }
+                None => return Err(SketchError::KeyMissing.into()),
+            }
+        }
+
+        let Some(data) = self.get_typed_value_for_update(&txn, dest, TYPE_CMS, true)? else {
+            return Err(SketchError::KeyMissing.into());
+        };
+
+        let mut sketch: CountMinSketch = serde_json::from_slice(&data)?;
+        sketch.merge(&src_sketches)?;
+
+        let data = serde_json::to_vec(&sketch)?;
+        self.update_typed_value_txn(&txn, dest, data, TYPE_CMS)?;
+
+        self.commit(txn, [dest])
+    }
+
+    fn topk_reserve(&self, key: &[u8], topk: TopK) -> Result<(), DatabaseError> {
+        self.create_object(key, TYPE_TOPK, &topk)
+    }
+
+    fn topk_add(
+        &self,
+        key: &[u8],
+        items: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.update_object(
+            key,
+            TYPE_TOPK,
+            || Err(SketchError::KeyMissing.into()),
+            |topk: &mut TopK| Ok(items.iter().map(|item| topk.add(item)).collect()),
+        )
+    }
+
+    fn topk_info(&self, key: &[u8]) -> Result<TopK, DatabaseError> {
+        match self.get_object(key, TYPE_TOPK)? {
+            Some(topk) => Ok(topk),
+            None => Err(SketchError::KeyMissing.into()),
+        }
+    }
+
+    fn tdigest_create(&self, key: &[u8], digest: TDigest) -> Result<(), DatabaseError> {
+        self.create_object(key, TYPE_TDIGEST, &digest)
+    }
+
+    fn tdigest_add(&self, key: &[u8], values: Vec<f64>) -> Result<(), DatabaseError> {
+        self.update_object(
+            key,
+            TYPE_TDIGEST,
+            || Err(SketchError::KeyMissing.into()),
+            |digest: &mut TDigest| Ok(digest.add(&values)?),
+        )
+    }
+
+    fn tdigest_info(&self, key: &[u8]) -> Result<TDigest, DatabaseError> {
+        match self.get_object(key, TYPE_TDIGEST)? {
+            Some(digest) => Ok(digest),
+            None => Err(SketchError::KeyMissing.into()),
+        }
+    }
+
+    fn tdigest_merge(
+        &self,
+        dest: &[u8],
+        sources: Vec<Vec<u8>>,
+        compression: Option<f64>,
+        override_dest: bool,
+    ) -> Result<(), DatabaseError> {
+        let txn = self
+            .shard_for_keys(sources.iter().map(|src| src.as_slice()).chain([dest]))?
+            .transaction();
+
+        let mut src_digests: Vec<TDigest> = vec![];
+        for src in sources.iter() {
+            match self.get_typed_value_for_update(&txn, src, TYPE_TDIGEST, false)? {
+                Some(data) => src_digests.push(serde_json::from_slice(&data)?),
+                None => return Err(SketchError::KeyMissing.into()),
+            }
+        }
+
+        // Without an explicit compression, the destination gets the
+        // largest compression among the sources so no precision is lost
+        let compression = compression.unwrap_or_else(|| {
+            src_digests
+                .iter()
+                .map(|d| d.compression())
+                .fold(f64::MIN, f64::max)
+        });
+
+        let existing = self.get_typed_value_for_update(&txn, dest, TYPE_TDIGEST, true)?;
+        let mut digest = match existing {
+            Some(data) if { !override_dest } => {
+                let existing: TDigest = serde_json::from_slice(&data)?;
+                let mut digest = TDigest::new(compression)?;
+                digest.merge(&[existing]);
+                digest
+            }
+            _ => TDigest::new(compression)?,
+        };
+        digest.merge(&src_digests);
+
+        // Merging into the destination keeps its TTL, while overriding it
+        // replaces it outright
+        let data = serde_json::to_vec(&digest)?;
+        if override_dest {
+            self.put_typed_value_txn(&txn, dest, data, TYPE_TDIGEST)?;
+        } else {
+            self.update_typed_value_txn(&txn, dest, data, TYPE_TDIGEST)?;
+        }
+
+        self.commit(txn, [dest])
+    }
+
+    fn ts_create(&self, key: &[u8], info: TimeSeriesInfo) -> Result<(), DatabaseError> {
+        let txn = self.shard(key).transaction();
+        if self.exists_for_update(&txn, key)? {
+            return Err(TimeSeriesError::KeyExists.into());
+        }
+
+        // Samples may be left behind by a series that expired
+        self.delete_samples_txn(&txn, key, u64::MAX)?;
+
+        let data = serde_json::to_vec(&info)?;
+        self.put_typed_value_txn(&txn, key, data, TYPE_TIMESERIES)?;
+
+        self.commit(txn, [key])
+    }
+
+    fn ts_add(
+        &self,
+        key: &[u8],
+        timestamp: u64,
+        value: f64,
+        defaults: TimeSeriesInfo,
+    ) -> Result<(), DatabaseError> {
+        let txn = self.shard(key).transaction();
+        let mut info: TimeSeriesInfo =
+            match self.get_typed_value_for_update(&txn, key, TYPE_TIMESERIES, true)? {
+                Some(data) => serde_json::from_slice(&data)?,
+                None => {
+                    self.delete_samples_txn(&txn, key, u64::MAX)?;
+                    defaults
+                }
+            };
+
+        if timestamp < info.retention_cutoff() {
+            return Err(TimeSeriesError::TooOld.into());
+        }
+
+        let sample_key = sample_key(key, timestamp);
+        if txn.get_for_update(&sample_key, true)?.is_some() {
+            return Err(TimeSeriesError::DuplicateSample.into());
+        }
+        txn.put(sample_key, value.to_be_bytes())?;
+
+        if info.total_samples == 0 {
+            info.first_timestamp = timestamp;
+            info.last_timestamp = timestamp;
+        } else {
+            info.first_timestamp = info.first_timestamp.min(timestamp);
+            info.last_timestamp = info.last_timestamp.max(timestamp);
+        }
+        info.total_samples += 1;
+
+        // Trim samples that fell out of the retention window
+        let cutoff = info.retention_cutoff();
+        if info.first_timestamp < cutoff {
+            info.total_samples -= self.delete_samples_txn(&txn, key, cutoff)?;
+            if let Some((first, _)) = self.first_sample_txn(&txn, key)? {
+                info.first_timestamp = first;
+            }
+        }
+
+        let data = serde_json::to_vec(&info)?;
+        self.update_typed_value_txn(&txn, key, data, TYPE_TIMESERIES)?;
+
+        self.commit(txn, [key])
+    }
+
+    fn ts_info(&self, key: &[u8]) -> Result<TimeSeriesInfo, DatabaseError> {
+        match self.get_object(key, TYPE_TIMESERIES)? {
+            Some(info) => Ok(info),
+            None => Err(TimeSeriesError::KeyMissing.into()),
+        }
+    }
+
+    fn ts_range(&self, key: &[u8], from: u64, to: u64) -> Result<Vec<(u64, f64)>, DatabaseError> {
+        // Samples are only read through the series, so expired or deleted
+        // series don't expose any leftovers
+        self.ts_info(key)?;
+
+        let prefix = sample_key_prefix(key);
+        let start = sample_key(key, from);
+        let mut samples = vec![];
+        for entry in self
+            .shard(key)
+            .iterator(IteratorMode::From(&start, Direction::Forward))
+        {
+            let (sample_key, value) = entry?;
+            if !sample_key.starts_with(&prefix) {
+                break;
+            }
+
+            let sample = parse_sample(prefix.len(), &sample_key, &value);
+            if sample.0 > to {
+                break;
+            }
+            samples.push(sample);
+        }
+
+        Ok(samples)
+    }
+
+    fn ts_query_index(
+        &self,
+        filters: Vec<LabelFilter>,
+    ) -> Result<Vec<(Vec<u8>, TimeSeriesInfo)>, DatabaseError> {
+        // Series can live on any shard, so every shard's index is scanned
+        let prefix = keyformat::namespace_prefix(Namespace::Type, DEFAULT_DB);
+        let mut results = vec![];
+        for shard in self.shards.iter() {
+            for entry in shard.iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                total_order(),
+            ) {
+                let (type_key, type_value) = entry?;
+                let key = match keyformat::decode(Namespace::Type, DEFAULT_DB, &type_key) {
+                    Some((key, _)) => key,
+                    None => break,
+                };
+                if !type_value.eq_ignore_ascii_case(TYPE_TIMESERIES.as_bytes()) {
+                    continue;
+                }
+
+                let info: Option<TimeSeriesInfo> = self.get_object(key, TYPE_TIMESERIES)?;
+                if let Some(info) = info {
+                    if filters.iter().all(|f| f.matches(&info)) {
+                        results.push((key.to_vec(), info));
+                    }
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn storage_stats(&self) -> Result<StorageStats, DatabaseError> {
+        let mut stats = StorageStats {
+            properties: STORAGE_PROPERTIES
+                .iter()
+                .map(|(name, _)| (*name, 0))
+                .collect(),
+            ..Default::default()
+        };
+
+        for shard in self.shards.iter() {
+            for (i, (_, property)) in STORAGE_PROPERTIES.iter().enumerate() {
+                stats.properties[i].1 += shard.property_int_value(*property)?.unwrap_or(0);
+            }
+
+            let levels = shard
+                .property_value("rocksdb.levelstats")?
+                .unwrap_or_default();
+            for (level, (files, size_mb)) in parse_level_stats(&levels).into_iter().enumerate() {
+                if stats.levels.len() <= level {
+                    stats.levels.push((0, 0));
+                }
+                stats.levels[level].0 += files;
+                stats.levels[level].1 += size_mb;
+            }
+
+            let stalls = shard
+                .property_value("rocksdb.cf-write-stall-stats")?
+                .unwrap_or_default();
+            stats.total_delays += parse_stall_count(&stalls, "total-delays");
+            stats.total_stops += parse_stall_count(&stalls, "total-stops");
+        }
+
+        Ok(stats)
+    }
+
+    fn check_integrity(&self, repair: bool) -> Result<Vec<Inconsistency>, DatabaseError> {
+        let mut found = vec![];
+        for shard in self.shards.iter() {
+            self.check_shard(shard, repair, &mut found)?;
+        }
+
+        Ok(found)
+    }
+
+    fn check_writable(&self) -> Result<(), DatabaseError> {
+        for shard in self.shards.iter() {
+            shard.put(WRITE_PROBE_KEY, b"1")?;
+            shard.delete(WRITE_PROBE_KEY)?;
+        }
+        Ok(())
+    }
+
+    fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn now(&self) -> Result<Duration, DatabaseError> {
+        Ok(self.clock.unix_timestamp()?)
+    }
+
+    fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        filter: ScanFilter,
+    ) -> Result<(u64, Vec<Vec<u8>>), DatabaseError> {
+        // The cursor is taken out while it's read from, so that scans only
+        // hold the lock to take and return their cursors
+        let mut scan = match cursor {
+            0 => self.start_scan(),
+            id => self
+                .cursors
+                .lock()
+                .unwrap()
+                .take(id)
+                .ok_or(DatabaseError::InvalidCursor)?,
+        };
+
+        let prefix = keyformat::namespace_prefix(Namespace::Type, DEFAULT_DB);
+        let mut keys = vec![];
+        let mut examined = 0;
+        while scan.shard < scan.snapshots.len() && examined < count {
+            let snapshot = scan.snapshots[scan.shard].snapshot();
+            let start = match scan.after.as_deref() {
+                Some(after) => encode_key(Namespace::Type, after),
+                None => prefix.clone(),
+            };
+
+            let mut finished_shard = true;
+            for entry in snapshot.iterator_opt(
+                IteratorMode::From(&start, Direction::Forward),
+                total_order(),
+            ) {
+                let (type_key, type_value) = entry?;
+                let key = match keyformat::decode(Namespace::Type, DEFAULT_DB, &type_key) {
+                    Some((key, _)) => key,
+                    None => break,
+                };
+                if scan.after.as_deref() == Some(key) {
+                    continue;
+                }
+                if examined == count {
+                    finished_shard = false;
+                    break;
+                }
+
+                examined += 1;
+                scan.after = Some(key.to_vec());
+                if !filter.matches(key, &type_value) {
+                    continue;
+                }
+                let ttl_value = snapshot.get(encode_key(Namespace::Ttl, key))?;
+                if !self.is_expired(&ttl_value)? {
+                    keys.push(key.to_vec());
+                }
+            }
+
+            if finished_shard {
+                scan.shard += 1;
+                scan.after = None;
+            }
+        }
+
+        if scan.shard == scan.snapshots.len() {
+            return Ok((0, keys));
+        }
+        Ok((self.cursors.lock().unwrap().insert(scan), keys))
+    }
+
+    fn key_sizes(
+        &self,
+        index: usize,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<KeySize>, DatabaseError> {
+        let shard = &self.shards[index];
+
+        // Keys come back ordered by length, then by content
+        let start = match after.as_deref() {
+            Some(after) => encode_key(Namespace::Type, after),
+            None => keyformat::namespace_prefix(Namespace::Type, DEFAULT_DB),
+        };
+        let mut sizes = vec![];
+        for entry in shard.iterator_opt(
+            IteratorMode::From(&start, Direction::Forward),
+            total_order(),
+        ) {
+            let (type_key, type_value) = entry?;
+            let key = match keyformat::decode(Namespace::Type, DEFAULT_DB, &type_key) {
+                Some((key, _)) if { sizes.len() < limit } => key,
+                _ => break,
+            };
+            if after.as_deref() == Some(key) {
+                continue;
+            }
+            if let Some(size) = self.key_size(shard, key, &type_value)? {
+                sizes.push(size);
+            }
+        }
+
+        Ok(sizes)
+    }
+
+    fn delete_expired(
+        &self,
+        index: usize,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, bool), DatabaseError> {
+        let shard = &self.shards[index];
+        let now = self.clock.unix_timestamp()?.as_millis() as u64;
+
+        // Entries are ordered by expiry time, so the first one that isn't
+        // due yet ends the scan
+        let prefix = keyformat::namespace_prefix(Namespace::Expiry, DEFAULT_DB);
+        let mut due = vec![];
+        let mut more = false;
+        for entry in shard.iterator_opt(
+            IteratorMode::From(&prefix, Direction::Forward),
+            total_order(),
+        ) {
+            let (index_key, _) = entry?;
+            match keyformat::decode_expiry(DEFAULT_DB, &index_key) {
+                Some((expires_at, _)) if { expires_at <= now } => {}
+                _ => break,
+            }
+            if due.len() == limit {
+                more = true;
+                break;
+            }
+            due.push(index_key);
+        }
+
+        let mut deleted = vec![];
+        for index_key in due {
+            let (expires_at, key) = keyformat::decode_expiry(DEFAULT_DB, &index_key).unwrap();
+
+            // The key's TTL is checked under the lock, since the entry may
+            // be stale, or the key may have been written since. The key is
+            // locked like a command would, so that nothing reading it sees
+            // it half expired.
+            let _guard = self.locks.lock_key(key);
+            let txn = shard.transaction();
+            let ttl_value = txn.get_for_update(encode_key(Namespace::Ttl, key), true)?;
+            let current = match ttl_value {
+                Some(ttl_value) => parse_timestamp(&ttl_value)?.as_millis() as u64 == expires_at,
+                None => false,
+            };
+            if current {
+                self.delete_typed_value_txn(&txn, key)?;
+                deleted.push(key.to_vec());
+            } else {
+                // Entries also come due for hash fields, and a hash whose
+                // last fields lapse goes with them
+                txn.delete(&index_key)?;
+                if self.expire_fields_txn(&txn, key)? {
+                    deleted.push(key.to_vec());
+                }
+            }
+            self.commit(txn, [key])?;
+        }
+
+        Ok((deleted, more))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, env, thread};
+
+    use rocksdb::DB;
+
+    use super::*;
+    use crate::time::MockClock;
+
+    fn with_database(name: &str, f: impl FnOnce(Arc<Database>)) {
+        with_sharded_database(name, 1, f)
+    }
+
+    fn with_sharded_database(name: &str, n_shards: usize, f: impl FnOnce(Arc<Database>)) {
+        with_clocked_database(name, n_shards, Arc::new(SystemClock), f)
+    }
+
+    fn with_clocked_database(
+        name: &str,
+        n_shards: usize,
+        clock: Arc<dyn Clock>,
+        f: impl FnOnce(Arc<Database>),
+    ) {
+        with_configured_database(name, n_shards, |db| db.with_clock(clock), f)
+    }
+
+    fn with_configured_database(
+        name: &str,
+        n_shards: usize,
+        configure: impl FnOnce(Database) -> Database,
+        f: impl FnOnce(Arc<Database>),
+    ) {
+        let paths: Vec<_> = (0..n_shards)
+            .map(|i| {
+                env::temp_dir().join(format!("wedis-test-{}-{}-{}", name, std::process::id(), i))
+            })
+            .collect();
+        for path in paths.iter() {
+            let _ = DB::destroy(&Options::default(), path);
+        }
+        {
+            let shards = paths
+                .iter()
+                .map(|path| open_shard(path).expect("Failed to open database"))
+                .collect();
+            f(Arc::new(configure(Database::with_shards(shards))));
+        }
+        for path in paths.iter() {
+            let _ = DB::destroy(&Options::default(), path);
+        }
+    }
+
+    #[test]
+    fn test_get_and_put_string_concurrent() {
+        with_database("getset", |db| {
+            let key = "key".as_bytes();
+            let n_threads = 8;
+            let n_writes = 50;
+
+            let handles: Vec<_> = (0..n_threads)
+                .map(|t| {
+                    let db = db.clone();
+                    thread::spawn(move || {
+                        let mut seen = vec![];
+                        for i in 0..n_writes {
+                            let value = format!("{}-{}", t, i);
+                            let old = db.get_and_put_string(key, value.as_bytes()).unwrap();
+                            seen.push(old);
+                        }
+                        seen
+                    })
+                })
+                .collect();
+
+            let mut returned: Vec<Option<Vec<u8>>> = vec![];
+            for handle in handles {
+                returned.extend(handle.join().unwrap());
+            }
+
+            // Every written value must be observed exactly once, either as the
+            // previous value of another write or as the final value
+            let n_missing = returned.iter().filter(|v| v.is_none()).count();
+            assert_eq!(1, n_missing);
+
+            let mut values: Vec<Vec<u8>> = returned.into_iter().flatten().collect();
+            values.push(db.get_string(key).unwrap().unwrap());
+
+            let unique: HashSet<Vec<u8>> = values.iter().cloned().collect();
+            assert_eq!(n_threads * n_writes, values.len());
+            assert_eq!(values.len(), unique.len());
+        });
+    }
+
+    #[test]
+    fn test_get_and_delete_string_concurrent() {
+        with_database("getdel", |db| {
+            let key = "key".as_bytes();
+            db.put_string(key, "value".as_bytes()).unwrap();
+
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    let db = db.clone();
+                    thread::spawn(move || db.get_and_delete_string(key).unwrap())
+                })
+                .collect();
+
+            let n_found = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|v| v.is_some())
+                .count();
+            assert_eq!(1, n_found);
+            assert_eq!(None, db.get_string(key).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_put_strings_if_absent_concurrent() {
+        with_database("setnx", |db| {
+            let key = "key".as_bytes();
+
+            let handles: Vec<_> = (0..8)
+                .map(|t: i32| {
+                    let db = db.clone();
+                    thread::spawn(move || {
+                        let value = t.to_string().into_bytes();
+                        db.put_strings_if_absent(vec![(key.to_vec(), value)])
+                            .unwrap()
+                    })
+                })
+                .collect();
+
+            let n_set = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|set| *set)
+                .count();
+            assert_eq!(1, n_set);
+        });
+    }
+
+    #[test]
+    fn test_put_strings_if_absent_partial() {
+        with_database("msetnx", |db| {
+            db.put_string("b".as_bytes(), "existing".as_bytes())
+                .unwrap();
+
+            let entries = vec![
+                ("a".as_bytes().to_vec(), "1".as_bytes().to_vec()),
+                ("b".as_bytes().to_vec(), "2".as_bytes().to_vec()),
+            ];
+            assert!(!db.put_strings_if_absent(entries).unwrap());
+
+            // Nothing may be written if any key already exists
+            assert_eq!(None, db.get_string("a".as_bytes()).unwrap());
+            assert_eq!(
+                Some("existing".as_bytes().to_vec()),
+                db.get_string("b".as_bytes()).unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_stored_integer() {
+        assert_eq!(Some(-12), parse_stored_integer(b"-12"));
+        assert_eq!(Some(0), parse_stored_integer(b"0"));
+        assert_eq!(
+            Some(i64::MIN),
+            parse_stored_integer(b"-9223372036854775808")
+        );
+        for value in [
+            "",
+            "-",
+            "-0",
+            "+1",
+            "01",
+            " 1",
+            "1.0",
+            "9223372036854775808",
+        ] {
+            assert_eq!(None, parse_stored_integer(value.as_bytes()), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_increment_by_errors() {
+        with_database("incr-errors", |db| {
+            db.put_string(b"text", b"abc").unwrap();
+            assert!(matches!(
+                db.increment_by(b"text", 1),
+                Err(DatabaseError::NotInteger)
+            ));
+
+            db.put_string(b"max", i64::MAX.to_string().as_bytes())
+                .unwrap();
+            assert!(matches!(
+                db.increment_by(b"max", 1),
+                Err(DatabaseError::Overflow)
+            ));
+            // The value is left as it was
+            assert_eq!(
+                Some(i64::MAX.to_string().into_bytes()),
+                db.get_string(b"max").unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!("10.6", format_float(10.5 + 0.1));
+        assert_eq!("3", format_float(3.0));
+        assert_eq!("0", format_float(-0.0));
+        assert_eq!("0.0000001", format_float(1e-7));
+        assert_eq!("5000000000000000000000", format_float(5e21));
+        assert_eq!("0.30000000000000004", format_float(0.1 + 0.2));
+    }
+
+    #[test]
+    fn test_increment_by_float() {
+        with_database("incrbyfloat", |db| {
+            db.put_string(b"key", b"10.50").unwrap();
+            assert_eq!(10.6, db.increment_by_float(b"key", 0.1).unwrap());
+            assert_eq!(Some(b"10.6".to_vec()), db.get_string(b"key").unwrap());
+
+            db.put_string(b"key", b"1e308").unwrap();
+            assert!(matches!(
+                db.increment_by_float(b"key", 1e308),
+                Err(DatabaseError::NotFinite)
+            ));
+            assert_eq!(Some(b"1e308".to_vec()), db.get_string(b"key").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_read_string() {
+        with_database("read-string", |db| {
+            let mut read = vec![];
+            assert!(!db.read_string(b"key", &mut |v| read = v.to_vec()).unwrap());
+
+            db.put_string(b"key", b"value").unwrap();
+            assert!(db.read_string(b"key", &mut |v| read = v.to_vec()).unwrap());
+            assert_eq!(b"value".to_vec(), read);
+
+            db.put_hash_fields(b"hash", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
+            assert!(matches!(
+                db.read_string(b"hash", &mut |_| {}),
+                Err(DatabaseError::WrongType { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_get_string_length() {
+        with_database("strlen", |db| {
+            assert_eq!(0, db.get_string_length(b"key").unwrap());
+
+            db.put_string(b"key", b"value").unwrap();
+            assert_eq!(5, db.get_string_length(b"key").unwrap());
+            db.append(b"key", b"s").unwrap();
+            assert_eq!(6, db.get_string_length(b"key").unwrap());
+
+            db.put_hash_fields(b"hash", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
+            assert!(matches!(
+                db.get_string_length(b"hash"),
+                Err(DatabaseError::WrongType { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_append() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("append", 1, clock.clone(), |db| {
+            assert_eq!(5, db.append(b"key", b"hello").unwrap());
+            assert_eq!(11, db.append(b"key", b" world").unwrap());
+            assert_eq!(
+                Some(b"hello world".to_vec()),
+                db.get_string(b"key").unwrap()
+            );
+
+            // Replacing the value forgets the recorded length
+            db.put_string(b"key", b"abc").unwrap();
+            assert_eq!(4, db.append(b"key", b"d").unwrap());
+            assert_eq!(Some(b"abcd".to_vec()), db.get_string(b"key").unwrap());
+
+            // Appending keeps the TTL, but an expired key starts over
+            db.put_expiry(b"key", Duration::from_secs(10)).unwrap();
+            assert_eq!(5, db.append(b"key", b"e").unwrap());
+            assert!(DatabaseOperations::get_expiry(&*db, b"key")
+                .unwrap()
+                .is_some());
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(1, db.append(b"key", b"f").unwrap());
+            assert_eq!(Some(b"f".to_vec()), db.get_string(b"key").unwrap());
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, b"key").unwrap());
+
+            db.put_hash_fields(b"hash", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
+            assert!(matches!(
+                db.append(b"hash", b"x"),
+                Err(DatabaseError::WrongType { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_delete_expired() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("delete-expired", 1, clock.clone(), |db| {
+            db.put_string_with_expiry(b"a", b"1", Duration::from_secs(10))
+                .unwrap();
+            db.put_string_with_expiry(b"b", b"2", Duration::from_secs(20))
+                .unwrap();
+            db.put_hash_fields(b"c", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
+            db.put_expiry(b"c", Duration::from_secs(5)).unwrap();
+
+            // Stale entries, from a TTL that was replaced and one that was
+            // removed along with its value
+            db.put_string_with_expiry(b"d", b"4", Duration::from_secs(1))
+                .unwrap();
+            db.put_string_with_expiry(b"d", b"4", Duration::from_secs(100))
+                .unwrap();
+            db.put_string_with_expiry(b"e", b"5", Duration::from_secs(1))
+                .unwrap();
+            db.put_string(b"e", b"5").unwrap();
+
+            assert_eq!((vec![], false), db.delete_expired(0, 10).unwrap());
+
+            // The stale entries come due first, and are only dropped
+            clock.advance(Duration::from_secs(10));
+            assert_eq!((vec![], true), db.delete_expired(0, 2).unwrap());
+            let (deleted, more) = db.delete_expired(0, 2).unwrap();
+            assert_eq!(vec![b"c".to_vec(), b"a".to_vec()], deleted);
+            assert!(!more);
+            assert_eq!((vec![], false), db.delete_expired(0, 2).unwrap());
+
+            // Every record of the deleted keys is gone, not just hidden
+            let shard = &db.shards[0];
+            for ns in [Namespace::Type, Namespace::Data, Namespace::Ttl] {
+                assert_eq!(None, shard.get(encode_key(ns, b"a")).unwrap());
+                assert_eq!(None, shard.get(encode_key(ns, b"c")).unwrap());
+            }
+            assert_eq!(Some(b"2".to_vec()), db.get_string(b"b").unwrap());
+            assert_eq!(Some(b"4".to_vec()), db.get_string(b"d").unwrap());
+            assert_eq!(Some(b"5".to_vec()), db.get_string(b"e").unwrap());
+
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(
+                (vec![b"b".to_vec()], false),
+                db.delete_expired(0, 10).unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn test_delete_many() {
+        with_sharded_database("delete-many", 2, |db| {
+            db.put_string(b"a", b"1").unwrap();
+            db.put_string_with_expiry(b"b", b"2", Duration::from_secs(100))
+                .unwrap();
+            db.put_hash_fields(b"c", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
+            db.ts_add(b"d", 1, 1.0, TimeSeriesInfo::default()).unwrap();
+            db.put_string(b"e", b"5").unwrap();
+
+            let keys = vec![
+                b"a".to_vec(),
+                b"b".to_vec(),
+                b"c".to_vec(),
+                b"d".to_vec(),
+                b"a".to_vec(),
+                b"missing".to_vec(),
+            ];
+            assert_eq!(4, db.delete_many(keys).unwrap());
+            for key in [&b"a"[..], b"b", b"c", b"d"] {
+                assert!(!db.exists(key).unwrap());
+            }
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, b"b").unwrap());
+            assert!(db.check_integrity(false).unwrap().is_empty());
+            assert_eq!(Some(b"5".to_vec()), db.get_string(b"e").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_unlink() {
+        with_database("unlink", |db| {
+            let samples = |key: &[u8]| {
+                let prefix = sample_key_prefix(key);
+                db.shards[0]
+                    .iterator(IteratorMode::From(&prefix, Direction::Forward))
+                    .map(|entry| entry.unwrap().0)
+                    .take_while(|record_key| record_key.starts_with(&prefix))
+                    .count()
+            };
+
+            db.ts_add(b"ts", 1, 1.0, TimeSeriesInfo::default()).unwrap();
+            db.ts_add(b"ts", 2, 2.0, TimeSeriesInfo::default()).unwrap();
+            assert_eq!(1, db.unlink(b"ts").unwrap());
+            assert_eq!(0, db.unlink(b"ts").unwrap());
+            assert!(!db.exists(b"ts").unwrap());
+            assert_eq!(2, samples(b"ts"));
+
+            db.reclaim(b"ts").unwrap();
+            assert_eq!(0, samples(b"ts"));
+
+            // Reclaiming a key that was created again keeps the new value
+            db.ts_add(b"ts", 1, 1.0, TimeSeriesInfo::default()).unwrap();
+            db.unlink(b"ts").unwrap();
+            db.ts_add(b"ts", 5, 5.0, TimeSeriesInfo::default()).unwrap();
+            db.reclaim(b"ts").unwrap();
+            assert_eq!(vec![(5, 5.0)], db.ts_range(b"ts", 0, 10).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_flush_keys() {
+        with_sharded_database("flush", 2, |db| {
+            db.put_string(b"a", b"1").unwrap();
+            db.put_string_with_expiry(b"b", b"2", Duration::from_secs(100))
+                .unwrap();
+            db.ts_add(b"ts1", 1, 1.0, TimeSeriesInfo::default())
+                .unwrap();
+            db.ts_add(b"ts2", 1, 1.0, TimeSeriesInfo::default())
+                .unwrap();
+
+            db.flush_keys(true).unwrap();
+            for key in [&b"a"[..], b"b", b"ts1", b"ts2"] {
+                assert!(!db.exists(key).unwrap());
+            }
+
+            // Series created since the flush keep their samples
+            db.ts_add(b"ts2", 5, 5.0, TimeSeriesInfo::default())
+                .unwrap();
+            assert_eq!(1, db.reclaim_all().unwrap());
+            assert_eq!(vec![(5, 5.0)], db.ts_range(b"ts2", 0, 10).unwrap());
+            assert!(db.check_integrity(false).unwrap().is_empty());
+
+            db.put_string_with_expiry(b"c", b"3", Duration::from_secs(100))
+                .unwrap();
+            db.flush_keys(false).unwrap();
+            assert!(!db.exists(b"ts2").unwrap());
+            assert!(!db.exists(b"c").unwrap());
+            for shard in db.shards.iter() {
+                for ns in [Namespace::Sample, Namespace::Expiry] {
+                    let prefix = keyformat::namespace_prefix(ns, DEFAULT_DB);
+                    let first = shard
+                        .iterator_opt(
+                            IteratorMode::From(&prefix, Direction::Forward),
+                            total_order(),
+                        )
+                        .next()
+                        .map(|entry| entry.unwrap().0);
+                    assert!(!first.is_some_and(|record_key| record_key.starts_with(&prefix)));
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_set_string() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("set", 1, clock.clone(), |db| {
+            let nx = SetOptions {
+                condition: SetCondition::IfAbsent,
+                expiry: SetExpiry::In(Duration::from_secs(10)),
+                get: false,
+            };
+            assert_eq!((true, None), db.set_string(b"key", b"1", nx).unwrap());
+            assert_eq!((false, None), db.set_string(b"key", b"2", nx).unwrap());
+
+            // KEEPTTL leaves the TTL of the value it replaces
+            let keep = SetOptions {
+                condition: SetCondition::IfPresent,
+                expiry: SetExpiry::Keep,
+                get: true,
+            };
+            assert_eq!(
+                (true, Some(b"1".to_vec())),
+                db.set_string(b"key", b"3", keep).unwrap()
+            );
+            assert_eq!(
+                Some(Duration::from_secs(10)),
+                DatabaseOperations::get_expiry(&*db, b"key").unwrap()
+            );
+
+            // XX doesn't create keys, and an expiry in the past deletes them
+            assert_eq!((false, None), db.set_string(b"new", b"1", keep).unwrap());
+            let past = SetOptions {
+                expiry: SetExpiry::At(Duration::from_secs(500)),
+                ..Default::default()
+            };
+            assert_eq!((true, None), db.set_string(b"key", b"4", past).unwrap());
+            assert_eq!(None, db.get_string(b"key").unwrap());
+
+            // GET needs the old value to be a string
+            db.put_hash_fields(b"hash", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
+            let get = SetOptions {
+                get: true,
+                ..Default::default()
+            };
+            assert!(matches!(
+                db.set_string(b"hash", b"1", get),
+                Err(DatabaseError::WrongType { expected: _ })
+            ));
+            assert_eq!(
+                (true, None),
+                db.set_string(b"hash", b"1", SetOptions::default()).unwrap()
+            );
+            assert_eq!(Some(b"1".to_vec()), db.get_string(b"hash").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_put_string_with_expiry() {
+        with_database("setex", |db| {
+            let key = "key".as_bytes();
+            db.put_string_with_expiry(key, "value".as_bytes(), Duration::from_secs(100))
+                .unwrap();
+
+            assert_eq!(
+                Some("value".as_bytes().to_vec()),
+                db.get_string(key).unwrap()
+            );
+
+            let ttl = DatabaseOperations::get_expiry(&*db, key).unwrap();
+            assert!(ttl.is_some_and(|ttl| ttl > Duration::from_secs(90)));
+        });
+    }
+
+    #[test]
+    fn test_expiry_follows_clock() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("expiry-clock", 1, clock.clone(), |db| {
+            let key = "key".as_bytes();
+            db.put_string_with_expiry(key, "value".as_bytes(), Duration::from_secs(10))
+                .unwrap();
+            assert_eq!(
+                Some(Duration::from_secs(10)),
+                DatabaseOperations::get_expiry(&*db, key).unwrap()
+            );
+            assert_eq!(
+                Some(Duration::from_secs(1010)),
+                DatabaseOperations::get_expiry_at(&*db, key).unwrap()
+            );
+
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(1, DatabaseOperations::exists(&*db, key).unwrap());
+
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(None, db.get_string(key).unwrap());
+            assert_eq!(0, DatabaseOperations::exists(&*db, key).unwrap());
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, key).unwrap());
+            assert_eq!(None, DatabaseOperations::get_expiry_at(&*db, key).unwrap());
+            assert_eq!(None, db.get_type(key).unwrap());
+            let (_, keys) = db.scan(0, 10, ScanFilter::default()).unwrap();
+            assert!(keys.is_empty());
+        });
+    }
 
-fn prepend_key(key: &[u8], prefix: &[u8]) -> Vec<u8> {
-    [prefix, key].concat()
-}
+    #[test]
+    fn test_delete_expiry() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("delete-expiry", 1, clock.clone(), |db| {
+            db.put_string(b"plain", b"1").unwrap();
+            assert_eq!(
+                0,
+                DatabaseOperations::delete_expiry(&*db, b"plain").unwrap()
+            );
+            assert_eq!(
+                0,
+                DatabaseOperations::delete_expiry(&*db, b"missing").unwrap()
+            );
 
-#[derive(Error, Debug)]
-pub enum DatabaseError {
-    #[error("rocksdb error")]
-    RocksDB(#[from] rocksdb::Error),
-    #[error("serialization error")]
-    Serde(#[from] serde_json::Error),
-    #[error("integer parse error")]
-    ParseInt(#[from] std::num::ParseIntError),
-    #[error("float parse error")]
-    ParseFloat(#[from] std::num::ParseFloatError),
-    #[error("time error")]
-    InvalidTime(#[from] TimeError),
-    #[error("unexpected value type (expected {expected:?})")]
-    WrongType { expected: String },
-}
+            db.put_string_with_expiry(b"key", b"1", Duration::from_secs(10))
+                .unwrap();
+            assert_eq!(1, DatabaseOperations::delete_expiry(&*db, b"key").unwrap());
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, b"key").unwrap());
 
-pub struct Database {
-    connect_count: i64,
-    db: TransactionDB,
-}
+            // Lapsed keys stay gone
+            db.put_string_with_expiry(b"lapsed", b"1", Duration::from_secs(10))
+                .unwrap();
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(
+                0,
+                DatabaseOperations::delete_expiry(&*db, b"lapsed").unwrap()
+            );
+            assert_eq!(None, db.get_string(b"lapsed").unwrap());
+        });
+    }
 
-#[cfg_attr(test, automock)]
-pub trait DatabaseOperations {
-    fn get_string(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError>;
+    #[test]
+    fn test_field_expiry() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("field-expiry", 1, clock.clone(), |db| {
+            let fields = |names: &[&str]| -> Vec<Vec<u8>> {
+                names.iter().map(|name| name.as_bytes().to_vec()).collect()
+            };
+            db.put_hash_fields(
+                b"hash",
+                vec![
+                    (b"a".to_vec(), b"1".to_vec()),
+                    (b"b".to_vec(), b"2".to_vec()),
+                    (b"c".to_vec(), b"3".to_vec()),
+                ],
+            )
+            .unwrap();
 
-    fn get_hash_field(&self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError>;
+            assert_eq!(
+                vec![1, 1, -2],
+                db.put_field_expiry(
+                    b"hash",
+                    fields(&["a", "b", "x"]),
+                    Duration::from_secs(10),
+                    ExpiryCondition::Always
+                )
+                .unwrap()
+            );
+            assert_eq!(
+                vec![0, 1],
+                db.put_field_expiry(
+                    b"hash",
+                    fields(&["a", "c"]),
+                    Duration::from_secs(20),
+                    ExpiryCondition::IfNone
+                )
+                .unwrap()
+            );
+            assert_eq!(
+                vec![-2; 2],
+                db.put_field_expiry(
+                    b"missing",
+                    fields(&["a", "b"]),
+                    Duration::from_secs(10),
+                    ExpiryCondition::Always
+                )
+                .unwrap()
+            );
+            assert_eq!(
+                vec![10_000, 10_000, 20_000, -2],
+                db.get_field_expiry(b"hash", fields(&["a", "b", "c", "x"]))
+                    .unwrap()
+            );
+            assert_eq!(
+                vec![1, -2],
+                db.delete_field_expiry(b"hash", fields(&["b", "x"]))
+                    .unwrap()
+            );
+            assert_eq!(
+                vec![-1],
+                db.get_field_expiry(b"hash", fields(&["b"])).unwrap()
+            );
 
-    fn get_expiry(&self, key: &[u8]) -> Result<Option<Duration>, DatabaseError>;
+            // Setting a field again clears its TTL
+            db.put_hash_fields(b"hash", vec![(b"c".to_vec(), b"4".to_vec())])
+                .unwrap();
+            assert_eq!(
+                vec![-1],
+                db.get_field_expiry(b"hash", fields(&["c"])).unwrap()
+            );
 
-    fn put_string(&self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError>;
+            // Lapsed fields are hidden right away, and deleted by the sweeper
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(None, db.get_hash_field(b"hash", b"a").unwrap());
+            assert_eq!(
+                Some(b"2".to_vec()),
+                db.get_hash_field(b"hash", b"b").unwrap()
+            );
+            assert_eq!((vec![], false), db.delete_expired(0, 10).unwrap());
+            let len: u64 = serde_json::from_slice(
+                &db.shards[0]
+                    .get(encode_key(Namespace::Data, b"hash"))
+                    .unwrap()
+                    .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(2, len);
+            assert_eq!(None, db.shards[0].get(member_key(b"hash", b"a")).unwrap());
 
-    fn put_hash_fields(
-        &self,
-        key: &[u8],
-        fields: Vec<(Vec<u8>, Vec<u8>)>,
-    ) -> Result<i64, DatabaseError>;
+            // A hash whose last fields lapse is deleted along with them
+            assert_eq!(
+                vec![1, 1],
+                db.put_field_expiry(
+                    b"hash",
+                    fields(&["b", "c"]),
+                    Duration::from_secs(5),
+                    ExpiryCondition::Always
+                )
+                .unwrap()
+            );
+            clock.advance(Duration::from_secs(5));
+            assert_eq!(
+                (vec![b"hash".to_vec()], false),
+                db.delete_expired(0, 10).unwrap()
+            );
+            for ns in KEY_NAMESPACES {
+                assert_eq!(None, db.shards[0].get(encode_key(ns, b"hash")).unwrap());
+            }
 
-    fn put_expiry(&self, key: &[u8], expires_in: Duration) -> Result<(), DatabaseError>;
+            // An expiry of zero deletes the field
+            db.put_hash_fields(b"hash", vec![(b"a".to_vec(), b"1".to_vec())])
+                .unwrap();
+            assert_eq!(
+                vec![2],
+                db.put_field_expiry(
+                    b"hash",
+                    fields(&["a"]),
+                    Duration::ZERO,
+                    ExpiryCondition::Always
+                )
+                .unwrap()
+            );
+            assert_eq!(None, db.get_type(b"hash").unwrap());
+        });
+    }
 
-    fn exists(&self, key: &[u8]) -> Result<i64, DatabaseError>;
+    #[test]
+    fn test_read_hash_while_fields_expire() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("read-hash-expire", 1, clock.clone(), |db| {
+            // Zero-padded, so that the expiring field comes last
+            let fields: Vec<(Vec<u8>, Vec<u8>)> = (0..HASH_CHUNK_SIZE + 1)
+                .map(|i| (format!("{:05}", i).into_bytes(), b"v".to_vec()))
+                .collect();
+            let last = fields.last().unwrap().0.clone();
+            db.put_hash_fields(b"hash", fields).unwrap();
+            db.put_field_expiry(
+                b"hash",
+                vec![last],
+                Duration::from_secs(10),
+                ExpiryCondition::Always,
+            )
+            .unwrap();
 
-    fn increment_by(&self, key: &[u8], amount: i64) -> Result<i64, DatabaseError>;
+            // The field lapses and is swept after the first chunk was read
+            let mut header = None;
+            let mut read = 0;
+            db.read_hash(b"hash", &mut |len, chunk| {
+                if header.is_none() {
+                    header = Some(len);
+                    clock.advance(Duration::from_secs(20));
+                    assert_eq!(0, db.delete_expired(0, 10).unwrap().0.len());
+                }
+                read += chunk.len();
+            })
+            .unwrap();
+            assert_eq!(Some(read), header);
 
-    fn increment_by_float(&self, key: &[u8], amount: f64) -> Result<f64, DatabaseError>;
+            // Read again, it's gone from both the count and the fields
+            let mut len = 0;
+            read = 0;
+            db.read_hash(b"hash", &mut |n, chunk| {
+                len = n;
+                read += chunk.len();
+            })
+            .unwrap();
+            assert_eq!((HASH_CHUNK_SIZE, HASH_CHUNK_SIZE), (len, read));
+        });
+    }
 
-    fn delete(&self, key: &[u8]) -> Result<i64, DatabaseError>;
+    #[test]
+    fn test_read_hash() {
+        with_database("read-hash", |db| {
+            let fields: Vec<(Vec<u8>, Vec<u8>)> = (0..HASH_CHUNK_SIZE + 1)
+                .map(|i| (i.to_string().into_bytes(), b"v".to_vec()))
+                .collect();
+            db.put_hash_fields(b"hash", fields).unwrap();
 
-    fn delete_expiry(&self, key: &[u8]) -> Result<i64, DatabaseError>;
-}
+            let mut chunks = vec![];
+            let mut read = 0;
+            let found = db
+                .read_hash(b"hash", &mut |len, chunk| {
+                    assert_eq!(HASH_CHUNK_SIZE + 1, len);
+                    chunks.push(chunk.len());
+                    read += chunk.iter().filter(|(_, value)| *value == b"v").count();
+                })
+                .unwrap();
+            assert!(found);
+            assert_eq!(vec![HASH_CHUNK_SIZE, 1], chunks);
+            assert_eq!(HASH_CHUNK_SIZE + 1, read);
 
-trait RString = AsRef<[u8]>;
+            // Fields and values are stored as they are, whatever bytes they
+            // hold
+            db.put_hash_fields(b"binary", vec![(b"\xff".to_vec(), b"\x00\xfe".to_vec())])
+                .unwrap();
+            assert_eq!(
+                Some(b"\x00\xfe".to_vec()),
+                db.get_hash_field(b"binary", b"\xff").unwrap()
+            );
+            db.read_hash(b"binary", &mut |len, chunk| {
+                assert_eq!(1, len);
+                assert_eq!(&[(b"\xff".as_slice(), b"\x00\xfe".as_slice())], chunk);
+            })
+            .unwrap();
 
-impl Database {
-    pub fn new(db: TransactionDB) -> Self {
-        Self {
-            db,
-            connect_count: 0,
-        }
+            assert!(!db.read_hash(b"missing", &mut |_, _| panic!()).unwrap());
+            db.put_string(b"string", b"1").unwrap();
+            assert!(matches!(
+                db.read_hash(b"string", &mut |_, _| panic!()),
+                Err(DatabaseError::WrongType { .. })
+            ));
+        });
     }
 
-    pub fn acquire_connection(&mut self) -> i64 {
-        let current = self.connect_count;
-        self.connect_count += 1;
-        current
+    fn list_elements(db: &Database, key: &[u8]) -> Vec<Vec<u8>> {
+        let prefix = element_key_prefix(key);
+        db.shards[0]
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .map(|entry| entry.unwrap())
+            .take_while(|(element_key, _)| element_key.starts_with(&prefix))
+            .map(|(_, value)| value.to_vec())
+            .collect()
     }
 
-    fn put_expiry<K: RString>(&self, key: K, expires_in: Duration) -> Result<(), DatabaseError> {
-        let data_key = prepend_key(key.as_ref(), DATA_KEY_PREFIX.as_bytes());
-        let ttl_key = prepend_key(key.as_ref(), TTL_KEY_PREFIX.as_bytes());
-        let ttl_ms = serialize_duration_as_timestamp(expires_in)?;
+    #[test]
+    fn test_push_list() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("push-list", 1, clock.clone(), |db| {
+            assert_eq!(
+                0,
+                db.push_list(b"list", vec![b"a".to_vec()], ListEnd::Front, true)
+                    .unwrap()
+            );
+            assert_eq!(None, db.get_type(b"list").unwrap());
 
-        // Begin a transaction on the data key to ensure we don't set
-        // a TTL while the value is being replaced.
-        let txn = self.db.transaction();
-        txn.get_for_update(data_key, true)?;
+            assert_eq!(
+                2,
+                db.push_list(
+                    b"list",
+                    vec![b"a".to_vec(), b"b".to_vec()],
+                    ListEnd::Front,
+                    false
+                )
+                .unwrap()
+            );
+            assert_eq!(
+                3,
+                db.push_list(b"list", vec![b"c".to_vec()], ListEnd::Back, true)
+                    .unwrap()
+            );
+            assert_eq!(Some("list".to_string()), db.get_type(b"list").unwrap());
+            assert_eq!(
+                vec![b"b".to_vec(), b"a".to_vec(), b"c".to_vec()],
+                list_elements(&db, b"list")
+            );
 
-        // Set the TTL
-        txn.put(ttl_key, ttl_ms)?;
+            db.put_string(b"string", b"1").unwrap();
+            assert!(matches!(
+                db.push_list(b"string", vec![b"a".to_vec()], ListEnd::Back, false),
+                Err(DatabaseError::WrongType { .. })
+            ));
 
-        Ok(txn.commit()?)
+            // A list pushed onto one whose TTL lapsed starts out empty
+            db.put_expiry(b"list", Duration::from_secs(10)).unwrap();
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(
+                1,
+                db.push_list(b"list", vec![b"d".to_vec()], ListEnd::Back, false)
+                    .unwrap()
+            );
+            assert_eq!(vec![b"d".to_vec()], list_elements(&db, b"list"));
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, b"list").unwrap());
+        });
     }
 
-    fn get_expiry<K: RString>(&self, key: K) -> Result<Option<Duration>, DatabaseError> {
-        let ttl_key = prepend_key(key.as_ref(), TTL_KEY_PREFIX.as_bytes());
-        let ttl = self.db.get(ttl_key)?;
+    #[test]
+    fn test_put_string_over_list() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("string-over-list", 1, clock.clone(), |db| {
+            let elements = vec![b"a".to_vec(), b"b".to_vec()];
+            db.push_list(b"list", elements.clone(), ListEnd::Back, false)
+                .unwrap();
+            db.put_expiry(b"list", Duration::from_secs(10)).unwrap();
 
-        match ttl {
-            Some(ttl) => Ok(Some(
-                parse_timestamp(&ttl)?.saturating_sub(unix_timestamp()?),
-            )),
-            None => Ok(None),
-        }
+            // The list's elements and expiry index entry go with it
+            db.put_string(b"list", b"1").unwrap();
+            assert!(list_elements(db, b"list").is_empty());
+            let index_key = keyformat::encode_expiry(DEFAULT_DB, 1_010_000, b"list");
+            assert_eq!(None, db.shards[0].get(index_key).unwrap());
+
+            db.push_list(b"other", elements.clone(), ListEnd::Back, false)
+                .unwrap();
+            db.put_string_with_expiry(b"other", b"1", Duration::from_secs(10))
+                .unwrap();
+            assert!(list_elements(db, b"other").is_empty());
+
+            // Even once its TTL has lapsed
+            db.push_list(b"lapsed", elements, ListEnd::Back, false)
+                .unwrap();
+            db.put_expiry(b"lapsed", Duration::from_secs(5)).unwrap();
+            clock.advance(Duration::from_secs(5));
+            db.set_string(b"lapsed", b"1", SetOptions::default())
+                .unwrap();
+            assert!(list_elements(db, b"lapsed").is_empty());
+
+            assert_eq!(Some(b"1".to_vec()), db.get_string(b"list").unwrap());
+            assert!(db.check_integrity(false).unwrap().is_empty());
+        });
     }
 
-    fn delete_expiry<K: RString>(&self, key: K) -> Result<i64, DatabaseError> {
-        let data_key = prepend_key(key.as_ref(), DATA_KEY_PREFIX.as_bytes());
-        let ttl_key = prepend_key(key.as_ref(), TTL_KEY_PREFIX.as_bytes());
+    #[test]
+    fn test_pop_list() {
+        with_database("pop-list", |db| {
+            assert_eq!(None, db.pop_list(b"list", ListEnd::Front, 1).unwrap());
 
-        // Begin a transaction on the data key to ensure we don't set
-        // a TTL while the value is being replaced.
-        let txn = self.db.transaction();
-        txn.get_for_update(data_key, true)?;
+            let elements = ["a", "b", "c", "d"].map(|e| e.as_bytes().to_vec()).to_vec();
+            db.push_list(b"list", elements, ListEnd::Back, false)
+                .unwrap();
+            assert_eq!(
+                Some(vec![b"a".to_vec()]),
+                db.pop_list(b"list", ListEnd::Front, 1).unwrap()
+            );
+            assert_eq!(
+                Some(vec![b"d".to_vec(), b"c".to_vec()]),
+                db.pop_list(b"list", ListEnd::Back, 2).unwrap()
+            );
+            assert_eq!(
+                Some(vec![]),
+                db.pop_list(b"list", ListEnd::Back, 0).unwrap()
+            );
+            assert_eq!(1, db.get_list_length(b"list").unwrap());
 
-        let existing_ttl = txn.get_for_update(ttl_key.clone(), true)?;
-        if let None = existing_ttl {
-            return Ok(0);
-        }
+            // Pushes carry on from where the pops left the ends
+            db.push_list(b"list", vec![b"e".to_vec()], ListEnd::Back, false)
+                .unwrap();
+            assert_eq!(
+                vec![b"b".to_vec(), b"e".to_vec()],
+                list_elements(&db, b"list")
+            );
 
-        // Delete the TTL
-        txn.delete(ttl_key)?;
-        txn.commit()?;
+            // Popping the last elements deletes the list
+            assert_eq!(
+                Some(vec![b"b".to_vec(), b"e".to_vec()]),
+                db.pop_list(b"list", ListEnd::Front, 10).unwrap()
+            );
+            assert_eq!(None, db.get_type(b"list").unwrap());
+            assert_eq!(0, db.get_list_length(b"list").unwrap());
+            assert_eq!(None, db.pop_list(b"list", ListEnd::Back, 1).unwrap());
+        });
+    }
 
-        Ok(1)
+    #[test]
+    fn test_list_elements_by_index() {
+        with_database("list-index", |db| {
+            let elements = ["a", "b", "c"].map(|e| e.as_bytes().to_vec()).to_vec();
+            db.push_list(b"list", elements, ListEnd::Back, false)
+                .unwrap();
+
+            assert_eq!(
+                Some(b"a".to_vec()),
+                db.get_list_element(b"list", 0).unwrap()
+            );
+            assert_eq!(
+                Some(b"c".to_vec()),
+                db.get_list_element(b"list", -1).unwrap()
+            );
+            assert_eq!(None, db.get_list_element(b"list", 3).unwrap());
+            assert_eq!(None, db.get_list_element(b"list", -4).unwrap());
+            assert_eq!(None, db.get_list_element(b"missing", 0).unwrap());
+
+            db.set_list_element(b"list", -2, b"x".to_vec()).unwrap();
+            assert_eq!(
+                Some(b"x".to_vec()),
+                db.get_list_element(b"list", 1).unwrap()
+            );
+            assert!(matches!(
+                db.set_list_element(b"list", 3, b"y".to_vec()),
+                Err(DatabaseError::IndexOutOfRange)
+            ));
+            assert!(matches!(
+                db.set_list_element(b"missing", 0, b"y".to_vec()),
+                Err(DatabaseError::NoSuchKey)
+            ));
+
+            // Lists with gaps in their sequence numbers are walked instead
+            let shard = &db.shards[0];
+            let txn = shard.transaction();
+            let mut info = db.get_list_for_update(&txn, b"list").unwrap().unwrap();
+            txn.delete(element_key(b"list", info.head + 1)).unwrap();
+            info.len -= 1;
+            db.put_list_txn(&txn, b"list", &info).unwrap();
+            db.commit(txn, [b"list".as_slice()]).unwrap();
+            assert_eq!(
+                Some(b"a".to_vec()),
+                db.get_list_element(b"list", 0).unwrap()
+            );
+            assert_eq!(
+                Some(b"c".to_vec()),
+                db.get_list_element(b"list", 1).unwrap()
+            );
+            assert_eq!(
+                Some(b"a".to_vec()),
+                db.get_list_element(b"list", -2).unwrap()
+            );
+            assert_eq!(None, db.get_list_element(b"list", 2).unwrap());
+        });
     }
 
-    fn get_triple<K: RString>(
-        &self,
-        key1: K,
-        key2: K,
-        key3: K,
-    ) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>, Option<Vec<u8>>), rocksdb::Error> {
-        let result =
-            self.db
-                .multi_get([key1, key2, key3])
-                .into_iter()
-                .fold(Ok(vec![]), |agg, next| {
-                    agg.and_then(|mut results| {
-                        next.and_then(|value| {
-                            results.push(value);
-                            Ok(results)
-                        })
-                    })
-                })?;
+    #[test]
+    fn test_insert_list() {
+        with_database("insert-list", |db| {
+            assert_eq!(
+                0,
+                db.insert_list(b"list", b"a", b"x".to_vec(), true).unwrap()
+            );
 
-        // This will always succeed if we reach this point
-        Ok(result.into_iter().next_tuple::<(_, _, _)>().unwrap())
+            let elements = ["a", "b", "c", "d"].map(|e| e.as_bytes().to_vec()).to_vec();
+            db.push_list(b"list", elements, ListEnd::Back, false)
+                .unwrap();
+            assert_eq!(
+                -1,
+                db.insert_list(b"list", b"z", b"x".to_vec(), true).unwrap()
+            );
+
+            // Near the front, near the back, and at either end
+            assert_eq!(
+                5,
+                db.insert_list(b"list", b"b", b"1".to_vec(), true).unwrap()
+            );
+            assert_eq!(
+                6,
+                db.insert_list(b"list", b"c", b"2".to_vec(), false).unwrap()
+            );
+            assert_eq!(
+                7,
+                db.insert_list(b"list", b"a", b"3".to_vec(), true).unwrap()
+            );
+            assert_eq!(
+                8,
+                db.insert_list(b"list", b"d", b"4".to_vec(), false).unwrap()
+            );
+            assert_eq!(
+                ["3", "a", "1", "b", "c", "2", "d", "4"]
+                    .map(|e| e.as_bytes().to_vec())
+                    .to_vec(),
+                list_elements(db, b"list")
+            );
+            assert_eq!(
+                Some(b"4".to_vec()),
+                db.pop_list(b"list", ListEnd::Back, 1)
+                    .unwrap()
+                    .unwrap()
+                    .pop()
+            );
+            assert_eq!(7, db.get_list_length(b"list").unwrap());
+
+            // Gaps left by removals are filled without moving anything
+            let shard = &db.shards[0];
+            let txn = shard.transaction();
+            let mut info = db.get_list_for_update(&txn, b"list").unwrap().unwrap();
+            txn.delete(element_key(b"list", info.head + 2)).unwrap();
+            info.len -= 1;
+            db.put_list_txn(&txn, b"list", &info).unwrap();
+            db.commit(txn, [b"list".as_slice()]).unwrap();
+            assert_eq!(
+                7,
+                db.insert_list(b"list", b"a", b"5".to_vec(), false).unwrap()
+            );
+            assert_eq!(
+                info.head,
+                db.get_list_for_update(&shard.transaction(), b"list")
+                    .unwrap()
+                    .unwrap()
+                    .head
+            );
+            assert_eq!(
+                ["3", "a", "5", "b", "c", "2", "d"]
+                    .map(|e| e.as_bytes().to_vec())
+                    .to_vec(),
+                list_elements(db, b"list")
+            );
+        });
     }
 
-    fn get_triple_for_update<K: RString>(
-        &self,
-        txn: &Transaction<TransactionDB>,
-        key1: K,
-        key2: K,
-        key3: K,
-        exclusive: bool,
-    ) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>, Option<Vec<u8>>), rocksdb::Error> {
-        let val1 = txn.get_for_update(key1, exclusive)?;
-        let val2 = txn.get_for_update(key2, exclusive)?;
-        let val3 = txn.get_for_update(key3, exclusive)?;
-        Ok((val1, val2, val3))
+    #[test]
+    fn test_remove_list() {
+        with_database("remove-list", |db| {
+            assert_eq!(0, db.remove_list(b"list", 0, b"a").unwrap());
+
+            let elements = ["a", "b", "a", "c", "a", "b", "a"]
+                .map(|e| e.as_bytes().to_vec())
+                .to_vec();
+            db.push_list(b"list", elements, ListEnd::Back, false)
+                .unwrap();
+            assert_eq!(0, db.remove_list(b"list", 0, b"z").unwrap());
+
+            assert_eq!(2, db.remove_list(b"list", 2, b"a").unwrap());
+            assert_eq!(
+                ["b", "c", "a", "b", "a"]
+                    .map(|e| e.as_bytes().to_vec())
+                    .to_vec(),
+                list_elements(db, b"list")
+            );
+            assert_eq!(1, db.remove_list(b"list", -1, b"b").unwrap());
+            assert_eq!(
+                ["b", "c", "a", "a"].map(|e| e.as_bytes().to_vec()).to_vec(),
+                list_elements(db, b"list")
+            );
+            assert_eq!(
+                Some(b"a".to_vec()),
+                db.get_list_element(b"list", 2).unwrap()
+            );
+            assert_eq!(
+                Some(b"c".to_vec()),
+                db.get_list_element(b"list", -3).unwrap()
+            );
+
+            // Removing every element deletes the list
+            assert_eq!(2, db.remove_list(b"list", 0, b"a").unwrap());
+            assert_eq!(1, db.remove_list(b"list", -5, b"b").unwrap());
+            assert_eq!(1, db.remove_list(b"list", 0, b"c").unwrap());
+            assert_eq!(0, db.get_list_length(b"list").unwrap());
+            assert!(list_elements(db, b"list").is_empty());
+        });
     }
 
-    fn get_typed_value<K: RString>(
-        &self,
-        key: K,
-        type_id: &str,
-    ) -> Result<Option<Vec<u8>>, DatabaseError> {
-        let type_key = prepend_key(key.as_ref(), TYPE_KEY_PREFIX.as_bytes());
-        let data_key = prepend_key(key.as_ref(), DATA_KEY_PREFIX.as_bytes());
-        let ttl_key = prepend_key(key.as_ref(), TTL_KEY_PREFIX.as_bytes());
+    #[test]
+    fn test_trim_list() {
+        with_database("trim-list", |db| {
+            db.trim_list(b"list", 0, -1).unwrap();
 
-        let (type_value, data_value, ttl_value) = self.get_triple(type_key, data_key, ttl_key)?;
-        if let Some(ttl) = ttl_value {
-            let ttl = parse_timestamp(&ttl)?.saturating_sub(unix_timestamp()?);
-            if ttl == Duration::ZERO {
-                return Ok(None);
-            }
-        }
+            let elements = ["a", "b", "c", "d", "e", "f"]
+                .map(|e| e.as_bytes().to_vec())
+                .to_vec();
+            db.push_list(b"list", elements, ListEnd::Back, false)
+                .unwrap();
+            db.trim_list(b"list", 1, -2).unwrap();
+            assert_eq!(
+                ["b", "c", "d", "e"].map(|e| e.as_bytes().to_vec()).to_vec(),
+                list_elements(db, b"list")
+            );
+            assert_eq!(4, db.get_list_length(b"list").unwrap());
 
-        Self::validate_typed_value(&type_value, type_id).and_then(|_| Ok(data_value))
+            // Lists with gaps are walked instead
+            assert_eq!(1, db.remove_list(b"list", 0, b"c").unwrap());
+            db.trim_list(b"list", -10, 1).unwrap();
+            assert_eq!(
+                ["b", "d"].map(|e| e.as_bytes().to_vec()).to_vec(),
+                list_elements(db, b"list")
+            );
+            assert_eq!(
+                Some(b"d".to_vec()),
+                db.get_list_element(b"list", -1).unwrap()
+            );
+
+            // An empty range deletes the list
+            db.trim_list(b"list", 2, 1).unwrap();
+            assert_eq!(0, db.get_list_length(b"list").unwrap());
+            assert!(list_elements(db, b"list").is_empty());
+        });
     }
 
-    fn get_typed_value_for_update<K: RString>(
-        &self,
-        txn: &Transaction<TransactionDB>,
-        key: K,
-        type_id: &str,
-        exclusive: bool,
-    ) -> Result<Option<Vec<u8>>, DatabaseError> {
-        let type_key = prepend_key(key.as_ref(), TYPE_KEY_PREFIX.as_bytes());
-        let data_key = prepend_key(key.as_ref(), DATA_KEY_PREFIX.as_bytes());
-        let ttl_key = prepend_key(key.as_ref(), TTL_KEY_PREFIX.as_bytes());
+    #[test]
+    fn test_list_range() {
+        with_database("list-range", |db| {
+            assert!(db.get_list_range(b"list", 0, -1).unwrap().is_empty());
 
-        let (type_value, data_value, ttl_value) =
-            self.get_triple_for_update(txn, type_key, data_key, ttl_key, exclusive)?;
-        if let Some(ttl) = ttl_value {
-            let ttl = parse_timestamp(&ttl)?.saturating_sub(unix_timestamp()?);
-            if ttl == Duration::ZERO {
-                return Ok(None);
-            }
-        }
+            let elements = ["a", "b", "c", "d", "e"]
+                .map(|e| e.as_bytes().to_vec())
+                .to_vec();
+            db.push_list(b"list", elements, ListEnd::Back, false)
+                .unwrap();
+            let range = |start, stop| -> Vec<String> {
+                db.get_list_range(b"list", start, stop)
+                    .unwrap()
+                    .into_iter()
+                    .map(|e| String::from_utf8(e).unwrap())
+                    .collect()
+            };
+            assert_eq!(vec!["a", "b", "c", "d", "e"], range(0, -1));
+            assert_eq!(vec!["b", "c"], range(1, 2));
+            assert_eq!(vec!["d", "e"], range(-2, 100));
+            assert!(range(3, 1).is_empty());
+            assert!(range(5, 10).is_empty());
 
-        Self::validate_typed_value(&type_value, type_id).and_then(|_| Ok(data_value))
+            // With a gap, from either end
+            assert_eq!(1, db.remove_list(b"list", 0, b"c").unwrap());
+            assert_eq!(vec!["a", "b", "d", "e"], range(-100, -1));
+            assert_eq!(vec!["b"], range(1, 1));
+            assert_eq!(vec!["d", "e"], range(2, 3));
+        });
     }
 
-    fn validate_typed_value(
-        type_value: &Option<Vec<u8>>,
-        expected_type_id: &str,
-    ) -> Result<(), DatabaseError> {
-        type_value.as_ref().map_or_else(
-            || Ok(()),
-            |tv| {
-                if !tv.eq_ignore_ascii_case(expected_type_id.as_bytes()) {
-                    Err(DatabaseError::WrongType {
-                        expected: expected_type_id.to_string(),
-                    })
-                } else {
-                    Ok(())
-                }
-            },
-        )
+    #[test]
+    fn test_set_members() {
+        with_database("set-members", |db| {
+            assert_eq!(
+                0,
+                db.remove_set_members(b"set", vec![b"a".to_vec()]).unwrap()
+            );
+            assert!(!db.is_set_member(b"set", b"a").unwrap());
+
+            let members = ["a", "b", "a", "c"].map(|m| m.as_bytes().to_vec()).to_vec();
+            assert_eq!(3, db.add_set_members(b"set", members).unwrap());
+            assert_eq!(0, db.add_set_members(b"set", vec![b"b".to_vec()]).unwrap());
+            assert!(db.is_set_member(b"set", b"b").unwrap());
+            assert!(!db.is_set_member(b"set", b"z").unwrap());
+
+            let mut members = db.get_set_members(b"set").unwrap();
+            members.sort();
+            assert_eq!(
+                ["a", "b", "c"].map(|m| m.as_bytes().to_vec()).to_vec(),
+                members
+            );
+
+            let members = ["a", "z", "a"].map(|m| m.as_bytes().to_vec()).to_vec();
+            assert_eq!(1, db.remove_set_members(b"set", members).unwrap());
+            assert_eq!(2, db.get_set_members(b"set").unwrap().len());
+
+            // Removing the last members deletes the set
+            let members = ["b", "c"].map(|m| m.as_bytes().to_vec()).to_vec();
+            assert_eq!(2, db.remove_set_members(b"set", members).unwrap());
+            assert!(!db.exists(b"set").unwrap());
+            assert!(list_elements(db, b"set").is_empty());
+
+            db.set_string(b"string", b"1", SetOptions::default())
+                .unwrap();
+            assert!(matches!(
+                db.add_set_members(b"string", vec![b"a".to_vec()]),
+                Err(DatabaseError::WrongType { expected: _ })
+            ));
+        });
     }
 
-    fn put_typed_value<K: RString, V: RString>(
-        &self,
-        key: K,
-        value: V,
-        type_id: &str,
-    ) -> Result<(), DatabaseError> {
-        let txn = self.db.transaction();
-        self.put_typed_value_txn(&txn, key, value, type_id)?;
-        Ok(txn.commit()?)
+    #[test]
+    fn test_put_string_over_set() {
+        with_database("string-over-set", |db| {
+            let members = ["a", "b"].map(|m| m.as_bytes().to_vec()).to_vec();
+            db.add_set_members(b"set", members).unwrap();
+            db.put_string(b"set", b"1").unwrap();
+
+            // Only the string is left, without any of the set's members
+            let (_, keys) = db.scan(0, 10, ScanFilter::default()).unwrap();
+            assert_eq!(vec![b"set".to_vec()], keys);
+            assert!(list_elements(db, b"set").is_empty());
+            assert!(db.check_integrity(false).unwrap().is_empty());
+            assert!(matches!(
+                db.is_set_member(b"set", b"a"),
+                Err(DatabaseError::WrongType { .. })
+            ));
+        });
     }
 
-    fn put_typed_value_txn<K: RString, V: RString>(
-        &self,
-        txn: &Transaction<TransactionDB>,
-        key: K,
-        value: V,
-        type_id: &str,
-    ) -> Result<(), DatabaseError> {
-        let type_key = prepend_key(key.as_ref(), TYPE_KEY_PREFIX.as_bytes());
-        let data_key = prepend_key(key.as_ref(), DATA_KEY_PREFIX.as_bytes());
-        let ttl_key = prepend_key(key.as_ref(), TTL_KEY_PREFIX.as_bytes());
+    #[test]
+    fn test_get_and_take_hash_fields() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("hash-getex", 1, clock.clone(), |db| {
+            let fields = |names: &[&str]| -> Vec<Vec<u8>> {
+                names.iter().map(|name| name.as_bytes().to_vec()).collect()
+            };
+            db.put_hash_fields(
+                b"hash",
+                vec![
+                    (b"a".to_vec(), b"1".to_vec()),
+                    (b"b".to_vec(), b"2".to_vec()),
+                ],
+            )
+            .unwrap();
 
-        txn.put(type_key, type_id.as_bytes())?;
-        txn.put(data_key, value)?;
-        txn.delete(ttl_key)?;
+            assert_eq!(
+                vec![Some(b"1".to_vec()), None],
+                db.get_hash_fields_with_expiry(
+                    b"hash",
+                    fields(&["a", "x"]),
+                    SetExpiry::In(Duration::from_secs(10))
+                )
+                .unwrap()
+            );
+            assert_eq!(
+                vec![10_000, -1, -2],
+                db.get_field_expiry(b"hash", fields(&["a", "b", "x"]))
+                    .unwrap()
+            );
+            db.get_hash_fields_with_expiry(b"hash", fields(&["a"]), SetExpiry::Keep)
+                .unwrap();
+            assert_eq!(
+                vec![10_000],
+                db.get_field_expiry(b"hash", fields(&["a"])).unwrap()
+            );
+            db.get_hash_fields_with_expiry(b"hash", fields(&["a"]), SetExpiry::Clear)
+                .unwrap();
+            assert_eq!(
+                vec![-1],
+                db.get_field_expiry(b"hash", fields(&["a"])).unwrap()
+            );
 
-        Ok(())
+            // A time that has already passed deletes the field
+            assert_eq!(
+                vec![Some(b"2".to_vec())],
+                db.get_hash_fields_with_expiry(
+                    b"hash",
+                    fields(&["b"]),
+                    SetExpiry::At(Duration::from_secs(500))
+                )
+                .unwrap()
+            );
+            assert_eq!(None, db.get_hash_field(b"hash", b"b").unwrap());
+
+            assert_eq!(
+                vec![None; 2],
+                db.take_hash_fields(b"missing", fields(&["a", "b"]))
+                    .unwrap()
+            );
+            assert_eq!(
+                vec![Some(b"1".to_vec()), None],
+                db.take_hash_fields(b"hash", fields(&["a", "a"])).unwrap()
+            );
+            assert_eq!(None, db.get_type(b"hash").unwrap());
+        });
     }
 
-    fn delete_typed_value<K: RString>(&self, key: K) -> Result<(), DatabaseError> {
-        let type_key = prepend_key(key.as_ref(), TYPE_KEY_PREFIX.as_bytes());
-        let data_key = prepend_key(key.as_ref(), DATA_KEY_PREFIX.as_bytes());
-        let ttl_key = prepend_key(key.as_ref(), TTL_KEY_PREFIX.as_bytes());
+    #[test]
+    fn test_expiry_without_type() {
+        with_database("expiry-without-type", |db| {
+            // A TTL record left behind on its own doesn't bring the key back
+            let ttl_value = encode_timestamp(Duration::from_secs(u32::MAX as u64)).unwrap();
+            db.shards[0]
+                .put(encode_key(Namespace::Ttl, b"key"), ttl_value)
+                .unwrap();
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, b"key").unwrap());
+            assert_eq!(
+                None,
+                DatabaseOperations::get_expiry_at(&*db, b"key").unwrap()
+            );
+        });
+    }
 
-        let txn = self.db.transaction();
-        txn.delete(type_key)?;
-        txn.delete(data_key)?;
-        txn.delete(ttl_key)?;
+    #[test]
+    fn test_put_expiry_zero_deletes() {
+        with_database("expire-zero", |db| {
+            let key = "key".as_bytes();
+            db.put_string(key, "value".as_bytes()).unwrap();
+
+            assert!(DatabaseOperations::put_expiry(&*db, key, Duration::ZERO).unwrap());
 
-        Ok(txn.commit()?)
+            assert_eq!(None, db.get_string(key).unwrap());
+            assert_eq!(0, DatabaseOperations::exists(&*db, key).unwrap());
+        });
     }
 
-    fn exists<K: RString>(&self, key: K) -> Result<bool, DatabaseError> {
-        let type_key = prepend_key(key.as_ref(), TYPE_KEY_PREFIX.as_bytes());
-        let type_value = self.db.get(type_key)?;
-        match type_value {
-            Some(_) => Ok(true),
-            None => Ok(false),
-        }
+    #[test]
+    fn test_put_expiry_missing_key() {
+        with_database("expire-missing", |db| {
+            let key = "key".as_bytes();
+            assert!(!DatabaseOperations::put_expiry(&*db, key, Duration::from_secs(10)).unwrap());
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, key).unwrap());
+
+            // Setting a value later doesn't pick up a stray TTL
+            db.put_string(key, "value".as_bytes()).unwrap();
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, key).unwrap());
+        });
     }
-}
 
-impl DatabaseOperations for Database {
-    fn get_string(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
-        self.get_typed_value(key, TYPE_STRING)
+    #[test]
+    fn test_mutations_keep_expiry() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("mutate-expiry", 1, clock.clone(), |db| {
+            let ttl = Some(Duration::from_secs(10));
+
+            db.put_string_with_expiry(b"counter", b"1", Duration::from_secs(10))
+                .unwrap();
+            db.increment_by(b"counter", 5).unwrap();
+            db.increment_by_float(b"counter", 0.5).unwrap();
+            assert_eq!(
+                ttl,
+                DatabaseOperations::get_expiry(&*db, b"counter").unwrap()
+            );
+
+            db.put_string_with_expiry(b"string", b"a", Duration::from_secs(10))
+                .unwrap();
+            db.update_string(b"string", b"ab").unwrap();
+            assert_eq!(
+                ttl,
+                DatabaseOperations::get_expiry(&*db, b"string").unwrap()
+            );
+
+            db.put_hash_fields(b"hash", vec![(b"a".to_vec(), b"1".to_vec())])
+                .unwrap();
+            DatabaseOperations::put_expiry(&*db, b"hash", Duration::from_secs(10)).unwrap();
+            db.put_hash_fields(b"hash", vec![(b"b".to_vec(), b"2".to_vec())])
+                .unwrap();
+            assert_eq!(ttl, DatabaseOperations::get_expiry(&*db, b"hash").unwrap());
+
+            // Replacing a value still drops its TTL
+            db.put_string(b"string", b"c").unwrap();
+            assert_eq!(
+                None,
+                DatabaseOperations::get_expiry(&*db, b"string").unwrap()
+            );
+        });
     }
 
-    fn get_hash_field(&self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
-        let hash = self.get_typed_value(key, TYPE_HASH)?;
-        if let None = hash {
-            return Ok(None);
-        }
+    #[test]
+    fn test_mutating_expired_key() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("mutate-expired", 1, clock.clone(), |db| {
+            db.put_string_with_expiry(b"key", b"1", Duration::from_secs(10))
+                .unwrap();
+            clock.advance(Duration::from_secs(10));
 
-        let hash = hash.unwrap();
-        let hash = String::from_utf8_lossy(&hash);
-        let dict: HashMap<String, String> = serde_json::from_str(&hash)?;
+            // The lapsed TTL belonged to the old value, and mustn't carry
+            // over to the one that replaces it
+            assert_eq!(1, db.increment_by(b"key", 1).unwrap());
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, b"key").unwrap());
+            assert_eq!(Some(b"1".to_vec()), db.get_string(b"key").unwrap());
+        });
+    }
 
-        let subkey = String::from_utf8_lossy(field).into_owned();
-        let value = dict.get(&subkey);
-        if let None = value {
-            return Ok(None);
-        }
+    #[test]
+    fn test_value_cache() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        let configure = |db: Database| db.with_clock(clock.clone()).with_value_cache(1 << 20);
+        with_configured_database("value-cache", 1, configure, |db| {
+            let key = "key".as_bytes();
+            db.put_string(key, "a".as_bytes()).unwrap();
+            assert_eq!(Some("a".as_bytes().to_vec()), db.get_string(key).unwrap());
+
+            // Reads after every kind of write see the new value rather than
+            // the cached one
+            db.update_string(key, "ab".as_bytes()).unwrap();
+            assert_eq!(Some("ab".as_bytes().to_vec()), db.get_string(key).unwrap());
 
-        let value = value.unwrap();
-        Ok(Some(value.as_bytes().to_vec()))
+            db.put_string(key, "abc".as_bytes()).unwrap();
+            assert_eq!(Some("abc".as_bytes().to_vec()), db.get_string(key).unwrap());
+
+            DatabaseOperations::put_expiry(&*db, key, Duration::from_secs(10)).unwrap();
+            assert_eq!(Some("abc".as_bytes().to_vec()), db.get_string(key).unwrap());
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(None, db.get_string(key).unwrap());
+
+            db.put_string(key, "a".as_bytes()).unwrap();
+            assert_eq!(Some("a".as_bytes().to_vec()), db.get_string(key).unwrap());
+            DatabaseOperations::delete(&*db, key).unwrap();
+            assert_eq!(None, db.get_string(key).unwrap());
+        });
     }
 
-    fn get_expiry(&self, key: &[u8]) -> Result<Option<Duration>, DatabaseError> {
-        self.get_expiry(key)
+    #[test]
+    fn test_hash_tag() {
+        assert_eq!(b"user1000", hash_tag(b"{user1000}.following"));
+        assert_eq!(b"user1000", hash_tag(b"foo{user1000}{bar}"));
+        assert_eq!(b"foo{}{bar}", hash_tag(b"foo{}{bar}"));
+        assert_eq!(b"foo{bar", hash_tag(b"foo{bar"));
     }
 
-    fn put_string(&self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
-        self.put_typed_value(key, value, TYPE_STRING)
+    #[test]
+    fn test_sharded_database() {
+        with_sharded_database("shards", 4, |db| {
+            let n_shards = db.shards.len();
+            let other = (0..)
+                .map(|i| format!("key{}", i))
+                .find(|key| shard_index(key.as_bytes(), n_shards) != shard_index(b"a", n_shards))
+                .unwrap();
+
+            db.put_string("a".as_bytes(), "1".as_bytes()).unwrap();
+            db.put_string(other.as_bytes(), "2".as_bytes()).unwrap();
+            assert_eq!(
+                Some("1".as_bytes().to_vec()),
+                db.get_string("a".as_bytes()).unwrap()
+            );
+            assert_eq!(
+                Some("2".as_bytes().to_vec()),
+                db.get_string(other.as_bytes()).unwrap()
+            );
+
+            let entries = vec![
+                ("a".as_bytes().to_vec(), "3".as_bytes().to_vec()),
+                (other.as_bytes().to_vec(), "4".as_bytes().to_vec()),
+            ];
+            assert!(matches!(
+                db.put_strings_if_absent(entries),
+                Err(DatabaseError::CrossShard)
+            ));
+
+            // Keys with the same hash tag always share a shard
+            let entries = vec![
+                ("{user}.a".as_bytes().to_vec(), "1".as_bytes().to_vec()),
+                ("{user}.b".as_bytes().to_vec(), "2".as_bytes().to_vec()),
+            ];
+            assert!(db.put_strings_if_absent(entries).unwrap());
+        });
     }
 
-    fn put_hash_fields(
-        &self,
-        key: &[u8],
-        fields: Vec<(Vec<u8>, Vec<u8>)>,
-    ) -> Result<i64, DatabaseError> {
-        let txn = self.db.transaction();
-        let existing = self.get_typed_value_for_update(&txn, key, TYPE_HASH, true)?;
+    #[test]
+    fn test_parse_level_stats() {
+        let stats = "Level Files Size(MB)\n--------------------\n  0        3       12\n  1        0        0\n";
+        assert_eq!(vec![(3, 12), (0, 0)], parse_level_stats(stats));
+    }
 
-        let mut dict = match existing {
-            Some(data) => {
-                let hash = String::from_utf8_lossy(&data);
-                let dict: HashMap<String, String> = serde_json::from_str(&hash)?;
-                dict
-            }
-            None => HashMap::new(),
-        };
+    #[test]
+    fn test_parse_stall_count() {
+        let stats = "Write Stall (count): cf-l0-file-count-limit-delays-with-ongoing-compaction: 0, total-delays: 4, total-stops: 2\n";
+        assert_eq!(4, parse_stall_count(stats, "total-delays"));
+        assert_eq!(2, parse_stall_count(stats, "total-stops"));
+        assert_eq!(0, parse_stall_count("", "total-stops"));
+    }
 
-        let mut n_fields = 0;
-        for (field, value) in fields {
-            // TODO: Avoid relying on encoding values as UTF-8 strings
-            let field = String::from_utf8_lossy(&field).into_owned();
-            let value = String::from_utf8_lossy(&value).into_owned();
-            dict.insert(field, value);
-            n_fields += 1;
-        }
+    #[test]
+    fn test_storage_stats() {
+        with_sharded_database("storage-stats", 2, |db| {
+            let stats = db.storage_stats().unwrap();
+            assert_eq!(STORAGE_PROPERTIES.len(), stats.properties.len());
+            assert!(!stats.levels.is_empty());
+        });
+    }
 
-        let value = serde_json::to_string(&dict)?;
-        self.put_typed_value_txn(&txn, key, value, TYPE_HASH)?;
+    #[test]
+    fn test_check_integrity() {
+        with_database("check", |db| {
+            let shard = &db.shards[0];
+            db.put_string("ok".as_bytes(), "value".as_bytes()).unwrap();
+            let record_key = |ns, key: &str| encode_key(ns, key.as_bytes());
+            shard
+                .put(record_key(Namespace::Data, "no-type"), "value")
+                .unwrap();
+            shard
+                .put(record_key(Namespace::Type, "no-data"), TYPE_STRING)
+                .unwrap();
+            shard
+                .put(record_key(Namespace::Type, "bad-hash"), TYPE_HASH)
+                .unwrap();
+            shard
+                .put(record_key(Namespace::Data, "bad-hash"), "not json")
+                .unwrap();
+            shard
+                .put(sample_key("no-series".as_bytes(), 1), 1.0f64.to_be_bytes())
+                .unwrap();
 
-        txn.commit()?;
+            // Half deleted by a compaction, but expired anyway
+            shard
+                .put(record_key(Namespace::Type, "expired"), TYPE_STRING)
+                .unwrap();
+            shard
+                .put(record_key(Namespace::Ttl, "expired"), "1")
+                .unwrap();
 
-        Ok(n_fields)
+            let found = db.check_integrity(false).unwrap();
+            let mut keys: Vec<&[u8]> = found.iter().map(|i| i.key.as_slice()).collect();
+            keys.sort();
+            assert_eq!(
+                vec![
+                    "bad-hash".as_bytes(),
+                    "no-data".as_bytes(),
+                    "no-series".as_bytes(),
+                    "no-type".as_bytes()
+                ],
+                keys
+            );
+
+            assert_eq!(4, db.check_integrity(true).unwrap().len());
+            assert!(db.check_integrity(false).unwrap().is_empty());
+            assert_eq!(
+                Some("value".as_bytes().to_vec()),
+                db.get_string("ok".as_bytes()).unwrap()
+            );
+        });
     }
 
-    fn put_expiry(&self, key: &[u8], expires_in: Duration) -> Result<(), DatabaseError> {
-        self.put_expiry(key, expires_in)
+    #[test]
+    fn test_migrate_legacy_keys() {
+        with_database("migrate", |db| {
+            let shard = &db.shards[0];
+            shard.put("t:key", TYPE_STRING).unwrap();
+            shard.put("d:key", "value").unwrap();
+            shard.put("t:series", TYPE_TIMESERIES).unwrap();
+            shard
+                .put(
+                    "d:series",
+                    serde_json::to_vec(&TimeSeriesInfo::default()).unwrap(),
+                )
+                .unwrap();
+            let legacy_sample = [&b"s:\x00\x00\x00\x06series"[..], &5u64.to_be_bytes()].concat();
+            shard.put(legacy_sample, 1.5f64.to_be_bytes()).unwrap();
+
+            assert_eq!(5, db.migrate_legacy_keys().unwrap());
+            assert_eq!(0, db.migrate_legacy_keys().unwrap());
+            assert_eq!(
+                Some("value".as_bytes().to_vec()),
+                db.get_string("key".as_bytes()).unwrap()
+            );
+            assert_eq!(
+                vec![(5, 1.5)],
+                db.ts_range("series".as_bytes(), 0, u64::MAX).unwrap()
+            );
+            assert!(db.check_integrity(false).unwrap().is_empty());
+        });
     }
 
-    fn exists(&self, key: &[u8]) -> Result<i64, DatabaseError> {
-        match self.exists(key)? {
-            true => Ok(1),
-            false => Ok(0),
-        }
+    #[test]
+    fn test_migrate_legacy_hashes() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("migrate-hashes", 1, clock, |db| {
+            let shard = &db.shards[0];
+            shard
+                .put(encode_key(Namespace::Type, b"hash"), TYPE_HASH)
+                .unwrap();
+            shard
+                .put(encode_key(Namespace::Data, b"hash"), r#"{"a":"1","b":"2"}"#)
+                .unwrap();
+            shard
+                .put(encode_key(Namespace::FieldTtl, b"hash"), r#"{"a":2000000}"#)
+                .unwrap();
+
+            assert_eq!(1, db.migrate_legacy_keys().unwrap());
+            assert_eq!(0, db.migrate_legacy_keys().unwrap());
+            assert_eq!(
+                Some(b"2".to_vec()),
+                db.get_hash_field(b"hash", b"b").unwrap()
+            );
+            assert_eq!(
+                vec![1_000_000, -1],
+                db.get_field_expiry(b"hash", vec![b"a".to_vec(), b"b".to_vec()])
+                    .unwrap()
+            );
+            assert!(db.check_integrity(false).unwrap().is_empty());
+        });
     }
 
-    fn increment_by(&self, key: &[u8], amount: i64) -> Result<i64, DatabaseError> {
-        let txn = self.db.transaction();
-        let current_value = self
-            .get_typed_value_for_update(&txn, key, TYPE_STRING, true)?
-            .unwrap_or_else(|| "0".as_bytes().to_vec());
+    #[test]
+    fn test_migrate_legacy_ttls() {
+        let clock = Arc::new(MockClock::new(Duration::from_secs(1000)));
+        with_clocked_database("migrate-ttls", 1, clock.clone(), |db| {
+            let shard = &db.shards[0];
+            shard
+                .put(encode_key(Namespace::Type, b"key"), TYPE_STRING)
+                .unwrap();
+            shard
+                .put(encode_key(Namespace::Data, b"key"), "value")
+                .unwrap();
+            shard
+                .put(encode_key(Namespace::Ttl, b"key"), "2000000")
+                .unwrap();
 
-        // This needs to be a valid UTF-8 string in order to parse it
-        let current_value = String::from_utf8_lossy(&current_value).into_owned();
-        let current_value = current_value.parse::<i64>()?;
-        let next_value = current_value + amount;
+            assert_eq!(1, db.migrate_legacy_keys().unwrap());
+            assert_eq!(0, db.migrate_legacy_keys().unwrap());
+            assert_eq!(
+                Some(2_000_000u64.to_be_bytes().to_vec()),
+                shard.get(encode_key(Namespace::Ttl, b"key")).unwrap()
+            );
+            assert_eq!(
+                Some(Duration::from_secs(1000)),
+                DatabaseOperations::get_expiry(&*db, b"key").unwrap()
+            );
 
-        self.put_typed_value_txn(&txn, key, next_value.to_string().as_bytes(), TYPE_STRING)?;
+            // Migrated TTLs are in the expiry index
+            clock.advance(Duration::from_secs(1000));
+            assert_eq!(
+                (vec![b"key".to_vec()], false),
+                db.delete_expired(0, 10).unwrap()
+            );
+        });
+    }
 
-        txn.commit()?;
+    #[test]
+    fn test_scan_snapshot() {
+        with_sharded_database("scan", 2, |db| {
+            for key in ["a", "b", "c", "d", "e"] {
+                db.put_string(key.as_bytes(), "value".as_bytes()).unwrap();
+            }
 
-        Ok(next_value)
+            let (mut cursor, mut keys) = db.scan(0, 2, ScanFilter::default()).unwrap();
+            assert_ne!(0, cursor);
+
+            // Writes made during the scan don't affect what it returns
+            DatabaseOperations::delete(&*db, "e".as_bytes()).unwrap();
+            db.put_string("f".as_bytes(), "value".as_bytes()).unwrap();
+
+            while cursor != 0 {
+                let (next, more) = db.scan(cursor, 2, ScanFilter::default()).unwrap();
+                cursor = next;
+                keys.extend(more);
+            }
+            keys.sort();
+            let expected: Vec<Vec<u8>> = ["a", "b", "c", "d", "e"]
+                .iter()
+                .map(|key| key.as_bytes().to_vec())
+                .collect();
+            assert_eq!(expected, keys);
+
+            assert!(matches!(
+                db.scan(12345, 2, ScanFilter::default()),
+                Err(DatabaseError::InvalidCursor)
+            ));
+        });
     }
 
-    fn increment_by_float(&self, key: &[u8], amount: f64) -> Result<f64, DatabaseError> {
-        let txn = self.db.transaction();
-        let current_value = self
-            .get_typed_value_for_update(&txn, key, TYPE_STRING, true)?
-            .unwrap_or_else(|| "0".as_bytes().to_vec());
+    #[test]
+    fn test_scan_cursor_expiry() {
+        with_database("scan-expiry", |db| {
+            db.put_string("a".as_bytes(), "value".as_bytes()).unwrap();
+            db.put_string("b".as_bytes(), "value".as_bytes()).unwrap();
 
-        // This needs to be a valid UTF-8 string in order to parse it
-        let current_value = String::from_utf8_lossy(&current_value).into_owned();
-        let current_value = current_value.parse::<f64>()?;
-        let next_value = current_value + amount;
+            let (cursor, _) = db.scan(0, 1, ScanFilter::default()).unwrap();
+            db.cursors.lock().unwrap().expire(Duration::ZERO);
+            assert!(matches!(
+                db.scan(cursor, 1, ScanFilter::default()),
+                Err(DatabaseError::InvalidCursor)
+            ));
+        });
+    }
 
-        self.put_typed_value_txn(&txn, key, next_value.to_string().as_bytes(), TYPE_STRING)?;
+    #[test]
+    fn test_scan_filter() {
+        with_database("scan-filter", |db| {
+            for key in ["user:1", "user:2", "item:1"] {
+                db.put_string(key.as_bytes(), "value".as_bytes()).unwrap();
+            }
+            db.put_hash_fields(b"user:3", vec![(b"f".to_vec(), b"v".to_vec())])
+                .unwrap();
 
-        txn.commit()?;
+            let filter = ScanFilter {
+                pattern: Some(b"user:*".to_vec()),
+                type_name: Some("STRING".into()),
+            };
+            let (cursor, mut keys) = db.scan(0, 100, filter.clone()).unwrap();
+            assert_eq!(0, cursor);
+            keys.sort();
+            assert_eq!(vec![b"user:1".to_vec(), b"user:2".to_vec()], keys);
 
-        Ok(next_value)
+            // COUNT is how many keys are looked at, not how many come back
+            let (cursor, keys) = db.scan(0, 1, filter).unwrap();
+            assert_ne!(0, cursor);
+            assert!(keys.len() <= 1);
+        });
     }
 
-    fn delete(&self, key: &[u8]) -> Result<i64, DatabaseError> {
-        if !self.exists(key)? {
-            return Ok(0);
-        }
+    #[test]
+    fn test_copy() {
+        with_database("copy", |db| {
+            assert!(!db.copy(b"missing", b"dest", false).unwrap());
 
-        self.delete_typed_value(key).and_then(|_| Ok(1))
+            db.put_string_with_expiry(b"src", b"value", Duration::from_secs(100))
+                .unwrap();
+            assert!(db.copy(b"src", b"dest", false).unwrap());
+            assert_eq!(Some(b"value".to_vec()), db.get_string(b"dest").unwrap());
+            assert!(DatabaseOperations::get_expiry(&*db, b"dest")
+                .unwrap()
+                .is_some());
+
+            // An existing destination is only replaced when asked to
+            db.put_string(b"src", b"other").unwrap();
+            assert!(!db.copy(b"src", b"dest", false).unwrap());
+            assert_eq!(Some(b"value".to_vec()), db.get_string(b"dest").unwrap());
+            assert!(db.copy(b"src", b"dest", true).unwrap());
+            assert_eq!(Some(b"other".to_vec()), db.get_string(b"dest").unwrap());
+            assert_eq!(None, DatabaseOperations::get_expiry(&*db, b"dest").unwrap());
+
+            db.ts_add(b"ts", 5, 1.5, TimeSeriesInfo::default()).unwrap();
+            assert!(db.copy(b"ts", b"ts-copy", false).unwrap());
+            assert_eq!(vec![(5, 1.5)], db.ts_range(b"ts-copy", 0, 10).unwrap());
+        });
     }
 
-    fn delete_expiry(&self, key: &[u8]) -> Result<i64, DatabaseError> {
-        self.delete_expiry(key)
+    #[test]
+    fn test_key_sizes() {
+        with_database("key_sizes", |db| {
+            db.put_string("a".as_bytes(), "value".as_bytes()).unwrap();
+            db.put_hash_fields(
+                "b".as_bytes(),
+                vec![("f1".into(), "v1".into()), ("f2".into(), "v2".into())],
+            )
+            .unwrap();
+            db.put_string("c".as_bytes(), "x".as_bytes()).unwrap();
+
+            let sizes = db.key_sizes(0, None, 2).unwrap();
+            assert_eq!(2, sizes.len());
+            assert_eq!(("string", 5), (sizes[0].type_name, sizes[0].elements));
+            assert_eq!(("hash", 2), (sizes[1].type_name, sizes[1].elements));
+
+            let sizes = db.key_sizes(0, Some("b".into()), 2).unwrap();
+            assert_eq!(1, sizes.len());
+            assert_eq!("c".as_bytes(), sizes[0].key);
+        });
     }
 }
@@ -0,0 +1,65 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("command exceeded its execution timeout")]
+pub struct DeadlineExceeded;
+
+// Commands run to completion on the connection's thread, so the deadline
+// for the command currently executing is tracked per thread
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// A zero timeout means commands never time out
+pub fn start(timeout: Duration) {
+    let deadline = if timeout.is_zero() {
+        None
+    } else {
+        Some(Instant::now() + timeout)
+    };
+    DEADLINE.set(deadline);
+}
+
+pub fn clear() {
+    DEADLINE.set(None);
+}
+
+// Called by long-running commands between units of work. Aborting is only
+// safe before anything has been written, so commands that check this must
+// not have started their reply yet.
+pub fn check() -> Result<(), DeadlineExceeded> {
+    match DEADLINE.get() {
+        Some(deadline) if { Instant::now() >= deadline } => Err(DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_deadline() {
+        assert!(check().is_ok());
+
+        start(Duration::from_millis(1));
+        thread::sleep(Duration::from_millis(5));
+        assert!(check().is_err());
+
+        clear();
+        assert!(check().is_ok());
+    }
+
+    #[test]
+    fn test_deadline_zero_disables() {
+        start(Duration::ZERO);
+        assert!(check().is_ok());
+    }
+}
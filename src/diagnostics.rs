@@ -0,0 +1,439 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use signal_hook::{consts::SIGUSR1, iterator::Signals};
+use tracing::info;
+
+use crate::{
+    database::{Database, DatabaseOperations},
+    slowlog,
+};
+
+const SLOWLOG_TAIL: usize = 10;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub failed: u64,
+    pub total: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    pub id: i64,
+    pub addr: SocketAddr,
+    pub name: String,
+    pub lib_name: String,
+    pub lib_version: String,
+    pub connected_at: Instant,
+    pub last_active: Instant,
+    pub commands: u64,
+    pub last_command: String,
+    // Set by CLIENT KILL. redcon only hands a connection back to wedis when
+    // it sends a command, so that's when it's closed.
+    pub killed: bool,
+    pub no_evict: bool,
+    pub no_touch: bool,
+}
+
+// Which connections CLIENT KILL closes. Every filter that's set has to
+// match.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KillFilter {
+    pub id: Option<i64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    // The connection asking, unless it asked to be included
+    pub skip: Option<i64>,
+}
+
+impl KillFilter {
+    fn matches(&self, info: &ConnectionInfo) -> bool {
+        self.id.is_none_or(|id| id == info.id)
+            && self
+                .addr
+                .as_ref()
+                .is_none_or(|addr| *addr == info.addr.to_string())
+            && self
+                .laddr
+                .as_ref()
+                .is_none_or(|laddr| listen_addr().is_some_and(|a| a.to_string() == *laddr))
+            && self.skip != Some(info.id)
+    }
+}
+
+fn command_stats() -> &'static Mutex<BTreeMap<String, CommandStats>> {
+    static COMMAND_STATS: OnceLock<Mutex<BTreeMap<String, CommandStats>>> = OnceLock::new();
+    COMMAND_STATS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn listen_addr_cell() -> &'static OnceLock<SocketAddr> {
+    static LISTEN_ADDR: OnceLock<SocketAddr> = OnceLock::new();
+    &LISTEN_ADDR
+}
+
+// Every connection's local address, since there's only one listener
+pub fn set_listen_addr(addr: SocketAddr) {
+    let _ = listen_addr_cell().set(addr);
+}
+
+fn listen_addr() -> Option<SocketAddr> {
+    listen_addr_cell().get().copied()
+}
+
+fn connections() -> &'static Mutex<HashMap<i64, ConnectionInfo>> {
+    static CONNECTIONS: OnceLock<Mutex<HashMap<i64, ConnectionInfo>>> = OnceLock::new();
+    CONNECTIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn record_command(name: &str, duration: Duration, failed: bool) {
+    let mut stats = command_stats().lock().unwrap();
+    let entry = stats.entry(name.to_string()).or_default();
+    entry.calls += 1;
+    entry.total += duration;
+    if failed {
+        entry.failed += 1;
+    }
+}
+
+pub fn connection_opened(id: i64, addr: SocketAddr) {
+    connections().lock().unwrap().insert(
+        id,
+        ConnectionInfo {
+            id,
+            addr,
+            name: String::new(),
+            lib_name: String::new(),
+            lib_version: String::new(),
+            connected_at: Instant::now(),
+            last_active: Instant::now(),
+            commands: 0,
+            last_command: String::new(),
+            killed: false,
+            no_evict: false,
+            no_touch: false,
+        },
+    );
+}
+
+pub fn connection_closed(id: i64) {
+    connections().lock().unwrap().remove(&id);
+}
+
+pub fn set_connection_addr(id: i64, addr: SocketAddr) {
+    if let Some(info) = connections().lock().unwrap().get_mut(&id) {
+        info.addr = addr;
+    }
+}
+
+pub fn set_connection_name(id: i64, name: &str) {
+    if let Some(info) = connections().lock().unwrap().get_mut(&id) {
+        info.name = name.to_string();
+    }
+}
+
+pub fn set_connection_lib_name(id: i64, lib_name: &str) {
+    if let Some(info) = connections().lock().unwrap().get_mut(&id) {
+        info.lib_name = lib_name.to_string();
+    }
+}
+
+pub fn set_connection_lib_version(id: i64, lib_version: &str) {
+    if let Some(info) = connections().lock().unwrap().get_mut(&id) {
+        info.lib_version = lib_version.to_string();
+    }
+}
+
+pub fn set_connection_no_evict(id: i64, no_evict: bool) {
+    if let Some(info) = connections().lock().unwrap().get_mut(&id) {
+        info.no_evict = no_evict;
+    }
+}
+
+pub fn set_connection_no_touch(id: i64, no_touch: bool) {
+    if let Some(info) = connections().lock().unwrap().get_mut(&id) {
+        info.no_touch = no_touch;
+    }
+}
+
+pub fn command_received(id: i64, name: &[u8]) {
+    if let Some(info) = connections().lock().unwrap().get_mut(&id) {
+        info.commands += 1;
+        info.last_active = Instant::now();
+        info.last_command = String::from_utf8_lossy(name).to_lowercase();
+    }
+}
+
+// Marks matching connections to be closed, returning how many there were
+pub fn kill_connections(filter: &KillFilter) -> usize {
+    let mut connections = connections().lock().unwrap();
+    let mut killed = 0;
+    for info in connections.values_mut() {
+        if !info.killed && filter.matches(info) {
+            info.killed = true;
+            killed += 1;
+        }
+    }
+    killed
+}
+
+pub fn is_killed(id: i64) -> bool {
+    connections()
+        .lock()
+        .unwrap()
+        .get(&id)
+        .is_some_and(|info| info.killed)
+}
+
+pub fn connection_count() -> usize {
+    connections().lock().unwrap().len()
+}
+
+// One line per connection, in the format of Redis' CLIENT LIST, for the
+// given connections or all of them. Fields that don't apply to wedis, like
+// pub/sub subscriptions and buffer sizes, are left out.
+pub fn client_list(ids: Option<&[i64]>) -> String {
+    let mut table: Vec<ConnectionInfo> = connections()
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|info| !info.killed && ids.is_none_or(|ids| ids.contains(&info.id)))
+        .cloned()
+        .collect();
+    table.sort_by_key(|info| info.id);
+
+    let mut list = String::new();
+    for info in table {
+        let flags = match (info.no_evict, info.no_touch) {
+            (false, false) => "N",
+            (true, false) => "e",
+            (false, true) => "T",
+            (true, true) => "eT",
+        };
+        let _ = writeln!(
+            list,
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 cmd={} lib-name={} lib-ver={}",
+            info.id,
+            info.addr,
+            listen_addr().map_or(String::new(), |addr| addr.to_string()),
+            info.name,
+            info.connected_at.elapsed().as_secs(),
+            info.last_active.elapsed().as_secs(),
+            flags,
+            if info.last_command.is_empty() {
+                "NULL"
+            } else {
+                &info.last_command
+            },
+            info.lib_name,
+            info.lib_version
+        );
+    }
+    list
+}
+
+pub fn total_commands() -> u64 {
+    command_stats()
+        .lock()
+        .unwrap()
+        .values()
+        .map(|stats| stats.calls)
+        .sum()
+}
+
+// Builds the report logged on SIGUSR1. Sections are kept line-oriented so
+// they stay readable when interleaved with other log output.
+pub fn report(db: &dyn DatabaseOperations) -> String {
+    let mut report = String::from("# Commands\n");
+    for (name, stats) in command_stats().lock().unwrap().iter() {
+        let _ = writeln!(
+            report,
+            "{}: calls={} failed={} usec={} usec_per_call={:.2}",
+            name.to_lowercase(),
+            stats.calls,
+            stats.failed,
+            stats.total.as_micros(),
+            stats.total.as_micros() as f64 / stats.calls as f64
+        );
+    }
+
+    report.push_str("# Connections\n");
+    let mut table: Vec<ConnectionInfo> = connections().lock().unwrap().values().cloned().collect();
+    table.sort_by_key(|info| info.id);
+    for info in table.iter() {
+        let _ = writeln!(
+            report,
+            "id={} addr={} age={} commands={} last={}",
+            info.id,
+            info.addr,
+            info.connected_at.elapsed().as_secs(),
+            info.commands,
+            info.last_command
+        );
+    }
+    // No command blocks yet, so no client can be waiting on one
+    let _ = writeln!(report, "connected={} blocked=0", table.len());
+
+    report.push_str("# Storage\n");
+    match db.storage_stats() {
+        Ok(stats) => {
+            for (name, value) in stats.properties.iter() {
+                let _ = writeln!(report, "{}: {}", name, value);
+            }
+            for (level, (files, size_mb)) in stats.levels.iter().enumerate() {
+                let _ = writeln!(
+                    report,
+                    "level{}: files={} size_mb={}",
+                    level, files, size_mb
+                );
+            }
+
+            // RocksDB's share of memory is what its caches and indexes hold,
+            // as the data itself lives on disk
+            let memory: u64 = stats
+                .properties
+                .iter()
+                .filter(|(name, _)| {
+                    ["memtable_size", "block_cache_usage", "table_readers_memory"].contains(name)
+                })
+                .map(|(_, value)| value)
+                .sum();
+            let _ = writeln!(report, "estimated_memory: {}", memory);
+        }
+        Err(err) => {
+            let _ = writeln!(report, "unavailable: {}", err);
+        }
+    }
+
+    report.push_str("# Slowlog\n");
+    for entry in slowlog::get(SLOWLOG_TAIL) {
+        let args: Vec<String> = entry
+            .args
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        let _ = writeln!(
+            report,
+            "{}: timestamp={} usec={} {}",
+            entry.id,
+            entry.timestamp,
+            entry.duration.as_micros(),
+            args.join(" ")
+        );
+    }
+
+    report
+}
+
+// Logs a diagnostics report whenever the process receives SIGUSR1
+pub fn handle_signal(db: Arc<Database>) -> Result<()> {
+    let mut signals = Signals::new([SIGUSR1])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Received SIGUSR1, writing diagnostics report");
+            let report = report(&*db);
+            for line in report.lines() {
+                info!("{}", line);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::{DatabaseError, MockDatabaseOperations, StorageStats};
+
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        record_command("GET", Duration::from_micros(10), false);
+        connection_opened(1, "127.0.0.1:1234".parse().unwrap());
+        command_received(1, b"GET");
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db.expect_storage_stats().times(1).returning(|| {
+            Ok(StorageStats {
+                properties: vec![("memtable_size", 100), ("block_cache_usage", 50)],
+                ..Default::default()
+            })
+        });
+
+        let dumped = report(&mock_db);
+        assert!(dumped.contains("get: calls="));
+        assert!(dumped.contains("id=1 addr=127.0.0.1:1234"));
+        assert!(dumped.contains("last=get"));
+        assert!(dumped.contains("estimated_memory: 150"));
+
+        connection_closed(1);
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_storage_stats()
+            .times(1)
+            .returning(|| Err(DatabaseError::CrossShard));
+        let dumped = report(&mock_db);
+        assert!(!dumped.contains("id=1 "));
+        assert!(dumped.contains("# Storage\nunavailable"));
+    }
+
+    #[test]
+    fn test_client_list() {
+        connection_opened(7001, "127.0.0.1:1234".parse().unwrap());
+        connection_opened(7002, "127.0.0.1:1235".parse().unwrap());
+        set_connection_name(7001, "worker");
+        set_connection_lib_name(7001, "redis-py");
+        set_connection_no_touch(7001, true);
+        command_received(7001, b"CLIENT");
+
+        let list = client_list(Some(&[7001]));
+        assert_eq!(
+            "id=7001 addr=127.0.0.1:1234 laddr= name=worker age=0 idle=0 flags=T db=0 cmd=client lib-name=redis-py lib-ver=\n",
+            list
+        );
+        assert!(client_list(None).contains(
+            "id=7002 addr=127.0.0.1:1235 laddr= name= age=0 idle=0 flags=N db=0 cmd=NULL "
+        ));
+
+        connection_closed(7001);
+        connection_closed(7002);
+        assert_eq!("", client_list(Some(&[7001])));
+    }
+
+    #[test]
+    fn test_kill_connections() {
+        connection_opened(7101, "127.0.0.1:2001".parse().unwrap());
+        connection_opened(7102, "127.0.0.1:2002".parse().unwrap());
+
+        // The connection asking is skipped
+        let filter = KillFilter {
+            id: Some(7101),
+            skip: Some(7101),
+            ..Default::default()
+        };
+        assert_eq!(0, kill_connections(&filter));
+
+        let filter = KillFilter {
+            addr: Some("127.0.0.1:2002".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(1, kill_connections(&filter));
+        assert!(is_killed(7102));
+        assert!(!is_killed(7101));
+        assert!(!client_list(None).contains("id=7102 "));
+
+        // Killed connections aren't killed twice
+        assert_eq!(0, kill_connections(&filter));
+
+        connection_closed(7101);
+        connection_closed(7102);
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    num::{ParseFloatError, ParseIntError, TryFromIntError},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tracing::{debug, error, warn};
+
+use crate::{
+    access, audit, blocking, config,
+    connection::{ClientError, Connection, ConnectionContext},
+    database::{DatabaseError, DatabaseOperations},
+    deadline::{self, DeadlineExceeded},
+    diagnostics, help, hotkeys, keyspec, notify, registry, slowlog,
+    time::TimeError,
+    tracking,
+};
+
+#[cfg(feature = "native-modules")]
+use crate::modules;
+#[cfg(feature = "wasm-plugins")]
+use crate::plugins;
+
+fn command_timeout() -> Duration {
+    let config = config::config().read().unwrap();
+    let timeout_ms = config
+        .value("command-timeout")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+    Duration::from_millis(timeout_ms)
+}
+
+fn log_command(args: &Vec<Vec<u8>>) {
+    let mut parsed_args: Vec<String> = vec![];
+    for arg in args {
+        parsed_args.push(String::from_utf8_lossy(&arg).into_owned())
+    }
+    debug!("> {:?}", parsed_args);
+}
+
+fn is_no_touch(conn: &mut dyn Connection) -> bool {
+    conn.context()
+        .as_mut()
+        .and_then(|ctx| ctx.downcast_mut::<ConnectionContext>())
+        .is_some_and(|ctx| ctx.no_touch())
+}
+
+pub fn dispatch(conn: &mut dyn Connection, db: &dyn DatabaseOperations, args: &Vec<Vec<u8>>) {
+    if args.is_empty() {
+        conn.write_error(ClientError::UnknownCommand);
+        return;
+    }
+
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+
+    log_command(args);
+
+    // Invalidations are pushed ahead of the reply, so a client never acts
+    // on a reply while still holding a stale cached value
+    let connection_id = conn.connection_id();
+    tracking::flush(conn, connection_id);
+
+    let timeout = command_timeout();
+    deadline::start(timeout);
+    let started = Instant::now();
+    let written = conn.written();
+    let result = run_command(conn, db, &name, args);
+    let duration = started.elapsed();
+    deadline::clear();
+    let failed = result.is_err();
+    let event = notify::take_event();
+
+    match result {
+        // Blocking commands that parked haven't done anything yet
+        Ok(_) if { blocking::is_parked() } => {}
+        Ok(_) if { notify::is_enabled() && !conn.replied_with_error() } => {
+            let event = event.unwrap_or(&name);
+            for key in keyspec::written_keys(args) {
+                notify::publish(event, key);
+            }
+        }
+        Ok(_) => {}
+        Err(err) if { err.is::<DeadlineExceeded>() } => {
+            warn!("{} aborted after {:?}", name, duration);
+            conn.write_error(ClientError::Timeout);
+        }
+        // A command that already started replying can't take it back, so
+        // its error is only logged
+        Err(err) if { conn.written() > written } => error!("{} failed: {}", name, err),
+        Err(err) => match into_client_error(err) {
+            Ok(err) => conn.write_error(err),
+            Err(err) => {
+                error!("{}", err);
+                conn.write_error(ClientError::Internal(err.to_string()));
+            }
+        },
+    }
+
+    // Commands that can't be aborted partway are still flagged when they
+    // overrun, so they show up in the slowlog
+    let timed_out = !timeout.is_zero() && duration >= timeout;
+    if timed_out {
+        warn!("{} exceeded the command timeout ({:?})", name, duration);
+    }
+    slowlog::record(args, duration, timed_out);
+    diagnostics::record_command(&name, duration, failed);
+    hotkeys::record(args);
+    if !is_no_touch(conn) {
+        access::record(args);
+    }
+    tracking::record(connection_id, args);
+    // Including invalidations caused by the command itself
+    tracking::flush(conn, connection_id);
+
+    if audit::is_enabled() && audit::is_audited(&name, args) {
+        let (client, client_id) = match conn
+            .context()
+            .as_mut()
+            .and_then(|ctx| ctx.downcast_mut::<ConnectionContext>())
+        {
+            Some(ctx) => (ctx.addr().to_string(), ctx.id()),
+            None => ("".to_string(), -1),
+        };
+        let ok = !failed && !conn.replied_with_error();
+        audit::record(&name, args, client, client_id, ok);
+    }
+}
+
+// Errors that made it past a handler get the reply Redis would send for
+// them, rather than leaving the client waiting. Anything else is an internal
+// failure, which is logged before replying with a generic error.
+fn into_client_error(err: anyhow::Error) -> Result<ClientError, anyhow::Error> {
+    if err.is::<ParseIntError>() || err.is::<TryFromIntError>() {
+        return Ok(ClientError::NotInteger);
+    }
+    if err.is::<ParseFloatError>() {
+        return Ok(ClientError::NotFloat);
+    }
+    let err = match err.downcast::<DatabaseError>() {
+        Ok(err) => return from_database_error(err).map_err(anyhow::Error::from),
+        Err(err) => err,
+    };
+    err.downcast::<ClientError>()
+}
+
+fn from_database_error(err: DatabaseError) -> Result<ClientError, DatabaseError> {
+    match err {
+        DatabaseError::ParseInt(_) | DatabaseError::NotInteger => Ok(ClientError::NotInteger),
+        DatabaseError::Overflow => Ok(ClientError::IncrementOverflow),
+        DatabaseError::NotFinite => Ok(ClientError::NotFinite),
+        DatabaseError::ParseFloat(_) => Ok(ClientError::NotFloat),
+        DatabaseError::InvalidTime(TimeError::Overflow) => Ok(ClientError::InvalidExpireTime),
+        DatabaseError::WrongType { expected: _ } => Ok(ClientError::WrongType),
+        DatabaseError::CrossShard => Ok(ClientError::CrossSlot),
+        DatabaseError::InvalidCursor => Ok(ClientError::InvalidCursor),
+        DatabaseError::IndexOutOfRange => Ok(ClientError::IndexOutOfRange),
+        DatabaseError::NoSuchKey => Ok(ClientError::NoSuchKey),
+        DatabaseError::Sketch(err) => Ok(ClientError::Module(err.to_string())),
+        DatabaseError::TimeSeries(err) => Ok(ClientError::Module(err.to_string())),
+        err => Err(err),
+    }
+}
+
+fn run_command(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    name: &str,
+    args: &Vec<Vec<u8>>,
+) -> Result<()> {
+    if help::is_help_request(name, args) {
+        return Ok(help::write_help(conn, name));
+    }
+
+    // Arity is checked the same way for every command, before its handler
+    // sees the arguments
+    if let Some(command) = registry::lookup(name) {
+        if !command.accepts(args.len()) {
+            return Ok(conn.write_error(ClientError::ArgCount));
+        }
+        return (command.handler)(conn, db, args);
+    }
+
+    #[cfg(feature = "native-modules")]
+    if modules::has_command(name) {
+        return Ok(modules::call(conn, db, name, args));
+    }
+    #[cfg(feature = "wasm-plugins")]
+    if plugins::has_command(name) {
+        return plugins::call(conn, name, args);
+    }
+
+    error!("Unknown command: {}", name);
+    Ok(conn.write_error(ClientError::UnknownCommand))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_into_client_error() {
+        let err = "x".parse::<i64>().unwrap_err();
+        assert!(matches!(
+            into_client_error(err.into()),
+            Ok(ClientError::NotInteger)
+        ));
+        assert!(matches!(
+            into_client_error(ClientError::Syntax.into()),
+            Ok(ClientError::Syntax)
+        ));
+        assert!(matches!(
+            into_client_error(DatabaseError::InvalidTime(TimeError::Overflow).into()),
+            Ok(ClientError::InvalidExpireTime)
+        ));
+        assert!(matches!(
+            into_client_error(
+                DatabaseError::WrongType {
+                    expected: "string".into()
+                }
+                .into()
+            ),
+            Ok(ClientError::WrongType)
+        ));
+        assert!(matches!(
+            into_client_error(DatabaseError::CrossShard.into()),
+            Ok(ClientError::CrossSlot)
+        ));
+        assert!(matches!(
+            into_client_error(DatabaseError::Overflow.into()),
+            Ok(ClientError::IncrementOverflow)
+        ));
+        assert!(into_client_error(anyhow::anyhow!("disk on fire")).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{
+    config,
+    database::{Database, DatabaseError, DatabaseOperations},
+    notify, shutdown,
+};
+
+// Reads only skip over expired keys, so a key that's never read again would
+// keep its records forever. The sweeper pops due keys off every shard's
+// expiry index, active-expire-hz times a second, and deletes them, the way
+// Redis' active expire cycle does.
+const PAGE_SIZE: usize = 200;
+
+static EXPIRED_KEYS: AtomicU64 = AtomicU64::new(0);
+
+// Keys deleted by the sweeper since the server started
+pub fn expired_keys() -> u64 {
+    EXPIRED_KEYS.load(Ordering::Relaxed)
+}
+
+// Deletes every due key, a page at a time, returning how many were deleted.
+// A shard is swept again while its page was full, so that a backlog doesn't
+// have to wait for later cycles.
+pub fn sweep(db: &dyn DatabaseOperations) -> Result<u64, DatabaseError> {
+    let mut n_deleted = 0;
+    for shard in 0..db.shard_count() {
+        loop {
+            let (deleted, more) = db.delete_expired(shard, PAGE_SIZE)?;
+            n_deleted += deleted.len() as u64;
+            EXPIRED_KEYS.fetch_add(deleted.len() as u64, Ordering::Relaxed);
+            for key in deleted {
+                notify::publish("expired", &key);
+            }
+            if !more {
+                break;
+            }
+        }
+    }
+    Ok(n_deleted)
+}
+
+fn frequency() -> u64 {
+    config::config()
+        .read()
+        .unwrap()
+        .value("active-expire-hz")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+// The frequency is read again before every sweep, so that CONFIG SET takes
+// effect right away. Setting it to 0 pauses the sweeper.
+pub fn start_worker(db: Arc<Database>) {
+    thread::spawn(move || {
+        while !shutdown::is_shutting_down() {
+            let hz = frequency();
+            if hz == 0 {
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+
+            thread::sleep(Duration::from_millis(1000 / hz.min(1000)));
+            if let Err(err) = sweep(&*db) {
+                warn!("Failed to delete expired keys: {}", err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::MockDatabaseOperations;
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db.expect_shard_count().returning(|| 2);
+        let mut pages = vec![
+            (vec![b"a".to_vec(), b"b".to_vec()], true),
+            (vec![b"c".to_vec()], false),
+        ]
+        .into_iter();
+        mock_db
+            .expect_delete_expired()
+            .with(eq(0), eq(PAGE_SIZE))
+            .times(2)
+            .returning(move |_, _| Ok(pages.next().unwrap()));
+        mock_db
+            .expect_delete_expired()
+            .with(eq(1), eq(PAGE_SIZE))
+            .times(1)
+            .returning(|_, _| Ok((vec![], false)));
+
+        let before = expired_keys();
+        assert_eq!(3, sweep(&mock_db).unwrap());
+        assert_eq!(before + 3, expired_keys());
+    }
+}
@@ -0,0 +1,102 @@
+// Glob-style matching with the same syntax Redis uses for KEYS, SCAN MATCH
+// and CONFIG GET: *, ?, [abc], [^abc], [a-z] and backslash escapes
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    match pattern.first() {
+        None => string.is_empty(),
+        Some(b'*') => {
+            // Collapse runs of stars, then try every possible split point
+            let rest = &pattern[1..];
+            if rest.first() == Some(&b'*') {
+                return glob_match(rest, string);
+            }
+
+            (0..=string.len()).any(|i| glob_match(rest, &string[i..]))
+        }
+        Some(b'?') => !string.is_empty() && glob_match(&pattern[1..], &string[1..]),
+        Some(b'[') => match string.first() {
+            Some(c) => match match_class(&pattern[1..], *c) {
+                Some((matched, rest)) => matched && glob_match(rest, &string[1..]),
+                // Unterminated class, treat the bracket literally
+                None => *c == b'[' && glob_match(&pattern[1..], &string[1..]),
+            },
+            None => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            string.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &string[1..])
+        }
+        Some(p) => string.first() == Some(p) && glob_match(&pattern[1..], &string[1..]),
+    }
+}
+
+// Matches a single character against a character class, returning whether it
+// matched and the remainder of the pattern after the closing bracket
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negate, mut pattern) = match pattern.first() {
+        Some(b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => return None,
+            [b']', rest @ ..] => return Some((matched != negate, rest)),
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == c;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (start, end) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= *start <= c && c <= *end;
+                pattern = rest;
+            }
+            [x, rest @ ..] => {
+                matched |= *x == c;
+                pattern = rest;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match_literal() {
+        assert!(glob_match(b"hello", b"hello"));
+        assert!(!glob_match(b"hello", b"hell"));
+    }
+
+    #[test]
+    fn test_glob_match_star() {
+        assert!(glob_match(b"h*o", b"hello"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"**llo", b"hello"));
+        assert!(!glob_match(b"h*x", b"hello"));
+    }
+
+    #[test]
+    fn test_glob_match_question_mark() {
+        assert!(glob_match(b"h?llo", b"hallo"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+    }
+
+    #[test]
+    fn test_glob_match_class() {
+        assert!(glob_match(b"h[ae]llo", b"hello"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-f]llo", b"hello"));
+        assert!(!glob_match(b"h[a-b]llo", b"hello"));
+    }
+
+    #[test]
+    fn test_glob_match_escape() {
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::connection::Connection;
+
+// One subcommand of a container command, as listed by its HELP
+pub struct Subcommand {
+    pub usage: &'static str,
+    pub summary: &'static str,
+}
+
+const fn sub(usage: &'static str, summary: &'static str) -> Subcommand {
+    Subcommand { usage, summary }
+}
+
+const CLIENT: &[Subcommand] = &[
+    sub("GETNAME", "Return the name of the current connection."),
+    sub("ID", "Return the ID of the current connection."),
+    sub(
+        "INFO",
+        "Return information about the current client connection.",
+    ),
+    sub(
+        "KILL <ip:port>|<filter> <value> [<filter> <value> ...]",
+        "Kill connections by address, or by ID, ADDR, LADDR and SKIPME filters.",
+    ),
+    sub(
+        "LIST [ID <id> [<id> ...]]",
+        "Return information about client connections.",
+    ),
+    sub(
+        "NO-EVICT (ON|OFF)",
+        "Protect current client connection from eviction.",
+    ),
+    sub(
+        "NO-TOUCH (ON|OFF)",
+        "Will not touch LRU/LFU stats when this mode is on.",
+    ),
+    sub(
+        "SETINFO <LIB-NAME|LIB-VER> <value>",
+        "Set client library metadata for the current connection.",
+    ),
+    sub(
+        "SETNAME <name>",
+        "Assign the name <name> to the current connection.",
+    ),
+    sub(
+        "TRACKING (ON|OFF)",
+        "Control server assisted client side caching.",
+    ),
+];
+
+const COMMAND: &[Subcommand] = &[
+    sub("(no subcommand)", "Return details about all commands."),
+    sub(
+        "COUNT",
+        "Return the total number of commands in this server.",
+    ),
+    sub(
+        "GETKEYS <full-command>",
+        "Return the keys from a full Redis command.",
+    ),
+    sub(
+        "GETKEYSANDFLAGS <full-command>",
+        "Return the keys and the access flags from a full Redis command.",
+    ),
+    sub(
+        "INFO [<command-name> ...]",
+        "Return details about multiple commands.",
+    ),
+    sub("LIST", "Return a list of all commands in this server."),
+];
+
+const CONFIG: &[Subcommand] = &[
+    sub(
+        "GET <pattern> [<pattern> ...]",
+        "Return parameters matching the glob-like <pattern> and their values.",
+    ),
+    sub(
+        "SET <directive> <value> [<directive> <value> ...]",
+        "Set the configuration <directive> to <value>.",
+    ),
+];
+
+#[cfg(feature = "native-modules")]
+const MODULE: &[Subcommand] = &[
+    sub("LIST", "Return a list of loaded modules."),
+    sub("LOAD <path>", "Load a module library from <path>."),
+    sub("UNLOAD <name>", "Unload a module."),
+];
+
+const OBJECT: &[Subcommand] = &[
+    sub(
+        "FREQ <key>",
+        "Return the access frequency index of the key <key>.",
+    ),
+    sub("IDLETIME <key>", "Return the idle time of the key <key>."),
+];
+
+const SLOWLOG: &[Subcommand] = &[
+    sub(
+        "GET [<count>]",
+        "Return top <count> entries from the slowlog (default: 10, -1 mean all).",
+    ),
+    sub("LEN", "Return the length of the slowlog."),
+    sub("RESET", "Reset the slowlog."),
+];
+
+// Subcommands of every container command, by uppercase name
+fn subcommands(name: &str) -> Option<&'static [Subcommand]> {
+    match name {
+        "CLIENT" => Some(CLIENT),
+        "COMMAND" => Some(COMMAND),
+        "CONFIG" => Some(CONFIG),
+        #[cfg(feature = "native-modules")]
+        "MODULE" => Some(MODULE),
+        "OBJECT" => Some(OBJECT),
+        "SLOWLOG" => Some(SLOWLOG),
+        _ => None,
+    }
+}
+
+// Container commands all answer <command> HELP, like redis-cli expects
+pub fn is_help_request(name: &str, args: &[Vec<u8>]) -> bool {
+    args.len() == 2 && args[1].eq_ignore_ascii_case(b"HELP") && subcommands(name).is_some()
+}
+
+// Laid out the same way as Redis' help replies, with each summary indented
+// under its subcommand
+fn help_lines(name: &str) -> Option<Vec<String>> {
+    let subcommands = subcommands(name)?;
+    let mut lines = vec![format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        name
+    )];
+    for subcommand in subcommands {
+        lines.push(subcommand.usage.to_string());
+        lines.push(format!("    {}", subcommand.summary));
+    }
+    lines.push("HELP".to_string());
+    lines.push("    Print this help.".to_string());
+    Some(lines)
+}
+
+pub fn write_help(conn: &mut dyn Connection, name: &str) {
+    let lines = help_lines(name).unwrap_or_default();
+    conn.write_array(lines.len());
+    for line in lines {
+        conn.write_string(&line);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_help_lines() {
+        let lines = help_lines("SLOWLOG").unwrap();
+        assert_eq!(
+            "SLOWLOG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            lines[0]
+        );
+        assert_eq!("LEN", lines[3]);
+        assert_eq!("    Return the length of the slowlog.", lines[4]);
+        assert_eq!(Some(&"    Print this help.".to_string()), lines.last());
+        assert_eq!(1 + 2 * SLOWLOG.len() + 2, lines.len());
+
+        assert_eq!(None, help_lines("GET"));
+    }
+
+    #[test]
+    fn test_is_help_request() {
+        let args: Vec<Vec<u8>> = vec!["CONFIG".into(), "help".into()];
+        assert!(is_help_request("CONFIG", &args));
+
+        let args: Vec<Vec<u8>> = vec!["GET".into(), "help".into()];
+        assert!(!is_help_request("GET", &args));
+
+        let args: Vec<Vec<u8>> = vec!["CONFIG".into(), "HELP".into(), "x".into()];
+        assert!(!is_help_request("CONFIG", &args));
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{config, keyspec, sketches::topk::TopK};
+
+// Sized so the whole tracker stays within a few hundred KiB no matter how
+// many distinct keys are accessed
+const TRACKED_KEYS: u32 = 64;
+const WIDTH: u32 = 2048;
+const DEPTH: u32 = 4;
+const DECAY: f64 = 0.9;
+
+fn new_sketch() -> TopK {
+    TopK::new(TRACKED_KEYS, WIDTH, DEPTH, DECAY).unwrap()
+}
+
+// Counts are kept per window, and reports combine the current window with
+// the previous one, so they always cover between one and two windows of
+// accesses rather than resetting to nothing at each boundary
+pub struct HotKeys {
+    window: Duration,
+    started: Instant,
+    current: TopK,
+    previous: TopK,
+}
+
+impl HotKeys {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            window,
+            started: now,
+            current: new_sketch(),
+            previous: new_sketch(),
+        }
+    }
+
+    fn rotate(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.started);
+        if elapsed < self.window {
+            return;
+        }
+
+        // After more than two idle windows nothing recent is left to keep
+        self.previous = if elapsed < self.window * 2 {
+            std::mem::replace(&mut self.current, new_sketch())
+        } else {
+            self.current = new_sketch();
+            new_sketch()
+        };
+        self.started = now;
+    }
+
+    pub fn record(&mut self, key: &[u8], now: Instant) {
+        self.rotate(now);
+        self.current.add(key);
+    }
+
+    // The most accessed keys with their approximate access counts, hottest
+    // first
+    pub fn top(&mut self, count: usize, now: Instant) -> Vec<(Vec<u8>, u64)> {
+        self.rotate(now);
+
+        let mut totals: HashMap<Vec<u8>, u64> = HashMap::new();
+        for (key, _) in self.current.list().into_iter().chain(self.previous.list()) {
+            let total = self.current.count(&key) + self.previous.count(&key);
+            totals.insert(key, total);
+        }
+
+        let mut top: Vec<(Vec<u8>, u64)> = totals.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(count);
+        top
+    }
+}
+
+fn hotkeys() -> &'static Mutex<Option<HotKeys>> {
+    static HOTKEYS: OnceLock<Mutex<Option<HotKeys>>> = OnceLock::new();
+    HOTKEYS.get_or_init(|| Mutex::new(None))
+}
+
+fn settings() -> (bool, Duration) {
+    let config = config::config().read().unwrap();
+    let window = config
+        .value("hotkeys-window-seconds")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60);
+    (
+        config.value("hotkeys-tracking") == Some("yes"),
+        Duration::from_secs(window.max(1)),
+    )
+}
+
+// Counts an access to each key of the command. Tracking follows
+// hotkeys-tracking, and turning it off discards what was collected.
+pub fn record(args: &[Vec<u8>]) {
+    let (enabled, window) = settings();
+    let mut tracker = hotkeys().lock().unwrap();
+    if !enabled {
+        *tracker = None;
+        return;
+    }
+
+    let keys = match keyspec::get_keys(args) {
+        Ok(keys) => keys,
+        Err(_) => return,
+    };
+
+    let now = Instant::now();
+    let tracker = tracker.get_or_insert_with(|| HotKeys::new(window, now));
+    tracker.window = window;
+    for (index, _) in keys {
+        tracker.record(&args[index], now);
+    }
+}
+
+// Forgets every access, as when the keyspace is flushed
+pub fn clear() {
+    *hotkeys().lock().unwrap() = None;
+}
+
+pub fn top(count: usize) -> Vec<(Vec<u8>, u64)> {
+    match hotkeys().lock().unwrap().as_mut() {
+        Some(tracker) => tracker.top(count, Instant::now()),
+        None => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hotkeys_top() {
+        let start = Instant::now();
+        let mut tracker = HotKeys::new(Duration::from_secs(10), start);
+        for _ in 0..5 {
+            tracker.record(b"hot", start);
+        }
+        tracker.record(b"cold", start);
+
+        assert_eq!(
+            vec![(b"hot".to_vec(), 5), (b"cold".to_vec(), 1)],
+            tracker.top(10, start)
+        );
+        assert_eq!(1, tracker.top(1, start).len());
+    }
+
+    #[test]
+    fn test_hotkeys_window() {
+        let start = Instant::now();
+        let mut tracker = HotKeys::new(Duration::from_secs(10), start);
+        tracker.record(b"old", start);
+
+        // The previous window still counts
+        let later = start + Duration::from_secs(15);
+        tracker.record(b"new", later);
+        assert_eq!(2, tracker.top(10, later).len());
+
+        // But not the one before it
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(vec![(b"new".to_vec(), 1)], tracker.top(10, much_later));
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use tracing::{info, warn};
+
+use crate::database::DatabaseOperations;
+
+const SCAN_COUNT: &str = "1000";
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        bail!("connection closed");
+    }
+    Ok(line.trim_end_matches("\r\n").to_string())
+}
+
+pub(crate) fn read_reply(reader: &mut impl BufRead) -> Result<Reply> {
+    let line = read_line(reader)?;
+    if line.is_empty() {
+        bail!("empty reply");
+    }
+
+    let (kind, rest) = line.split_at(1);
+    match kind {
+        "+" => Ok(Reply::Simple(rest.to_string())),
+        "-" => Ok(Reply::Error(rest.to_string())),
+        ":" => Ok(Reply::Integer(rest.parse()?)),
+        "$" => {
+            let len: i64 = rest.parse()?;
+            if len < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+
+            // Read the payload and its trailing CRLF
+            let mut data = vec![0; len as usize + 2];
+            reader.read_exact(&mut data)?;
+            data.truncate(len as usize);
+            Ok(Reply::Bulk(Some(data)))
+        }
+        "*" => {
+            let len: i64 = rest.parse()?;
+            if len < 0 {
+                return Ok(Reply::Array(None));
+            }
+
+            let items = (0..len)
+                .map(|_| read_reply(reader))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Reply::Array(Some(items)))
+        }
+        _ => bail!("unexpected reply type '{}'", kind),
+    }
+}
+
+pub(crate) fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        request.extend_from_slice(arg);
+        request.extend_from_slice(b"\r\n");
+    }
+    request
+}
+
+// Just enough of a RESP2 client to read keys out of a Redis server
+pub(crate) struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    pub(crate) fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    // Sends a command and returns its reply as-is, error replies included
+    pub(crate) fn request(&mut self, args: &[&[u8]]) -> Result<Reply> {
+        self.writer.write_all(&encode_command(args))?;
+        read_reply(&mut self.reader)
+    }
+
+    fn command(&mut self, args: &[&[u8]]) -> Result<Reply> {
+        match self.request(args)? {
+            Reply::Error(err) => Err(anyhow!("{}", err)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub imported: u64,
+    // Keys of types wedis doesn't support, or that vanished mid-import
+    pub skipped: u64,
+}
+
+// Copies every key from a running Redis server, along with its TTL. Keys are
+// read one at a time, so writes made to the source during the import may or
+// may not be picked up.
+pub fn import_from(addr: &str, db: &dyn DatabaseOperations) -> Result<ImportSummary> {
+    let mut client = Client::connect(addr)?;
+    let mut summary = ImportSummary::default();
+
+    let mut cursor = b"0".to_vec();
+    loop {
+        let reply = client.command(&[b"SCAN", &cursor, b"COUNT", SCAN_COUNT.as_bytes()])?;
+        let (next_cursor, keys) = match reply {
+            Reply::Array(Some(items)) => match items.into_iter().collect_tuple() {
+                Some((Reply::Bulk(Some(next_cursor)), Reply::Array(Some(keys)))) => {
+                    (next_cursor, keys)
+                }
+                _ => bail!("malformed SCAN reply"),
+            },
+            _ => bail!("malformed SCAN reply"),
+        };
+
+        for key in keys {
+            let key = match key {
+                Reply::Bulk(Some(key)) => key,
+                _ => bail!("malformed SCAN reply"),
+            };
+
+            if import_key(&mut client, db, &key)? {
+                summary.imported += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+
+        if next_cursor == b"0" {
+            break;
+        }
+        cursor = next_cursor;
+    }
+
+    info!(
+        "Imported {} keys from {} ({} skipped)",
+        summary.imported, addr, summary.skipped
+    );
+    Ok(summary)
+}
+
+fn import_key(client: &mut Client, db: &dyn DatabaseOperations, key: &[u8]) -> Result<bool> {
+    let key_type = match client.command(&[b"TYPE", key])? {
+        Reply::Simple(key_type) => key_type,
+        _ => bail!("malformed TYPE reply"),
+    };
+
+    // -1 means no TTL and -2 means the key is gone
+    let ttl = match client.command(&[b"PTTL", key])? {
+        Reply::Integer(-2) => return Ok(false),
+        Reply::Integer(ttl) if { ttl >= 0 } => Some(Duration::from_millis(ttl as u64)),
+        Reply::Integer(_) => None,
+        _ => bail!("malformed PTTL reply"),
+    };
+
+    match key_type.as_str() {
+        "string" => {
+            let value = match client.command(&[b"GET", key])? {
+                Reply::Bulk(Some(value)) => value,
+                Reply::Bulk(None) => return Ok(false),
+                _ => bail!("malformed GET reply"),
+            };
+
+            match ttl {
+                Some(ttl) => db.put_string_with_expiry(key, &value, ttl)?,
+                None => db.put_string(key, &value)?,
+            }
+        }
+        "hash" => {
+            let fields = match client.command(&[b"HGETALL", key])? {
+                Reply::Array(Some(items)) if { !items.is_empty() } => items
+                    .into_iter()
+                    .map(|item| match item {
+                        Reply::Bulk(Some(data)) => Ok(data),
+                        _ => Err(anyhow!("malformed HGETALL reply")),
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                Reply::Array(_) => return Ok(false),
+                _ => bail!("malformed HGETALL reply"),
+            };
+
+            db.put_hash_fields(key, fields.into_iter().tuples().collect())?;
+            if let Some(ttl) = ttl {
+                db.put_expiry(key, ttl)?;
+            }
+        }
+        "none" => return Ok(false),
+        _ => {
+            warn!(
+                "Skipping {} ({} isn't supported)",
+                String::from_utf8_lossy(key),
+                key_type
+            );
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_reply() {
+        let mut input: &[u8] = b"*2\r\n$1\r\n0\r\n*2\r\n$3\r\nfoo\r\n$-1\r\n";
+        assert_eq!(
+            Reply::Array(Some(vec![
+                Reply::Bulk(Some(b"0".to_vec())),
+                Reply::Array(Some(vec![
+                    Reply::Bulk(Some(b"foo".to_vec())),
+                    Reply::Bulk(None)
+                ])),
+            ])),
+            read_reply(&mut input).unwrap()
+        );
+
+        let mut input: &[u8] = b":-2\r\n+string\r\n-ERR oops\r\n";
+        assert_eq!(Reply::Integer(-2), read_reply(&mut input).unwrap());
+        assert_eq!(
+            Reply::Simple("string".into()),
+            read_reply(&mut input).unwrap()
+        );
+        assert_eq!(
+            Reply::Error("ERR oops".into()),
+            read_reply(&mut input).unwrap()
+        );
+    }
+}
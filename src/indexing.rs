@@ -1,12 +1,12 @@
 fn adjust_index(end_index: usize, x: i64) -> usize {
-    let iend_index: i64 = end_index.try_into().unwrap();
+    let iend_index: i64 = end_index.try_into().unwrap_or(i64::MAX);
     if x > iend_index {
         end_index
     } else if x >= 0 {
-        x.try_into().unwrap()
+        x as usize
     } else {
-        // x < 0
-        (iend_index + x + 1).try_into().unwrap()
+        // x < 0, counting back from the end and stopping at the start
+        (iend_index + x + 1).max(0) as usize
     }
 }
 
@@ -14,6 +14,29 @@ pub fn adjust_indices(end_index: usize, start: i64, end: i64) -> (usize, usize)
     (adjust_index(end_index, start), adjust_index(end_index, end))
 }
 
+// Clamps an inclusive range the same way Redis does for string ranges,
+// returning None if nothing is left of it
+pub fn normalize_range(len: usize, start: i64, end: i64) -> Option<(usize, usize)> {
+    let ilen: i64 = len.try_into().ok()?;
+    let mut start = if start < 0 { ilen + start } else { start };
+    let mut end = if end < 0 { ilen + end } else { end };
+    if start < 0 {
+        start = 0;
+    }
+    if end < 0 {
+        end = 0;
+    }
+    if end >= ilen {
+        end = ilen - 1;
+    }
+
+    if len == 0 || start > end {
+        return None;
+    }
+
+    Some((start.try_into().ok()?, end.try_into().ok()?))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -28,4 +51,27 @@ mod test {
         assert_eq!(2, start);
         assert_eq!(4, end);
     }
+
+    #[test]
+    fn test_adjust_indices_too_negative() {
+        let (start, end) = adjust_indices(4, i64::MIN, -100);
+        assert_eq!(0, start);
+        assert_eq!(0, end);
+    }
+
+    #[test]
+    fn test_normalize_range_negative() {
+        assert_eq!(Some((2, 4)), normalize_range(5, -3, -1));
+    }
+
+    #[test]
+    fn test_normalize_range_clamped() {
+        assert_eq!(Some((0, 4)), normalize_range(5, -100, 100));
+    }
+
+    #[test]
+    fn test_normalize_range_empty() {
+        assert_eq!(None, normalize_range(5, 3, 1));
+        assert_eq!(None, normalize_range(0, 0, -1));
+    }
 }
@@ -0,0 +1,261 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use rocksdb::{Direction, IteratorMode, DB};
+
+use crate::{
+    database::{
+        element_key_prefix, encode_key, parse_sample, sample_key_prefix, shard_index,
+        shard_options, shard_paths, total_order, type_name, DatabaseError, DumpedKey,
+    },
+    glob::glob_match,
+    keyformat::{self, Namespace, DEFAULT_DB},
+    time::{parse_timestamp, unix_timestamp},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expiry {
+    Never,
+    In(Duration),
+    // The TTL lapsed, but the key hasn't been cleaned up yet
+    Expired,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyInfo {
+    pub type_name: &'static str,
+    pub expiry: Expiry,
+    pub data_size: usize,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    pub shards: usize,
+    // Number of keys and their total data size, by type
+    pub types: BTreeMap<&'static str, (u64, u64)>,
+    pub with_ttl: u64,
+    pub expired: u64,
+    pub samples: u64,
+}
+
+// Read-only access to a data directory, for looking at datasets without
+// starting the server. RocksDB allows this even while the server is running.
+pub struct Inspector {
+    shards: Vec<DB>,
+}
+
+// Sharded directories are recognized by their first shard
+pub fn detect_shards(root: &Path) -> usize {
+    if !root.join("shard-0").is_dir() {
+        return 1;
+    }
+    (0..)
+        .take_while(|i| root.join(format!("shard-{}", i)).is_dir())
+        .count()
+}
+
+impl Inspector {
+    pub fn open(root: &Path) -> Result<Self, DatabaseError> {
+        let shards = shard_paths(root, detect_shards(root))
+            .iter()
+            .map(|path| DB::open_for_read_only(&shard_options(), path, false))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { shards })
+    }
+
+    fn shard(&self, key: &[u8]) -> &DB {
+        &self.shards[shard_index(key, self.shards.len())]
+    }
+
+    pub fn keys(&self, pattern: &[u8]) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        let prefix = keyformat::namespace_prefix(Namespace::Type, DEFAULT_DB);
+        let mut keys = vec![];
+        for shard in self.shards.iter() {
+            for entry in shard.iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                total_order(),
+            ) {
+                let (type_key, _) = entry?;
+                let key = match keyformat::decode(Namespace::Type, DEFAULT_DB, &type_key) {
+                    Some((key, _)) => key,
+                    None => break,
+                };
+                if glob_match(pattern, key) {
+                    keys.push(key.to_vec());
+                }
+            }
+        }
+
+        keys.sort();
+        Ok(keys)
+    }
+
+    pub fn key_info(&self, key: &[u8]) -> Result<Option<KeyInfo>, DatabaseError> {
+        let shard = self.shard(key);
+        let Some(type_value) = shard.get(encode_key(Namespace::Type, key))? else {
+            return Ok(None);
+        };
+
+        let ttl_value = shard.get(encode_key(Namespace::Ttl, key))?;
+        let data_size = shard
+            .get(encode_key(Namespace::Data, key))?
+            .map_or(0, |data| data.len());
+
+        Ok(Some(KeyInfo {
+            type_name: type_name(&type_value),
+            expiry: expiry(&ttl_value)?,
+            data_size,
+        }))
+    }
+
+    pub fn value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        Ok(self.shard(key).get(encode_key(Namespace::Data, key))?)
+    }
+
+    // Everything stored for a key, or None if it doesn't exist or has expired
+    pub fn dump(&self, key: &[u8]) -> Result<Option<DumpedKey>, DatabaseError> {
+        let shard = self.shard(key);
+        let type_value = shard.get(encode_key(Namespace::Type, key))?;
+        let data = shard.get(encode_key(Namespace::Data, key))?;
+        let ttl_value = shard.get(encode_key(Namespace::Ttl, key))?;
+        let (type_value, data) = match (type_value, data, expiry(&ttl_value)?) {
+            (_, _, Expiry::Expired) => return Ok(None),
+            (Some(type_value), Some(data), _) => (type_value, data),
+            _ => return Ok(None),
+        };
+
+        let prefix = sample_key_prefix(key);
+        let mut samples = vec![];
+        for entry in shard.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (sample_key, value) = entry?;
+            if !sample_key.starts_with(&prefix) {
+                break;
+            }
+            samples.push(parse_sample(prefix.len(), &sample_key, &value));
+        }
+
+        // Elements come out in list order, since they're keyed by position.
+        // Set members and hash fields are the keys themselves.
+        let type_name = type_name(&type_value);
+        let prefix = element_key_prefix(key);
+        let mut elements = vec![];
+        let mut fields = vec![];
+        for entry in shard.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (element_key, value) = entry?;
+            if !element_key.starts_with(&prefix) {
+                break;
+            }
+            let member = element_key[prefix.len()..].to_vec();
+            match type_name {
+                "set" => elements.push(member),
+                "hash" => fields.push((member, value.to_vec())),
+                _ => elements.push(value.to_vec()),
+            }
+        }
+
+        Ok(Some(DumpedKey {
+            key: key.to_vec(),
+            type_name: type_name.to_string(),
+            data,
+            expires_at: ttl_value.map(|ttl| parse_timestamp(&ttl)).transpose()?,
+            samples,
+            elements,
+            fields,
+        }))
+    }
+
+    pub fn stats(&self) -> Result<Stats, DatabaseError> {
+        let mut stats = Stats {
+            shards: self.shards.len(),
+            ..Default::default()
+        };
+
+        for key in self.keys(b"*")? {
+            if let Some(info) = self.key_info(&key)? {
+                let (count, size) = stats.types.entry(info.type_name).or_default();
+                *count += 1;
+                *size += info.data_size as u64;
+
+                match info.expiry {
+                    Expiry::Never => {}
+                    Expiry::In(_) => stats.with_ttl += 1,
+                    Expiry::Expired => stats.expired += 1,
+                }
+            }
+        }
+
+        let prefix = keyformat::namespace_prefix(Namespace::Sample, DEFAULT_DB);
+        for shard in self.shards.iter() {
+            for entry in shard.iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                total_order(),
+            ) {
+                let (sample_key, _) = entry?;
+                if !sample_key.starts_with(&prefix) {
+                    break;
+                }
+                stats.samples += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+fn expiry(ttl_value: &Option<Vec<u8>>) -> Result<Expiry, DatabaseError> {
+    match ttl_value {
+        Some(ttl) => {
+            let remaining = parse_timestamp(ttl)?.saturating_sub(unix_timestamp()?);
+            if remaining.is_zero() {
+                Ok(Expiry::Expired)
+            } else {
+                Ok(Expiry::In(remaining))
+            }
+        }
+        None => Ok(Expiry::Never),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use rocksdb::Options;
+
+    use crate::database::{open_shard, Database, DatabaseOperations};
+
+    use super::*;
+
+    #[test]
+    fn test_inspector() {
+        let path = env::temp_dir().join(format!("wedis-test-inspect-{}", std::process::id()));
+        let _ = DB::destroy(&Options::default(), &path);
+        {
+            let db_raw = open_shard(&path).expect("Failed to open database");
+            let db = Database::new(db_raw);
+            db.put_string("a".as_bytes(), "1".as_bytes()).unwrap();
+            db.put_string_with_expiry("b".as_bytes(), "22".as_bytes(), Duration::from_secs(100))
+                .unwrap();
+        }
+
+        let inspector = Inspector::open(&path).unwrap();
+        assert_eq!(
+            vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec()],
+            inspector.keys(b"*").unwrap()
+        );
+        assert_eq!(
+            Some("22".as_bytes().to_vec()),
+            inspector.value("b".as_bytes()).unwrap()
+        );
+
+        let info = inspector.key_info("b".as_bytes()).unwrap().unwrap();
+        assert_eq!("string", info.type_name);
+        assert!(matches!(info.expiry, Expiry::In(_)));
+
+        let stats = inspector.stats().unwrap();
+        assert_eq!(Some(&(2, 3)), stats.types.get("string"));
+        assert_eq!(1, stats.with_ttl);
+
+        drop(inspector);
+        let _ = DB::destroy(&Options::default(), &path);
+    }
+}
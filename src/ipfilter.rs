@@ -0,0 +1,153 @@
+use std::{
+    net::IpAddr,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use thiserror::Error;
+use tracing::warn;
+
+use crate::config;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CidrError {
+    #[error("invalid CIDR block '{0}'")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u32,
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    // Accepts a.b.c.d/n and its IPv6 equivalent, or a bare address for a
+    // single host
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CidrError::Invalid(s.to_string());
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse::<u32>().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Clients connecting over IPv6 to a dual-stack socket show up with
+        // IPv4-mapped addresses, which should still match IPv4 rules
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn parse_rules(rules: &str) -> Result<Vec<Cidr>, CidrError> {
+    rules.split_whitespace().map(Cidr::from_str).collect()
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self { allow, deny }
+    }
+
+    // Deny rules win over allow rules, and an empty allowlist allows
+    // everyone who isn't denied
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    // Rules are validated when they're set, so they always parse here
+    pub fn from_config() -> Self {
+        let config = config::config().read().unwrap();
+        let rules = |name| parse_rules(config.value(name).unwrap_or("")).unwrap_or_default();
+        Self::new(rules("ip-allow"), rules("ip-deny"))
+    }
+}
+
+static REJECTED: AtomicU64 = AtomicU64::new(0);
+
+// Checked as connections are accepted, before they can send anything
+pub fn allow_connection(ip: IpAddr) -> bool {
+    if IpFilter::from_config().is_allowed(ip) {
+        return true;
+    }
+
+    warn!("Rejected connection from {}", ip);
+    REJECTED.fetch_add(1, Ordering::Relaxed);
+    false
+}
+
+pub fn rejected_connections() -> u64 {
+    REJECTED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cidr_contains() {
+        let cidr: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(cidr.contains("10.1.2.3".parse().unwrap()));
+        assert!(cidr.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!cidr.contains("10.2.0.1".parse().unwrap()));
+
+        let cidr: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(cidr.contains("192.168.1.1".parse().unwrap()));
+
+        let cidr: Cidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains("fd12::1".parse().unwrap()));
+        assert!(!cidr.contains("10.1.2.3".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("nope".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let filter = IpFilter::new(
+            parse_rules("10.0.0.0/8 127.0.0.1").unwrap(),
+            parse_rules("10.0.0.66").unwrap(),
+        );
+        assert!(filter.is_allowed("127.0.0.1".parse().unwrap()));
+        assert!(filter.is_allowed("10.0.0.1".parse().unwrap()));
+        assert!(!filter.is_allowed("10.0.0.66".parse().unwrap()));
+        assert!(!filter.is_allowed("192.168.0.1".parse().unwrap()));
+
+        let filter = IpFilter::default();
+        assert!(filter.is_allowed("192.168.0.1".parse().unwrap()));
+    }
+}
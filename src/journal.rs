@@ -0,0 +1,190 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use tracing::{error, info};
+
+use crate::{
+    config,
+    import::{encode_command, read_reply, Client, Reply},
+    time::unix_timestamp,
+};
+
+// Every incoming command, as it was received. Journals are written as RESP
+// arrays of the timestamp, the connection id and then the command itself, so
+// they can be read back with the same parser as replies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    // Unix time in milliseconds
+    pub timestamp: u64,
+    pub connection_id: i64,
+    pub args: Vec<Vec<u8>>,
+}
+
+impl JournalEntry {
+    pub fn encode(&self) -> Vec<u8> {
+        let timestamp = self.timestamp.to_string();
+        let connection_id = self.connection_id.to_string();
+        let mut fields: Vec<&[u8]> = vec![timestamp.as_bytes(), connection_id.as_bytes()];
+        fields.extend(self.args.iter().map(|arg| arg.as_slice()));
+        encode_command(&fields)
+    }
+
+    // Reads the next entry, or None at the end of the journal
+    pub fn read(reader: &mut impl BufRead) -> Result<Option<JournalEntry>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let mut fields = match read_reply(reader)? {
+            Reply::Array(Some(items)) if { items.len() > 2 } => items
+                .into_iter()
+                .map(|item| match item {
+                    Reply::Bulk(Some(data)) => Ok(data),
+                    _ => Err(anyhow!("malformed journal entry")),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => bail!("malformed journal entry"),
+        };
+
+        let args = fields.split_off(2);
+        Ok(Some(JournalEntry {
+            timestamp: String::from_utf8_lossy(&fields[0]).parse()?,
+            connection_id: String::from_utf8_lossy(&fields[1]).parse()?,
+            args,
+        }))
+    }
+}
+
+fn journal() -> &'static OnceLock<Mutex<File>> {
+    static JOURNAL: OnceLock<Mutex<File>> = OnceLock::new();
+    &JOURNAL
+}
+
+// Opens the journal configured by journal-file. It can only be enabled at
+// startup, and records every command until the server exits, so it's meant
+// for debugging rather than for running in production.
+pub fn start_from_config() -> io::Result<()> {
+    let path = config::config()
+        .read()
+        .unwrap()
+        .value("journal-file")
+        .unwrap_or("")
+        .to_string();
+    if path.is_empty() {
+        return Ok(());
+    }
+
+    let file = OpenOptions::new().append(true).create(true).open(&path)?;
+    let _ = journal().set(Mutex::new(file));
+    info!("Journaling commands to {}", path);
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    journal().get().is_some()
+}
+
+pub fn record(connection_id: i64, args: &[Vec<u8>]) {
+    let journal = match journal().get() {
+        Some(journal) => journal,
+        None => return,
+    };
+
+    let entry = JournalEntry {
+        timestamp: unix_timestamp().map(|t| t.as_millis() as u64).unwrap_or(0),
+        connection_id,
+        args: args.to_vec(),
+    };
+    // Entries are written in one piece, so a crash can at most cut off the
+    // last one
+    if let Err(err) = journal.lock().unwrap().write_all(&entry.encode()) {
+        error!("Failed to write command journal: {}", err);
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplaySummary {
+    pub commands: u64,
+    pub connections: u64,
+    // Commands the server replied to with an error
+    pub errors: u64,
+}
+
+// Feeds a journal to the server at addr, with one connection per recorded
+// connection. Each reply is awaited before the next command is sent, so
+// commands run in the order they were recorded. With timing, the gaps
+// between commands are reproduced as well.
+pub fn replay(path: &Path, addr: &str, timing: bool) -> Result<ReplaySummary> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut clients: HashMap<i64, Client> = HashMap::new();
+    let mut summary = ReplaySummary::default();
+
+    let started = Instant::now();
+    let mut first_timestamp = None;
+    while let Some(entry) = JournalEntry::read(&mut reader)? {
+        if timing {
+            let first = *first_timestamp.get_or_insert(entry.timestamp);
+            let due = Duration::from_millis(entry.timestamp.saturating_sub(first));
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        let client = match clients.entry(entry.connection_id) {
+            Entry::Occupied(client) => client.into_mut(),
+            Entry::Vacant(slot) => {
+                summary.connections += 1;
+                slot.insert(Client::connect(addr)?)
+            }
+        };
+
+        let args: Vec<&[u8]> = entry.args.iter().map(|arg| arg.as_slice()).collect();
+        if let Reply::Error(_) = client.request(&args)? {
+            summary.errors += 1;
+        }
+        summary.commands += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_round_trip() {
+        let entries = [
+            JournalEntry {
+                timestamp: 1000,
+                connection_id: 1,
+                args: vec!["SET".into(), "key".into(), "a\r\nb".into()],
+            },
+            JournalEntry {
+                timestamp: 1005,
+                connection_id: 2,
+                args: vec!["GET".into(), "key".into()],
+            },
+        ];
+        let journal: Vec<u8> = entries.iter().flat_map(|entry| entry.encode()).collect();
+
+        let mut reader: &[u8] = &journal;
+        assert_eq!(
+            Some(entries[0].clone()),
+            JournalEntry::read(&mut reader).unwrap()
+        );
+        assert_eq!(
+            Some(entries[1].clone()),
+            JournalEntry::read(&mut reader).unwrap()
+        );
+        assert_eq!(None, JournalEntry::read(&mut reader).unwrap());
+    }
+}
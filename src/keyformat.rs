@@ -0,0 +1,216 @@
+// Internal record keys are laid out as
+//
+//   tag '/' db key_len key [suffix]
+//
+// with the database index as a big-endian u16 and the key length as a
+// big-endian u32. Since the user key's length is spelled out, no user key
+// can run into another key's records, or into another database or
+// namespace, whatever bytes it contains. The suffix is only used by time
+// series samples, for their timestamps, and list elements, for their
+// sequence numbers.
+//
+// Entries of the expiry index put the expiry time first instead, as
+//
+//   tag '/' db expires_at key_len key
+//
+// with the time in milliseconds as a big-endian u64, so that they're
+// ordered by when they come due.
+//
+// RocksDB's prefix bloom filters are keyed on a record key without its
+// suffix, so that all of one key's records in a namespace share a prefix.
+//
+// Before this layout, records were keyed as tag ':' key. The different
+// separator keeps the two layouts apart, so legacy records can be found
+// and upgraded in place.
+
+pub const DEFAULT_DB: u16 = 0;
+
+const SEPARATOR: u8 = b'/';
+const LEGACY_SEPARATOR: u8 = b':';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Type,
+    Data,
+    Ttl,
+    Sample,
+    Length,
+    Expiry,
+    FieldTtl,
+    Element,
+}
+
+impl Namespace {
+    // The namespaces of the legacy layout, which predates the expiry index
+    pub const ALL: [Namespace; 5] = [
+        Namespace::Type,
+        Namespace::Data,
+        Namespace::Ttl,
+        Namespace::Sample,
+        Namespace::Length,
+    ];
+
+    fn tag(self) -> u8 {
+        match self {
+            Namespace::Type => b't',
+            Namespace::Data => b'd',
+            Namespace::Ttl => b'T',
+            Namespace::Sample => b's',
+            Namespace::Length => b'l',
+            Namespace::Expiry => b'e',
+            Namespace::FieldTtl => b'f',
+            Namespace::Element => b'i',
+        }
+    }
+}
+
+// Every record key of one namespace in one database starts with this
+pub fn namespace_prefix(ns: Namespace, db: u16) -> Vec<u8> {
+    [&[ns.tag(), SEPARATOR][..], &db.to_be_bytes()].concat()
+}
+
+pub fn encode(ns: Namespace, db: u16, key: &[u8]) -> Vec<u8> {
+    let key_len = (key.len() as u32).to_be_bytes();
+    [namespace_prefix(ns, db).as_slice(), &key_len, key].concat()
+}
+
+// Splits a record key into its user key and suffix, or returns None if it
+// isn't from the given namespace and database
+pub fn decode(ns: Namespace, db: u16, record_key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let rest = record_key.strip_prefix(namespace_prefix(ns, db).as_slice())?;
+    split_key(rest)
+}
+
+pub fn encode_expiry(db: u16, expires_at: u64, key: &[u8]) -> Vec<u8> {
+    let key_len = (key.len() as u32).to_be_bytes();
+    [
+        namespace_prefix(Namespace::Expiry, db).as_slice(),
+        &expires_at.to_be_bytes(),
+        &key_len,
+        key,
+    ]
+    .concat()
+}
+
+// Splits an expiry index entry into its expiry time and user key
+pub fn decode_expiry(db: u16, record_key: &[u8]) -> Option<(u64, &[u8])> {
+    let rest = record_key.strip_prefix(namespace_prefix(Namespace::Expiry, db).as_slice())?;
+    let (expires_at, rest) = rest.split_first_chunk::<8>()?;
+    let (key, _) = split_key(rest)?;
+    Some((u64::from_be_bytes(*expires_at), key))
+}
+
+fn split_key(record_key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (key_len, rest) = record_key.split_first_chunk::<4>()?;
+    let key_len = u32::from_be_bytes(*key_len) as usize;
+    if rest.len() < key_len {
+        return None;
+    }
+    Some(rest.split_at(key_len))
+}
+
+// A record key up to the end of its user key, or None for keys without one,
+// like expiry index entries and legacy records
+pub fn key_prefix(record_key: &[u8]) -> Option<&[u8]> {
+    match record_key {
+        [tag, SEPARATOR, _, _, rest @ ..] if { *tag != Namespace::Expiry.tag() } => {
+            let (key, _) = split_key(rest)?;
+            Some(&record_key[..8 + key.len()])
+        }
+        _ => None,
+    }
+}
+
+pub fn legacy_prefix(ns: Namespace) -> [u8; 2] {
+    [ns.tag(), LEGACY_SEPARATOR]
+}
+
+// Rewrites a legacy record key in the current layout. Legacy keys all
+// belong to the default database, and legacy sample keys already
+// length-prefixed the series key.
+pub fn upgrade_legacy(ns: Namespace, record_key: &[u8]) -> Option<Vec<u8>> {
+    let rest = record_key.strip_prefix(&legacy_prefix(ns))?;
+    match ns {
+        Namespace::Sample => {
+            let (key, timestamp) = split_key(rest)?;
+            Some([encode(ns, DEFAULT_DB, key).as_slice(), timestamp].concat())
+        }
+        _ => Some(encode(ns, DEFAULT_DB, rest)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let record_key = encode(Namespace::Data, 3, b"key");
+        assert_eq!(b"d/\x00\x03\x00\x00\x00\x03key".to_vec(), record_key);
+        assert_eq!(
+            Some((&b"key"[..], &b""[..])),
+            decode(Namespace::Data, 3, &record_key)
+        );
+        assert_eq!(None, decode(Namespace::Data, 0, &record_key));
+        assert_eq!(None, decode(Namespace::Type, 3, &record_key));
+    }
+
+    #[test]
+    fn test_encode_decode_expiry() {
+        let soon = encode_expiry(0, 1000, b"zzz");
+        let later = encode_expiry(0, 2000, b"a");
+        assert!(soon < later);
+        assert_eq!(Some((1000, &b"zzz"[..])), decode_expiry(0, &soon));
+        assert_eq!(None, decode_expiry(1, &soon));
+        assert_eq!(None, decode_expiry(0, &encode(Namespace::Ttl, 0, b"a")));
+    }
+
+    #[test]
+    fn test_keys_dont_overlap() {
+        // With plain prefixes, one key's records would be a prefix of the
+        // records of every key that starts with it
+        let record_key = encode(Namespace::Data, 0, b"ab");
+        assert!(!record_key.starts_with(&encode(Namespace::Data, 0, b"a")));
+        assert!(!record_key.starts_with(&namespace_prefix(Namespace::Data, 1)));
+    }
+
+    #[test]
+    fn test_key_prefix() {
+        let record_key = encode(Namespace::Data, 0, b"key");
+        assert_eq!(Some(record_key.as_slice()), key_prefix(&record_key));
+
+        let prefix = encode(Namespace::Sample, 0, b"ts");
+        let sample = [prefix.as_slice(), &7u64.to_be_bytes()].concat();
+        assert_eq!(Some(prefix.as_slice()), key_prefix(&sample));
+
+        assert_eq!(None, key_prefix(&namespace_prefix(Namespace::Type, 0)));
+        assert_eq!(None, key_prefix(&encode_expiry(0, 1000, b"key")));
+        assert_eq!(None, key_prefix(b"d:key"));
+    }
+
+    #[test]
+    fn test_upgrade_legacy() {
+        assert_eq!(
+            Some(encode(Namespace::Ttl, DEFAULT_DB, b"key")),
+            upgrade_legacy(Namespace::Ttl, b"T:key")
+        );
+
+        let legacy_sample = [&b"s:\x00\x00\x00\x03key"[..], &7u64.to_be_bytes()].concat();
+        assert_eq!(
+            Some(
+                [
+                    encode(Namespace::Sample, DEFAULT_DB, b"key"),
+                    7u64.to_be_bytes().to_vec()
+                ]
+                .concat()
+            ),
+            upgrade_legacy(Namespace::Sample, &legacy_sample)
+        );
+
+        assert_eq!(None, upgrade_legacy(Namespace::Data, b"t:key"));
+        assert_eq!(
+            None,
+            upgrade_legacy(Namespace::Sample, b"s:\x00\x00\x00\x09key")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    keyspec::{self, KeySpecError},
+    sketches::hash64,
+};
+
+#[cfg(feature = "native-modules")]
+use crate::modules;
+#[cfg(feature = "wasm-plugins")]
+use crate::plugins;
+
+// Keys are spread over this many independently locked stripes. Commands on
+// keys in different stripes run in parallel.
+const STRIPES: usize = 1024;
+
+// Keyless commands that act on every key at once, and so need the keyspace
+// to themselves. Other keyless commands, like PING or INFO, only take the
+// keyspace lock shared.
+const KEYSPACE_COMMANDS: &[&str] = &["FLUSHALL", "FLUSHDB", "KEYS", "CHECK", "SHUTDOWN"];
+
+// Commands hold locks on the keys they name for as long as they run, so
+// that a command reading a key and then writing it back can't interleave
+// with another command on the same key. Commands that act on every key, like
+// KEYS or FLUSHDB, and commands whose keys can't be told up front, like a
+// module's commands, lock the whole keyspace instead.
+//
+// Every command takes the keyspace lock first, shared or exclusive, and then
+// its stripes in ascending order, so commands can't deadlock each other.
+pub struct KeyLocks {
+    keyspace: RwLock<()>,
+    stripes: Vec<Mutex<()>>,
+}
+
+// Releases the locks when dropped
+pub struct KeyGuard<'a> {
+    _shared: Option<RwLockReadGuard<'a, ()>>,
+    _exclusive: Option<RwLockWriteGuard<'a, ()>>,
+    _stripes: Vec<MutexGuard<'a, ()>>,
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyLocks {
+    pub fn new() -> Self {
+        Self {
+            keyspace: RwLock::new(()),
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    // The stripes to lock, which are none for keyless commands, or None if
+    // the command needs the whole keyspace. Commands that will only be
+    // answered with an error, like unknown ones or ones with too few
+    // arguments, lock nothing.
+    fn stripes_for(args: &[Vec<u8>]) -> Option<Vec<usize>> {
+        let keys = match keyspec::get_keys(args) {
+            Ok(keys) => keys,
+            Err(KeySpecError::NoKeys) => {
+                let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+                if KEYSPACE_COMMANDS.contains(&name.as_str()) {
+                    return None;
+                }
+                vec![]
+            }
+            Err(KeySpecError::UnknownCommand) => {
+                let name = args
+                    .first()
+                    .map(|name| String::from_utf8_lossy(name).to_uppercase())
+                    .unwrap_or_default();
+                if Self::is_extension_command(&name) {
+                    return None;
+                }
+                vec![]
+            }
+            Err(KeySpecError::InvalidArguments) => vec![],
+        };
+        let mut stripes: Vec<usize> = keys
+            .into_iter()
+            .map(|(index, _)| (hash64(&args[index], 0) % STRIPES as u64) as usize)
+            .collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        Some(stripes)
+    }
+
+    // Commands added by modules and plugins have no key specs, so their keys
+    // can't be told up front
+    #[allow(unused_variables)]
+    fn is_extension_command(name: &str) -> bool {
+        #[cfg(feature = "native-modules")]
+        if modules::has_command(name) {
+            return true;
+        }
+        #[cfg(feature = "wasm-plugins")]
+        if plugins::has_command(name) {
+            return true;
+        }
+        false
+    }
+
+    // Locks the keys of a full command line, including the command name.
+    // The locks guard nothing but the keys' turn, so a command that panicked
+    // while holding them doesn't keep others out.
+    pub fn lock(&self, args: &[Vec<u8>]) -> KeyGuard<'_> {
+        match Self::stripes_for(args) {
+            Some(stripes) => self.lock_stripes(stripes),
+            None => self.lock_keyspace(),
+        }
+    }
+
+    // Locks a single key, for work done on it outside of a command, like
+    // deleting it once it expires
+    pub fn lock_key(&self, key: &[u8]) -> KeyGuard<'_> {
+        self.lock_stripes(vec![(hash64(key, 0) % STRIPES as u64) as usize])
+    }
+
+    fn lock_stripes(&self, stripes: Vec<usize>) -> KeyGuard<'_> {
+        KeyGuard {
+            _shared: Some(self.keyspace.read().unwrap_or_else(PoisonError::into_inner)),
+            _exclusive: None,
+            _stripes: stripes
+                .into_iter()
+                .map(|i| {
+                    self.stripes[i]
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                })
+                .collect(),
+        }
+    }
+
+    // Waits for every running command to finish, and keeps new ones from
+    // starting
+    pub fn lock_keyspace(&self) -> KeyGuard<'_> {
+        KeyGuard {
+            _shared: None,
+            _exclusive: Some(
+                self.keyspace
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner),
+            ),
+            _stripes: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<Vec<u8>> {
+        line.split(' ').map(|arg| arg.into()).collect()
+    }
+
+    #[test]
+    fn test_stripes_for() {
+        let stripes = KeyLocks::stripes_for(&args("MGET a b a")).unwrap();
+        assert!(stripes.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(1, KeyLocks::stripes_for(&args("GET b")).unwrap().len());
+
+        // Keyless commands only lock stripes, which is none of them, unless
+        // they act on the whole keyspace
+        assert_eq!(Some(vec![]), KeyLocks::stripes_for(&args("PING")));
+        assert_eq!(Some(vec![]), KeyLocks::stripes_for(&args("info")));
+        assert_eq!(None, KeyLocks::stripes_for(&args("FLUSHDB")));
+        assert_eq!(None, KeyLocks::stripes_for(&args("shutdown")));
+
+        // Commands that will only get an error back lock nothing
+        assert_eq!(Some(vec![]), KeyLocks::stripes_for(&args("MYMODULE.CMD a")));
+        assert_eq!(Some(vec![]), KeyLocks::stripes_for(&args("GET")));
+        assert_eq!(Some(vec![]), KeyLocks::stripes_for(&[]));
+    }
+
+    #[test]
+    fn test_lock() {
+        let locks = KeyLocks::new();
+        let guard = locks.lock(&args("GET a"));
+        assert!(locks.keyspace.try_read().is_ok());
+        assert!(locks.keyspace.try_write().is_err());
+        drop(guard);
+
+        // Keyless commands share the keyspace with everything but a flush
+        let ping = locks.lock(&args("PING"));
+        let get = locks.lock(&args("GET a"));
+        assert!(locks.keyspace.try_write().is_err());
+        drop(ping);
+        drop(get);
+
+        let guard = locks.lock(&args("FLUSHDB"));
+        assert!(locks.keyspace.try_read().is_err());
+        drop(guard);
+        assert!(locks.keyspace.try_write().is_ok());
+
+        // Unknown commands don't hold up the keyspace
+        let guard = locks.lock(&args("NOPE a"));
+        assert!(locks.keyspace.try_read().is_ok());
+        drop(guard);
+
+        // A key locked on its own shuts out commands on it
+        let guard = locks.lock_key(b"a");
+        let stripe = KeyLocks::stripes_for(&args("GET a")).unwrap()[0];
+        assert!(locks.stripes[stripe].try_lock().is_err());
+        assert!(locks.keyspace.try_write().is_err());
+        drop(guard);
+    }
+}
@@ -0,0 +1,303 @@
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum KeySpecError {
+    #[error("ERR Invalid command specified")]
+    UnknownCommand,
+    #[error("ERR The command has no key arguments")]
+    NoKeys,
+    #[error("ERR Invalid arguments specified for command")]
+    InvalidArguments,
+}
+
+// Flags follow Redis' key specifications: how the key is accessed (RW, RO,
+// OW for overwrite, RM for remove) and what happens to its data
+pub const READ: &[&str] = &["RO", "access"];
+pub const UPDATE: &[&str] = &["RW", "access", "update"];
+pub const INSERT: &[&str] = &["RW", "insert"];
+pub const OVERWRITE: &[&str] = &["OW", "update"];
+pub const DELETE: &[&str] = &["RM", "delete"];
+// Reads a key's metadata without accessing its data
+pub const INSPECT: &[&str] = &["RO"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Keys {
+    // Keys from index `first` through `last`, which counts back from the
+    // end of the arguments when negative, every `step` arguments
+    Range {
+        first: usize,
+        last: isize,
+        step: usize,
+    },
+    // A key count at index `numkeys`, followed by that many keys
+    Counted {
+        numkeys: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeySpec {
+    keys: Keys,
+    flags: &'static [&'static str],
+}
+
+const fn single(index: usize, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        keys: Keys::Range {
+            first: index,
+            last: index as isize,
+            step: 1,
+        },
+        flags,
+    }
+}
+
+const fn all(step: usize, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        keys: Keys::Range {
+            first: 1,
+            last: -1,
+            step,
+        },
+        flags,
+    }
+}
+
+const fn counted(numkeys: usize, flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        keys: Keys::Counted { numkeys },
+        flags,
+    }
+}
+
+const FIRST_READ: &[KeySpec] = &[single(1, READ)];
+const FIRST_UPDATE: &[KeySpec] = &[single(1, UPDATE)];
+const FIRST_OVERWRITE: &[KeySpec] = &[single(1, OVERWRITE)];
+const FIRST_INSERT: &[KeySpec] = &[single(1, INSERT)];
+const FIRST_DELETE: &[KeySpec] = &[single(1, DELETE)];
+const ALL_READ: &[KeySpec] = &[all(1, READ)];
+const ALL_DELETE: &[KeySpec] = &[all(1, DELETE)];
+// Every key but the trailing timeout
+const BLOCKING_DELETE: &[KeySpec] = &[KeySpec {
+    keys: Keys::Range {
+        first: 1,
+        last: -2,
+        step: 1,
+    },
+    flags: DELETE,
+}];
+const PAIRS_INSERT: &[KeySpec] = &[all(2, INSERT)];
+const MERGE: &[KeySpec] = &[single(1, OVERWRITE), counted(2, READ)];
+const SECOND_INSPECT: &[KeySpec] = &[single(2, INSPECT)];
+const COPY: &[KeySpec] = &[single(1, READ), single(2, OVERWRITE)];
+
+// Key specifications for every command the server knows, by uppercase name.
+// Commands without keys have no specifications.
+fn key_specs(name: &str) -> Option<&'static [KeySpec]> {
+    let specs: &'static [KeySpec] =
+        match name {
+            "QUIT" | "AUTH" | "HELLO" | "PING" | "ECHO" | "CLIENT" | "SELECT" | "INFO"
+            | "CONFIG" | "SLOWLOG" | "CHECK" | "SHUTDOWN" | "COMMAND" | "HOTKEYS" | "BIGKEYS"
+            | "MODULE" | "TIME" | "SCAN" | "TS.MRANGE" | "FLUSHDB" | "FLUSHALL" => &[],
+            "GET" | "GETRANGE" | "STRLEN" | "SUBSTR" | "TYPE" | "EXPIRETIME" | "PEXPIRETIME"
+            | "TTL" | "PTTL" | "HGET" | "HSTRLEN" | "HGETALL" | "HTTL" | "LLEN" | "LINDEX"
+            | "LRANGE" | "SMEMBERS" | "SISMEMBER" | "BITCOUNT" | "BITPOS" | "GETBIT"
+            | "BF.EXISTS" | "BF.MEXISTS" | "CMS.INFO" | "CMS.QUERY" | "TDIGEST.CDF"
+            | "TDIGEST.QUANTILE" | "TOPK.COUNT" | "TOPK.INFO" | "TOPK.LIST" | "TOPK.QUERY"
+            | "TS.INFO" | "TS.RANGE" => FIRST_READ,
+            "APPEND" | "SETRANGE" | "INCR" | "INCRBY" | "INCRBYFLOAT" | "DECR" | "DECRBY"
+            | "GETSET" | "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" | "PERSIST" | "HSET"
+            | "HEXPIRE" | "HPEXPIRE" | "HPERSIST" | "HGETEX" | "HGETDEL" | "LSET" | "SETBIT"
+            | "BF.ADD" | "BF.MADD" | "CMS.INCRBY" | "TDIGEST.ADD" | "TOPK.ADD" | "TS.ADD" => {
+                FIRST_UPDATE
+            }
+            "SET" | "SETEX" | "PSETEX" => FIRST_OVERWRITE,
+            "SETNX" | "LPUSH" | "RPUSH" | "LPUSHX" | "RPUSHX" | "LINSERT" | "SADD"
+            | "BF.RESERVE" | "CMS.INITBYDIM" | "CMS.INITBYPROB" | "TDIGEST.CREATE"
+            | "TOPK.RESERVE" | "TS.CREATE" => FIRST_INSERT,
+            "GETDEL" | "LPOP" | "RPOP" | "LREM" | "LTRIM" | "SREM" => FIRST_DELETE,
+            "MGET" | "EXISTS" => ALL_READ,
+            "DEL" | "UNLINK" => ALL_DELETE,
+            "BLPOP" | "BRPOP" => BLOCKING_DELETE,
+            "MSETNX" => PAIRS_INSERT,
+            "CMS.MERGE" | "TDIGEST.MERGE" => MERGE,
+            "OBJECT" => SECOND_INSPECT,
+            "COPY" => COPY,
+            _ => return None,
+        };
+    Some(specs)
+}
+
+fn spec_keys(spec: &KeySpec, args: &[Vec<u8>]) -> Result<Vec<usize>, KeySpecError> {
+    match spec.keys {
+        Keys::Range { first, last, step } => {
+            let last = if last < 0 {
+                args.len() as isize + last
+            } else {
+                last
+            };
+            if last < first as isize || last as usize >= args.len() {
+                return Err(KeySpecError::InvalidArguments);
+            }
+            Ok((first..=last as usize).step_by(step).collect())
+        }
+        Keys::Counted { numkeys } => {
+            let n = args
+                .get(numkeys)
+                .and_then(|arg| String::from_utf8_lossy(arg).parse::<usize>().ok());
+            match n {
+                Some(n) if { n > 0 && numkeys + n < args.len() } => {
+                    Ok((numkeys + 1..=numkeys + n).collect())
+                }
+                _ => Err(KeySpecError::InvalidArguments),
+            }
+        }
+    }
+}
+
+// The first key, last key and step between keys of a command, as COMMAND
+// INFO reports them, or zeros when it takes no keys at a fixed position
+pub fn key_range(name: &str) -> Option<(i64, i64, i64)> {
+    let range = match key_specs(name)?.first() {
+        Some(KeySpec {
+            keys: Keys::Range { first, last, step },
+            ..
+        }) => (*first as i64, *last as i64, *step as i64),
+        _ => (0, 0, 0),
+    };
+    Some(range)
+}
+
+// Finds the key arguments of a full command line, including the command
+// name, returning each key's argument index along with its flags. This is
+// the single source of key positions for anything that needs to know which
+// keys a command touches before running it.
+pub fn get_keys(args: &[Vec<u8>]) -> Result<Vec<(usize, &'static [&'static str])>, KeySpecError> {
+    if args.is_empty() {
+        return Err(KeySpecError::UnknownCommand);
+    }
+
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+    let specs = key_specs(&name).ok_or(KeySpecError::UnknownCommand)?;
+    if specs.is_empty() {
+        return Err(KeySpecError::NoKeys);
+    }
+
+    let mut keys = vec![];
+    for spec in specs {
+        for index in spec_keys(spec, args)? {
+            keys.push((index, spec.flags));
+        }
+    }
+    Ok(keys)
+}
+
+// The keys a command may modify, for anything that reacts to writes. Read-only
+// keys and malformed commands yield nothing.
+pub fn written_keys(args: &[Vec<u8>]) -> Vec<&[u8]> {
+    match get_keys(args) {
+        Ok(keys) => keys
+            .into_iter()
+            .filter(|(_, flags)| flags[0] != "RO")
+            .map(|(index, _)| args[index].as_slice())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+// The keys a command only reads, for client-side caching. Keys that are
+// only inspected yield nothing, nor do malformed commands.
+pub fn read_keys(args: &[Vec<u8>]) -> Vec<&[u8]> {
+    match get_keys(args) {
+        Ok(keys) => keys
+            .into_iter()
+            .filter(|(_, flags)| *flags == READ)
+            .map(|(index, _)| args[index].as_slice())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+// The keys a command reads or writes, for tracking accesses. Keys that are
+// only inspected yield nothing, nor do malformed commands.
+pub fn accessed_keys(args: &[Vec<u8>]) -> Vec<&[u8]> {
+    match get_keys(args) {
+        Ok(keys) => keys
+            .into_iter()
+            .filter(|(_, flags)| *flags != INSPECT)
+            .map(|(index, _)| args[index].as_slice())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<Vec<u8>> {
+        line.split(' ').map(|arg| arg.into()).collect()
+    }
+
+    #[test]
+    fn test_get_keys() {
+        assert_eq!(Ok(vec![(1, READ)]), get_keys(&args("get key")));
+        assert_eq!(
+            Ok(vec![(1, DELETE), (2, DELETE), (3, DELETE)]),
+            get_keys(&args("DEL a b c"))
+        );
+        assert_eq!(
+            Ok(vec![(1, INSERT), (3, INSERT)]),
+            get_keys(&args("MSETNX a 1 b 2"))
+        );
+        assert_eq!(
+            Ok(vec![(1, DELETE), (2, DELETE)]),
+            get_keys(&args("BLPOP a b 0"))
+        );
+        assert_eq!(
+            Ok(vec![(1, OVERWRITE), (3, READ), (4, READ)]),
+            get_keys(&args("TDIGEST.MERGE dest 2 a b OVERRIDE"))
+        );
+    }
+
+    #[test]
+    fn test_written_keys() {
+        let merge = args("CMS.MERGE dest 2 a b");
+        assert_eq!(vec![b"dest".as_slice()], written_keys(&merge));
+        assert!(written_keys(&args("MGET a b")).is_empty());
+    }
+
+    #[test]
+    fn test_accessed_keys() {
+        assert_eq!(vec![b"a".as_slice()], accessed_keys(&args("GET a")));
+        assert_eq!(vec![b"a".as_slice()], accessed_keys(&args("SET a 1")));
+        assert!(accessed_keys(&args("OBJECT FREQ a")).is_empty());
+    }
+
+    #[test]
+    fn test_read_keys() {
+        assert_eq!(vec![b"a".as_slice()], read_keys(&args("GET a")));
+        assert!(read_keys(&args("SET a 1")).is_empty());
+        assert_eq!(vec![b"b".as_slice()], read_keys(&args("CMS.MERGE a 1 b")));
+    }
+
+    #[test]
+    fn test_get_keys_errors() {
+        assert_eq!(Err(KeySpecError::UnknownCommand), get_keys(&args("NOPE a")));
+        assert_eq!(Err(KeySpecError::NoKeys), get_keys(&args("PING")));
+        assert_eq!(Err(KeySpecError::InvalidArguments), get_keys(&args("GET")));
+        assert_eq!(
+            Err(KeySpecError::InvalidArguments),
+            get_keys(&args("CMS.MERGE dest 3 a b"))
+        );
+    }
+
+    #[test]
+    fn test_key_range() {
+        assert_eq!(Some((1, 1, 1)), key_range("GET"));
+        assert_eq!(Some((1, -1, 2)), key_range("MSETNX"));
+        assert_eq!(Some((2, 2, 1)), key_range("OBJECT"));
+        assert_eq!(Some((0, 0, 0)), key_range("PING"));
+        assert_eq!(None, key_range("NOPE"));
+    }
+}
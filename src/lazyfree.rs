@@ -0,0 +1,137 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+};
+
+use tracing::warn;
+
+use crate::database::{Database, DatabaseError, DatabaseOperations};
+
+// UNLINK only deletes a key's own records before replying, and leaves the
+// rest, like the samples of a time series, to a worker thread, so that
+// unlinking a big key doesn't hold up its client or the keys it locks.
+// FLUSHALL ASYNC does the same for every key at once.
+
+static PENDING: AtomicU64 = AtomicU64::new(0);
+static FREED: AtomicU64 = AtomicU64::new(0);
+
+enum Job {
+    Key(Vec<u8>),
+    // Everything a lazy flush left behind
+    Keyspace,
+}
+
+fn sender() -> &'static Mutex<Option<Sender<Job>>> {
+    static SENDER: OnceLock<Mutex<Option<Sender<Job>>>> = OnceLock::new();
+    SENDER.get_or_init(|| Mutex::new(None))
+}
+
+// Keys waiting to be reclaimed
+pub fn pending_objects() -> u64 {
+    PENDING.load(Ordering::Relaxed)
+}
+
+// Keys reclaimed since the server started
+pub fn freed_objects() -> u64 {
+    FREED.load(Ordering::Relaxed)
+}
+
+pub fn reclaim_key(db: &dyn DatabaseOperations, key: &[u8]) -> Result<(), DatabaseError> {
+    db.reclaim(key)?;
+    FREED.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+fn run(db: &dyn DatabaseOperations, job: &Job) -> Result<(), DatabaseError> {
+    match job {
+        Job::Key(key) => reclaim_key(db, key),
+        Job::Keyspace => {
+            let reclaimed = db.reclaim_all()?;
+            FREED.fetch_add(reclaimed, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+}
+
+// Without a worker, as in tools and tests, jobs run right away
+fn submit(db: &dyn DatabaseOperations, job: Job) -> Result<(), DatabaseError> {
+    let sender = sender().lock().unwrap();
+    match sender.as_ref() {
+        Some(tx) => {
+            PENDING.fetch_add(1, Ordering::Relaxed);
+            if let Err(err) = tx.send(job) {
+                PENDING.fetch_sub(1, Ordering::Relaxed);
+                return run(db, &err.0);
+            }
+            Ok(())
+        }
+        None => run(db, &job),
+    }
+}
+
+// Hands an unlinked key to the worker
+pub fn free(db: &dyn DatabaseOperations, key: &[u8]) -> Result<(), DatabaseError> {
+    submit(db, Job::Key(key.to_vec()))
+}
+
+// Hands what a lazy flush left behind to the worker
+pub fn free_all(db: &dyn DatabaseOperations) -> Result<(), DatabaseError> {
+    submit(db, Job::Keyspace)
+}
+
+pub fn start_worker(db: Arc<Database>) {
+    let (tx, rx) = mpsc::channel::<Job>();
+    *sender().lock().unwrap() = Some(tx);
+
+    thread::spawn(move || {
+        for job in rx {
+            if let Err(err) = run(&*db, &job) {
+                match &job {
+                    Job::Key(key) => warn!(
+                        "Failed to reclaim {}: {}",
+                        String::from_utf8_lossy(key),
+                        err
+                    ),
+                    Job::Keyspace => warn!("Failed to reclaim flushed keys: {}", err),
+                }
+            }
+            PENDING.fetch_sub(1, Ordering::Relaxed);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::MockDatabaseOperations;
+    use mockall::predicate::*;
+
+    use super::*;
+
+    #[test]
+    fn test_free_without_worker() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_reclaim()
+            .with(eq(b"key".as_slice()))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let before = freed_objects();
+        free(&mock_db, b"key").unwrap();
+        assert_eq!(before + 1, freed_objects());
+        assert_eq!(0, pending_objects());
+    }
+
+    #[test]
+    fn test_free_all_without_worker() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db.expect_reclaim_all().times(1).returning(|| Ok(0));
+
+        free_all(&mock_db).unwrap();
+        assert_eq!(0, pending_objects());
+    }
+}
@@ -0,0 +1,53 @@
+#![feature(trait_alias)]
+
+pub mod access;
+pub mod admin;
+pub mod audit;
+pub mod backup;
+pub mod bigkeys;
+pub mod blocking;
+mod cache;
+pub mod commands;
+mod compaction;
+pub mod config;
+pub mod connection;
+pub mod database;
+mod deadline;
+pub mod diagnostics;
+pub mod dispatch;
+pub mod expire;
+mod glob;
+pub mod help;
+pub mod hotkeys;
+pub mod import;
+mod indexing;
+pub mod inspect;
+pub mod ipfilter;
+pub mod journal;
+pub mod keyformat;
+pub mod keylocks;
+pub mod keyspec;
+pub mod known_issues;
+pub mod lazyfree;
+pub mod lists;
+#[cfg(feature = "native-modules")]
+pub mod modules;
+pub mod notify;
+#[cfg(feature = "wasm-plugins")]
+pub mod plugins;
+pub mod protocol;
+pub mod proxy;
+pub mod ratelimit;
+pub mod registry;
+pub mod resp;
+pub mod shutdown;
+pub mod sketches;
+pub mod slowlog;
+pub mod time;
+pub mod timeseries;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod tracking;
+
+#[macro_use(concat_string)]
+extern crate concat_string;
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+// New lists start in the middle of the sequence numbers, so they can grow
+// as far in either direction
+const START_SEQ: u64 = 1 << 63;
+
+// Which end of a list elements are pushed onto or popped from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListEnd {
+    Front,
+    Back,
+}
+
+// Per-list metadata. Elements are stored as individual records keyed by a
+// sequence number, from head up to but not including tail, so pushes and
+// pops only write the records at the end they touch. Elements removed from
+// the middle leave gaps in the sequence, so the length is kept as well.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ListInfo {
+    pub head: u64,
+    pub tail: u64,
+    pub len: u64,
+}
+
+impl Default for ListInfo {
+    fn default() -> Self {
+        Self {
+            head: START_SEQ,
+            tail: START_SEQ,
+            len: 0,
+        }
+    }
+}
+
+impl ListInfo {
+    // Takes the sequence number for an element pushed onto the given end
+    pub fn push(&mut self, end: ListEnd) -> u64 {
+        self.len += 1;
+        match end {
+            ListEnd::Front => {
+                self.head -= 1;
+                self.head
+            }
+            ListEnd::Back => {
+                self.tail += 1;
+                self.tail - 1
+            }
+        }
+    }
+
+    // Whether every sequence number from head to tail holds an element, so
+    // an index maps straight to a sequence number
+    pub fn is_dense(&self) -> bool {
+        self.tail - self.head == self.len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let mut info = ListInfo::default();
+        assert_eq!(START_SEQ, info.push(ListEnd::Back));
+        assert_eq!(START_SEQ + 1, info.push(ListEnd::Back));
+        assert_eq!(START_SEQ - 1, info.push(ListEnd::Front));
+        assert_eq!(
+            ListInfo {
+                head: START_SEQ - 1,
+                tail: START_SEQ + 2,
+                len: 3,
+            },
+            info
+        );
+        assert!(info.is_dense());
+
+        info.len -= 1;
+        assert!(!info.is_dense());
+    }
+}
@@ -1,124 +1,431 @@
-#![feature(trait_alias)]
+use std::{
+    env,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-mod commands;
-mod connection;
-mod database;
-mod indexing;
-mod known_issues;
-mod time;
+use redcon::Conn;
+use rocksdb::{Options, TransactionDB, TransactionDBOptions};
+use tracing::{error, info, warn};
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, reload};
+use wedis::{
+    access, admin, audit, bigkeys, blocking, commands, config,
+    connection::{Client, ClientError, Connection, ConnectionContext},
+    database::{self, Database, DatabaseOperations},
+    diagnostics,
+    dispatch::dispatch,
+    expire, import, ipfilter, journal, known_issues, lazyfree, notify,
+    protocol::ProtocolLimits,
+    proxy,
+    ratelimit::{self, Limits},
+    shutdown, tracking,
+};
 
-use std::sync::{Arc, Mutex};
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
-use anyhow::Result;
-use connection::{Client, ClientError, Connection, ConnectionContext};
-use database::Database;
-use redcon::Conn;
-use rocksdb::{Options, TransactionDB, DB};
-use tracing::{debug, error, info, Level};
-use tracing_subscriber;
+fn handle_command(conn: &mut Conn, db: &Database, args: Vec<Vec<u8>>) {
+    if let Err(err) = ProtocolLimits::from_config().check(&args) {
+        Client::new(conn).write_error(err.into());
+        conn.close();
+        return;
+    }
 
-#[macro_use(concat_string)]
-extern crate concat_string;
+    if handle_proxy_header(conn, &args) {
+        return;
+    }
 
-fn handle_result(result: Result<()>) {
-    if let Err(err) = result {
-        error!("{}", err)
+    // Connections killed with CLIENT KILL are closed before they get to
+    // run anything else
+    if diagnostics::is_killed(connection_id(conn)) {
+        conn.close();
+        return;
     }
-}
 
-fn log_command(args: Vec<Vec<u8>>) {
-    let mut parsed_args: Vec<String> = vec![];
-    for arg in args {
-        parsed_args.push(String::from_utf8_lossy(&arg).into_owned())
+    if journal::is_enabled() {
+        journal::record(connection_id(conn), &args);
+    }
+    if let Some(name) = args.first() {
+        diagnostics::command_received(connection_id(conn), name);
     }
-    debug!("> {:?}", parsed_args);
-}
 
-fn handle_command(conn: &mut Conn, db: &Database, args: Vec<Vec<u8>>) {
+    let in_flight = shutdown::command_started();
+    if shutdown::is_shutting_down() {
+        Client::new(conn).write_error(ClientError::ShuttingDown);
+        conn.close();
+        return;
+    }
+
+    if !commands::check_auth(&mut Client::new(conn), &args) {
+        return;
+    }
+
+    if !is_within_rate_limit(conn, &args) {
+        Client::new(conn).write_error(ClientError::RateLimited);
+        return;
+    }
+
+    let id = connection_id(conn);
     let mut conn = Client::new(conn);
-    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
-
-    log_command(args.clone());
-    match name.as_str() {
-        "QUIT" => commands::quit(&mut conn),
-        "HELLO" => commands::hello(&mut conn, &args),
-        "PING" => commands::ping(&mut conn, &args),
-        "ECHO" => commands::echo(&mut conn, &args),
-        "CLIENT" => commands::client(&mut conn, &args),
-        "APPEND" => handle_result(commands::append(&mut conn, db, &args)),
-        "SET" => handle_result(commands::set(&mut conn, db, &args)),
-        "SETEX" => handle_result(commands::setex(&mut conn, db, &args)),
-        "SETNX" => handle_result(commands::setnx(&mut conn, db, &args)),
-        "SETRANGE" => handle_result(commands::setrange(&mut conn, db, &args)),
-        "GET" => handle_result(commands::get(&mut conn, db, &args)),
-        "MGET" => handle_result(commands::mget(&mut conn, db, &args)),
-        "GETRANGE" => handle_result(commands::getrange(&mut conn, db, &args)),
-        "GETDEL" => handle_result(commands::getdel(&mut conn, db, &args)),
-        "GETSET" => handle_result(commands::getset(&mut conn, db, &args)),
-        "STRLEN" => handle_result(commands::strlen(&mut conn, db, &args)),
-        "SUBSTR" => handle_result(commands::substr(&mut conn, db, &args)),
-        "INCR" => handle_result(commands::incr(&mut conn, db, &args)),
-        "INCRBY" => handle_result(commands::incrby(&mut conn, db, &args)),
-        "INCRBYFLOAT" => handle_result(commands::incrbyfloat(&mut conn, db, &args)),
-        "DECR" => handle_result(commands::decr(&mut conn, db, &args)),
-        "DECRBY" => handle_result(commands::decrby(&mut conn, db, &args)),
-        "DEL" => handle_result(commands::del(&mut conn, db, &args)),
-        "UNLINK" => handle_result(commands::unlink(&mut conn, db, &args)),
-        "EXISTS" => handle_result(commands::exists(&mut conn, db, &args)),
-        "EXPIRE" => handle_result(commands::expire(&mut conn, db, &args)),
-        "PEXPIRE" => handle_result(commands::pexpire(&mut conn, db, &args)),
-        "EXPIREAT" => handle_result(commands::expireat(&mut conn, db, &args)),
-        "PEXPIREAT" => handle_result(commands::pexpireat(&mut conn, db, &args)),
-        "EXPIRETIME" => handle_result(commands::expiretime(&mut conn, db, &args)),
-        "PEXPIRETIME" => handle_result(commands::pexpiretime(&mut conn, db, &args)),
-        "PERSIST" => handle_result(commands::persist(&mut conn, db, &args)),
-        "TTL" => handle_result(commands::ttl(&mut conn, db, &args)),
-        "PTTL" => handle_result(commands::pttl(&mut conn, db, &args)),
-        "HSET" => handle_result(commands::hset(&mut conn, db, &args)),
-        "HGET" => handle_result(commands::hget(&mut conn, db, &args)),
-        "HSTRLEN" => handle_result(commands::hstrlen(&mut conn, db, &args)),
-        "BITCOUNT" => handle_result(commands::bitcount(&mut conn, db, &args)),
-        "BITPOS" => handle_result(commands::bitpos(&mut conn, db, &args)),
-        "GETBIT" => handle_result(commands::getbit(&mut conn, db, &args)),
-        "SETBIT" => handle_result(commands::setbit(&mut conn, db, &args)),
-        "SELECT" => conn.write_string("OK"),
-        "INFO" => commands::info(&mut conn, &args),
-        "TIME" => handle_result(commands::time(&mut conn)),
-        _ => {
-            error!("Unknown command: {}", name);
-            conn.write_error(ClientError::UnknownCommand)
+    {
+        let _guard = db.lock_keys(&args);
+        dispatch(&mut conn, db, &args);
+    }
+    drop(in_flight);
+
+    // Blocking commands that found nothing wait here, without their key
+    // locks, and run again once woken. A waiting command isn't in flight,
+    // so it doesn't hold up a shutdown.
+    while let Some(waiter) = blocking::take_parked() {
+        if !waiter.wait(id) {
+            conn.write_null();
+            break;
+        }
+        let _in_flight = shutdown::command_started();
+        let _guard = db.lock_keys(&args);
+        blocking::resume(&waiter, || dispatch(&mut conn, db, &args));
+    }
+}
+
+// The PROXY header arrives as an inline command ahead of everything else.
+// Returns true if the command was the header, or if the connection was
+// closed for not sending one.
+fn handle_proxy_header(conn: &mut Conn, args: &[Vec<u8>]) -> bool {
+    let ctx = match conn
+        .context
+        .as_mut()
+        .and_then(|ctx| ctx.downcast_mut::<ConnectionContext>())
+    {
+        Some(ctx) if { ctx.awaiting_proxy_header() || is_tls_forwarded(args, ctx.addr()) } => ctx,
+        _ => return false,
+    };
+
+    let addr = match proxy::parse_v1(args) {
+        Ok(addr) => addr.unwrap_or(ctx.addr()),
+        Err(err) => {
+            Client::new(conn).write_error(err.into());
+            conn.close();
+            return true;
         }
+    };
+    ctx.set_proxied_addr(addr);
+    diagnostics::set_connection_addr(ctx.id(), addr);
+    info!("Connection {} is proxied for {}", ctx.id(), addr);
+
+    // IP rules are checked against the client rather than the proxy
+    if !ipfilter::allow_connection(addr.ip()) {
+        conn.close();
+    }
+    true
+}
+
+// Connections from the TLS listener announce their client with a PROXY
+// header, whether or not proxy-protocol is enabled
+#[cfg(feature = "tls")]
+fn is_tls_forwarded(args: &[Vec<u8>], addr: SocketAddr) -> bool {
+    args.first().is_some_and(|name| name == b"PROXY") && wedis::tls::is_forwarded(addr)
+}
+
+#[cfg(not(feature = "tls"))]
+fn is_tls_forwarded(_args: &[Vec<u8>], _addr: SocketAddr) -> bool {
+    false
+}
+
+fn client_addr(conn: &mut Conn) -> SocketAddr {
+    match conn
+        .context
+        .as_mut()
+        .and_then(|ctx| ctx.downcast_mut::<ConnectionContext>())
+    {
+        Some(ctx) => ctx.addr(),
+        None => conn.addr(),
+    }
+}
+
+fn connection_id(conn: &mut Conn) -> i64 {
+    match conn
+        .context
+        .as_mut()
+        .and_then(|ctx| ctx.downcast_mut::<ConnectionContext>())
+    {
+        Some(ctx) => ctx.id(),
+        None => -1,
+    }
+}
+
+fn is_within_rate_limit(conn: &mut Conn, args: &Vec<Vec<u8>>) -> bool {
+    let limits = Limits::from_config();
+    if !limits.is_enabled() {
+        return true;
+    }
+
+    let n_bytes = args.iter().map(|arg| arg.len()).sum();
+    if limits.per_ip {
+        return ratelimit::allow_ip(client_addr(conn).ip(), &limits, n_bytes);
+    }
+
+    match conn
+        .context
+        .as_mut()
+        .and_then(|ctx| ctx.downcast_mut::<ConnectionContext>())
+    {
+        Some(ctx) => ctx.limiter().allow(&limits, n_bytes, Instant::now()),
+        None => true,
+    }
+}
+
+// Checked before the new connection is counted, so it would be one too many
+fn at_max_clients() -> bool {
+    let max_clients = config::config()
+        .read()
+        .unwrap()
+        .value("maxclients")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(10000);
+    diagnostics::connection_count() >= max_clients
+}
+
+// The shard count is only read at startup, since changing it would move
+// keys to different shards
+fn shard_paths(root: &Path) -> Vec<PathBuf> {
+    let n_shards = config::config()
+        .read()
+        .unwrap()
+        .value("shards")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1);
+    database::shard_paths(root, n_shards)
+}
+
+// In bytes. Like the shard count, it's only read at startup.
+fn value_cache_size() -> usize {
+    config::config()
+        .read()
+        .unwrap()
+        .value("value-cache-size")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+fn storage_options() -> Options {
+    let mut opts = database::shard_options();
+
+    // Files older than this are recompacted in the background, so that
+    // space held by overwritten and deleted keys is eventually reclaimed
+    // even when nothing else would trigger a compaction. 0 keeps RocksDB's
+    // default.
+    let period = config::config()
+        .read()
+        .unwrap()
+        .value("periodic-compaction-seconds")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+    if period > 0 {
+        opts.set_periodic_compaction_seconds(period);
+    }
+
+    opts
+}
+
+// Looks for inconsistencies left behind by a crash before accepting
+// connections, like redis-check-aof does for Redis
+fn check_on_startup(db: &Database) {
+    let mode = config::config()
+        .read()
+        .unwrap()
+        .value("check-on-startup")
+        .unwrap_or("no")
+        .to_string();
+    if mode == "no" {
+        return;
+    }
+
+    let repair = mode == "repair";
+    info!("Checking database consistency");
+    let found = db
+        .check_integrity(repair)
+        .expect("Failed to check database consistency");
+    for inconsistency in found.iter() {
+        warn!(
+            "{}: {}",
+            String::from_utf8_lossy(&inconsistency.key),
+            inconsistency.problem
+        );
+    }
+
+    match (found.len(), repair) {
+        (0, _) => info!("No inconsistencies found"),
+        (n, true) => info!("Repaired {} inconsistencies", n),
+        (n, false) => warn!(
+            "Found {} inconsistencies (set check-on-startup to repair to fix them)",
+            n
+        ),
+    }
+}
+
+// IPv6 addresses need brackets to be told apart from the port
+fn listen_addr(bind: &str, port: &str) -> String {
+    let port = port.parse::<u16>().expect("Invalid port");
+    if bind.contains(':') && !bind.starts_with('[') {
+        format!("[{}]:{}", bind, port)
+    } else {
+        format!("{}:{}", bind, port)
+    }
+}
+
+// Like Redis' loadmodule directive, but with every path in one value
+#[cfg(feature = "native-modules")]
+fn load_modules() {
+    let paths = config::config()
+        .read()
+        .unwrap()
+        .value("loadmodule")
+        .unwrap_or("")
+        .to_string();
+    for path in paths.split_whitespace() {
+        wedis::modules::load(path).expect("Failed to load module");
     }
 }
 
 fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(Level::TRACE)
+    let (log_filter, log_filter_handle) = reload::Layer::new(LevelFilter::TRACE);
+    tracing_subscriber::registry()
+        .with(log_filter)
+        .with(fmt::layer())
         .init();
 
-    let path = ".wedis";
-    {
-        let db_raw = TransactionDB::open_default(path).expect("Failed to open database");
-        let db = Arc::new(Mutex::new(Database::new(db_raw)));
+    config::config()
+        .write()
+        .unwrap()
+        .on_log_level_change(Box::new(move |level| {
+            let _ = log_filter_handle.modify(|filter| *filter = level);
+        }));
+
+    let mut config_path = None;
+    let mut import_from = None;
+    let mut bind = "127.0.0.1".to_string();
+    let mut port = "6379".to_string();
+    let mut dir = ".wedis".to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--import-from" => import_from = args.next(),
+            "--bind" => bind = args.next().unwrap_or(bind),
+            "--port" => port = args.next().unwrap_or(port),
+            "--dir" => dir = args.next().unwrap_or(dir),
+            // Like redis-server, the configuration file is the first argument
+            _ if { config_path.is_none() } => config_path = Some(arg),
+            _ => error!("Unexpected argument '{}'", arg),
+        }
+    }
 
-        let mut s = redcon::listen("127.0.0.1:6379", db).expect("Failed to start server");
-        s.opened = Some(|conn, db| {
-            info!("Got new connection from {}", conn.addr());
+    if let Some(config_path) = config_path {
+        let config_path = PathBuf::from(config_path);
+        config::config()
+            .write()
+            .unwrap()
+            .load(&config_path)
+            .expect("Failed to load configuration");
+        config::handle_reload_signal(config_path).expect("Failed to register signal handlers");
+    }
+
+    let addr = listen_addr(&bind, &port);
+    let data_dir = Path::new(&dir);
+    let paths = shard_paths(data_dir);
+    let opts = storage_options();
+    let shards = paths
+        .iter()
+        .map(|path| {
+            TransactionDB::open(&opts, &TransactionDBOptions::default(), path)
+                .expect("Failed to open database")
+        })
+        .collect();
+    let db = Arc::new(Database::with_shards(shards).with_value_cache(value_cache_size()));
+
+    admin::start_from_config(db.clone()).expect("Failed to start admin endpoints");
 
-            let connection_id = db.lock().unwrap().acquire_connection();
-            conn.context = Some(Box::new(ConnectionContext::new(connection_id)));
-        });
-        s.closed = Some(|_conn, _db, err| {
-            if let Some(err) = err {
-                error!("{}", err)
-            }
-        });
-        s.command = Some(|conn, db, args| handle_command(conn, &db.lock().unwrap(), args));
-        info!("Serving at {}", s.local_addr());
+    let migrated = db.migrate_legacy_keys().expect("Failed to migrate keys");
+    if migrated > 0 {
+        info!("Migrated {} records to the current key format", migrated);
+    }
 
-        known_issues::warn_known_issues();
+    check_on_startup(&db);
 
-        s.serve().expect("Failed to execute server");
+    if let Some(addr) = import_from {
+        info!("Importing keys from {}", addr);
+        import::import_from(&addr, &*db).expect("Failed to import keys");
     }
-    let _ = DB::destroy(&Options::default(), path);
+
+    #[cfg(feature = "native-modules")]
+    load_modules();
+
+    #[cfg(feature = "wasm-plugins")]
+    wedis::plugins::load_from_config(db.clone()).expect("Failed to load plugins");
+
+    audit::start_from_config().expect("Failed to open audit log");
+    journal::start_from_config().expect("Failed to open command journal");
+    notify::start_from_config().expect("Failed to start keyspace event sink");
+    access::start_from_config(data_dir).expect("Failed to load access metadata");
+
+    bigkeys::start_worker(db.clone());
+    expire::start_worker(db.clone());
+    lazyfree::start_worker(db.clone());
+
+    shutdown::handle_signals(db.clone(), SHUTDOWN_GRACE_PERIOD)
+        .expect("Failed to register signal handlers");
+    diagnostics::handle_signal(db.clone()).expect("Failed to register signal handlers");
+
+    admin::set_loading(false);
+
+    let mut s = redcon::listen(addr, db).expect("Failed to start server");
+    s.opened = Some(|conn, db| {
+        if shutdown::is_shutting_down() {
+            conn.close();
+            return;
+        }
+
+        if at_max_clients() {
+            Client::new(conn).write_error(ClientError::MaxClients);
+            conn.close();
+            return;
+        }
+
+        // Behind a proxy, the client's address is only known once its
+        // PROXY header arrives
+        let proxied = proxy::is_enabled();
+        if !proxied && !ipfilter::allow_connection(conn.addr().ip()) {
+            conn.close();
+            return;
+        }
+
+        info!("Got new connection from {}", conn.addr());
+
+        let connection_id = db.acquire_connection();
+        let mut ctx = ConnectionContext::new(connection_id, conn.addr());
+        if proxied {
+            ctx.expect_proxy_header();
+        }
+        conn.context = Some(Box::new(ctx));
+        diagnostics::connection_opened(connection_id, conn.addr());
+    });
+    s.closed = Some(|conn, _db, err| {
+        tracking::disable(connection_id(conn));
+        diagnostics::connection_closed(connection_id(conn));
+        if let Some(err) = err {
+            error!("{}", err)
+        }
+    });
+    s.command = Some(|conn, db, args| handle_command(conn, db, args));
+    info!("Serving at {}", s.local_addr());
+    diagnostics::set_listen_addr(s.local_addr());
+
+    #[cfg(feature = "tls")]
+    wedis::tls::start_from_config(s.local_addr()).expect("Failed to start TLS listener");
+
+    known_issues::warn_known_issues();
+
+    admin::set_accepting(true);
+
+    s.serve().expect("Failed to execute server");
 }
@@ -0,0 +1,520 @@
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    panic::{self, AssertUnwindSafe},
+    slice,
+    sync::{Mutex, OnceLock},
+};
+
+use libloading::Library;
+use thiserror::Error;
+use tracing::{error, info};
+
+use crate::{
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    registry,
+};
+
+// Modules are shared libraries exporting INIT_SYMBOL, which returns a
+// descriptor for the module. Everything crossing the library boundary is
+// #[repr(C)], and the descriptor carries ABI_VERSION so that modules built
+// against a different ABI are refused rather than misinterpreted. Module
+// authors implement NativeModule and use export_module! instead of dealing
+// with the raw ABI.
+pub const ABI_VERSION: u32 = 1;
+pub const INIT_SYMBOL: &[u8] = b"wedis_module_init";
+
+#[derive(Error, Debug)]
+pub enum ModuleError {
+    #[error("ERR error loading the extension. Please check the server logs.")]
+    Load(#[from] libloading::Error),
+    #[error("ERR module ABI version {0} is not supported")]
+    AbiVersion(u32),
+    #[error("ERR module {0} is already loaded")]
+    AlreadyLoaded(String),
+    #[error("ERR command {0} already exists")]
+    CommandExists(String),
+    #[error("ERR no such module with that name")]
+    NotLoaded,
+    #[error("ERR host operation failed")]
+    HostFailed,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawSlice {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl RawSlice {
+    pub fn new(data: &[u8]) -> Self {
+        RawSlice {
+            ptr: data.as_ptr(),
+            len: data.len(),
+        }
+    }
+
+    // Safety: the slice must point to len valid bytes that outlive 'a
+    unsafe fn as_slice<'a>(&self) -> &'a [u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        slice::from_raw_parts(self.ptr, self.len)
+    }
+}
+
+pub type ValueCallback = extern "C" fn(user: *mut c_void, value: RawSlice);
+
+// Host functions available to modules. Values read from the database are
+// only borrowed for the duration of the callback.
+#[repr(C)]
+pub struct HostApi {
+    pub ctx: *mut c_void,
+    // 1 if found was called with the value, 0 if the key is missing, -1 on error
+    pub get: extern "C" fn(
+        ctx: *mut c_void,
+        key: RawSlice,
+        found: ValueCallback,
+        user: *mut c_void,
+    ) -> i32,
+    // 0 on success, -1 on error
+    pub set: extern "C" fn(ctx: *mut c_void, key: RawSlice, value: RawSlice) -> i32,
+    // The number of keys deleted, -1 on error
+    pub del: extern "C" fn(ctx: *mut c_void, key: RawSlice) -> i64,
+    pub reply_bulk: extern "C" fn(ctx: *mut c_void, msg: RawSlice),
+    pub reply_string: extern "C" fn(ctx: *mut c_void, msg: RawSlice),
+    pub reply_error: extern "C" fn(ctx: *mut c_void, msg: RawSlice),
+    pub reply_integer: extern "C" fn(ctx: *mut c_void, x: i64),
+    pub reply_null: extern "C" fn(ctx: *mut c_void),
+    pub reply_array: extern "C" fn(ctx: *mut c_void, count: usize),
+}
+
+#[repr(C)]
+pub struct ModuleV1 {
+    pub abi_version: u32,
+    pub name: RawSlice,
+    pub version: u32,
+    pub commands: *const RawSlice,
+    pub n_commands: usize,
+    // Runs commands[command], returning 0 on success
+    pub call: extern "C" fn(
+        command: usize,
+        host: *const HostApi,
+        args: *const RawSlice,
+        n_args: usize,
+    ) -> i32,
+}
+
+// The safe interface for module authors
+pub trait NativeModule {
+    const NAME: &'static str;
+    const VERSION: u32;
+    // Command names, which are called by their index in this list
+    const COMMANDS: &'static [&'static str];
+
+    fn call(command: usize, host: &mut Host, args: &[&[u8]]) -> Result<(), ModuleError>;
+}
+
+pub struct Host<'a>(&'a HostApi);
+
+extern "C" fn collect_value(user: *mut c_void, value: RawSlice) {
+    let out = unsafe { &mut *user.cast::<Vec<u8>>() };
+    out.extend_from_slice(unsafe { value.as_slice() });
+}
+
+impl Host<'_> {
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, ModuleError> {
+        let mut value: Vec<u8> = vec![];
+        let user = (&mut value as *mut Vec<u8>).cast::<c_void>();
+        match (self.0.get)(self.0.ctx, RawSlice::new(key), collect_value, user) {
+            1 => Ok(Some(value)),
+            0 => Ok(None),
+            _ => Err(ModuleError::HostFailed),
+        }
+    }
+
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), ModuleError> {
+        match (self.0.set)(self.0.ctx, RawSlice::new(key), RawSlice::new(value)) {
+            0 => Ok(()),
+            _ => Err(ModuleError::HostFailed),
+        }
+    }
+
+    pub fn del(&mut self, key: &[u8]) -> Result<i64, ModuleError> {
+        match (self.0.del)(self.0.ctx, RawSlice::new(key)) {
+            n if { n >= 0 } => Ok(n),
+            _ => Err(ModuleError::HostFailed),
+        }
+    }
+
+    pub fn reply_bulk(&mut self, msg: &[u8]) {
+        (self.0.reply_bulk)(self.0.ctx, RawSlice::new(msg))
+    }
+
+    pub fn reply_string(&mut self, msg: &str) {
+        (self.0.reply_string)(self.0.ctx, RawSlice::new(msg.as_bytes()))
+    }
+
+    pub fn reply_error(&mut self, msg: &str) {
+        (self.0.reply_error)(self.0.ctx, RawSlice::new(msg.as_bytes()))
+    }
+
+    pub fn reply_integer(&mut self, x: i64) {
+        (self.0.reply_integer)(self.0.ctx, x)
+    }
+
+    pub fn reply_null(&mut self) {
+        (self.0.reply_null)(self.0.ctx)
+    }
+
+    pub fn reply_array(&mut self, count: usize) {
+        (self.0.reply_array)(self.0.ctx, count)
+    }
+}
+
+// Panics must not unwind across the library boundary
+extern "C" fn call_module<M: NativeModule>(
+    command: usize,
+    host: *const HostApi,
+    args: *const RawSlice,
+    n_args: usize,
+) -> i32 {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut host = Host(unsafe { &*host });
+        let args: Vec<&[u8]> = unsafe { slice::from_raw_parts(args, n_args) }
+            .iter()
+            .map(|arg| unsafe { arg.as_slice() })
+            .collect();
+        M::call(command, &mut host, &args)
+    }));
+    match result {
+        Ok(Ok(_)) => 0,
+        _ => -1,
+    }
+}
+
+// Builds the descriptor returned from a module's init function. It's leaked,
+// since it has to stay valid for as long as the module is loaded.
+pub fn module_descriptor<M: NativeModule>() -> *const ModuleV1 {
+    let commands: &'static [RawSlice] = Box::leak(
+        M::COMMANDS
+            .iter()
+            .map(|command| RawSlice::new(command.as_bytes()))
+            .collect::<Box<[RawSlice]>>(),
+    );
+    Box::leak(Box::new(ModuleV1 {
+        abi_version: ABI_VERSION,
+        name: RawSlice::new(M::NAME.as_bytes()),
+        version: M::VERSION,
+        commands: commands.as_ptr(),
+        n_commands: commands.len(),
+        call: call_module::<M>,
+    }))
+}
+
+#[macro_export]
+macro_rules! export_module {
+    ($module:ty) => {
+        #[no_mangle]
+        pub extern "C" fn wedis_module_init() -> *const $crate::modules::ModuleV1 {
+            $crate::modules::module_descriptor::<$module>()
+        }
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Bulk(Vec<u8>),
+    String(String),
+    Error(String),
+    Integer(i64),
+    Null,
+    Array(usize),
+}
+
+// Replies are buffered until the command finishes, so that a module that
+// fails partway through can't leave a half-written reply on the connection
+struct CallContext<'a> {
+    db: &'a dyn DatabaseOperations,
+    replies: Vec<Reply>,
+}
+
+fn context<'a>(ctx: *mut c_void) -> &'a mut CallContext<'a> {
+    unsafe { &mut *ctx.cast::<CallContext>() }
+}
+
+extern "C" fn host_get(
+    ctx: *mut c_void,
+    key: RawSlice,
+    found: ValueCallback,
+    user: *mut c_void,
+) -> i32 {
+    match context(ctx).db.get_string(unsafe { key.as_slice() }) {
+        Ok(Some(value)) => {
+            found(user, RawSlice::new(&value));
+            1
+        }
+        Ok(None) => 0,
+        Err(_) => -1,
+    }
+}
+
+extern "C" fn host_set(ctx: *mut c_void, key: RawSlice, value: RawSlice) -> i32 {
+    let (key, value) = unsafe { (key.as_slice(), value.as_slice()) };
+    match context(ctx).db.put_string(key, value) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+extern "C" fn host_del(ctx: *mut c_void, key: RawSlice) -> i64 {
+    context(ctx)
+        .db
+        .delete(unsafe { key.as_slice() })
+        .unwrap_or(-1)
+}
+
+fn lossy(msg: RawSlice) -> String {
+    String::from_utf8_lossy(unsafe { msg.as_slice() }).into_owned()
+}
+
+extern "C" fn host_reply_bulk(ctx: *mut c_void, msg: RawSlice) {
+    let msg = unsafe { msg.as_slice() }.to_vec();
+    context(ctx).replies.push(Reply::Bulk(msg));
+}
+
+extern "C" fn host_reply_string(ctx: *mut c_void, msg: RawSlice) {
+    context(ctx).replies.push(Reply::String(lossy(msg)));
+}
+
+extern "C" fn host_reply_error(ctx: *mut c_void, msg: RawSlice) {
+    context(ctx).replies.push(Reply::Error(lossy(msg)));
+}
+
+extern "C" fn host_reply_integer(ctx: *mut c_void, x: i64) {
+    context(ctx).replies.push(Reply::Integer(x));
+}
+
+extern "C" fn host_reply_null(ctx: *mut c_void) {
+    context(ctx).replies.push(Reply::Null);
+}
+
+extern "C" fn host_reply_array(ctx: *mut c_void, count: usize) {
+    context(ctx).replies.push(Reply::Array(count));
+}
+
+fn call_descriptor(
+    descriptor: &ModuleV1,
+    command: usize,
+    db: &dyn DatabaseOperations,
+    args: &[Vec<u8>],
+) -> Option<Vec<Reply>> {
+    let mut ctx = CallContext {
+        db,
+        replies: vec![],
+    };
+    let host = HostApi {
+        ctx: (&mut ctx as *mut CallContext).cast::<c_void>(),
+        get: host_get,
+        set: host_set,
+        del: host_del,
+        reply_bulk: host_reply_bulk,
+        reply_string: host_reply_string,
+        reply_error: host_reply_error,
+        reply_integer: host_reply_integer,
+        reply_null: host_reply_null,
+        reply_array: host_reply_array,
+    };
+    let raw_args: Vec<RawSlice> = args.iter().map(|arg| RawSlice::new(arg)).collect();
+
+    let status = (descriptor.call)(command, &host, raw_args.as_ptr(), raw_args.len());
+    match status {
+        0 => Some(ctx.replies),
+        _ => None,
+    }
+}
+
+struct LoadedModule {
+    descriptor: *const ModuleV1,
+    version: u32,
+    path: String,
+    commands: Vec<String>,
+    // Dropped last, since the descriptor points into the library
+    _library: Library,
+}
+
+// Descriptors are immutable once loaded, and only used under the lock
+unsafe impl Send for LoadedModule {}
+
+#[derive(Default)]
+struct Modules {
+    loaded: HashMap<String, LoadedModule>,
+    // Command name to the module implementing it and the command's index
+    commands: HashMap<String, (String, usize)>,
+}
+
+fn modules() -> &'static Mutex<Modules> {
+    static MODULES: OnceLock<Mutex<Modules>> = OnceLock::new();
+    MODULES.get_or_init(|| Mutex::new(Modules::default()))
+}
+
+// Returns the name of the loaded module
+pub fn load(path: &str) -> Result<String, ModuleError> {
+    // Loading a library runs arbitrary code, so modules are as trusted as
+    // the server itself
+    let library = unsafe { Library::new(path)? };
+    let init = unsafe { *library.get::<extern "C" fn() -> *const ModuleV1>(INIT_SYMBOL)? };
+    let descriptor = init();
+    let module = unsafe { &*descriptor };
+    if module.abi_version != ABI_VERSION {
+        return Err(ModuleError::AbiVersion(module.abi_version));
+    }
+
+    let name = lossy(module.name);
+    let commands: Vec<String> =
+        unsafe { slice::from_raw_parts(module.commands, module.n_commands) }
+            .iter()
+            .map(|command| lossy(*command).to_uppercase())
+            .collect();
+
+    let mut modules = modules().lock().unwrap();
+    if modules.loaded.contains_key(&name) {
+        return Err(ModuleError::AlreadyLoaded(name));
+    }
+    for command in commands.iter() {
+        if registry::lookup(command).is_some() || modules.commands.contains_key(command) {
+            return Err(ModuleError::CommandExists(command.clone()));
+        }
+    }
+
+    for (i, command) in commands.iter().enumerate() {
+        modules.commands.insert(command.clone(), (name.clone(), i));
+    }
+    modules.loaded.insert(
+        name.clone(),
+        LoadedModule {
+            descriptor,
+            version: module.version,
+            path: path.to_string(),
+            commands,
+            _library: library,
+        },
+    );
+    info!("Loaded module {} from {}", name, path);
+    Ok(name)
+}
+
+pub fn unload(name: &str) -> Result<(), ModuleError> {
+    let mut modules = modules().lock().unwrap();
+    let module = modules.loaded.remove(name).ok_or(ModuleError::NotLoaded)?;
+    for command in module.commands.iter() {
+        modules.commands.remove(command);
+    }
+    info!("Unloaded module {}", name);
+    Ok(())
+}
+
+// Name, version and path of each loaded module
+pub fn list() -> Vec<(String, u32, String)> {
+    let modules = modules().lock().unwrap();
+    modules
+        .loaded
+        .iter()
+        .map(|(name, module)| (name.clone(), module.version, module.path.clone()))
+        .collect()
+}
+
+pub fn has_command(name: &str) -> bool {
+    modules().lock().unwrap().commands.contains_key(name)
+}
+
+// The lock is held for the whole call, so a module can't be unloaded while
+// one of its commands is running
+#[tracing::instrument(skip_all)]
+pub fn call(
+    conn: &mut dyn Connection,
+    db: &dyn DatabaseOperations,
+    name: &str,
+    args: &Vec<Vec<u8>>,
+) {
+    let modules = modules().lock().unwrap();
+    let replies = match modules.commands.get(name) {
+        Some((module, command)) => {
+            let descriptor = unsafe { &*modules.loaded[module].descriptor };
+            call_descriptor(descriptor, *command, db, args)
+        }
+        None => {
+            conn.write_error(ClientError::UnknownCommand);
+            return;
+        }
+    };
+
+    match replies {
+        Some(replies) => {
+            for reply in replies {
+                match reply {
+                    Reply::Bulk(msg) => conn.write_bulk(&msg),
+                    Reply::String(msg) => conn.write_string(&msg),
+                    Reply::Error(msg) => conn.write_error(ClientError::Module(msg)),
+                    Reply::Integer(x) => conn.write_integer(x),
+                    Reply::Null => conn.write_null(),
+                    Reply::Array(n) => conn.write_array(n),
+                }
+            }
+        }
+        None => {
+            error!("Module command {} failed", name);
+            conn.write_error(ClientError::Module(format!("ERR {} failed", name)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::MockDatabaseOperations;
+    use mockall::predicate::*;
+
+    use super::*;
+
+    struct TestModule;
+
+    impl NativeModule for TestModule {
+        const NAME: &'static str = "test";
+        const VERSION: u32 = 1;
+        const COMMANDS: &'static [&'static str] = &["test.get", "test.panic"];
+
+        fn call(command: usize, host: &mut Host, args: &[&[u8]]) -> Result<(), ModuleError> {
+            match command {
+                0 => match host.get(args[1])? {
+                    Some(value) => host.reply_bulk(&value),
+                    None => host.reply_null(),
+                },
+                _ => panic!("test panic"),
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_call_descriptor() {
+        let descriptor = unsafe { &*module_descriptor::<TestModule>() };
+        assert_eq!(ABI_VERSION, descriptor.abi_version);
+        assert_eq!(2, descriptor.n_commands);
+
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_string()
+            .with(eq("key".as_bytes()))
+            .times(1)
+            .returning(|_| Ok(Some("value".into())));
+
+        let args: Vec<Vec<u8>> = vec!["TEST.GET".into(), "key".into()];
+        let replies = call_descriptor(descriptor, 0, &mock_db, &args);
+        assert_eq!(Some(vec![Reply::Bulk("value".into())]), replies);
+
+        let args: Vec<Vec<u8>> = vec!["TEST.PANIC".into()];
+        assert_eq!(None, call_descriptor(descriptor, 1, &mock_db, &args));
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    cell::Cell,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        OnceLock,
+    },
+    thread,
+};
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{error, info};
+
+use crate::{config, time::unix_timestamp};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SinkError {
+    #[error("invalid keyspace event sink '{0}' (expected http://, nats:// or pipe:)")]
+    InvalidSpec(String),
+    #[error("keyspace event sink already started")]
+    AlreadyStarted,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyspaceEvent {
+    // The lowercased name of the command that touched the key, or of what
+    // it did instead, like "del" for an EXPIREAT in the past
+    pub event: String,
+    pub key: String,
+    // Unix time in milliseconds
+    pub timestamp: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum SinkSpec {
+    // POSTs each batch as newline-delimited JSON
+    Webhook { addr: String, path: String },
+    // Publishes each event to a subject over the NATS text protocol
+    Nats { addr: String, subject: String },
+    // Appends newline-delimited JSON to a file or named pipe
+    Pipe(PathBuf),
+}
+
+fn parse_spec(spec: &str) -> Result<SinkSpec, SinkError> {
+    let invalid = || SinkError::InvalidSpec(spec.to_string());
+    if let Some(rest) = spec.strip_prefix("http://") {
+        let (addr, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if addr.is_empty() {
+            return Err(invalid());
+        }
+        return Ok(SinkSpec::Webhook {
+            addr: addr.to_string(),
+            path: path.to_string(),
+        });
+    }
+
+    if let Some(rest) = spec.strip_prefix("nats://") {
+        return match rest.split_once('/') {
+            Some((addr, subject)) if { !addr.is_empty() && !subject.is_empty() } => {
+                Ok(SinkSpec::Nats {
+                    addr: addr.to_string(),
+                    subject: subject.to_string(),
+                })
+            }
+            _ => Err(invalid()),
+        };
+    }
+
+    match spec.strip_prefix("pipe:") {
+        Some(path) if { !path.is_empty() } => Ok(SinkSpec::Pipe(PathBuf::from(path))),
+        _ => Err(invalid()),
+    }
+}
+
+fn encode_batch(batch: &[KeyspaceEvent]) -> Vec<u8> {
+    let mut body = vec![];
+    for event in batch {
+        // Serializing plain strings and integers can't fail
+        serde_json::to_writer(&mut body, event).unwrap();
+        body.push(b'\n');
+    }
+    body
+}
+
+// Connections are opened lazily and dropped on failure, so a sink that goes
+// away is reconnected to on the next batch
+struct Sink {
+    spec: SinkSpec,
+    nats: Option<TcpStream>,
+    pipe: Option<File>,
+}
+
+impl Sink {
+    fn deliver(&mut self, batch: &[KeyspaceEvent]) -> io::Result<()> {
+        let result = match &self.spec {
+            SinkSpec::Webhook { addr, path } => post(addr, path, &encode_batch(batch)),
+            SinkSpec::Nats { addr, subject } => {
+                if self.nats.is_none() {
+                    self.nats = Some(nats_connect(addr)?);
+                }
+                let stream = self.nats.as_mut().unwrap();
+                let mut out = vec![];
+                for event in batch {
+                    let payload = encode_batch(std::slice::from_ref(event));
+                    write!(out, "PUB {} {}\r\n", subject, payload.len() - 1)?;
+                    out.extend_from_slice(&payload[..payload.len() - 1]);
+                    out.extend_from_slice(b"\r\n");
+                }
+                stream.write_all(&out).and_then(|_| stream.flush())
+            }
+            SinkSpec::Pipe(path) => {
+                if self.pipe.is_none() {
+                    self.pipe = Some(OpenOptions::new().append(true).create(true).open(path)?);
+                }
+                let pipe = self.pipe.as_mut().unwrap();
+                pipe.write_all(&encode_batch(batch))
+            }
+        };
+
+        if result.is_err() {
+            self.nats = None;
+            self.pipe = None;
+        }
+        result
+    }
+}
+
+fn post(addr: &str, path: &str, body: &[u8]) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-ndjson\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        addr,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if { code.starts_with('2') } => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "webhook returned '{}'",
+            status.trim_end()
+        ))),
+    }
+}
+
+fn nats_connect(addr: &str) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(addr)?;
+    // The server greets with an INFO line before accepting CONNECT
+    let mut info = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut info)?;
+    if !info.starts_with("INFO") {
+        return Err(io::Error::other("unexpected NATS greeting"));
+    }
+    stream.write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n")?;
+    Ok(stream)
+}
+
+fn run(mut sink: Sink, events: Receiver<KeyspaceEvent>, batch_size: usize) {
+    // Events that queued up while the previous batch was being delivered
+    // are sent together, so batches grow with load rather than with a timer
+    while let Ok(event) = events.recv() {
+        let mut batch = vec![event];
+        while batch.len() < batch_size {
+            match events.try_recv() {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+
+        // Retry once, since the first failure is usually a stale connection
+        if let Err(err) = sink.deliver(&batch).or_else(|_| sink.deliver(&batch)) {
+            error!(
+                "Dropped {} keyspace events, failed to deliver: {}",
+                batch.len(),
+                err
+            );
+        }
+    }
+}
+
+fn sender() -> &'static OnceLock<SyncSender<KeyspaceEvent>> {
+    static SENDER: OnceLock<SyncSender<KeyspaceEvent>> = OnceLock::new();
+    &SENDER
+}
+
+// Starts forwarding events to the sink configured by notify-sink, if any.
+// The sink can only be chosen at startup.
+pub fn start_from_config() -> Result<(), SinkError> {
+    let (spec, batch_size, queue_size) = {
+        let config = config::config().read().unwrap();
+        let parse = |name| {
+            config
+                .value(name)
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(1)
+                .max(1)
+        };
+        (
+            config.value("notify-sink").unwrap_or("").to_string(),
+            parse("notify-sink-batch-size"),
+            parse("notify-sink-queue-size"),
+        )
+    };
+    if spec.is_empty() {
+        return Ok(());
+    }
+
+    let sink = Sink {
+        spec: parse_spec(&spec)?,
+        nats: None,
+        pipe: None,
+    };
+    // A bounded queue pushes back on writers when the sink can't keep up,
+    // rather than buffering without limit
+    let (tx, rx) = mpsc::sync_channel(queue_size);
+    sender().set(tx).map_err(|_| SinkError::AlreadyStarted)?;
+    thread::spawn(move || run(sink, rx, batch_size));
+
+    info!("Forwarding keyspace events to {}", spec);
+    Ok(())
+}
+
+// Events are named after the command that wrote the key, unless the
+// command did something else to it, like EXPIREAT deleting a key whose time
+// has already passed. Commands run on their connection's thread, so the
+// replacement is kept per thread until the dispatcher takes it.
+thread_local! {
+    static EVENT: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+pub fn replace_event(event: &'static str) {
+    EVENT.set(Some(event));
+}
+
+pub fn take_event() -> Option<&'static str> {
+    EVENT.take()
+}
+
+pub fn is_enabled() -> bool {
+    sender().get().is_some()
+}
+
+// Blocks while the queue is full
+pub fn publish(event: &str, key: &[u8]) {
+    if let Some(tx) = sender().get() {
+        let _ = tx.send(KeyspaceEvent {
+            event: event.to_lowercase(),
+            key: String::from_utf8_lossy(key).into_owned(),
+            timestamp: unix_timestamp().map(|t| t.as_millis() as u64).unwrap_or(0),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(
+            Ok(SinkSpec::Webhook {
+                addr: "localhost:8080".into(),
+                path: "/events".into()
+            }),
+            parse_spec("http://localhost:8080/events")
+        );
+        assert_eq!(
+            Ok(SinkSpec::Nats {
+                addr: "localhost:4222".into(),
+                subject: "wedis.keys".into()
+            }),
+            parse_spec("nats://localhost:4222/wedis.keys")
+        );
+        assert_eq!(
+            Ok(SinkSpec::Pipe("/tmp/events".into())),
+            parse_spec("pipe:/tmp/events")
+        );
+        assert!(parse_spec("nats://localhost:4222").is_err());
+        assert!(parse_spec("udp://localhost").is_err());
+    }
+
+    #[test]
+    fn test_pipe_sink() {
+        let path = std::env::temp_dir().join("wedis_test_pipe_sink");
+        let _ = fs::remove_file(&path);
+
+        let mut sink = Sink {
+            spec: SinkSpec::Pipe(path.clone()),
+            nats: None,
+            pipe: None,
+        };
+        let event = KeyspaceEvent {
+            event: "set".into(),
+            key: "key".into(),
+            timestamp: 1,
+        };
+        sink.deliver(&[event.clone(), event]).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            "{\"event\":\"set\",\"key\":\"key\",\"timestamp\":1}\n".repeat(2),
+            written
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::{anyhow, bail, Result};
+use tracing::info;
+use wasmtime::{
+    Caller, Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+};
+
+use crate::{
+    config,
+    connection::{ClientError, Connection},
+    database::DatabaseOperations,
+    protocol::ProtocolLimits,
+};
+
+// Plugins are WebAssembly modules that export their commands as functions
+// named command_<name>, taking no parameters and returning nothing. Commands
+// are registered as <MODULE>.<NAME> after the module's file name, so they
+// can't shadow built-in commands.
+const COMMAND_PREFIX: &str = "command_";
+
+// Status codes returned by host functions
+const MISSING: i64 = -1;
+const FAILED: i64 = -2;
+
+// How large a plugin's linear memory may grow
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Bulk(Vec<u8>),
+    String(String),
+    Error(String),
+    Integer(i64),
+    Null,
+    Array(usize),
+}
+
+// Replies are buffered until the command finishes, so that a plugin that
+// traps partway through can't leave a half-written reply on the connection.
+// The state owns everything it holds, so that one linker serves every call.
+struct HostState {
+    db: Arc<dyn DatabaseOperations + Send + Sync>,
+    args: Vec<Vec<u8>>,
+    replies: Vec<Reply>,
+    limits: StoreLimits,
+    max_bulk_len: usize,
+}
+
+struct Plugins {
+    engine: Engine,
+    linker: Linker<HostState>,
+    db: Arc<dyn DatabaseOperations + Send + Sync>,
+    fuel: u64,
+    // Longest string a plugin may hand the host, same as for clients
+    max_bulk_len: usize,
+    // Command name to the module and export that implement it
+    commands: HashMap<String, (Module, String)>,
+}
+
+fn plugins() -> &'static OnceLock<Plugins> {
+    static PLUGINS: OnceLock<Plugins> = OnceLock::new();
+    &PLUGINS
+}
+
+fn load(
+    engine: &Engine,
+    path: &Path,
+    commands: &mut HashMap<String, (Module, String)>,
+) -> Result<()> {
+    let module = Module::from_file(engine, path)?;
+    let prefix = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_uppercase())
+        .ok_or_else(|| anyhow!("invalid plugin path {}", path.display()))?;
+
+    for export in module.exports() {
+        let name = export.name();
+        if export.ty().func().is_none() || !name.starts_with(COMMAND_PREFIX) {
+            continue;
+        }
+        let command = format!("{}.{}", prefix, name[COMMAND_PREFIX.len()..].to_uppercase());
+        info!("Loaded plugin command {}", command);
+        commands.insert(command, (module.clone(), name.to_string()));
+    }
+    Ok(())
+}
+
+// Loads the modules listed in wasm-plugins. Plugins can only be loaded at
+// startup.
+pub fn load_from_config(db: Arc<dyn DatabaseOperations + Send + Sync>) -> Result<()> {
+    let (paths, fuel) = {
+        let config = config::config().read().unwrap();
+        (
+            config.value("wasm-plugins").unwrap_or("").to_string(),
+            config
+                .value("wasm-plugin-fuel")
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0),
+        )
+    };
+    if paths.trim().is_empty() {
+        return Ok(());
+    }
+
+    let mut engine_config = Config::new();
+    engine_config.consume_fuel(true);
+    let engine = Engine::new(&engine_config)?;
+    let linker = link(&engine)?;
+
+    let mut commands = HashMap::new();
+    for path in paths.split_whitespace() {
+        load(&engine, Path::new(path), &mut commands)?;
+    }
+
+    plugins()
+        .set(Plugins {
+            engine,
+            linker,
+            db,
+            fuel,
+            max_bulk_len: ProtocolLimits::from_config().max_bulk_len,
+            commands,
+        })
+        .map_err(|_| anyhow!("plugins already loaded"))
+}
+
+pub fn has_command(name: &str) -> bool {
+    plugins()
+        .get()
+        .is_some_and(|plugins| plugins.commands.contains_key(name))
+}
+
+fn memory(caller: &mut Caller<'_, HostState>) -> Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| anyhow!("plugin doesn't export its memory"))
+}
+
+// The range is checked before anything is allocated for it, so a plugin
+// can't have the host allocate more than it could have written itself
+fn read(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>> {
+    let ptr = usize::try_from(ptr)?;
+    let len = usize::try_from(len)?;
+    if len > caller.data().max_bulk_len {
+        bail!("plugin read of {} bytes is too long", len);
+    }
+    let memory = memory(caller)?;
+    if !matches!(ptr.checked_add(len), Some(end) if end <= memory.data_size(&*caller)) {
+        bail!("plugin read of {} bytes at {} is out of bounds", len, ptr);
+    }
+
+    let mut buf = vec![0; len];
+    memory.read(&*caller, ptr, &mut buf)?;
+    Ok(buf)
+}
+
+// Copies as much of data as fits in the guest's buffer, returning the full
+// length so the guest can retry with a larger buffer
+fn write(caller: &mut Caller<'_, HostState>, ptr: i32, cap: i32, data: &[u8]) -> Result<i64> {
+    let n = data.len().min(usize::try_from(cap)?);
+    memory(caller)?.write(&mut *caller, usize::try_from(ptr)?, &data[..n])?;
+    Ok(data.len() as i64)
+}
+
+fn reply(caller: &mut Caller<'_, HostState>, reply: Reply) {
+    caller.data_mut().replies.push(reply);
+}
+
+// The host API is intentionally narrow: command arguments, string reads and
+// writes, deletes, and reply helpers
+fn link(engine: &Engine) -> Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap("wedis", "arg_count", |caller: Caller<'_, HostState>| {
+        caller.data().args.len() as i32
+    })?;
+    linker.func_wrap(
+        "wedis",
+        "arg",
+        |mut caller: Caller<'_, HostState>, i: i32, ptr: i32, cap: i32| -> Result<i64> {
+            let arg = usize::try_from(i)
+                .ok()
+                .and_then(|i| caller.data().args.get(i))
+                .cloned();
+            match arg {
+                Some(arg) => write(&mut caller, ptr, cap, &arg),
+                None => Ok(MISSING),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "get",
+        |mut caller: Caller<'_, HostState>,
+         key_ptr: i32,
+         key_len: i32,
+         ptr: i32,
+         cap: i32|
+         -> Result<i64> {
+            let key = read(&mut caller, key_ptr, key_len)?;
+            match caller.data().db.get_string(&key) {
+                Ok(Some(value)) => write(&mut caller, ptr, cap, &value),
+                Ok(None) => Ok(MISSING),
+                Err(_) => Ok(FAILED),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "set",
+        |mut caller: Caller<'_, HostState>,
+         key_ptr: i32,
+         key_len: i32,
+         value_ptr: i32,
+         value_len: i32|
+         -> Result<i64> {
+            let key = read(&mut caller, key_ptr, key_len)?;
+            let value = read(&mut caller, value_ptr, value_len)?;
+            match caller.data().db.put_string(&key, &value) {
+                Ok(_) => Ok(0),
+                Err(_) => Ok(FAILED),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "del",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32| -> Result<i64> {
+            let key = read(&mut caller, key_ptr, key_len)?;
+            Ok(caller.data().db.delete(&key).unwrap_or(FAILED))
+        },
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "reply_bulk",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<()> {
+            let msg = read(&mut caller, ptr, len)?;
+            reply(&mut caller, Reply::Bulk(msg));
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "reply_string",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<()> {
+            let msg = read(&mut caller, ptr, len)?;
+            let msg = String::from_utf8_lossy(&msg).into_owned();
+            reply(&mut caller, Reply::String(msg));
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "reply_error",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<()> {
+            let msg = read(&mut caller, ptr, len)?;
+            let msg = String::from_utf8_lossy(&msg).into_owned();
+            reply(&mut caller, Reply::Error(msg));
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "reply_integer",
+        |mut caller: Caller<'_, HostState>, x: i64| reply(&mut caller, Reply::Integer(x)),
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "reply_null",
+        |mut caller: Caller<'_, HostState>| reply(&mut caller, Reply::Null),
+    )?;
+    linker.func_wrap(
+        "wedis",
+        "reply_array",
+        |mut caller: Caller<'_, HostState>, n: i32| -> Result<()> {
+            let n = usize::try_from(n)?;
+            reply(&mut caller, Reply::Array(n));
+            Ok(())
+        },
+    )?;
+    Ok(linker)
+}
+
+fn run(plugins: &Plugins, module: &Module, export: &str, args: &[Vec<u8>]) -> Result<Vec<Reply>> {
+    // Each call gets a fresh instance, so plugins can't keep state between
+    // commands other than through the database
+    let mut store = Store::new(
+        &plugins.engine,
+        HostState {
+            db: plugins.db.clone(),
+            args: args.to_vec(),
+            replies: vec![],
+            limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+            max_bulk_len: plugins.max_bulk_len,
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(plugins.fuel)?;
+    let instance = plugins.linker.instantiate(&mut store, module)?;
+    instance
+        .get_typed_func::<(), ()>(&mut store, export)?
+        .call(&mut store, ())?;
+    Ok(store.into_data().replies)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn call(conn: &mut dyn Connection, name: &str, args: &Vec<Vec<u8>>) -> Result<()> {
+    let plugins = match plugins().get() {
+        Some(plugins) => plugins,
+        None => return Ok(conn.write_error(ClientError::UnknownCommand)),
+    };
+    let (module, export) = match plugins.commands.get(name) {
+        Some(command) => command,
+        None => return Ok(conn.write_error(ClientError::UnknownCommand)),
+    };
+
+    match run(plugins, module, export, args) {
+        Ok(replies) => {
+            for reply in replies {
+                match reply {
+                    Reply::Bulk(msg) => conn.write_bulk(&msg),
+                    Reply::String(msg) => conn.write_string(&msg),
+                    Reply::Error(msg) => conn.write_error(ClientError::Module(msg)),
+                    Reply::Integer(x) => conn.write_integer(x),
+                    Reply::Null => conn.write_null(),
+                    Reply::Array(n) => conn.write_array(n),
+                }
+            }
+            Ok(())
+        }
+        Err(err) if { err.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) } => {
+            Ok(conn.write_error(ClientError::Module(format!("ERR {} ran out of fuel", name))))
+        }
+        Err(err) => {
+            conn.write_error(ClientError::Module(format!("ERR {} failed", name)));
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::MockDatabaseOperations;
+    use mockall::predicate::*;
+
+    use super::*;
+
+    const ECHO_GET: &str = r#"
+        (module
+          (import "wedis" "arg" (func $arg (param i32 i32 i32) (result i64)))
+          (import "wedis" "get" (func $get (param i32 i32 i32 i32) (result i64)))
+          (import "wedis" "reply_bulk" (func $reply_bulk (param i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "command_get")
+            (local $len i64)
+            (local.set $len (call $arg (i32.const 1) (i32.const 0) (i32.const 64)))
+            (local.set $len
+              (call $get (i32.const 0) (i32.wrap_i64 (local.get $len)) (i32.const 64) (i32.const 64)))
+            (call $reply_bulk (i32.const 64) (i32.wrap_i64 (local.get $len))))
+          (func (export "command_spin")
+            (loop $forever (br $forever)))
+          (func (export "command_overread")
+            (call $reply_bulk (i32.const 65000) (i32.const 1000)))
+          (func (export "command_overlong")
+            (call $reply_bulk (i32.const 0) (i32.const 2147483647))))
+    "#;
+
+    fn plugins(db: MockDatabaseOperations) -> Plugins {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).unwrap();
+        Plugins {
+            linker: link(&engine).unwrap(),
+            engine,
+            db: Arc::new(db),
+            fuel: 10_000,
+            max_bulk_len: 512 * 1024 * 1024,
+            commands: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let mut mock_db = MockDatabaseOperations::new();
+        mock_db
+            .expect_get_string()
+            .with(eq("key".as_bytes()))
+            .times(1)
+            .returning(|_| Ok(Some("value".into())));
+        let plugins = plugins(mock_db);
+        let module = Module::new(&plugins.engine, ECHO_GET).unwrap();
+
+        let args: Vec<Vec<u8>> = vec!["TEST.GET".into(), "key".into()];
+        let replies = run(&plugins, &module, "command_get", &args).unwrap();
+        assert_eq!(vec![Reply::Bulk("value".into())], replies);
+    }
+
+    #[test]
+    fn test_run_out_of_fuel() {
+        let plugins = plugins(MockDatabaseOperations::new());
+        let module = Module::new(&plugins.engine, ECHO_GET).unwrap();
+
+        let args: Vec<Vec<u8>> = vec!["TEST.SPIN".into()];
+        let err = run(&plugins, &module, "command_spin", &args).unwrap_err();
+        assert_eq!(Some(&Trap::OutOfFuel), err.downcast_ref::<Trap>());
+    }
+
+    #[test]
+    fn test_run_reads_out_of_bounds() {
+        let plugins = plugins(MockDatabaseOperations::new());
+        let module = Module::new(&plugins.engine, ECHO_GET).unwrap();
+
+        // Past the end of the plugin's memory, and past the longest string
+        // the host accepts
+        let args: Vec<Vec<u8>> = vec!["TEST.OVERREAD".into()];
+        assert!(run(&plugins, &module, "command_overread", &args).is_err());
+        let args: Vec<Vec<u8>> = vec!["TEST.OVERLONG".into()];
+        assert!(run(&plugins, &module, "command_overlong", &args).is_err());
+    }
+}
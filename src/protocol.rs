@@ -0,0 +1,70 @@
+use thiserror::Error;
+
+use crate::config;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ProtocolError {
+    #[error("ERR Protocol error: invalid bulk length")]
+    BulkTooLong,
+    #[error("ERR Protocol error: invalid multibulk length")]
+    MultibulkTooLong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProtocolLimits {
+    pub max_bulk_len: usize,
+    pub max_multibulk_len: usize,
+}
+
+impl ProtocolLimits {
+    pub fn from_config() -> Self {
+        let config = config::config().read().unwrap();
+        let parse = |name, default| {
+            config
+                .value(name)
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(default)
+        };
+
+        Self {
+            max_bulk_len: parse("proto-max-bulk-len", 512 * 1024 * 1024),
+            max_multibulk_len: parse("proto-max-multibulk-len", 1024 * 1024),
+        }
+    }
+
+    // redcon parses requests before handing them over, so these can only be
+    // checked once a request has arrived in full. They still keep oversized
+    // arguments from reaching storage, and the connection is closed before
+    // the client can send more.
+    pub fn check(&self, args: &[Vec<u8>]) -> Result<(), ProtocolError> {
+        if args.len() > self.max_multibulk_len {
+            return Err(ProtocolError::MultibulkTooLong);
+        }
+        if args.iter().any(|arg| arg.len() > self.max_bulk_len) {
+            return Err(ProtocolError::BulkTooLong);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limits = ProtocolLimits {
+            max_bulk_len: 3,
+            max_multibulk_len: 2,
+        };
+
+        let args: Vec<Vec<u8>> = vec!["GET".into(), "key".into()];
+        assert_eq!(Ok(()), limits.check(&args));
+
+        let args: Vec<Vec<u8>> = vec!["GET".into(), "long".into()];
+        assert_eq!(Err(ProtocolError::BulkTooLong), limits.check(&args));
+
+        let args: Vec<Vec<u8>> = vec!["DEL".into(), "a".into(), "b".into()];
+        assert_eq!(Err(ProtocolError::MultibulkTooLong), limits.check(&args));
+    }
+}
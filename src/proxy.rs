@@ -0,0 +1,84 @@
+use std::net::{IpAddr, SocketAddr};
+
+use thiserror::Error;
+
+use crate::config;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ProxyError {
+    #[error("ERR expected a PROXY protocol header")]
+    Missing,
+    #[error("ERR invalid PROXY protocol header")]
+    Invalid,
+}
+
+// With proxy-protocol enabled, every connection has to start with a PROXY
+// header from the load balancer in front of wedis
+pub fn is_enabled() -> bool {
+    config::config().read().unwrap().value("proxy-protocol") == Some("yes")
+}
+
+// Parses a version 1 (text) header, e.g.
+//
+//   PROXY TCP4 192.168.0.1 192.168.0.11 56324 6379
+//
+// which arrives as an inline command. Returns the original client's address,
+// or None for UNKNOWN, which load balancers send for their own health
+// checks.
+//
+// Version 2 headers are binary and can't be told apart from a RESP request
+// stream, so they aren't supported.
+pub fn parse_v1(args: &[Vec<u8>]) -> Result<Option<SocketAddr>, ProxyError> {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    if args.first().map(|arg| arg.as_str()) != Some("PROXY") {
+        return Err(ProxyError::Missing);
+    }
+
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("UNKNOWN") => Ok(None),
+        Some(protocol @ ("TCP4" | "TCP6")) if { args.len() == 6 } => {
+            let ip: IpAddr = args[2].parse().map_err(|_| ProxyError::Invalid)?;
+            let port: u16 = args[4].parse().map_err(|_| ProxyError::Invalid)?;
+            if ip.is_ipv4() != (protocol == "TCP4") {
+                return Err(ProxyError::Invalid);
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(ProxyError::Invalid),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(line: &str) -> Vec<Vec<u8>> {
+        line.split(' ').map(|arg| arg.into()).collect()
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        assert_eq!(
+            Ok(Some("192.168.0.1:56324".parse().unwrap())),
+            parse_v1(&header("PROXY TCP4 192.168.0.1 192.168.0.11 56324 6379"))
+        );
+        assert_eq!(
+            Ok(Some("[fd00::1]:4000".parse().unwrap())),
+            parse_v1(&header("PROXY TCP6 fd00::1 fd00::2 4000 6379"))
+        );
+        assert_eq!(Ok(None), parse_v1(&header("PROXY UNKNOWN")));
+
+        assert_eq!(
+            Err(ProxyError::Invalid),
+            parse_v1(&header("PROXY TCP6 192.168.0.1 192.168.0.11 56324 6379"))
+        );
+        assert_eq!(
+            Err(ProxyError::Invalid),
+            parse_v1(&header("PROXY TCP4 192.168.0.1"))
+        );
+        assert_eq!(Err(ProxyError::Missing), parse_v1(&header("GET key")));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::config;
+
+// Buckets hold at most one second's worth of tokens, so clients can burst up
+// to their per-second limit after idling but no further
+#[derive(Debug, Default)]
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, rate: f64, now: Instant) {
+        self.tokens = match self.last_refill {
+            Some(last) => (self.tokens + now.duration_since(last).as_secs_f64() * rate).min(rate),
+            None => rate,
+        };
+        self.last_refill = Some(now);
+    }
+
+    // A request larger than the whole bucket is let through once the
+    // bucket is full, leaving it in debt, so it can't be starved forever
+    fn can_take(&self, n: f64, rate: f64) -> bool {
+        self.tokens >= n.min(rate)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.last_refill
+            .is_none_or(|last| now.duration_since(last) > Duration::from_secs(1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    // 0 means unlimited
+    pub commands_per_second: u64,
+    pub bytes_per_second: u64,
+    // Whether limits are shared by all connections from the same IP,
+    // rather than applied to each connection separately
+    pub per_ip: bool,
+}
+
+impl Limits {
+    pub fn from_config() -> Self {
+        let config = config::config().read().unwrap();
+        let parse = |name| {
+            config
+                .value(name)
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+
+        Self {
+            commands_per_second: parse("ratelimit-commands"),
+            bytes_per_second: parse("ratelimit-bytes"),
+            per_ip: config.value("ratelimit-scope") == Some("ip"),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.commands_per_second > 0 || self.bytes_per_second > 0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Limiter {
+    commands: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl Limiter {
+    // Takes one command and n_bytes of bandwidth if both are available.
+    // Nothing is taken from either bucket when the request is throttled.
+    pub fn allow(&mut self, limits: &Limits, n_bytes: usize, now: Instant) -> bool {
+        let commands_rate = limits.commands_per_second as f64;
+        let bytes_rate = limits.bytes_per_second as f64;
+        let n_bytes = n_bytes as f64;
+
+        self.commands.refill(commands_rate, now);
+        self.bytes.refill(bytes_rate, now);
+
+        let allowed = (commands_rate == 0.0 || self.commands.can_take(1.0, commands_rate))
+            && (bytes_rate == 0.0 || self.bytes.can_take(n_bytes, bytes_rate));
+        if allowed {
+            self.commands.tokens -= 1.0;
+            self.bytes.tokens -= n_bytes;
+        }
+
+        allowed
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.commands.is_idle(now) && self.bytes.is_idle(now)
+    }
+}
+
+fn ip_limiters() -> &'static Mutex<HashMap<IpAddr, Limiter>> {
+    static IP_LIMITERS: OnceLock<Mutex<HashMap<IpAddr, Limiter>>> = OnceLock::new();
+    IP_LIMITERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn allow_ip(ip: IpAddr, limits: &Limits, n_bytes: usize) -> bool {
+    let now = Instant::now();
+    let mut limiters = ip_limiters().lock().unwrap();
+    if !limiters.contains_key(&ip) {
+        // Idle limiters have full buckets, which is the same as starting
+        // over, so they can be dropped rather than kept around forever
+        limiters.retain(|_, limiter| !limiter.is_idle(now));
+    }
+
+    limiters.entry(ip).or_default().allow(limits, n_bytes, now)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_limiter_commands() {
+        let limits = Limits {
+            commands_per_second: 2,
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        let mut limiter = Limiter::default();
+        assert!(limiter.allow(&limits, 0, start));
+        assert!(limiter.allow(&limits, 0, start));
+        assert!(!limiter.allow(&limits, 0, start));
+
+        // Half a second refills one command
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.allow(&limits, 0, later));
+        assert!(!limiter.allow(&limits, 0, later));
+    }
+
+    #[test]
+    fn test_limiter_bytes() {
+        let limits = Limits {
+            bytes_per_second: 100,
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        let mut limiter = Limiter::default();
+        assert!(limiter.allow(&limits, 60, start));
+        assert!(!limiter.allow(&limits, 60, start));
+        assert!(limiter.allow(&limits, 40, start));
+    }
+
+    #[test]
+    fn test_limiter_oversized_request() {
+        let limits = Limits {
+            bytes_per_second: 100,
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        let mut limiter = Limiter::default();
+        assert!(limiter.allow(&limits, 250, start));
+        assert!(!limiter.allow(&limits, 1, start + Duration::from_secs(1)));
+        assert!(limiter.allow(&limits, 1, start + Duration::from_secs(3)));
+    }
+}
@@ -0,0 +1,241 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::Result;
+
+use crate::{commands, connection::Connection, database::DatabaseOperations};
+
+pub type Handler = fn(&mut dyn Connection, &dyn DatabaseOperations, &Vec<Vec<u8>>) -> Result<()>;
+
+// Flags as COMMAND INFO reports them
+const WRITE: &[&str] = &["write"];
+const READONLY: &[&str] = &["readonly"];
+const ADMIN: &[&str] = &["admin"];
+const NONE: &[&str] = &[];
+
+pub struct Command {
+    pub name: &'static str,
+    // Like Redis, the number of arguments including the command name, or its
+    // negation when that's only the minimum
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    pub handler: Handler,
+}
+
+impl Command {
+    pub fn accepts(&self, argc: usize) -> bool {
+        if self.arity < 0 {
+            argc as i64 >= -self.arity
+        } else {
+            argc as i64 == self.arity
+        }
+    }
+}
+
+const fn command(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    handler: Handler,
+) -> Command {
+    Command {
+        name,
+        arity,
+        flags,
+        handler,
+    }
+}
+
+// Every server command. Key positions are kept in keyspec, along with how
+// each key is accessed.
+pub static COMMANDS: &[Command] = &[
+    command("QUIT", -1, NONE, |conn, _, _| Ok(commands::quit(conn))),
+    command("AUTH", -2, NONE, |conn, _, args| {
+        Ok(commands::auth(conn, args))
+    }),
+    command("HELLO", -1, NONE, |conn, _, args| {
+        Ok(commands::hello(conn, args))
+    }),
+    command("PING", -1, NONE, |conn, _, args| {
+        Ok(commands::ping(conn, args))
+    }),
+    command("ECHO", 2, NONE, |conn, _, args| {
+        Ok(commands::echo(conn, args))
+    }),
+    command("CLIENT", -2, NONE, |conn, _, args| {
+        Ok(commands::client(conn, args))
+    }),
+    command("APPEND", 3, WRITE, commands::append),
+    command("SET", -3, WRITE, commands::set),
+    command("SETEX", 4, WRITE, commands::setex),
+    command("PSETEX", 4, WRITE, commands::psetex),
+    command("SETNX", 3, WRITE, commands::setnx),
+    command("MSETNX", -3, WRITE, commands::msetnx),
+    command("SETRANGE", 4, WRITE, commands::setrange),
+    command("GET", 2, READONLY, commands::get),
+    command("MGET", -2, READONLY, commands::mget),
+    command("GETRANGE", 4, READONLY, commands::getrange),
+    command("GETDEL", 2, WRITE, commands::getdel),
+    command("GETSET", 3, WRITE, commands::getset),
+    command("STRLEN", 2, READONLY, commands::strlen),
+    command("SUBSTR", 4, READONLY, commands::substr),
+    command("INCR", 2, WRITE, commands::incr),
+    command("INCRBY", 3, WRITE, commands::incrby),
+    command("INCRBYFLOAT", 3, WRITE, commands::incrbyfloat),
+    command("DECR", 2, WRITE, commands::decr),
+    command("DECRBY", 3, WRITE, commands::decrby),
+    command("DEL", -2, WRITE, commands::del),
+    command("UNLINK", -2, WRITE, commands::unlink),
+    command("COPY", -3, WRITE, commands::copy),
+    command("EXISTS", -2, READONLY, commands::exists),
+    command("TYPE", 2, READONLY, commands::r#type),
+    command("OBJECT", -2, READONLY, commands::object),
+    command("EXPIRE", -3, WRITE, commands::expire),
+    command("PEXPIRE", -3, WRITE, commands::pexpire),
+    command("EXPIREAT", -3, WRITE, commands::expireat),
+    command("PEXPIREAT", -3, WRITE, commands::pexpireat),
+    command("EXPIRETIME", 2, READONLY, commands::expiretime),
+    command("PEXPIRETIME", 2, READONLY, commands::pexpiretime),
+    command("PERSIST", 2, WRITE, commands::persist),
+    command("TTL", 2, READONLY, commands::ttl),
+    command("SCAN", -2, READONLY, commands::scan),
+    command("PTTL", 2, READONLY, commands::pttl),
+    command("HSET", -4, WRITE, commands::hset),
+    command("HGET", 3, READONLY, commands::hget),
+    command("HSTRLEN", 3, READONLY, commands::hstrlen),
+    command("HGETALL", 2, READONLY, commands::hgetall),
+    command("HEXPIRE", -6, WRITE, commands::hexpire),
+    command("HPEXPIRE", -6, WRITE, commands::hpexpire),
+    command("HTTL", -5, READONLY, commands::httl),
+    command("HPERSIST", -5, WRITE, commands::hpersist),
+    command("HGETEX", -5, WRITE, commands::hgetex),
+    command("HGETDEL", -5, WRITE, commands::hgetdel),
+    command("LPUSH", -3, WRITE, commands::lpush),
+    command("RPUSH", -3, WRITE, commands::rpush),
+    command("LPUSHX", -3, WRITE, commands::lpushx),
+    command("RPUSHX", -3, WRITE, commands::rpushx),
+    command("LPOP", -2, WRITE, commands::lpop),
+    command("RPOP", -2, WRITE, commands::rpop),
+    command("BLPOP", -3, WRITE, commands::blpop),
+    command("BRPOP", -3, WRITE, commands::brpop),
+    command("LLEN", 2, READONLY, commands::llen),
+    command("LINDEX", 3, READONLY, commands::lindex),
+    command("LRANGE", 4, READONLY, commands::lrange),
+    command("LSET", 4, WRITE, commands::lset),
+    command("LINSERT", 5, WRITE, commands::linsert),
+    command("LREM", 4, WRITE, commands::lrem),
+    command("LTRIM", 4, WRITE, commands::ltrim),
+    command("SADD", -3, WRITE, commands::sadd),
+    command("SREM", -3, WRITE, commands::srem),
+    command("SMEMBERS", 2, READONLY, commands::smembers),
+    command("SISMEMBER", 3, READONLY, commands::sismember),
+    command("BITCOUNT", -2, READONLY, commands::bitcount),
+    command("BF.ADD", 3, WRITE, commands::bf_add),
+    command("BF.EXISTS", 3, READONLY, commands::bf_exists),
+    command("BF.MADD", -3, WRITE, commands::bf_madd),
+    command("BF.MEXISTS", -3, READONLY, commands::bf_mexists),
+    command("BF.RESERVE", -4, WRITE, commands::bf_reserve),
+    command("CMS.INCRBY", -4, WRITE, commands::cms_incrby),
+    command("CMS.INFO", 2, READONLY, commands::cms_info),
+    command("CMS.INITBYDIM", 4, WRITE, commands::cms_initbydim),
+    command("CMS.INITBYPROB", 4, WRITE, commands::cms_initbyprob),
+    command("CMS.MERGE", -4, WRITE, commands::cms_merge),
+    command("CMS.QUERY", -3, READONLY, commands::cms_query),
+    command("BITPOS", -3, READONLY, commands::bitpos),
+    command("GETBIT", 3, READONLY, commands::getbit),
+    command("SETBIT", 4, WRITE, commands::setbit),
+    command("SELECT", 2, NONE, |conn, _, _| Ok(conn.write_string("OK"))),
+    command("INFO", -1, NONE, commands::info),
+    command("CONFIG", -2, ADMIN, |conn, _, args| {
+        Ok(commands::config(conn, args))
+    }),
+    command("SLOWLOG", -2, ADMIN, |conn, _, args| {
+        Ok(commands::slowlog(conn, args))
+    }),
+    command("CHECK", -1, ADMIN, commands::check),
+    // Every key lives in the default database, so flushing it flushes
+    // everything
+    command("FLUSHDB", -1, WRITE, commands::flushall),
+    command("FLUSHALL", -1, WRITE, commands::flushall),
+    command("SHUTDOWN", -1, ADMIN, |conn, _, args| {
+        Ok(commands::shutdown(conn, args))
+    }),
+    command("HOTKEYS", -1, ADMIN, |conn, _, args| {
+        Ok(commands::hotkeys(conn, args))
+    }),
+    command("BIGKEYS", -1, ADMIN, |conn, _, args| {
+        Ok(commands::bigkeys(conn, args))
+    }),
+    command("COMMAND", -1, NONE, |conn, _, args| {
+        Ok(commands::command(conn, args))
+    }),
+    command("TDIGEST.ADD", -3, WRITE, commands::tdigest_add),
+    command("TDIGEST.CDF", -3, READONLY, commands::tdigest_cdf),
+    command("TDIGEST.CREATE", -2, WRITE, commands::tdigest_create),
+    command("TDIGEST.MERGE", -4, WRITE, commands::tdigest_merge),
+    command("TDIGEST.QUANTILE", -3, READONLY, commands::tdigest_quantile),
+    command("TIME", 1, NONE, |conn, _, _| commands::time(conn)),
+    command("TOPK.ADD", -3, WRITE, commands::topk_add),
+    command("TOPK.COUNT", -3, READONLY, commands::topk_count),
+    command("TOPK.INFO", 2, READONLY, commands::topk_info),
+    command("TOPK.LIST", -2, READONLY, commands::topk_list),
+    command("TOPK.QUERY", -3, READONLY, commands::topk_query),
+    command("TOPK.RESERVE", -3, WRITE, commands::topk_reserve),
+    command("TS.ADD", -4, WRITE, commands::ts_add),
+    command("TS.CREATE", -2, WRITE, commands::ts_create),
+    command("TS.INFO", -2, READONLY, commands::ts_info),
+    command("TS.MRANGE", -5, READONLY, commands::ts_mrange),
+    command("TS.RANGE", -4, READONLY, commands::ts_range),
+    #[cfg(feature = "native-modules")]
+    command("MODULE", -2, ADMIN, |conn, _, args| {
+        Ok(commands::module(conn, args))
+    }),
+];
+
+fn by_name() -> &'static HashMap<&'static str, &'static Command> {
+    static BY_NAME: OnceLock<HashMap<&'static str, &'static Command>> = OnceLock::new();
+    BY_NAME.get_or_init(|| {
+        COMMANDS
+            .iter()
+            .map(|command| (command.name, command))
+            .collect()
+    })
+}
+
+// Finds a server command by its uppercase name. Commands added by modules
+// and plugins aren't registered here.
+pub fn lookup(name: &str) -> Option<&'static Command> {
+    by_name().get(name).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::keyspec;
+
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let get = lookup("GET").unwrap();
+        assert_eq!(READONLY, get.flags);
+        assert!(get.accepts(2));
+        assert!(!get.accepts(3));
+
+        let del = lookup("DEL").unwrap();
+        assert!(!del.accepts(1));
+        assert!(del.accepts(5));
+
+        assert!(lookup("get").is_none());
+        assert_eq!(COMMANDS.len(), by_name().len());
+    }
+
+    #[test]
+    fn test_commands_have_key_specs() {
+        for command in COMMANDS {
+            assert!(
+                keyspec::key_range(command.name).is_some(),
+                "{} has no key specs",
+                command.name
+            );
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// Encoding for reply types that redcon doesn't write itself, which are
+// written out raw
+
+// Values that can be attached to replies as attributes, or pushed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bulk(Vec<u8>),
+    Integer(i64),
+    Double(f64),
+    Map(Vec<(Vec<u8>, Value)>),
+    Array(Vec<Value>),
+}
+
+fn encode_bulk(out: &mut Vec<u8>, bulk: &[u8]) {
+    out.extend_from_slice(format!("${}\r\n", bulk.len()).as_bytes());
+    out.extend_from_slice(bulk);
+    out.extend_from_slice(b"\r\n");
+}
+
+// Spelled the way Redis spells doubles, which RESP2 clients get as bulk
+// strings
+pub fn format_double(x: f64) -> String {
+    if x.is_nan() {
+        "nan".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+fn encode_double(out: &mut Vec<u8>, x: f64) {
+    out.extend_from_slice(format!(",{}\r\n", format_double(x)).as_bytes());
+}
+
+fn encode_entries(out: &mut Vec<u8>, entries: &[(Vec<u8>, Value)]) {
+    for (key, value) in entries {
+        encode_bulk(out, key);
+        encode_value(out, value);
+    }
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Bulk(bulk) => encode_bulk(out, bulk),
+        Value::Integer(x) => out.extend_from_slice(format!(":{}\r\n", x).as_bytes()),
+        Value::Double(x) => encode_double(out, *x),
+        Value::Map(entries) => {
+            out.extend_from_slice(format!("%{}\r\n", entries.len()).as_bytes());
+            encode_entries(out, entries);
+        }
+        Value::Array(items) => {
+            out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+            for item in items {
+                encode_value(out, item);
+            }
+        }
+    }
+}
+
+// An attribute map (the RESP3 | type), which is sent right before the reply
+// it describes
+pub fn encode_attribute(attributes: &[(Vec<u8>, Value)]) -> Vec<u8> {
+    let mut out = format!("|{}\r\n", attributes.len()).into_bytes();
+    encode_entries(&mut out, attributes);
+    out
+}
+
+// An out-of-band push message (the RESP3 > type), like the invalidations
+// sent for client-side caching
+pub fn encode_push(items: &[Value]) -> Vec<u8> {
+    let mut out = format!(">{}\r\n", items.len()).into_bytes();
+    for item in items {
+        encode_value(&mut out, item);
+    }
+    out
+}
+
+// A verbatim string (the RESP3 = type), which is text along with a three
+// character format, like txt or mkd, telling clients how to show it
+pub fn encode_verbatim(format: &str, text: &[u8]) -> Vec<u8> {
+    let mut out = format!("={}\r\n{}:", format.len() + 1 + text.len(), format).into_bytes();
+    out.extend_from_slice(text);
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_attribute() {
+        let attributes = vec![(
+            b"key-popularity".to_vec(),
+            Value::Map(vec![
+                (b"a".to_vec(), Value::Double(0.1923)),
+                (b"b".to_vec(), Value::Integer(2)),
+            ]),
+        )];
+        assert_eq!(
+            b"|1\r\n$14\r\nkey-popularity\r\n%2\r\n$1\r\na\r\n,0.1923\r\n$1\r\nb\r\n:2\r\n"
+                .to_vec(),
+            encode_attribute(&attributes)
+        );
+    }
+
+    #[test]
+    fn test_encode_double() {
+        let mut out = vec![];
+        encode_double(&mut out, f64::NEG_INFINITY);
+        assert_eq!(b",-inf\r\n".to_vec(), out);
+        assert_eq!("nan", format_double(f64::NAN));
+        assert_eq!("1.5", format_double(1.5));
+    }
+
+    #[test]
+    fn test_encode_verbatim() {
+        assert_eq!(
+            b"=15\r\ntxt:Some string\r\n".to_vec(),
+            encode_verbatim("txt", b"Some string")
+        );
+    }
+
+    #[test]
+    fn test_encode_push() {
+        let items = vec![
+            Value::Bulk(b"invalidate".to_vec()),
+            Value::Array(vec![Value::Bulk(b"a".to_vec())]),
+        ];
+        assert_eq!(
+            b">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\na\r\n".to_vec(),
+            encode_push(&items)
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use tracing::{error, info, warn};
+
+use crate::{access, database::Database};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+// How often draining checks whether in-flight commands are done
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+// What a shutdown drains, set once signal handling is set up
+struct Target {
+    db: Arc<Database>,
+    grace_period: Duration,
+}
+
+fn target() -> &'static OnceLock<Target> {
+    static TARGET: OnceLock<Target> = OnceLock::new();
+    &TARGET
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+// Counts a command as in flight for as long as it's held
+pub struct InFlight(());
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Taken before checking whether the server is shutting down, so that a
+// command either sees the shutdown and backs off, or is waited for
+pub fn command_started() -> InFlight {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    InFlight(())
+}
+
+// Waits until no commands are in flight, for at most the grace period.
+// Returns false if some were still running when it ran out.
+fn wait_for_in_flight(in_flight: &AtomicUsize, grace_period: Duration) -> bool {
+    let deadline = Instant::now() + grace_period;
+    while in_flight.load(Ordering::SeqCst) > 0 {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep((deadline - now).min(DRAIN_INTERVAL));
+    }
+    true
+}
+
+// With save, memtables are flushed to disk before exiting, so that the next
+// start doesn't have to replay the write-ahead log
+fn drain(db: Arc<Database>, grace_period: Duration, save: bool) {
+    // Give in-flight commands up to the grace period to finish and reply
+    if !wait_for_in_flight(&IN_FLIGHT, grace_period) {
+        warn!(
+            "{} commands still running after {:?}",
+            IN_FLIGHT.load(Ordering::SeqCst),
+            grace_period
+        );
+    }
+
+    // Once the whole keyspace is locked, nothing can be in the middle of a
+    // write
+    let _guard = db.lock_keyspace();
+    if save {
+        if let Err(err) = db.flush() {
+            error!("Failed to flush storage: {}", err);
+        }
+        if let Err(err) = access::save() {
+            error!("Failed to save access metadata: {}", err);
+        }
+    }
+    info!("Shutdown complete");
+    process::exit(0);
+}
+
+// Stops accepting connections and commands, and exits once in-flight
+// commands are done. Returns false if a shutdown is already underway, or
+// if there's nothing to shut down.
+pub fn begin(save: bool) -> bool {
+    let target = match target().get() {
+        Some(target) => target,
+        None => return false,
+    };
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    info!(
+        "Shutting down once in-flight commands are done, waiting at most {:?}",
+        target.grace_period
+    );
+    let db = target.db.clone();
+    let grace_period = target.grace_period;
+    thread::spawn(move || drain(db, grace_period, save));
+    true
+}
+
+pub fn handle_signals(db: Arc<Database>, grace_period: Duration) -> Result<()> {
+    let _ = target().set(Target { db, grace_period });
+
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            info!("Received signal {}", signal);
+            if !begin(true) {
+                warn!(
+                    "Received signal {} while shutting down, exiting now",
+                    signal
+                );
+                process::exit(1);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wait_for_in_flight() {
+        // A counter of its own, since other tests' commands count towards
+        // the server's
+        let in_flight = AtomicUsize::new(0);
+        assert!(wait_for_in_flight(&in_flight, Duration::ZERO));
+
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        assert!(!wait_for_in_flight(&in_flight, Duration::from_millis(20)));
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            });
+            let started = Instant::now();
+            assert!(wait_for_in_flight(&in_flight, Duration::from_secs(5)));
+            assert!(started.elapsed() < Duration::from_secs(5));
+        });
+    }
+}
@@ -0,0 +1,196 @@
+use std::f64::consts::LN_2;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sketches::{hash64, SketchError};
+
+// Defaults used when BF.ADD creates a filter implicitly, matching RedisBloom
+const DEFAULT_ERROR_RATE: f64 = 0.01;
+const DEFAULT_CAPACITY: u64 = 100;
+const DEFAULT_EXPANSION: u32 = 2;
+
+// Each new layer gets a tighter error rate so that the compound error rate
+// of the whole filter stays bounded
+const TIGHTENING_RATIO: f64 = 0.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct BloomLayer {
+    capacity: u64,
+    count: u64,
+    n_hashes: u32,
+    n_bits: u64,
+    bits: Vec<u64>,
+}
+
+impl BloomLayer {
+    fn new(capacity: u64, error_rate: f64) -> Self {
+        let n_bits = (-(capacity as f64) * error_rate.ln() / (LN_2 * LN_2))
+            .ceil()
+            .max(64.0) as u64;
+        let n_hashes = ((n_bits as f64 / capacity as f64) * LN_2).ceil().max(1.0) as u32;
+
+        Self {
+            capacity,
+            count: 0,
+            n_hashes,
+            n_bits,
+            bits: vec![0; n_bits.div_ceil(64) as usize],
+        }
+    }
+
+    fn positions(&self, item: &[u8]) -> impl Iterator<Item = u64> {
+        // Kirsch-Mitzenmacher double hashing
+        let h1 = hash64(item, 0);
+        let h2 = hash64(item, h1) | 1;
+        let n_bits = self.n_bits;
+        (0..self.n_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % n_bits)
+    }
+
+    fn contains(&self, item: &[u8]) -> bool {
+        self.positions(item)
+            .all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+
+    fn insert(&mut self, item: &[u8]) {
+        let positions: Vec<u64> = self.positions(item).collect();
+        for pos in positions {
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+        self.count += 1;
+    }
+
+    fn is_full(&self) -> bool {
+        self.count >= self.capacity
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    error_rate: f64,
+    expansion: u32,
+    nonscaling: bool,
+    layers: Vec<BloomLayer>,
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_ERROR_RATE,
+            DEFAULT_CAPACITY,
+            DEFAULT_EXPANSION,
+            false,
+        )
+        .unwrap()
+    }
+}
+
+impl BloomFilter {
+    pub fn new(
+        error_rate: f64,
+        capacity: u64,
+        expansion: u32,
+        nonscaling: bool,
+    ) -> Result<Self, SketchError> {
+        if !(error_rate > 0.0 && error_rate < 1.0) {
+            return Err(SketchError::InvalidArgument("(0 < error rate range < 1)"));
+        }
+        if capacity == 0 {
+            return Err(SketchError::InvalidArgument(
+                "(capacity should be larger than 0)",
+            ));
+        }
+        if expansion == 0 {
+            return Err(SketchError::InvalidArgument(
+                "expansion should be greater or equal to 1",
+            ));
+        }
+
+        Ok(Self {
+            error_rate,
+            expansion,
+            nonscaling,
+            layers: vec![BloomLayer::new(capacity, error_rate)],
+        })
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.layers.iter().any(|layer| layer.contains(item))
+    }
+
+    // Returns false if the item was (probably) already present
+    pub fn add(&mut self, item: &[u8]) -> Result<bool, SketchError> {
+        if self.contains(item) {
+            return Ok(false);
+        }
+
+        let last = self.layers.last().unwrap();
+        if last.is_full() {
+            if self.nonscaling {
+                return Err(SketchError::Full);
+            }
+
+            let n = self.layers.len() as i32;
+            let capacity = last.capacity.saturating_mul(self.expansion as u64);
+            let error_rate = self.error_rate * TIGHTENING_RATIO.powi(n);
+            self.layers.push(BloomLayer::new(capacity, error_rate));
+        }
+
+        self.layers.last_mut().unwrap().insert(item);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bloom_add_contains() {
+        let mut filter = BloomFilter::default();
+        assert!(filter.add(b"item").unwrap());
+        assert!(!filter.add(b"item").unwrap());
+        assert!(filter.contains(b"item"));
+        assert!(!filter.contains(b"other"));
+    }
+
+    #[test]
+    fn test_bloom_scales() {
+        let mut filter = BloomFilter::new(0.01, 10, 2, false).unwrap();
+        for i in 0..100 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+
+        assert!(filter.layers.len() > 1);
+        for i in 0..100 {
+            assert!(filter.contains(i.to_string().as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_bloom_nonscaling_full() {
+        let mut filter = BloomFilter::new(0.01, 2, 2, true).unwrap();
+        filter.add(b"a").unwrap();
+        filter.add(b"b").unwrap();
+        assert!(matches!(filter.add(b"c"), Err(SketchError::Full)));
+    }
+
+    #[test]
+    fn test_bloom_false_positive_rate() {
+        let mut filter = BloomFilter::new(0.01, 1000, 2, false).unwrap();
+        for i in 0..1000 {
+            filter.add(format!("in-{}", i).as_bytes()).unwrap();
+        }
+
+        let false_positives = (0..10000)
+            .filter(|i| filter.contains(format!("out-{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_bloom_invalid_arguments() {
+        assert!(BloomFilter::new(1.5, 100, 2, false).is_err());
+        assert!(BloomFilter::new(0.01, 0, 2, false).is_err());
+        assert!(BloomFilter::new(0.01, 100, 0, false).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+use crate::sketches::{hash64, SketchError};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CountMinSketch {
+    width: u32,
+    depth: u32,
+    count: u64,
+    counters: Vec<u64>,
+}
+
+impl CountMinSketch {
+    pub fn by_dim(width: u32, depth: u32) -> Result<Self, SketchError> {
+        if width == 0 || depth == 0 {
+            return Err(SketchError::InvalidArgument(
+                "width and depth must be greater than 0",
+            ));
+        }
+
+        Ok(Self {
+            width,
+            depth,
+            count: 0,
+            counters: vec![0; width as usize * depth as usize],
+        })
+    }
+
+    // Sizes the sketch so that estimates overshoot by at most
+    // error * total count, with the given probability of exceeding that
+    pub fn by_prob(error: f64, probability: f64) -> Result<Self, SketchError> {
+        if !(error > 0.0 && error < 1.0) {
+            return Err(SketchError::InvalidArgument(
+                "error must be between 0 and 1",
+            ));
+        }
+        if !(probability > 0.0 && probability < 1.0) {
+            return Err(SketchError::InvalidArgument(
+                "probability must be between 0 and 1",
+            ));
+        }
+
+        let width = (2.0 / error).ceil() as u32;
+        let depth = (probability.ln() / 0.5f64.ln()).ceil().max(1.0) as u32;
+        Self::by_dim(width, depth)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn indices<'a>(&self, item: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let width = self.width as u64;
+        (0..self.depth as u64).map(move |row| (row * width + hash64(item, row) % width) as usize)
+    }
+
+    // Returns the new estimate for the item
+    pub fn incr_by(&mut self, item: &[u8], increment: u64) -> u64 {
+        let indices: Vec<usize> = self.indices(item).collect();
+        for i in indices {
+            self.counters[i] = self.counters[i].saturating_add(increment);
+        }
+        self.count = self.count.saturating_add(increment);
+        self.query(item)
+    }
+
+    pub fn query(&self, item: &[u8]) -> u64 {
+        self.indices(item)
+            .map(|i| self.counters[i])
+            .min()
+            .unwrap_or(0)
+    }
+
+    // Replaces this sketch's counters with the weighted sum of the sources
+    pub fn merge(&mut self, sources: &[(CountMinSketch, u64)]) -> Result<(), SketchError> {
+        if sources
+            .iter()
+            .any(|(src, _)| src.width != self.width || src.depth != self.depth)
+        {
+            return Err(SketchError::DimensionMismatch);
+        }
+
+        self.counters.fill(0);
+        self.count = 0;
+        for (src, weight) in sources {
+            for (counter, value) in self.counters.iter_mut().zip(src.counters.iter()) {
+                *counter = counter.saturating_add(value.saturating_mul(*weight));
+            }
+            self.count = self.count.saturating_add(src.count.saturating_mul(*weight));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cms_incr_query() {
+        let mut sketch = CountMinSketch::by_dim(100, 5).unwrap();
+        assert_eq!(sketch.incr_by(b"a", 3), 3);
+        assert_eq!(sketch.incr_by(b"a", 2), 5);
+        assert_eq!(sketch.query(b"a"), 5);
+        assert_eq!(sketch.count(), 5);
+    }
+
+    #[test]
+    fn test_cms_by_prob() {
+        let sketch = CountMinSketch::by_prob(0.001, 0.01).unwrap();
+        assert_eq!(sketch.width(), 2000);
+        assert_eq!(sketch.depth(), 7);
+    }
+
+    #[test]
+    fn test_cms_merge() {
+        let mut a = CountMinSketch::by_dim(100, 5).unwrap();
+        let mut b = CountMinSketch::by_dim(100, 5).unwrap();
+        a.incr_by(b"x", 1);
+        b.incr_by(b"x", 2);
+
+        let mut dest = CountMinSketch::by_dim(100, 5).unwrap();
+        dest.merge(&[(a, 1), (b, 3)]).unwrap();
+        assert_eq!(dest.query(b"x"), 7);
+        assert_eq!(dest.count(), 7);
+    }
+
+    #[test]
+    fn test_cms_merge_dimension_mismatch() {
+        let src = CountMinSketch::by_dim(10, 5).unwrap();
+        let mut dest = CountMinSketch::by_dim(100, 5).unwrap();
+        assert!(matches!(
+            dest.merge(&[(src, 1)]),
+            Err(SketchError::DimensionMismatch)
+        ));
+    }
+}
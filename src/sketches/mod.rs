@@ -0,0 +1,51 @@
+pub mod bloom;
+pub mod cms;
+pub mod tdigest;
+pub mod topk;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SketchError {
+    #[error("ERR item exists")]
+    KeyExists,
+    #[error("ERR not found")]
+    KeyMissing,
+    #[error("ERR {0}")]
+    InvalidArgument(&'static str),
+    #[error("ERR non scaling filter is full")]
+    Full,
+    #[error("ERR width/depth is not equal")]
+    DimensionMismatch,
+}
+
+// Stable 64-bit hash for sketch indexing. This is persisted implicitly
+// through the positions it selects, so it must never change between
+// releases (unlike std's DefaultHasher).
+pub fn hash64(data: &[u8], seed: u64) -> u64 {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf29ce484222325 ^ seed;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    // splitmix64 finalizer, to spread FNV's weak low bits
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58476d1ce4e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash64_stable() {
+        assert_eq!(hash64(b"item", 0), hash64(b"item", 0));
+        assert_ne!(hash64(b"item", 0), hash64(b"item", 1));
+        assert_ne!(hash64(b"item", 0), hash64(b"iten", 0));
+    }
+}
@@ -0,0 +1,255 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sketches::SketchError;
+
+pub const DEFAULT_COMPRESSION: f64 = 100.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+// Merging t-digest (Dunning, 2019) using the k1 scale function, which keeps
+// centroids small near the tails so that extreme quantiles stay accurate
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    count: f64,
+    // Only meaningful when count > 0. These aren't initialized to +/-inf
+    // because JSON can't represent infinities.
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Result<Self, SketchError> {
+        if !(compression.is_finite() && compression >= 1.0) {
+            return Err(SketchError::InvalidArgument(
+                "T-Digest: compression parameter needs to be a positive integer",
+            ));
+        }
+
+        Ok(Self {
+            compression,
+            centroids: vec![],
+            count: 0.0,
+            min: 0.0,
+            max: 0.0,
+        })
+    }
+
+    pub fn compression(&self) -> f64 {
+        self.compression
+    }
+
+    pub fn count(&self) -> f64 {
+        self.count
+    }
+
+    pub fn add(&mut self, values: &[f64]) -> Result<(), SketchError> {
+        if values.iter().any(|v| !v.is_finite()) {
+            return Err(SketchError::InvalidArgument(
+                "T-Digest: error parsing val parameter",
+            ));
+        }
+
+        for value in values {
+            self.push(Centroid {
+                mean: *value,
+                weight: 1.0,
+            });
+        }
+        self.compress();
+        Ok(())
+    }
+
+    pub fn merge(&mut self, sources: &[TDigest]) {
+        for src in sources {
+            for centroid in src.centroids.iter() {
+                self.push(*centroid);
+            }
+        }
+        self.compress();
+    }
+
+    fn push(&mut self, centroid: Centroid) {
+        if self.count == 0.0 {
+            self.min = centroid.mean;
+            self.max = centroid.mean;
+        } else {
+            self.min = self.min.min(centroid.mean);
+            self.max = self.max.max(centroid.mean);
+        }
+        self.count += centroid.weight;
+        self.centroids.push(centroid);
+    }
+
+    fn k(&self, q: f64) -> f64 {
+        self.compression / (2.0 * PI) * (2.0 * q - 1.0).asin()
+    }
+
+    fn k_inverse(&self, k: f64) -> f64 {
+        ((2.0 * PI * k / self.compression).sin() + 1.0) / 2.0
+    }
+
+    fn compress(&mut self) {
+        if self.centroids.len() <= 1 {
+            return;
+        }
+
+        self.centroids
+            .sort_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap());
+
+        let mut merged: Vec<Centroid> = vec![];
+        let mut current = self.centroids[0];
+        let mut weight_so_far = 0.0;
+        let mut q_limit = self.k_inverse(self.k(0.0) + 1.0);
+        for next in self.centroids[1..].iter() {
+            let q = (weight_so_far + current.weight + next.weight) / self.count;
+            if q <= q_limit {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / weight;
+                current.weight = weight;
+            } else {
+                weight_so_far += current.weight;
+                merged.push(current);
+                current = *next;
+                q_limit = self.k_inverse(self.k(weight_so_far / self.count) + 1.0);
+            }
+        }
+        merged.push(current);
+
+        self.centroids = merged;
+    }
+
+    // Estimated value at quantile q (0 <= q <= 1), or NaN when empty
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.centroids.is_empty() {
+            return f64::NAN;
+        }
+        if q <= 0.0 {
+            return self.min;
+        }
+        if q >= 1.0 {
+            return self.max;
+        }
+
+        // Each centroid's weight is treated as centered on its mean, with
+        // linear interpolation between neighbouring centers
+        let index = q * self.count;
+        let mut prev_center = 0.0;
+        let mut prev_mean = self.min;
+        let mut cumulative = 0.0;
+        for centroid in self.centroids.iter() {
+            let center = cumulative + centroid.weight / 2.0;
+            if index < center {
+                let t = (index - prev_center) / (center - prev_center);
+                return prev_mean + t * (centroid.mean - prev_mean);
+            }
+            prev_center = center;
+            prev_mean = centroid.mean;
+            cumulative += centroid.weight;
+        }
+
+        let t = (index - prev_center) / (self.count - prev_center);
+        prev_mean + t * (self.max - prev_mean)
+    }
+
+    // Estimated fraction of observations at or below value, or NaN when
+    // empty
+    pub fn cdf(&self, value: f64) -> f64 {
+        if self.centroids.is_empty() {
+            return f64::NAN;
+        }
+        if value < self.min {
+            return 0.0;
+        }
+        if value >= self.max {
+            return 1.0;
+        }
+
+        let mut prev_center = 0.0;
+        let mut prev_mean = self.min;
+        let mut cumulative = 0.0;
+        for centroid in self.centroids.iter() {
+            let center = cumulative + centroid.weight / 2.0;
+            if value < centroid.mean {
+                let t = (value - prev_mean) / (centroid.mean - prev_mean);
+                return (prev_center + t * (center - prev_center)) / self.count;
+            }
+            prev_center = center;
+            prev_mean = centroid.mean;
+            cumulative += centroid.weight;
+        }
+
+        let t = (value - prev_mean) / (self.max - prev_mean);
+        (prev_center + t * (self.count - prev_center)) / self.count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn uniform(n: usize) -> TDigest {
+        let mut digest = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        let values: Vec<f64> = (1..=n).map(|x| x as f64).collect();
+        digest.add(&values).unwrap();
+        digest
+    }
+
+    #[test]
+    fn test_tdigest_quantile() {
+        let digest = uniform(10000);
+        assert_eq!(digest.quantile(0.0), 1.0);
+        assert_eq!(digest.quantile(1.0), 10000.0);
+        assert!((digest.quantile(0.5) - 5000.0).abs() < 100.0);
+        assert!((digest.quantile(0.99) - 9900.0).abs() < 20.0);
+    }
+
+    #[test]
+    fn test_tdigest_cdf() {
+        let digest = uniform(10000);
+        assert_eq!(digest.cdf(0.0), 0.0);
+        assert_eq!(digest.cdf(10000.0), 1.0);
+        assert!((digest.cdf(2500.0) - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_tdigest_compresses() {
+        let digest = uniform(10000);
+        assert!(digest.centroids.len() < 200);
+        assert_eq!(digest.count(), 10000.0);
+    }
+
+    #[test]
+    fn test_tdigest_merge() {
+        let mut a = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        a.add(&[1.0, 2.0]).unwrap();
+        let mut b = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        b.add(&[3.0, 4.0]).unwrap();
+
+        a.merge(&[b]);
+        assert_eq!(a.count(), 4.0);
+        assert_eq!(a.quantile(0.0), 1.0);
+        assert_eq!(a.quantile(1.0), 4.0);
+    }
+
+    #[test]
+    fn test_tdigest_empty() {
+        let digest = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        assert!(digest.quantile(0.5).is_nan());
+        assert!(digest.cdf(1.0).is_nan());
+    }
+
+    #[test]
+    fn test_tdigest_rejects_non_finite() {
+        let mut digest = TDigest::new(DEFAULT_COMPRESSION).unwrap();
+        assert!(digest.add(&[f64::NAN]).is_err());
+        assert!(digest.add(&[f64::INFINITY]).is_err());
+    }
+}
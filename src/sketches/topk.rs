@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+
+use crate::sketches::{hash64, SketchError};
+
+// Defaults used when TOPK.RESERVE is called with only k, matching RedisBloom
+pub const DEFAULT_WIDTH: u32 = 8;
+pub const DEFAULT_DEPTH: u32 = 7;
+pub const DEFAULT_DECAY: f64 = 0.9;
+
+// Seed for item fingerprints, distinct from the per-row bucket seeds
+const FINGERPRINT_SEED: u64 = u64::MAX;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+struct Bucket {
+    fingerprint: u64,
+    count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct HeapEntry {
+    item: Vec<u8>,
+    count: u64,
+}
+
+// HeavyKeeper (Gong et al., 2018): a count-min-like array of buckets that
+// probabilistically decays colliding counts, plus a min-heap of the k
+// heaviest items seen so far.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TopK {
+    k: u32,
+    width: u32,
+    depth: u32,
+    decay: f64,
+    buckets: Vec<Bucket>,
+    heap: Vec<HeapEntry>,
+    // State for the decay coin flips, stored so that replays of the same
+    // commands produce the same sketch
+    rng: u64,
+}
+
+impl TopK {
+    pub fn new(k: u32, width: u32, depth: u32, decay: f64) -> Result<Self, SketchError> {
+        if k == 0 || width == 0 || depth == 0 {
+            return Err(SketchError::InvalidArgument(
+                "k, width and depth must be greater than 0",
+            ));
+        }
+        if !(decay > 0.0 && decay <= 1.0) {
+            return Err(SketchError::InvalidArgument(
+                "decay must be between 0 and 1",
+            ));
+        }
+
+        Ok(Self {
+            k,
+            width,
+            depth,
+            decay,
+            buckets: vec![Bucket::default(); width as usize * depth as usize],
+            heap: vec![],
+            rng: 0x9e3779b97f4a7c15,
+        })
+    }
+
+    pub fn k(&self) -> u32 {
+        self.k
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn decay(&self) -> f64 {
+        self.decay
+    }
+
+    fn next_random(&mut self) -> f64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn bucket_index(&self, item: &[u8], row: u32) -> usize {
+        let col = hash64(item, row as u64) % self.width as u64;
+        (row * self.width) as usize + col as usize
+    }
+
+    // Adds an item, returning the item expelled from the top-k list to make
+    // room for it, if any
+    pub fn add(&mut self, item: &[u8]) -> Option<Vec<u8>> {
+        let fingerprint = hash64(item, FINGERPRINT_SEED);
+
+        let mut max_count = 0;
+        for row in 0..self.depth {
+            let i = self.bucket_index(item, row);
+            let bucket = self.buckets[i];
+            if bucket.count == 0 || bucket.fingerprint == fingerprint {
+                self.buckets[i] = Bucket {
+                    fingerprint,
+                    count: bucket.count + 1,
+                };
+                max_count = max_count.max(bucket.count + 1);
+            } else {
+                let decay = self.decay.powf(bucket.count as f64);
+                if self.next_random() < decay {
+                    self.buckets[i].count -= 1;
+                    if self.buckets[i].count == 0 {
+                        self.buckets[i] = Bucket {
+                            fingerprint,
+                            count: 1,
+                        };
+                        max_count = max_count.max(1);
+                    }
+                }
+            }
+        }
+
+        if let Some(entry) = self.heap.iter_mut().find(|e| e.item == item) {
+            entry.count = entry.count.max(max_count);
+            return None;
+        }
+
+        if (self.heap.len() as u32) < self.k {
+            self.heap.push(HeapEntry {
+                item: item.to_vec(),
+                count: max_count,
+            });
+            return None;
+        }
+
+        let (min_index, min_entry) = self
+            .heap
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, e)| e.count)
+            .unwrap();
+        if max_count <= min_entry.count {
+            return None;
+        }
+
+        let expelled = std::mem::replace(
+            &mut self.heap[min_index],
+            HeapEntry {
+                item: item.to_vec(),
+                count: max_count,
+            },
+        );
+        Some(expelled.item)
+    }
+
+    pub fn query(&self, item: &[u8]) -> bool {
+        self.heap.iter().any(|e| e.item == item)
+    }
+
+    pub fn count(&self, item: &[u8]) -> u64 {
+        let fingerprint = hash64(item, FINGERPRINT_SEED);
+        (0..self.depth)
+            .map(|row| self.buckets[self.bucket_index(item, row)])
+            .filter(|bucket| bucket.fingerprint == fingerprint)
+            .map(|bucket| bucket.count)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Items in the top-k list with their counts, heaviest first
+    pub fn list(&self) -> Vec<(Vec<u8>, u64)> {
+        let mut entries: Vec<(Vec<u8>, u64)> = self
+            .heap
+            .iter()
+            .map(|e| (e.item.clone(), e.count))
+            .collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_topk_heavy_hitters() {
+        let mut topk = TopK::new(2, DEFAULT_WIDTH, DEFAULT_DEPTH, DEFAULT_DECAY).unwrap();
+        for _ in 0..50 {
+            topk.add(b"a");
+        }
+        for _ in 0..30 {
+            topk.add(b"b");
+        }
+        for i in 0..20 {
+            topk.add(format!("noise-{}", i).as_bytes());
+        }
+
+        assert!(topk.query(b"a"));
+        assert!(topk.query(b"b"));
+        assert!(!topk.query(b"noise-0"));
+        assert_eq!(topk.list()[0].0, b"a".to_vec());
+    }
+
+    #[test]
+    fn test_topk_expels_lightest() {
+        let mut topk = TopK::new(1, 64, DEFAULT_DEPTH, DEFAULT_DECAY).unwrap();
+        assert_eq!(topk.add(b"a"), None);
+        assert_eq!(topk.add(b"b"), None);
+        assert_eq!(topk.add(b"b"), Some(b"a".to_vec()));
+        assert_eq!(topk.count(b"b"), 2);
+    }
+
+    #[test]
+    fn test_topk_invalid_arguments() {
+        assert!(TopK::new(0, 8, 7, 0.9).is_err());
+        assert!(TopK::new(10, 8, 7, 1.5).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{config, time::unix_timestamp};
+
+// Arguments beyond this are summarized, like Redis does, so that huge
+// commands don't bloat the log
+const MAX_ARGS: usize = 32;
+const MAX_ARG_LEN: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowlogEntry {
+    pub id: u64,
+    // Unix time in seconds at which the command was logged
+    pub timestamp: u64,
+    pub duration: Duration,
+    pub args: Vec<Vec<u8>>,
+    // Set when the command ran past the configured command-timeout
+    pub timed_out: bool,
+}
+
+struct Slowlog {
+    next_id: u64,
+    entries: VecDeque<SlowlogEntry>,
+}
+
+fn slowlog() -> &'static Mutex<Slowlog> {
+    static SLOWLOG: OnceLock<Mutex<Slowlog>> = OnceLock::new();
+    SLOWLOG.get_or_init(|| {
+        Mutex::new(Slowlog {
+            next_id: 0,
+            entries: VecDeque::new(),
+        })
+    })
+}
+
+fn summarize_args(args: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut summary: Vec<Vec<u8>> = args
+        .iter()
+        .take(if args.len() > MAX_ARGS {
+            MAX_ARGS - 1
+        } else {
+            MAX_ARGS
+        })
+        .map(|arg| {
+            if arg.len() > MAX_ARG_LEN {
+                let more = format!("... ({} more bytes)", arg.len() - MAX_ARG_LEN);
+                [&arg[..MAX_ARG_LEN], more.as_bytes()].concat()
+            } else {
+                arg.clone()
+            }
+        })
+        .collect();
+    if args.len() > MAX_ARGS {
+        summary.push(format!("... ({} more arguments)", args.len() - MAX_ARGS + 1).into_bytes());
+    }
+    summary
+}
+
+// Records a command if it was slower than slowlog-log-slower-than. Timed out
+// commands are always recorded, so they can be found after the fact.
+pub fn record(args: &[Vec<u8>], duration: Duration, timed_out: bool) {
+    let (threshold, max_len) = {
+        let config = config::config().read().unwrap();
+        let threshold = config
+            .value("slowlog-log-slower-than")
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(-1);
+        let max_len = config
+            .value("slowlog-max-len")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        (threshold, max_len)
+    };
+
+    let is_slow = threshold >= 0 && duration.as_micros() >= threshold as u128;
+    if !(is_slow || timed_out) || max_len == 0 {
+        return;
+    }
+
+    let timestamp = unix_timestamp().map(|t| t.as_secs()).unwrap_or(0);
+    let mut log = slowlog().lock().unwrap();
+    let id = log.next_id;
+    log.next_id += 1;
+    log.entries.push_front(SlowlogEntry {
+        id,
+        timestamp,
+        duration,
+        args: summarize_args(args),
+        timed_out,
+    });
+    log.entries.truncate(max_len);
+}
+
+// Most recent entries first
+pub fn get(count: usize) -> Vec<SlowlogEntry> {
+    let log = slowlog().lock().unwrap();
+    log.entries.iter().take(count).cloned().collect()
+}
+
+pub fn len() -> usize {
+    slowlog().lock().unwrap().entries.len()
+}
+
+pub fn reset() {
+    slowlog().lock().unwrap().entries.clear();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summarize_args() {
+        let args: Vec<Vec<u8>> = (0..40).map(|i| i.to_string().into_bytes()).collect();
+        let summary = summarize_args(&args);
+        assert_eq!(MAX_ARGS, summary.len());
+        assert_eq!(b"... (9 more arguments)".to_vec(), summary[MAX_ARGS - 1]);
+
+        let args = vec![vec![b'a'; 200]];
+        let summary = summarize_args(&args);
+        assert!(summary[0].ends_with(b"... (72 more bytes)"));
+    }
+}
@@ -1,4 +1,7 @@
-use std::time::{Duration, SystemTime};
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
 use thiserror::Error;
 use tracing::trace;
 
@@ -10,27 +13,121 @@ pub enum TimeError {
     ConvertInt(#[from] std::num::TryFromIntError),
     #[error("system time is before UNIX epoch")]
     SystemTimeBeforeUnix(#[from] std::time::SystemTimeError),
+    #[error("timestamp out of range")]
+    Overflow,
+}
+
+// Where expiry logic gets the current time from, so that it can be tested
+// against a clock that only moves when told to
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    fn unix_timestamp(&self) -> Result<Duration, TimeError> {
+        Ok(self.now().duration_since(SystemTime::UNIX_EPOCH)?)
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+pub struct MockClock(Mutex<SystemTime>);
+
+impl MockClock {
+    pub fn new(unix_timestamp: Duration) -> Self {
+        Self(Mutex::new(SystemTime::UNIX_EPOCH + unix_timestamp))
+    }
+
+    pub fn set(&self, unix_timestamp: Duration) {
+        *self.0.lock().unwrap() = SystemTime::UNIX_EPOCH + unix_timestamp;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
 }
 
 pub fn unix_timestamp() -> Result<Duration, TimeError> {
-    Ok(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?)
+    SystemClock.unix_timestamp()
+}
+
+// Timestamps are stored as big-endian u64 milliseconds. They used to be
+// stored as decimal strings, which never start with a zero byte, while the
+// binary ones always do, since they're capped below 2^56 ms.
+const MAX_TIMESTAMP_MS: u64 = (1 << 56) - 1;
+
+pub fn serialize_duration_as_timestamp(
+    clock: &dyn Clock,
+    duration: Duration,
+) -> Result<Vec<u8>, TimeError> {
+    let timestamp = clock
+        .unix_timestamp()?
+        .checked_add(duration)
+        .ok_or(TimeError::Overflow)?;
+    trace!("Serialized duration: {:?}", timestamp);
+    encode_timestamp(timestamp)
+}
+
+pub fn encode_timestamp(timestamp: Duration) -> Result<Vec<u8>, TimeError> {
+    let ms = u64::try_from(timestamp.as_millis())
+        .ok()
+        .filter(|ms| *ms <= MAX_TIMESTAMP_MS)
+        .ok_or(TimeError::Overflow)?;
+    Ok(ms.to_be_bytes().to_vec())
 }
 
-pub fn serialize_duration_as_timestamp(duration: Duration) -> Result<Vec<u8>, TimeError> {
-    let total_ms: i64 = (unix_timestamp()? + duration).as_millis().try_into()?;
-    let total_ms = total_ms.to_string();
-    trace!("Serialized duration: {}", total_ms);
-    Ok(total_ms.to_string().into_bytes())
+pub fn is_legacy_timestamp(timestamp: &[u8]) -> bool {
+    timestamp.len() != 8 || timestamp[0] != 0
 }
 
 pub fn parse_timestamp(timestamp: &[u8]) -> Result<Duration, TimeError> {
-    let timestamp = String::from_utf8_lossy(&timestamp)
-        .parse::<i64>()
-        .and_then(|ts| {
-            Ok(Duration::from_millis(
-                ts.try_into().expect("Failed to convert timestamp to i64"),
-            ))
-        })?;
+    if !is_legacy_timestamp(timestamp) {
+        let ms = u64::from_be_bytes(timestamp.try_into().unwrap());
+        return Ok(Duration::from_millis(ms));
+    }
+
+    let timestamp = String::from_utf8_lossy(&timestamp).parse::<i64>()?;
+    let timestamp = Duration::from_millis(timestamp.try_into()?);
     trace!("Parsed duration: {:?}", timestamp);
     Ok(timestamp)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timestamps() {
+        let clock = MockClock::new(Duration::from_millis(1_700_000_000_000));
+        let encoded = serialize_duration_as_timestamp(&clock, Duration::from_secs(1)).unwrap();
+        assert_eq!(1_700_000_001_000u64.to_be_bytes().to_vec(), encoded);
+        assert!(!is_legacy_timestamp(&encoded));
+        assert_eq!(
+            Duration::from_millis(1_700_000_001_000),
+            parse_timestamp(&encoded).unwrap()
+        );
+
+        // Decimal timestamps from before are still read, even eight digit
+        // ones
+        assert!(is_legacy_timestamp(b"12345678"));
+        assert_eq!(
+            Duration::from_millis(12345678),
+            parse_timestamp(b"12345678").unwrap()
+        );
+
+        assert!(matches!(
+            encode_timestamp(Duration::from_millis(1 << 56)),
+            Err(TimeError::Overflow)
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TimeSeriesError {
+    #[error("ERR TSDB: key already exists")]
+    KeyExists,
+    #[error("ERR TSDB: the key does not exist")]
+    KeyMissing,
+    #[error("ERR TSDB: Timestamp is older than retention")]
+    TooOld,
+    #[error("ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode")]
+    DuplicateSample,
+}
+
+// Per-series metadata. Samples themselves are stored as individual records
+// ordered by timestamp, so ranges can be read without loading the series.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct TimeSeriesInfo {
+    // Maximum age of samples relative to the newest one, in milliseconds.
+    // 0 keeps samples forever.
+    pub retention: u64,
+    pub labels: Vec<(String, String)>,
+    pub total_samples: u64,
+    pub first_timestamp: u64,
+    pub last_timestamp: u64,
+}
+
+impl TimeSeriesInfo {
+    pub fn new(retention: u64, labels: Vec<(String, String)>) -> Self {
+        Self {
+            retention,
+            labels,
+            ..Default::default()
+        }
+    }
+
+    // Samples older than this are trimmed, and can't be added
+    pub fn retention_cutoff(&self) -> u64 {
+        if self.retention == 0 || self.total_samples == 0 {
+            0
+        } else {
+            self.last_timestamp.saturating_sub(self.retention)
+        }
+    }
+
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(l, _)| l == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// A TS.MRANGE filter expression, either "label=value" or "label!=value". A
+// missing label is treated as having an empty value, so "label=" matches
+// series without the label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelFilter {
+    pub label: String,
+    pub value: String,
+    pub negate: bool,
+}
+
+impl LabelFilter {
+    pub fn parse(expr: &str) -> Option<Self> {
+        if let Some((label, value)) = expr.split_once("!=") {
+            return Some(Self {
+                label: label.to_string(),
+                value: value.to_string(),
+                negate: true,
+            });
+        }
+
+        expr.split_once('=').map(|(label, value)| Self {
+            label: label.to_string(),
+            value: value.to_string(),
+            negate: false,
+        })
+    }
+
+    pub fn matches(&self, info: &TimeSeriesInfo) -> bool {
+        let actual = info.label(&self.label).unwrap_or("");
+        (actual == self.value) != self.negate
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+}
+
+impl Aggregation {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "avg" => Some(Self::Avg),
+            "sum" => Some(Self::Sum),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "count" => Some(Self::Count),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, values: &[f64]) -> f64 {
+        match self {
+            Self::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Sum => values.iter().sum(),
+            Self::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            Self::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            Self::Count => values.len() as f64,
+        }
+    }
+}
+
+// Groups timestamp-ordered samples into buckets aligned to multiples of
+// bucket_duration, reporting each bucket at its start time. Empty buckets
+// are omitted.
+pub fn aggregate(
+    samples: &[(u64, f64)],
+    aggregation: Aggregation,
+    bucket_duration: u64,
+) -> Vec<(u64, f64)> {
+    samples
+        .chunk_by(|a, b| a.0 / bucket_duration == b.0 / bucket_duration)
+        .map(|bucket| {
+            let start = bucket[0].0 - bucket[0].0 % bucket_duration;
+            let values: Vec<f64> = bucket.iter().map(|(_, v)| *v).collect();
+            (start, aggregation.apply(&values))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        let samples = vec![(0, 1.0), (5, 3.0), (10, 10.0), (25, 4.0), (29, 2.0)];
+        assert_eq!(
+            aggregate(&samples, Aggregation::Avg, 10),
+            vec![(0, 2.0), (10, 10.0), (20, 3.0)]
+        );
+        assert_eq!(
+            aggregate(&samples, Aggregation::Max, 10),
+            vec![(0, 3.0), (10, 10.0), (20, 4.0)]
+        );
+        assert_eq!(aggregate(&samples, Aggregation::Count, 100), vec![(0, 5.0)]);
+    }
+
+    #[test]
+    fn test_label_filter() {
+        let info = TimeSeriesInfo::new(0, vec![("sensor".into(), "a".into())]);
+        assert!(LabelFilter::parse("sensor=a").unwrap().matches(&info));
+        assert!(!LabelFilter::parse("sensor!=a").unwrap().matches(&info));
+        assert!(LabelFilter::parse("room=").unwrap().matches(&info));
+        assert!(!LabelFilter::parse("sensor=").unwrap().matches(&info));
+        assert!(LabelFilter::parse("sensor").is_none());
+    }
+
+    #[test]
+    fn test_retention_cutoff() {
+        let mut info = TimeSeriesInfo::new(100, vec![]);
+        assert_eq!(info.retention_cutoff(), 0);
+
+        info.total_samples = 1;
+        info.last_timestamp = 1000;
+        assert_eq!(info.retention_cutoff(), 900);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig, ServerConnection,
+};
+use tracing::{info, warn};
+
+use crate::{config, ipfilter};
+
+// redcon only serves plain TCP, so TLS is terminated on a listener of its
+// own, which forwards each decrypted stream to the plain listener over
+// loopback, the way stunnel is used in front of Redis. Forwarded connections
+// start with a PROXY header carrying the TLS client's address, which the
+// server takes from them whether or not proxy-protocol is enabled.
+const BUFFER_SIZE: usize = 16 * 1024;
+
+// Local addresses of the connections forwarded to the plain listener
+fn forwarded() -> &'static Mutex<HashSet<SocketAddr>> {
+    static FORWARDED: OnceLock<Mutex<HashSet<SocketAddr>>> = OnceLock::new();
+    FORWARDED.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+// Whether a connection to the plain listener, by its client address, came
+// from the TLS listener
+pub fn is_forwarded(addr: SocketAddr) -> bool {
+    forwarded().lock().unwrap().contains(&addr)
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(rustls_pemfile::certs(&mut reader).collect::<Result<_, _>>()?)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| anyhow!("no private key in {}", path))
+}
+
+// Like Redis' tls-auth-clients, clients have to present a certificate signed
+// by the CA with "yes", may present one with "optional", and aren't asked
+// for one with "no"
+fn server_config(
+    cert_file: &str,
+    key_file: &str,
+    ca_cert_file: &str,
+    auth_clients: &str,
+) -> Result<ServerConfig> {
+    let builder = ServerConfig::builder();
+    let builder = match auth_clients {
+        "no" => builder.with_no_client_auth(),
+        _ => {
+            if ca_cert_file.is_empty() {
+                return Err(anyhow!(
+                    "tls-ca-cert-file has to be set to authenticate clients"
+                ));
+            }
+
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_cert_file)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = if auth_clients == "optional" {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+    };
+    Ok(builder.with_single_cert(load_certs(cert_file)?, load_key(key_file)?)?)
+}
+
+fn proxy_header(client: SocketAddr, server: SocketAddr) -> String {
+    format!(
+        "PROXY {} {} {} {} {}\r\n",
+        if client.is_ipv4() { "TCP4" } else { "TCP6" },
+        client.ip(),
+        server.ip(),
+        client.port(),
+        server.port()
+    )
+}
+
+// Feeds bytes read from the client to rustls, returning the plaintext and
+// whether the client has closed the session. Anything rustls has to send
+// back, like alerts, is written before returning.
+fn receive(
+    tls: &Mutex<ServerConnection>,
+    mut received: &[u8],
+    tcp: &mut TcpStream,
+) -> Result<(Vec<u8>, bool)> {
+    let mut tls = tls.lock().unwrap();
+    let mut plaintext = vec![];
+    let mut closed = false;
+    while !received.is_empty() {
+        tls.read_tls(&mut received)?;
+        let state = match tls.process_new_packets() {
+            Ok(state) => state,
+            Err(err) => {
+                let _ = tls.write_tls(tcp);
+                return Err(err.into());
+            }
+        };
+
+        let start = plaintext.len();
+        plaintext.resize(start + state.plaintext_bytes_to_read(), 0);
+        tls.reader().read_exact(&mut plaintext[start..])?;
+        closed |= state.peer_has_closed();
+    }
+
+    while tls.wants_write() {
+        tls.write_tls(tcp)?;
+    }
+    Ok((plaintext, closed))
+}
+
+// Encrypts replies from the server until it closes the connection
+fn send(tls: &Mutex<ServerConnection>, mut backend: TcpStream, mut tcp: TcpStream) -> Result<()> {
+    let mut buf = [0; BUFFER_SIZE];
+    loop {
+        let n = backend.read(&mut buf)?;
+        let mut tls = tls.lock().unwrap();
+        if n == 0 {
+            tls.send_close_notify();
+        } else {
+            tls.writer().write_all(&buf[..n])?;
+        }
+        while tls.wants_write() {
+            tls.write_tls(&mut tcp)?;
+        }
+
+        if n == 0 {
+            let _ = tcp.shutdown(Shutdown::Both);
+            return Ok(());
+        }
+    }
+}
+
+// Decrypts requests from the client until it closes the session
+fn forward(
+    tls: Arc<Mutex<ServerConnection>>,
+    tcp: TcpStream,
+    mut backend: TcpStream,
+) -> Result<()> {
+    let sender = {
+        let tls = tls.clone();
+        let backend = backend.try_clone()?;
+        let tcp = tcp.try_clone()?;
+        thread::spawn(move || send(&tls, backend, tcp))
+    };
+
+    let mut reader = tcp.try_clone()?;
+    let mut writer = tcp;
+    let mut buf = [0; BUFFER_SIZE];
+    let result = loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(err) => break Err(err.into()),
+        };
+
+        // The lock is released before writing to the server, which may be
+        // waiting for its replies to be sent
+        let (plaintext, closed) = match receive(&tls, &buf[..n], &mut writer) {
+            Ok(received) => received,
+            Err(err) => break Err(err),
+        };
+        if let Err(err) = backend.write_all(&plaintext) {
+            break Err(err.into());
+        }
+        if closed {
+            break Ok(());
+        }
+    };
+
+    // The server closes its end once it sees ours closed, which lets the
+    // sender finish
+    let _ = backend.shutdown(Shutdown::Write);
+    if result.is_err() {
+        let _ = backend.shutdown(Shutdown::Both);
+    }
+    let _ = sender.join();
+    result
+}
+
+fn handle(mut tcp: TcpStream, config: Arc<ServerConfig>, server_addr: SocketAddr) -> Result<()> {
+    let client = tcp.peer_addr()?;
+    let mut tls = ServerConnection::new(config)?;
+    while tls.is_handshaking() {
+        tls.complete_io(&mut tcp)?;
+    }
+
+    let mut backend = TcpStream::connect(server_addr)?;
+    let local = backend.local_addr()?;
+    forwarded().lock().unwrap().insert(local);
+    let result = backend
+        .write_all(proxy_header(client, server_addr).as_bytes())
+        .map_err(anyhow::Error::from)
+        .and_then(|_| forward(Arc::new(Mutex::new(tls)), tcp, backend));
+    forwarded().lock().unwrap().remove(&local);
+    result
+}
+
+// Serves TLS on tls-port, on the same interface as the plain listener at
+// server_addr, if tls-port is set. It can only be enabled at startup.
+pub fn start_from_config(server_addr: SocketAddr) -> Result<()> {
+    let (port, cert_file, key_file, ca_cert_file, auth_clients) = {
+        let config = config::config().read().unwrap();
+        let value = |name| config.value(name).unwrap_or("").to_string();
+        (
+            config
+                .value("tls-port")
+                .and_then(|v| v.parse::<u16>().ok())
+                .unwrap_or(0),
+            value("tls-cert-file"),
+            value("tls-key-file"),
+            value("tls-ca-cert-file"),
+            value("tls-auth-clients"),
+        )
+    };
+    if port == 0 {
+        return Ok(());
+    }
+
+    let config = Arc::new(server_config(
+        &cert_file,
+        &key_file,
+        &ca_cert_file,
+        &auth_clients,
+    )?);
+    let listener = TcpListener::bind(SocketAddr::new(server_addr.ip(), port))?;
+
+    // Listening on every interface still means forwarding over loopback
+    let server_addr = match server_addr.ip() {
+        IpAddr::V4(ip) if { ip.is_unspecified() } => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), server_addr.port())
+        }
+        IpAddr::V6(ip) if { ip.is_unspecified() } => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), server_addr.port())
+        }
+        _ => server_addr,
+    };
+
+    info!("Serving TLS at {}", listener.local_addr()?);
+    thread::spawn(move || {
+        for tcp in listener.incoming() {
+            let tcp = match tcp {
+                Ok(tcp) => tcp,
+                Err(err) => {
+                    warn!("Failed to accept TLS connection: {}", err);
+                    continue;
+                }
+            };
+
+            // Rejected before the handshake, rather than once the header
+            // reaches the server
+            match tcp.peer_addr() {
+                Ok(addr) if { ipfilter::allow_connection(addr.ip()) } => {}
+                _ => continue,
+            }
+
+            let config = config.clone();
+            thread::spawn(move || {
+                if let Err(err) = handle(tcp, config, server_addr) {
+                    warn!("TLS connection failed: {}", err);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::proxy;
+
+    use super::*;
+
+    #[test]
+    fn test_proxy_header() {
+        let client: SocketAddr = "192.168.0.1:56324".parse().unwrap();
+        let server: SocketAddr = "127.0.0.1:6379".parse().unwrap();
+        let header = proxy_header(client, server);
+        assert_eq!("PROXY TCP4 192.168.0.1 127.0.0.1 56324 6379\r\n", header);
+
+        let args: Vec<Vec<u8>> = header.trim_end().split(' ').map(|arg| arg.into()).collect();
+        assert_eq!(Ok(Some(client)), proxy::parse_v1(&args));
+    }
+
+    #[test]
+    fn test_server_config_needs_ca() {
+        let err = server_config("cert.pem", "key.pem", "", "yes").unwrap_err();
+        assert!(err.to_string().contains("tls-ca-cert-file"));
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{connection::Connection, keyspec, resp::Value};
+
+// Server-assisted client-side caching, in Redis' default mode: the server
+// remembers which keys each tracking connection read, and once one of them
+// is written, tells the connection to drop it from its cache. A key is only
+// invalidated once per read, so connections that don't read it again
+// aren't told again.
+//
+// redcon only hands a connection to wedis when it sends a command, so
+// invalidations are queued and pushed around the connection's next command.
+#[derive(Default)]
+struct Tracking {
+    enabled: HashSet<i64>,
+    // The tracking connections that read each key
+    readers: HashMap<Vec<u8>, HashSet<i64>>,
+    // Invalidated keys still to be pushed to each connection
+    pending: HashMap<i64, Vec<Vec<u8>>>,
+}
+
+impl Tracking {
+    fn invalidate(&mut self, key: &[u8], readers: HashSet<i64>) {
+        for reader in readers {
+            if self.enabled.contains(&reader) {
+                self.pending.entry(reader).or_default().push(key.to_vec());
+            }
+        }
+    }
+}
+
+fn tracking() -> &'static Mutex<Tracking> {
+    static TRACKING: OnceLock<Mutex<Tracking>> = OnceLock::new();
+    TRACKING.get_or_init(|| Mutex::new(Tracking::default()))
+}
+
+pub fn enable(id: i64) {
+    tracking().lock().unwrap().enabled.insert(id);
+}
+
+// Also called when the connection closes. Keys it read are left in the
+// table, and skipped when they're invalidated.
+pub fn disable(id: i64) {
+    let mut tracking = tracking().lock().unwrap();
+    tracking.enabled.remove(&id);
+    tracking.pending.remove(&id);
+}
+
+pub fn is_enabled(id: i64) -> bool {
+    tracking().lock().unwrap().enabled.contains(&id)
+}
+
+// Records the keys a command read, and invalidates the keys it wrote for
+// every connection that read them
+pub fn record(id: i64, args: &[Vec<u8>]) {
+    let mut tracking = tracking().lock().unwrap();
+    if tracking.enabled.is_empty() {
+        return;
+    }
+
+    for key in keyspec::written_keys(args) {
+        let readers = match tracking.readers.remove(key) {
+            Some(readers) => readers,
+            None => continue,
+        };
+        tracking.invalidate(key, readers);
+    }
+
+    if tracking.enabled.contains(&id) {
+        for key in keyspec::read_keys(args) {
+            tracking.readers.entry(key.to_vec()).or_default().insert(id);
+        }
+    }
+}
+
+// Invalidates every key that was read, as when the keyspace is flushed
+pub fn invalidate_all() {
+    let mut tracking = tracking().lock().unwrap();
+    for (key, readers) in mem::take(&mut tracking.readers) {
+        tracking.invalidate(&key, readers);
+    }
+}
+
+pub fn take_pending(id: i64) -> Vec<Vec<u8>> {
+    tracking()
+        .lock()
+        .unwrap()
+        .pending
+        .remove(&id)
+        .unwrap_or_default()
+}
+
+// Pushes the connection's pending invalidations, all keys in one message
+pub fn flush(conn: &mut dyn Connection, id: i64) {
+    let keys = take_pending(id);
+    if keys.is_empty() {
+        return;
+    }
+
+    conn.write_push(vec![
+        Value::Bulk(b"invalidate".to_vec()),
+        Value::Array(keys.into_iter().map(Value::Bulk).collect()),
+    ]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<Vec<u8>> {
+        line.split(' ').map(|arg| arg.into()).collect()
+    }
+
+    #[test]
+    fn test_invalidation() {
+        enable(8001);
+        enable(8002);
+        record(8001, &args("GET tracked-a"));
+        record(8002, &args("MGET tracked-a tracked-b"));
+
+        record(8003, &args("SET tracked-a 1"));
+        assert_eq!(vec![b"tracked-a".to_vec()], take_pending(8001));
+        assert_eq!(vec![b"tracked-a".to_vec()], take_pending(8002));
+
+        // Keys are invalidated once per read
+        record(8003, &args("DEL tracked-a"));
+        assert!(take_pending(8001).is_empty());
+
+        // Connections that stopped tracking aren't told
+        disable(8002);
+        record(8003, &args("DEL tracked-b"));
+        assert!(take_pending(8002).is_empty());
+        disable(8001);
+    }
+}